@@ -0,0 +1,16 @@
+fn main() {
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    // Recorded in the build information of compiled programs
+    let git_hash = std::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(hash) = git_hash {
+        println!("cargo:rustc-env=MOVE2MIDEN_GIT_HASH={}", hash.trim());
+    }
+    println!("cargo:rerun-if-changed=.git/HEAD");
+}
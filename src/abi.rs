@@ -0,0 +1,614 @@
+//! Module defining how the entry function exchanges values with the outside world.
+//!
+//! Parameters of the entry function are flattened into stack elements in
+//! declaration order. The first 16 elements are passed on the initial stack,
+//! the last of them being on top. Any remaining elements are passed on the
+//! advice stack and pushed onto the operand stack by a generated prologue, so
+//! the entry function sees all of its parameters on the stack as if they had
+//! been passed by a regular call. `encode_inputs` produces both parts from the
+//! flattened parameter values. A `u64`, `u128` or `u256` is flattened into
+//! its 32-bit limbs, the low limb first (see `integers` module), and an
+//! `address` or `signer` is the ID of the Miden account (see `accounts`
+//! module). Inputs are untyped field elements, so the prologue also checks
+//! every parameter is a value of its type, aborting with the code of
+//! `Check::InvalidInput` otherwise. Structs, vectors and references are
+//! pointers into memory, whose contents inputs can't provide, so entry
+//! functions can't take them.
+//!
+//! Return values of the entry function are placed on the output stack,
+//! the first return value being on top of the stack (position 0).
+//! Values taking up multiple elements keep their in-stack layout.
+//! All remaining positions of the 16-element output stack are zero.
+//! Programs compiled to assert the result of a predicate (see
+//! `compiler::EntryMode::AssertTrue`) have no outputs: they fail unless the
+//! entry function returns true.
+//!
+//! Programs compiled to check a batch of instances of a predicate (see
+//! `compiler::EntryMode::Batch`) take the number of instances as their only
+//! stack input, and the flattened parameter values of every instance, one
+//! after the other, on the advice stack (see `encode_batch`). They fail unless
+//! the entry function returns true for every instance, and have no outputs.
+//!
+//! These conventions are versioned: the interface of every compiled program
+//! records the `ABI_VERSION` it was compiled with, which changes whenever the
+//! encoding of inputs or outputs does, and hosts refuse to run programs of
+//! other versions (see `EntryAbi::check_version`) instead of silently passing
+//! them values they would read differently.
+//!
+//! `StorageLayout` describes where the program keeps values in memory, for
+//! integrators reading or writing aggregates (see `memory` module).
+
+use {
+    crate::{
+        accounts::AccountId,
+        diagnostics,
+        error_map::{Check, ErrorCodes},
+        memory::MemoryMap,
+        types,
+    },
+    anyhow::Context,
+    miden::math::Felt,
+    miden_assembly::ast::{CodeBody, Instruction, Node},
+    move_binary_format::{
+        access::ModuleAccess,
+        file_format::{FunctionDefinition, Signature, SignatureToken, StructFieldInformation},
+        CompiledModule,
+    },
+    serde::{Deserialize, Serialize},
+    std::fmt,
+};
+
+/// Number of elements in the Miden input stack.
+pub const MAX_INPUTS: usize = 16;
+
+/// Number of elements in the Miden output stack.
+pub const MAX_OUTPUTS: usize = 16;
+
+/// Version of the conventions for encoding the inputs and outputs of programs.
+pub const ABI_VERSION: u32 = 1;
+
+/// Move type of a value exchanged with the entry function.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbiType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    U256,
+    Address,
+    Signer,
+    Vector(Box<AbiType>),
+    Struct { module: String, name: String },
+    Reference(Box<AbiType>),
+    MutableReference(Box<AbiType>),
+    TypeParameter(u16),
+}
+
+impl AbiType {
+    pub fn new(module: &CompiledModule, token: &SignatureToken) -> Self {
+        match token {
+            SignatureToken::Bool => Self::Bool,
+            SignatureToken::U8 => Self::U8,
+            SignatureToken::U16 => Self::U16,
+            SignatureToken::U32 => Self::U32,
+            SignatureToken::U64 => Self::U64,
+            SignatureToken::U128 => Self::U128,
+            SignatureToken::U256 => Self::U256,
+            SignatureToken::Address => Self::Address,
+            SignatureToken::Signer => Self::Signer,
+            SignatureToken::Vector(inner) => Self::Vector(Box::new(Self::new(module, inner))),
+            SignatureToken::Struct(index) | SignatureToken::StructInstantiation(index, _) => {
+                let handle = module.struct_handle_at(*index);
+                let module_handle = module.module_handle_at(handle.module);
+                Self::Struct {
+                    module: module.identifier_at(module_handle.name).to_string(),
+                    name: module.identifier_at(handle.name).to_string(),
+                }
+            }
+            SignatureToken::Reference(inner) => Self::Reference(Box::new(Self::new(module, inner))),
+            SignatureToken::MutableReference(inner) => {
+                Self::MutableReference(Box::new(Self::new(module, inner)))
+            }
+            SignatureToken::TypeParameter(index) => Self::TypeParameter(*index),
+        }
+    }
+}
+
+impl fmt::Display for AbiType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool => write!(f, "bool"),
+            Self::U8 => write!(f, "u8"),
+            Self::U16 => write!(f, "u16"),
+            Self::U32 => write!(f, "u32"),
+            Self::U64 => write!(f, "u64"),
+            Self::U128 => write!(f, "u128"),
+            Self::U256 => write!(f, "u256"),
+            Self::Address => write!(f, "address"),
+            Self::Signer => write!(f, "signer"),
+            Self::Vector(inner) => write!(f, "vector<{inner}>"),
+            Self::Struct { module, name } => write!(f, "{module}::{name}"),
+            Self::Reference(inner) => write!(f, "&{inner}"),
+            Self::MutableReference(inner) => write!(f, "&mut {inner}"),
+            Self::TypeParameter(index) => write!(f, "T{index}"),
+        }
+    }
+}
+
+/// Interface of the entry function of a compiled program.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryAbi {
+    pub function: String,
+    pub params: Vec<AbiType>,
+    pub returns: Vec<AbiType>,
+    pub inputs: InputLayout,
+    // Number of output stack elements holding return values.
+    pub output_width: usize,
+    // True if the program asserts the entry function returns true instead of
+    // outputting its result.
+    #[serde(default)]
+    pub asserts_result: bool,
+    // True if the program checks a batch of instances read from the advice
+    // stack, `inputs` being the layout of a single instance.
+    #[serde(default)]
+    pub batch: bool,
+    // Version of the encoding conventions of the program (see `ABI_VERSION`).
+    // Interfaces recorded before versioning follow the first version.
+    #[serde(default = "first_version")]
+    pub version: u32,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl EntryAbi {
+    pub fn new(module: &CompiledModule, func_def: &FunctionDefinition) -> anyhow::Result<Self> {
+        let handle = module.function_handle_at(func_def.function);
+        let params = module.signature_at(handle.parameters);
+        let returns = module.signature_at(handle.return_);
+        Ok(Self {
+            function: module.identifier_at(handle.name).to_string(),
+            params: params.0.iter().map(|t| AbiType::new(module, t)).collect(),
+            returns: returns.0.iter().map(|t| AbiType::new(module, t)).collect(),
+            inputs: InputLayout::new(params)?,
+            output_width: signature_width(returns)?,
+            asserts_result: false,
+            batch: false,
+            version: ABI_VERSION,
+        })
+    }
+
+    /// Fails unless the program encodes its inputs and outputs as this crate does.
+    pub fn check_version(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.version == ABI_VERSION,
+            "The program was compiled with ABI version {}, but values are encoded with \
+             version {ABI_VERSION}",
+            self.version
+        );
+        Ok(())
+    }
+}
+
+/// How the parameters of the entry function are split between the stack and advice inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputLayout {
+    pub stack_width: usize,
+    pub advice_width: usize,
+}
+
+impl InputLayout {
+    pub fn new(params: &Signature) -> anyhow::Result<Self> {
+        let width = signature_width(params)?;
+        let stack_width = width.min(MAX_INPUTS);
+        Ok(Self {
+            stack_width,
+            advice_width: width - stack_width,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.stack_width + self.advice_width
+    }
+}
+
+/// How a program uses Miden memory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageLayout {
+    pub heap_pointer_address: u32,
+    // First of the `MAX_OUTPUTS` addresses used by the epilogue.
+    pub output_buffer_address: u32,
+    pub heap_start: u32,
+    // Number of addresses at the start of the heap holding vector constants,
+    // memoized results and the locals of the entry function.
+    pub data_section_size: u32,
+    // Structs defined by the module, one address per stack element of a field.
+    pub structs: Vec<StructLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StructLayout {
+    pub name: String,
+    // Empty for native structs.
+    pub fields: Vec<FieldLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldLayout {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: AbiType,
+    // Offset of the field from the struct pointer.
+    pub offset: u32,
+}
+
+impl StorageLayout {
+    pub fn new(module: &CompiledModule, data_section_size: u32) -> Self {
+        Self::with_memory(module, data_section_size, &MemoryMap::default())
+    }
+
+    /// Layout of a program using the memory regions of `memory`.
+    pub fn with_memory(
+        module: &CompiledModule,
+        data_section_size: u32,
+        memory: &MemoryMap,
+    ) -> Self {
+        let structs = module
+            .struct_defs()
+            .iter()
+            .map(|struct_def| {
+                let handle = module.struct_handle_at(struct_def.struct_handle);
+                let fields = match &struct_def.field_information {
+                    StructFieldInformation::Declared(fields) => fields
+                        .iter()
+                        .scan(0, |offset, field| {
+                            let layout = FieldLayout {
+                                name: module.identifier_at(field.name).to_string(),
+                                ty: AbiType::new(module, &field.signature.0),
+                                offset: *offset,
+                            };
+                            // Fields of unsupported types are given a single address
+                            *offset += types::stack_width(&field.signature.0).unwrap_or(1) as u32;
+                            Some(layout)
+                        })
+                        .collect(),
+                    StructFieldInformation::Native => Vec::new(),
+                };
+                StructLayout {
+                    name: module.identifier_at(handle.name).to_string(),
+                    fields,
+                }
+            })
+            .collect();
+        Self {
+            heap_pointer_address: memory.heap_pointer,
+            output_buffer_address: memory.output_buffer,
+            heap_start: memory.heap_start,
+            data_section_size,
+            structs,
+        }
+    }
+}
+
+/// Inputs of the entry function as they must be provided to the Miden VM.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodedInputs {
+    // In the order expected by `StackInputs`, i.e. the first element is on top of the stack.
+    pub stack: Vec<u64>,
+    // In the order the prologue reads them, i.e. the first element is at the top of the advice stack.
+    pub advice: Vec<u64>,
+}
+
+/// Splits the flattened parameter values of the entry function into stack and advice inputs.
+pub fn encode_inputs(layout: &InputLayout, values: &[u64]) -> anyhow::Result<EncodedInputs> {
+    if values.len() != layout.width() {
+        anyhow::bail!(
+            "Entry function takes {} stack elements but {} were given",
+            layout.width(),
+            values.len()
+        );
+    }
+    let (stack, advice) = values.split_at(layout.stack_width);
+    Ok(EncodedInputs {
+        stack: stack.iter().rev().copied().collect(),
+        advice: advice.to_vec(),
+    })
+}
+
+/// Encodes the flattened parameter values of a batch of instances of the entry
+/// function, given the layout of a single instance: the number of instances
+/// is on the stack and the values of every instance on the advice stack.
+pub fn encode_batch(layout: &InputLayout, instances: &[Vec<u64>]) -> anyhow::Result<EncodedInputs> {
+    let mut advice = Vec::with_capacity(layout.width() * instances.len());
+    for (index, values) in instances.iter().enumerate() {
+        if values.len() != layout.width() {
+            anyhow::bail!(
+                "Entry function takes {} stack elements but {} were given for instance {index}",
+                layout.width(),
+                values.len()
+            );
+        }
+        advice.extend_from_slice(values);
+    }
+    Ok(EncodedInputs {
+        stack: vec![instances.len() as u64],
+        advice,
+    })
+}
+
+/// Generates the code which must run at the start of the entry function
+/// to move parameters which did not fit on the input stack from the advice stack,
+/// and to check every parameter is a value of its type. Elements on the input
+/// stack are checked first, then every element from the advice stack once it's
+/// pushed, so the checked element is never deeper than `dup` can reach.
+pub fn prologue(params: &Signature, codes: &ErrorCodes) -> anyhow::Result<Vec<Node>> {
+    let invalid = codes.code(Check::InvalidInput);
+    let layout = InputLayout::new(params)?;
+    let mut bounds = Vec::new();
+    for token in &params.0 {
+        let bound = input_bound(token)?;
+        for _ in 0..types::stack_width(token)? {
+            bounds.push(bound);
+        }
+    }
+    let (stack, advice) = bounds.split_at(layout.stack_width);
+    let mut result = Vec::new();
+    for (offset, bound) in stack.iter().enumerate() {
+        // The last element of the input stack is on top
+        result.extend(check_input(
+            *bound,
+            layout.stack_width - 1 - offset,
+            invalid,
+        ));
+    }
+    for bound in advice {
+        result.push(Node::Instruction(Instruction::AdvPush(1)));
+        result.extend(check_input(*bound, 0, invalid));
+    }
+    Ok(result)
+}
+
+/// Largest value of every input element of a parameter of the given type, if
+/// it must be checked. Wider integers are made of u32 limbs, while addresses
+/// and signers are account IDs, and every element is the ID of an account (see
+/// `accounts` module); hosts check the values they are given are field
+/// elements (see `check_inputs`). Aggregates and references are pointers into
+/// memory, whose contents inputs can't provide, so they can't be parameters.
+fn input_bound(token: &SignatureToken) -> anyhow::Result<Option<u32>> {
+    match token {
+        SignatureToken::Bool => Ok(Some(1)),
+        SignatureToken::U8 => Ok(Some(u8::MAX as u32)),
+        SignatureToken::U16 => Ok(Some(u16::MAX as u32)),
+        SignatureToken::U32 | SignatureToken::U64 | SignatureToken::U128 | SignatureToken::U256 => {
+            Ok(Some(u32::MAX))
+        }
+        SignatureToken::Address | SignatureToken::Signer => Ok(None),
+        _ => Err(diagnostics::UNSUPPORTED_TYPE.error(format!(
+            "Entry function parameters of type {token:?} are pointers into memory, which \
+             inputs can't provide"
+        ))),
+    }
+}
+
+/// Fails unless the flattened parameter values of the entry function are
+/// values of the parameter types which the prologue doesn't check: addresses
+/// and signers must be account IDs.
+pub fn check_inputs(params: &[AbiType], values: &[u64]) -> anyhow::Result<()> {
+    let mut values = values.iter();
+    for param in params {
+        let width = match param {
+            AbiType::U64 => 2,
+            AbiType::U128 => 4,
+            AbiType::U256 => 8,
+            _ => 1,
+        };
+        for value in values.by_ref().take(width) {
+            if matches!(param, AbiType::Address | AbiType::Signer) {
+                AccountId::new(*value)
+                    .with_context(|| format!("Invalid {param} input {value:#x}"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the input element at `depth` on the stack is at most `bound`,
+/// aborting with `code` otherwise.
+fn check_input(bound: Option<u32>, depth: usize, code: u32) -> Vec<Node> {
+    let Some(bound) = bound else {
+        return Vec::new();
+    };
+    vec![
+        Node::Instruction(dup(depth)),
+        Node::Instruction(Instruction::PushU32(bound)),
+        Node::Instruction(Instruction::Lte),
+        Node::Instruction(Instruction::AssertWithError(code)),
+    ]
+}
+
+pub(crate) fn dup(depth: usize) -> Instruction {
+    match depth {
+        0 => Instruction::Dup0,
+        1 => Instruction::Dup1,
+        2 => Instruction::Dup2,
+        3 => Instruction::Dup3,
+        4 => Instruction::Dup4,
+        5 => Instruction::Dup5,
+        6 => Instruction::Dup6,
+        7 => Instruction::Dup7,
+        8 => Instruction::Dup8,
+        9 => Instruction::Dup9,
+        10 => Instruction::Dup10,
+        11 => Instruction::Dup11,
+        12 => Instruction::Dup12,
+        13 => Instruction::Dup13,
+        14 => Instruction::Dup14,
+        _ => Instruction::Dup15,
+    }
+}
+
+/// Moves the element at `depth` to the top of the stack, if it isn't there already.
+pub(crate) fn movup(depth: usize) -> Option<Instruction> {
+    match depth {
+        0 => None,
+        1 => Some(Instruction::Swap1),
+        2 => Some(Instruction::MovUp2),
+        3 => Some(Instruction::MovUp3),
+        4 => Some(Instruction::MovUp4),
+        5 => Some(Instruction::MovUp5),
+        6 => Some(Instruction::MovUp6),
+        7 => Some(Instruction::MovUp7),
+        8 => Some(Instruction::MovUp8),
+        9 => Some(Instruction::MovUp9),
+        10 => Some(Instruction::MovUp10),
+        11 => Some(Instruction::MovUp11),
+        12 => Some(Instruction::MovUp12),
+        13 => Some(Instruction::MovUp13),
+        14 => Some(Instruction::MovUp14),
+        _ => Some(Instruction::MovUp15),
+    }
+}
+
+/// Moves the element on top of the stack to `depth`, if it isn't there already.
+pub(crate) fn movdn(depth: usize) -> Option<Instruction> {
+    match depth {
+        0 => None,
+        1 => Some(Instruction::Swap1),
+        2 => Some(Instruction::MovDn2),
+        3 => Some(Instruction::MovDn3),
+        4 => Some(Instruction::MovDn4),
+        5 => Some(Instruction::MovDn5),
+        6 => Some(Instruction::MovDn6),
+        7 => Some(Instruction::MovDn7),
+        8 => Some(Instruction::MovDn8),
+        9 => Some(Instruction::MovDn9),
+        10 => Some(Instruction::MovDn10),
+        11 => Some(Instruction::MovDn11),
+        12 => Some(Instruction::MovDn12),
+        13 => Some(Instruction::MovDn13),
+        14 => Some(Instruction::MovDn14),
+        _ => Some(Instruction::MovDn15),
+    }
+}
+
+/// Generates the code which must run at the end of a predicate entry function,
+/// failing unless it returns true, with no outputs.
+pub fn assert_epilogue(returns: &Signature) -> anyhow::Result<Vec<Node>> {
+    if returns.0 != [SignatureToken::Bool] {
+        return Err(diagnostics::ENTRY_POINT
+            .error("Only entry functions returning a single bool can be asserted"));
+    }
+    let mut result = vec![Node::Instruction(Instruction::Assert)];
+    result.extend(clear_stack());
+    Ok(result)
+}
+
+/// Generates a program checking a batch of instances of the entry function,
+/// whose code is `instance`, with the number of instances on top of the stack.
+/// Every instance reads all its parameters from the advice stack, checking
+/// them as `prologue` does, and fails unless the entry function returns true.
+pub fn batch(
+    params: &Signature,
+    returns: &Signature,
+    instance: &[Node],
+    codes: &ErrorCodes,
+) -> anyhow::Result<Vec<Node>> {
+    let invalid = codes.code(Check::InvalidInput);
+    if returns.0 != [SignatureToken::Bool] {
+        return Err(diagnostics::ENTRY_POINT
+            .error("Only entry functions returning a single bool can be checked in batches"));
+    }
+    let mut body = Vec::new();
+    for token in &params.0 {
+        let bound = input_bound(token)?;
+        for _ in 0..types::stack_width(token)? {
+            body.push(Node::Instruction(Instruction::AdvPush(1)));
+            body.extend(check_input(bound, 0, invalid));
+        }
+    }
+    body.extend_from_slice(instance);
+    // [result, count] -> [count - 1 != 0, count - 1]
+    body.push(Node::Instruction(Instruction::Assert));
+    body.extend(
+        [
+            Instruction::SubImm(Felt::new(1)),
+            Instruction::Dup0,
+            Instruction::NeqImm(Felt::new(0)),
+        ]
+        .map(Node::Instruction),
+    );
+    // The count must be a u32, so the loop ends
+    let mut result = check_input(Some(u32::MAX), 0, invalid);
+    result.extend([Instruction::Dup0, Instruction::NeqImm(Felt::new(0))].map(Node::Instruction));
+    result.push(Node::While {
+        body: CodeBody::new(body),
+    });
+    result.push(Node::Instruction(Instruction::Drop));
+    result.extend(clear_stack());
+    Ok(result)
+}
+
+/// Generates the code which must run at the end of the entry function to
+/// arrange its return values (on top of the stack) according to the output convention.
+pub fn epilogue(returns: &Signature, memory: &MemoryMap) -> anyhow::Result<Vec<Node>> {
+    let width = signature_width(returns)?;
+    if width > MAX_OUTPUTS {
+        return Err(diagnostics::ABI_LIMIT.error(format!(
+            "Entry function returns {width} stack elements, but the output stack only holds {MAX_OUTPUTS}"
+        )));
+    }
+    let width = width as u32;
+    let mut result = Vec::new();
+
+    // Save return values; the last one is on top of the stack.
+    for i in (0..width).rev() {
+        result.push(Node::Instruction(Instruction::MemStoreImm(
+            memory.output_buffer + i,
+        )));
+    }
+
+    result.extend(clear_stack());
+
+    // Restore the return values, replacing zeros so the depth stays minimal.
+    for i in (0..width).rev() {
+        result.push(Node::Instruction(Instruction::MemLoadImm(
+            memory.output_buffer + i,
+        )));
+        result.push(Node::Instruction(Instruction::Swap1));
+        result.push(Node::Instruction(Instruction::Drop));
+    }
+    Ok(result)
+}
+
+/// Drops everything until the stack has its minimal depth, then zeroes out the
+/// remaining elements.
+fn clear_stack() -> Vec<Node> {
+    let mut result = Vec::new();
+    let depth_exceeds_min = [
+        Node::Instruction(Instruction::Sdepth),
+        Node::Instruction(Instruction::PushU32(MAX_OUTPUTS as u32)),
+        Node::Instruction(Instruction::Gt),
+    ];
+    result.extend_from_slice(&depth_exceeds_min);
+    let mut loop_body = vec![Node::Instruction(Instruction::Drop)];
+    loop_body.extend_from_slice(&depth_exceeds_min);
+    result.push(Node::While {
+        body: CodeBody::new(loop_body),
+    });
+    for _ in 0..(MAX_OUTPUTS / 4) {
+        result.push(Node::Instruction(Instruction::DropW));
+    }
+    result
+}
+
+fn signature_width(signature: &Signature) -> anyhow::Result<usize> {
+    let mut width = 0;
+    for token in &signature.0 {
+        width += types::stack_width(token)?;
+    }
+    Ok(width)
+}
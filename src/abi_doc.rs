@@ -0,0 +1,202 @@
+//! Module rendering the interface of a compiled program as a report for integrators.
+//!
+//! The report is generated from the ABI and storage layout recorded in a bundle,
+//! so it always matches what the compiler produced.
+
+use {
+    crate::{
+        abi::{AbiType, EntryAbi, StorageLayout, MAX_INPUTS, MAX_OUTPUTS},
+        bundle::Bundle,
+        error_map::Check,
+    },
+    std::fmt::Write,
+};
+
+/// Renders the interface of the program in `bundle` as Markdown.
+pub fn render(bundle: &Bundle) -> anyhow::Result<String> {
+    let (Some(abi), Some(layout)) = (&bundle.abi, &bundle.layout) else {
+        anyhow::bail!("The bundle has no interface; rebuild it with `move2miden build --bundle`");
+    };
+    let mut report = String::new();
+    writeln!(report, "# Interface of `{}`\n", abi.function)?;
+    writeln!(report, "Program hash: `{}`\n", bundle.program_hash)?;
+    writeln!(report, "ABI version: {}\n", abi.version)?;
+    render_entry(&mut report, abi)?;
+    render_layout(&mut report, layout)?;
+    writeln!(report, "\n## Abort codes\n")?;
+    for (code, error) in &bundle.error_map.errors {
+        writeln!(
+            report,
+            "- `{code}`: `{}::{}` {}",
+            error.module, error.name, error.doc
+        )?;
+    }
+    // Checks emitted by the compiler
+    let checks = &bundle.error_map.checks;
+    for check in Check::ALL {
+        writeln!(
+            report,
+            "- `{}`: `{}` {}",
+            checks.code(check),
+            check.name(),
+            check.doc()
+        )?;
+    }
+    Ok(report)
+}
+
+fn render_entry(report: &mut String, abi: &EntryAbi) -> anyhow::Result<()> {
+    writeln!(report, "## Entry point\n")?;
+    let params: Vec<String> = abi.params.iter().map(|t| t.to_string()).collect();
+    let returns: Vec<String> = abi.returns.iter().map(|t| t.to_string()).collect();
+    writeln!(
+        report,
+        "`{}({})`{}\n",
+        abi.function,
+        params.join(", "),
+        match returns.as_slice() {
+            [] => String::new(),
+            [single] => format!(": {single}"),
+            _ => format!(": ({})", returns.join(", ")),
+        }
+    )?;
+    writeln!(report, "### Inputs\n")?;
+    writeln!(
+        report,
+        "Parameters are flattened into {} field elements in declaration order.",
+        abi.inputs.width()
+    )?;
+    if abi
+        .params
+        .iter()
+        .chain(&abi.returns)
+        .any(|t| *t == AbiType::U64)
+    {
+        writeln!(
+            report,
+            "A `u64` takes two elements, its low 32 bits followed by its high 32 bits."
+        )?;
+    }
+    if abi
+        .params
+        .iter()
+        .chain(&abi.returns)
+        .any(|t| *t == AbiType::U128)
+    {
+        writeln!(
+            report,
+            "A `u128` takes four elements, its 32-bit limbs from the lowest to the highest."
+        )?;
+    }
+    if abi
+        .params
+        .iter()
+        .chain(&abi.returns)
+        .any(|t| *t == AbiType::U256)
+    {
+        writeln!(
+            report,
+            "A `u256` takes eight elements, its 32-bit limbs from the lowest to the highest."
+        )?;
+    }
+    if abi
+        .params
+        .iter()
+        .chain(&abi.returns)
+        .any(|t| matches!(t, AbiType::Address | AbiType::Signer))
+    {
+        writeln!(
+            report,
+            "An `address` or `signer` takes one element, the ID of the Miden account with the same value."
+        )?;
+    }
+    if abi.batch {
+        writeln!(
+            report,
+            "The program checks a batch of instances: the number of instances is passed on the \
+             initial stack, and the elements of every instance, one instance after the other, \
+             on the advice stack, the first of them on top."
+        )?;
+    } else {
+        writeln!(
+            report,
+            "The first {} are passed on the initial stack (at most {MAX_INPUTS}), the last of them on top.",
+            abi.inputs.stack_width
+        )?;
+    }
+    if abi.inputs.advice_width > 0 && !abi.batch {
+        writeln!(
+            report,
+            "The remaining {} are passed on the advice stack, the first of them on top.",
+            abi.inputs.advice_width
+        )?;
+    }
+    writeln!(report, "\n### Outputs\n")?;
+    if abi.asserts_result {
+        writeln!(
+            report,
+            "The program has no outputs: it fails unless `{}` returns true{}.",
+            abi.function,
+            if abi.batch { " for every instance" } else { "" }
+        )?;
+        return Ok(());
+    }
+    writeln!(
+        report,
+        "Return values take the first {} of the {MAX_OUTPUTS} output stack elements, \
+         the first return value on top. Other elements are zero.",
+        abi.output_width
+    )?;
+    Ok(())
+}
+
+fn render_layout(report: &mut String, layout: &StorageLayout) -> anyhow::Result<()> {
+    writeln!(report, "\n## Memory layout\n")?;
+    writeln!(report, "| Address | Contents |")?;
+    writeln!(report, "|---|---|")?;
+    writeln!(
+        report,
+        "| {} | Offset of the next free heap address |",
+        layout.heap_pointer_address
+    )?;
+    writeln!(
+        report,
+        "| {}..{} | Return values, while the output stack is cleared |",
+        layout.output_buffer_address,
+        layout.output_buffer_address + MAX_OUTPUTS as u32
+    )?;
+    let heap = layout.heap_start + layout.data_section_size;
+    if layout.data_section_size > 0 {
+        writeln!(
+            report,
+            "| {}..{heap} | Vector constants |",
+            layout.heap_start
+        )?;
+    }
+    writeln!(report, "| {heap}.. | Heap |")?;
+    if layout.structs.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        report,
+        "\nStructs are stored on the heap, one address per field (one per 32-bit limb of wider integers), \
+         and passed by pointer."
+    )?;
+    for s in &layout.structs {
+        writeln!(report, "\n### `{}`\n", s.name)?;
+        if s.fields.is_empty() {
+            writeln!(report, "Native struct, with no known layout.")?;
+            continue;
+        }
+        writeln!(report, "| Offset | Field | Type |")?;
+        writeln!(report, "|---|---|---|")?;
+        for field in &s.fields {
+            writeln!(
+                report,
+                "| {} | `{}` | `{}` |",
+                field.offset, field.name, field.ty
+            )?;
+        }
+    }
+    Ok(())
+}
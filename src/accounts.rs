@@ -0,0 +1,93 @@
+//! Module mapping Move account addresses to Miden account IDs and back.
+//!
+//! Programs hold an `address`, and a `signer`, which is lowered to the address
+//! of its account, in a single field element: the ID of the Miden account. An
+//! address maps to the account ID of the same numeric value, so only addresses
+//! below the field modulus have an account ID, and every account ID maps back
+//! to a single address. Storage keys (see `witness` module) and simulated
+//! states (see `state` module) are keyed by account ID, so they can be
+//! cross-referenced with the addresses of Move code through this mapping.
+
+use {
+    crate::bytes,
+    miden::math::{Felt, StarkField},
+    move_core_types::account_address::AccountAddress,
+    serde::{Deserialize, Serialize},
+    std::fmt,
+};
+
+/// ID of a Miden account, a field element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct AccountId(u64);
+
+impl AccountId {
+    pub fn new(id: u64) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            id < Felt::MODULUS,
+            "Account ID {id:#x} is not a field element"
+        );
+        Ok(Self(id))
+    }
+
+    /// Account ID of a Move address, failing if the address doesn't fit in a
+    /// field element.
+    pub fn from_address(address: &AccountAddress) -> anyhow::Result<Self> {
+        let element = bytes::address_to_felt(address).map_err(|_| {
+            anyhow::anyhow!(
+                "Address {} doesn't fit in a Miden account ID",
+                address.to_hex_literal()
+            )
+        })?;
+        Ok(Self(element.as_int()))
+    }
+
+    /// Move address of the account.
+    pub fn to_address(self) -> AccountAddress {
+        bytes::address_from_felt(self.element())
+    }
+
+    /// Stack element holding the account, e.g. as an `address` or `signer`
+    /// parameter of an entry function.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn element(self) -> Felt {
+        Felt::new(self.0)
+    }
+}
+
+impl TryFrom<u64> for AccountId {
+    type Error = anyhow::Error;
+
+    fn try_from(id: u64) -> anyhow::Result<Self> {
+        Self::new(id)
+    }
+}
+
+impl From<AccountId> for u64 {
+    fn from(id: AccountId) -> Self {
+        id.0
+    }
+}
+
+impl TryFrom<&AccountAddress> for AccountId {
+    type Error = anyhow::Error;
+
+    fn try_from(address: &AccountAddress) -> anyhow::Result<Self> {
+        Self::from_address(address)
+    }
+}
+
+impl From<AccountId> for AccountAddress {
+    fn from(id: AccountId) -> Self {
+        id.to_address()
+    }
+}
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
@@ -0,0 +1,438 @@
+//! Module for the analysis of a Move module done before code generation.
+//!
+//! Compilation runs in two passes: `analyze` builds the control flow graph of
+//! every function, recognizes its counted loops and collects its types and
+//! calls, and `compiler::codegen` lowers the analyzed functions to Miden code.
+//! Tools which only need the analysis (e.g. audit dashboards or cost estimators)
+//! can skip code generation, and code can be generated several times from the
+//! same analysis, e.g. with different cost models.
+//!
+//! The control flow graph, counted loops and bytecode cost of a function only
+//! depend on its bytecode, so they can be kept in an `AnalysisCache` and reused
+//! when a function with the same code is analyzed again in the same session.
+
+use {
+    crate::{
+        cfg::{Cfg, Graph, Label, OutgoingEdge},
+        compiler::CodeLocation,
+        cost::Cost,
+        loops, ranges,
+    },
+    move_binary_format::{
+        access::ModuleAccess,
+        file_format::{
+            Bytecode, CodeOffset, FunctionDefinitionIndex, SignatureToken,
+            StructDefInstantiationIndex, StructDefinitionIndex,
+        },
+        CompiledModule,
+    },
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+#[derive(Debug)]
+pub struct AnalysisResult<'a> {
+    pub module: &'a CompiledModule,
+    // One entry per function definition, in definition order.
+    pub functions: Vec<FunctionAnalysis<'a>>,
+}
+
+#[derive(Debug)]
+pub struct FunctionAnalysis<'a> {
+    pub name: String,
+    pub definition: FunctionDefinitionIndex,
+    pub is_entry: bool,
+    pub params: Vec<SignatureToken>,
+    pub returns: Vec<SignatureToken>,
+    // Declared locals, after the parameters.
+    pub locals: Vec<SignatureToken>,
+    // `None` for native functions.
+    pub cfg: Option<Cfg<'a>>,
+    // Number of iterations of every counted loop, by loop header (see `loops` module).
+    // Code generation only compiles loops to `repeat` blocks up to
+    // `CostModel::max_repeat` iterations.
+    pub counted_loops: BTreeMap<Label, u32>,
+    // Offsets of the additions, subtractions and multiplications which may
+    // overflow, so their result must be checked (see `ranges` module).
+    pub overflow_checks: BTreeSet<CodeOffset>,
+    // Functions called, as `module::function`.
+    pub calls: BTreeSet<String>,
+    // Resources of the module a call may read or move out of global storage,
+    // by struct name, from the `acquires` annotation of the function.
+    pub acquires: BTreeSet<String>,
+    // Resources of the module accessed in global storage by the function itself,
+    // excluding the functions called, by struct name.
+    pub storage: BTreeMap<String, StorageAccess>,
+    // Worst-case number of Move instructions executed by a call, excluding the
+    // functions called. A cheap proxy for the cost in cycles, which needs code
+    // generation (see `ProcedureInfo::cost`).
+    pub bytecode_cost: Cost,
+}
+
+/// How a function accesses a resource in global storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StorageAccess {
+    // `exists` or `borrow_global`.
+    Read,
+    // `move_to`, `move_from` or `borrow_global_mut`.
+    Write,
+}
+
+impl AnalysisResult<'_> {
+    /// Functions of the module called by every function of the module, by name.
+    pub fn call_graph(&self) -> BTreeMap<&str, BTreeSet<&str>> {
+        let module_name = self.module.self_id().name().to_string();
+        let local: BTreeSet<&str> = self.functions.iter().map(|f| f.name.as_str()).collect();
+        self.functions
+            .iter()
+            .map(|function| {
+                let callees = function
+                    .calls
+                    .iter()
+                    .filter_map(|call| call.strip_prefix(&module_name)?.strip_prefix("::"))
+                    .filter_map(|name| local.get(name).copied())
+                    .collect();
+                (function.name.as_str(), callees)
+            })
+            .collect()
+    }
+
+    /// Functions of the module without side effects, by name: they don't access
+    /// global storage, take no mutable references and only call functions of
+    /// the module without side effects. Their result only depends on their
+    /// arguments, so calls with the same arguments can share their result.
+    pub fn pure_functions(&self) -> BTreeSet<&str> {
+        let module_name = self.module.self_id().name().to_string();
+        let mut pure: BTreeSet<&str> = self
+            .functions
+            .iter()
+            .filter(|f| {
+                f.cfg.is_some()
+                    && f.storage.is_empty()
+                    && !f
+                        .params
+                        .iter()
+                        .any(|t| matches!(t, SignatureToken::MutableReference(_)))
+            })
+            .map(|f| f.name.as_str())
+            .collect();
+        // Drop functions calling others with side effects until none are left
+        loop {
+            let impure: Vec<&str> = self
+                .functions
+                .iter()
+                .filter(|f| pure.contains(f.name.as_str()))
+                .filter(|f| {
+                    f.calls.iter().any(|call| {
+                        let local = call
+                            .strip_prefix(&module_name)
+                            .and_then(|call| call.strip_prefix("::"));
+                        !matches!(local, Some(name) if pure.contains(name))
+                    })
+                })
+                .map(|f| f.name.as_str())
+                .collect();
+            if impure.is_empty() {
+                return pure;
+            }
+            for name in impure {
+                pure.remove(name);
+            }
+        }
+    }
+}
+
+/// Results of the analysis of functions which only depend on their bytecode, by
+/// bytecode. It can be shared between threads, e.g. by the compilations of a server.
+#[derive(Debug, Default)]
+pub struct AnalysisCache {
+    functions: Mutex<HashMap<Vec<Bytecode>, CachedFunction>>,
+    // Number of functions analyzed with results from the cache.
+    hits: AtomicUsize,
+}
+
+#[derive(Debug, Clone)]
+struct CachedFunction {
+    graph: Arc<Graph>,
+    counted_loops: BTreeMap<Label, u32>,
+    bytecode_cost: Cost,
+}
+
+impl AnalysisCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of functions analyzed with results from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn get(&self, code: &[Bytecode]) -> Option<CachedFunction> {
+        let cached = self.functions.lock().ok()?.get(code).cloned()?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(cached)
+    }
+
+    fn insert(&self, code: &[Bytecode], function: CachedFunction) {
+        // A poisoned lock only means results are not cached
+        if let Ok(mut functions) = self.functions.lock() {
+            functions.insert(code.to_vec(), function);
+        }
+    }
+}
+
+/// Analyzes every function of the module.
+pub fn analyze(module: &CompiledModule) -> anyhow::Result<AnalysisResult<'_>> {
+    analyze_with(module, None)
+}
+
+/// Analyzes every function of the module, reusing the results in `cache` for
+/// functions analyzed before and adding the others.
+pub fn analyze_cached<'a>(
+    module: &'a CompiledModule,
+    cache: &AnalysisCache,
+) -> anyhow::Result<AnalysisResult<'a>> {
+    analyze_with(module, Some(cache))
+}
+
+fn analyze_with<'a>(
+    module: &'a CompiledModule,
+    cache: Option<&AnalysisCache>,
+) -> anyhow::Result<AnalysisResult<'a>> {
+    let functions = (0..module.function_defs().len())
+        .map(|index| {
+            let definition = FunctionDefinitionIndex::new(index as u16);
+            analyze_function_with(module, definition, cache)
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(AnalysisResult { module, functions })
+}
+
+/// Analyzes a single function of the module.
+pub fn analyze_function(
+    module: &CompiledModule,
+    definition: FunctionDefinitionIndex,
+) -> anyhow::Result<FunctionAnalysis<'_>> {
+    analyze_function_with(module, definition, None)
+}
+
+fn analyze_function_with<'a>(
+    module: &'a CompiledModule,
+    definition: FunctionDefinitionIndex,
+    cache: Option<&AnalysisCache>,
+) -> anyhow::Result<FunctionAnalysis<'a>> {
+    let func_def = module.function_def_at(definition);
+    let handle = module.function_handle_at(func_def.function);
+    let mut function = FunctionAnalysis {
+        name: module.identifier_at(handle.name).to_string(),
+        definition,
+        is_entry: func_def.is_entry,
+        params: module.signature_at(handle.parameters).0.clone(),
+        returns: module.signature_at(handle.return_).0.clone(),
+        locals: Vec::new(),
+        cfg: None,
+        counted_loops: BTreeMap::new(),
+        overflow_checks: BTreeSet::new(),
+        calls: BTreeSet::new(),
+        acquires: func_def
+            .acquires_global_resources
+            .iter()
+            .map(|index| {
+                let handle = module.struct_handle_at(module.struct_def_at(*index).struct_handle);
+                module.identifier_at(handle.name).to_string()
+            })
+            .collect(),
+        storage: BTreeMap::new(),
+        bytecode_cost: Cost {
+            cycles: 0,
+            bounded: true,
+        },
+    };
+    let Some(code) = &func_def.code else {
+        return Ok(function);
+    };
+    function.locals = module.signature_at(code.locals).0.clone();
+    for bytecode in &code.code {
+        if let Some((index, access)) = storage_access(module, bytecode) {
+            let handle = module.struct_handle_at(module.struct_def_at(index).struct_handle);
+            let current = function
+                .storage
+                .entry(module.identifier_at(handle.name).to_string())
+                .or_insert(access);
+            *current = (*current).max(access);
+        }
+        let callee = match bytecode {
+            Bytecode::Call(index) => Some(*index),
+            Bytecode::CallGeneric(index) => Some(module.function_instantiation_at(*index).handle),
+            _ => None,
+        };
+        if let Some(index) = callee {
+            let callee = module.function_handle_at(index);
+            let callee_module = module.identifier_at(module.module_handle_at(callee.module).name);
+            let callee_name = module.identifier_at(callee.name);
+            function
+                .calls
+                .insert(format!("{callee_module}::{callee_name}"));
+        }
+    }
+    function.overflow_checks =
+        ranges::overflow_checks(module, &code.code, &function.params, &function.locals);
+    if let Some(cached) = cache.and_then(|cache| cache.get(&code.code)) {
+        function.cfg = Some(Cfg::with_graph(&code.code, cached.graph));
+        function.counted_loops = cached.counted_loops;
+        function.bytecode_cost = cached.bytecode_cost;
+        return Ok(function);
+    }
+    let cfg = Cfg::new(&code.code).map_err(|e| {
+        // Point at the branch found invalid
+        let location = CodeLocation {
+            function: definition,
+            offset: Some(e.branch as CodeOffset),
+        };
+        anyhow::Error::new(e.with_function(&function.name)).context(location)
+    })?;
+    // `repeat` counts are 32 bits wide
+    function.counted_loops = loops::counted_loops(&cfg, &code.code, u32::MAX as u64);
+    function.bytecode_cost =
+        bytecode_cost(&cfg, &function.counted_loops, Label::Entry, Label::Exit)?;
+    if let Some(cache) = cache {
+        let cached = CachedFunction {
+            graph: cfg.graph().clone(),
+            counted_loops: function.counted_loops.clone(),
+            bytecode_cost: function.bytecode_cost,
+        };
+        cache.insert(&code.code, cached);
+    }
+    function.cfg = Some(cfg);
+    Ok(function)
+}
+
+/// Resource accessed in global storage by the instruction, if any.
+fn storage_access(
+    module: &CompiledModule,
+    bytecode: &Bytecode,
+) -> Option<(StructDefinitionIndex, StorageAccess)> {
+    let generic = |index: StructDefInstantiationIndex| module.struct_instantiation_at(index).def;
+    let result = match bytecode {
+        Bytecode::Exists(index) | Bytecode::ImmBorrowGlobal(index) => (*index, StorageAccess::Read),
+        Bytecode::ExistsGeneric(index) | Bytecode::ImmBorrowGlobalGeneric(index) => {
+            (generic(*index), StorageAccess::Read)
+        }
+        Bytecode::MoveTo(index) | Bytecode::MoveFrom(index) | Bytecode::MutBorrowGlobal(index) => {
+            (*index, StorageAccess::Write)
+        }
+        Bytecode::MoveToGeneric(index)
+        | Bytecode::MoveFromGeneric(index)
+        | Bytecode::MutBorrowGlobalGeneric(index) => (generic(*index), StorageAccess::Write),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Worst-case number of instructions executed from `current` until `target`,
+/// following the structure of the generated code.
+fn bytecode_cost(
+    cfg: &Cfg<'_>,
+    loops: &BTreeMap<Label, u32>,
+    current: Label,
+    target: Label,
+) -> anyhow::Result<Cost> {
+    let weight = |block: &[Bytecode]| -> anyhow::Result<Cost> {
+        Ok(Cost {
+            cycles: block.len() as u64,
+            bounded: true,
+        })
+    };
+    weighted_cost(cfg, loops, current, target, &weight)
+}
+
+/// Worst-case sum of the weights of the blocks run from `current` until
+/// `target`, following the structure of the generated code. Loops without a
+/// constant bound make the result unbounded, unless their body weighs nothing.
+pub(crate) fn weighted_cost(
+    cfg: &Cfg<'_>,
+    loops: &BTreeMap<Label, u32>,
+    current: Label,
+    target: Label,
+    weight: &dyn Fn(&[Bytecode]) -> anyhow::Result<Cost>,
+) -> anyhow::Result<Cost> {
+    if current == target {
+        return Ok(Cost {
+            cycles: 0,
+            bounded: true,
+        });
+    }
+    let edge = cfg.edge(&current)?;
+    if let (Some(times), OutgoingEdge::WhileTrue { body_start, after }) =
+        (loops.get(&current), edge)
+    {
+        let body = weighted_cost(cfg, loops, *body_start, target, weight)?;
+        let repeated = Cost {
+            cycles: body.cycles * *times as u64,
+            bounded: body.bounded,
+        };
+        return Ok(repeated.then(weighted_cost(cfg, loops, *after, target, weight)?));
+    }
+    match cfg.rotated_loop_latch(&current) {
+        Some(latch) if latch != target => {
+            let body = weighted_cost(cfg, loops, current, latch, weight)?
+                .then(weight(cfg.block(&latch)?)?);
+            let after = match cfg.edge(&latch)? {
+                OutgoingEdge::DoWhileTrue { after, .. }
+                | OutgoingEdge::DoWhileFalse { after, .. } => *after,
+                _ => anyhow::bail!("Invalid rotated loop"),
+            };
+            return Ok(unbounded_loop(body).then(weighted_cost(cfg, loops, after, target, weight)?));
+        }
+        _ => (),
+    }
+    let block = weight(cfg.block(&current)?)?;
+    let rest = match edge {
+        OutgoingEdge::Pass { next } => weighted_cost(cfg, loops, *next, target, weight)?,
+        OutgoingEdge::If {
+            true_case,
+            false_case,
+        } => {
+            let join = cfg.first_common_ancestor(true_case, false_case);
+            weighted_cost(cfg, loops, *true_case, join, weight)?
+                .max(weighted_cost(cfg, loops, *false_case, join, weight)?)
+                .then(weighted_cost(cfg, loops, join, target, weight)?)
+        }
+        OutgoingEdge::LoopBack { header } if loops.contains_key(header) => Cost {
+            cycles: 0,
+            bounded: true,
+        },
+        // The loop condition is evaluated again at the end of every iteration
+        OutgoingEdge::LoopBack { header } => {
+            let condition = cfg.loop_condition(header);
+            weighted_cost(cfg, loops, *header, condition, weight)?
+                .then(weight(cfg.block(&condition)?)?)
+        }
+        OutgoingEdge::WhileTrue { body_start, after }
+        | OutgoingEdge::WhileFalse { body_start, after } => {
+            let body = weighted_cost(cfg, loops, *body_start, target, weight)?;
+            unbounded_loop(body).then(weighted_cost(cfg, loops, *after, target, weight)?)
+        }
+        // Counted with the header of the loop
+        OutgoingEdge::DoWhileTrue { .. } | OutgoingEdge::DoWhileFalse { .. } => Cost {
+            cycles: 0,
+            bounded: true,
+        },
+    };
+    Ok(block.then(rest))
+}
+
+/// Cost of a loop running `body` an unknown number of times, only counting
+/// one iteration.
+fn unbounded_loop(body: Cost) -> Cost {
+    Cost {
+        bounded: body.bounded && body.cycles == 0,
+        ..body
+    }
+}
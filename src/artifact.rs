@@ -0,0 +1,365 @@
+//! Module defining the result of compiling a Move module.
+
+use {
+    crate::{
+        abi::{EntryAbi, StorageLayout},
+        backend::{DefaultBackend, MidenBackend},
+        bundle::{self, Bundle, BundleProcedure, COMPILER_VERSION},
+        compiler::{CodeLocation, TargetContext},
+        cost::{self, Cost, CostModel},
+        diagnostics::Diagnostics,
+        error_map::{ErrorCodes, ErrorMap},
+        footprint::MemoryFootprint,
+        memory::MemoryMap,
+        passes::PassIr,
+    },
+    miden_assembly::{
+        ast::{CodeBody, Instruction, Node, ProcedureAst, ProgramAst},
+        Program,
+    },
+    move_binary_format::{file_format::FunctionDefinitionIndex, CompiledModule},
+    move_bytecode_source_map::source_map::SourceMap,
+    move_core_types::language_storage::ModuleId,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, fmt, path::Path},
+};
+
+/// Everything produced by compiling a Move module to a Miden program.
+#[derive(Debug)]
+pub struct CompiledArtifact {
+    pub program: ProgramAst,
+    // One entry per Move function definition, in definition order.
+    pub procedures: Vec<ProcedureInfo>,
+    pub abi: EntryAbi,
+    pub layout: StorageLayout,
+    // Only available when the module was compiled from source.
+    pub source_map: Option<SourceMap>,
+    pub stats: Stats,
+    // Non-fatal problems found during compilation.
+    pub diagnostics: Diagnostics,
+    // Instructions left out of the program (see `OpcodePolicy::Skip`).
+    pub skipped_opcodes: Vec<CodeLocation>,
+    // Program after each compiler pass, if requested (see `CodegenOptions::emit_pass_ir`).
+    pub pass_ir: Vec<PassIr>,
+    pub metadata: ModuleMetadata,
+    // Descriptions of the abort codes of the module (see `error_map` module).
+    pub error_map: ErrorMap,
+    pub build_info: BuildInfo,
+    pub(crate) entry: EntryFunction,
+}
+
+impl CompiledArtifact {
+    /// The Move function compiled into the body of the program.
+    pub fn entry(&self) -> &EntryFunction {
+        &self.entry
+    }
+
+    /// Miden assembly of the program, preceded by a comment describing how it was built.
+    pub fn masm(&self) -> String {
+        format!("{}\n{}", self.build_info.masm_header(), self.program)
+    }
+}
+
+/// A compiled program loaded from a bundle, with what hosts need to run it
+/// again (see `runner::execute_artifact`) without the Move module it was
+/// compiled from or the compiler pipeline.
+#[derive(Debug)]
+pub struct Artifact {
+    pub program: Program,
+    // Hex encoded MAST root of the program, as recorded in the bundle.
+    pub program_hash: String,
+    pub abi: EntryAbi,
+    pub layout: Option<StorageLayout>,
+    pub error_map: ErrorMap,
+    pub build_info: Option<BuildInfo>,
+    // Procedures of the program, with the location of their Move function in
+    // the sources when known, the body of the program last.
+    pub procedures: Vec<BundleProcedure>,
+}
+
+impl Artifact {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Self::from_bundle(Bundle::load(path)?)
+    }
+
+    /// Assembles the program of the bundle, failing unless the bundle records
+    /// its interface and the program has the recorded hash.
+    pub fn from_bundle(bundle: Bundle) -> anyhow::Result<Self> {
+        let Some(abi) = bundle.abi else {
+            anyhow::bail!(
+                "The bundle has no interface; rebuild it with `move2miden build --bundle`"
+            );
+        };
+        abi.check_version()?;
+        let ast = ProgramAst::parse(&bundle.masm)
+            .map_err(|e| anyhow::anyhow!("Failed to parse the assembly of the bundle: {e}"))?;
+        let backend = DefaultBackend::default();
+        let program = backend.assemble(&ast)?;
+        let hash = bundle::to_hex(&backend.program_hash(&program));
+        anyhow::ensure!(
+            hash == bundle.program_hash,
+            "Program hash mismatch: recorded {}, assembled {hash}",
+            bundle.program_hash
+        );
+        Ok(Self {
+            program,
+            program_hash: bundle.program_hash,
+            abi,
+            layout: bundle.layout,
+            error_map: bundle.error_map,
+            build_info: bundle.build_info,
+            procedures: bundle.procedures,
+        })
+    }
+}
+
+/// How a program was produced, so a program hash can be traced back to the
+/// compiler build and options used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub compiler_version: String,
+    // Commit the compiler was built from, when built from a git checkout.
+    pub git_hash: Option<String>,
+    pub options: CompilerOptions,
+}
+
+/// Options a module was compiled with.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompilerOptions {
+    // Parameters of the cost model (see `CostModel::parameters`).
+    pub cost_model: BTreeMap<String, u64>,
+    // Whether the `miden` attributes of the sources were applied.
+    pub attributes: bool,
+    // Whether repeated calls of pure functions with constant arguments were memoized.
+    #[serde(default)]
+    pub memoize: bool,
+    // Whether opcodes the compiler can't lower were left out of the program.
+    #[serde(default)]
+    pub skip_unsupported_opcodes: bool,
+    // Whether heap allocations are checked with canary words (see `memory` module).
+    #[serde(default)]
+    pub canaries: bool,
+    // Context the program was compiled to run in.
+    #[serde(default)]
+    pub target: TargetContext,
+    // Addresses of the memory regions used by the program.
+    #[serde(default)]
+    pub memory: MemoryMap,
+    // Abort codes of the checks emitted by the compiler.
+    #[serde(default)]
+    pub error_codes: ErrorCodes,
+}
+
+impl BuildInfo {
+    pub fn new(options: CompilerOptions) -> Self {
+        Self {
+            compiler_version: COMPILER_VERSION.into(),
+            git_hash: option_env!("MOVE2MIDEN_GIT_HASH").map(String::from),
+            options,
+        }
+    }
+
+    /// Comment lines to put at the start of Miden assembly files.
+    pub fn masm_header(&self) -> String {
+        let mut header = format!("# Compiled by move2miden {}", self.compiler_version);
+        if let Some(hash) = &self.git_hash {
+            header.push_str(&format!(" ({hash})"));
+        }
+        let options = serde_json::to_string(&self.options).unwrap_or_default();
+        header.push_str(&format!("\n# Options: {options}\n"));
+        header
+    }
+}
+
+/// Identity of the Move entry function of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryFunction {
+    pub module: ModuleId,
+    pub function: String,
+    pub definition: FunctionDefinitionIndex,
+    // Name of the empty procedure standing in for the entry function (see `mangle` module).
+    pub procedure_name: String,
+}
+
+impl fmt::Display for EntryFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.module, self.function)
+    }
+}
+
+/// Metadata of the compiled Move module, kept for tools which rely on it,
+/// such as explorers or decoders of abort codes using the error map.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleMetadata {
+    // Version of the Move bytecode format the module was compiled to.
+    pub bytecode_version: u32,
+    pub entries: Vec<MetadataEntry>,
+}
+
+/// Metadata entry of a Move module. Keys and values are arbitrary bytes, hex
+/// encoded when serialized; e.g. Aptos stores error maps under `aptos::metadata_v1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataEntry {
+    #[serde(with = "hex_bytes")]
+    pub key: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub value: Vec<u8>,
+}
+
+impl ModuleMetadata {
+    pub fn new(module: &CompiledModule) -> Self {
+        Self {
+            bytecode_version: module.version,
+            entries: module
+                .metadata
+                .iter()
+                .map(|m| MetadataEntry {
+                    key: m.key.clone(),
+                    value: m.value.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Value of the first entry with the given key.
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|e| e.key == key)
+            .map(|e| e.value.as_slice())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|e| e.key.as_slice())
+    }
+}
+
+mod hex_bytes {
+    use {
+        crate::bundle::{from_hex, to_hex},
+        serde::{de::Error, Deserialize, Deserializer, Serializer},
+    };
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        from_hex(&hex).map_err(D::Error::custom)
+    }
+}
+
+/// Mapping between a Move function and the code generated for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcedureInfo {
+    pub function: String,
+    pub definition: FunctionDefinitionIndex,
+    // Index of the local procedure, or `None` for the entry function,
+    // which is the body of the program.
+    pub procedure: Option<u16>,
+    pub cost: Cost,
+    // Resources the function acquires (see `FunctionAnalysis::acquires`), i.e. the
+    // storage keys a call may touch, so hosts can provide their proofs up front.
+    pub acquires: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub procedures: usize,
+    // Instructions of the assembled procedures and body. The assembler copies the
+    // body of a `repeat` block once per iteration, and a local procedure into
+    // every call site, so they are counted as many times.
+    pub instructions: usize,
+    // Estimated cost of running the body of the program, excluding called procedures.
+    pub entry_cost: Cost,
+    // Memory used by every Move function, in definition order (see `footprint` module).
+    pub memory: Vec<MemoryFootprint>,
+}
+
+impl Stats {
+    pub fn new(program: &ProgramAst, cost_model: &dyn CostModel) -> Self {
+        let procedures = program.procedures();
+        let mut sizes = vec![None; procedures.len()];
+        let instructions = (0..procedures.len())
+            .map(|index| procedure_size(procedures, index, &mut sizes))
+            .fold(
+                assembled_size(program.body(), procedures, &mut sizes),
+                usize::saturating_add,
+            );
+        Self {
+            procedures: program.procedures().len(),
+            instructions,
+            entry_cost: cost::estimate_with(program.body(), cost_model),
+            memory: Vec::new(),
+        }
+    }
+
+    /// Functions whose memory may reach a reserved region, with the regions reached.
+    pub fn memory_collisions(&self) -> impl Iterator<Item = (&str, &[&'static str])> {
+        self.memory
+            .iter()
+            .filter(|footprint| !footprint.collisions.is_empty())
+            .map(|footprint| (footprint.function.as_str(), footprint.collisions.as_slice()))
+    }
+}
+
+/// Instructions of a code body as written, with nested blocks counted once.
+pub(crate) fn code_size(body: &CodeBody) -> usize {
+    body.nodes()
+        .iter()
+        .map(|node| match node {
+            Node::Instruction(_) => 1,
+            Node::IfElse {
+                true_case,
+                false_case,
+            } => code_size(true_case) + code_size(false_case),
+            Node::Repeat { body, .. } | Node::While { body } => code_size(body),
+        })
+        .sum()
+}
+
+/// Instructions of a code body once assembled (see `Stats::instructions`), given
+/// the sizes of the local procedures computed so far.
+fn assembled_size(
+    body: &CodeBody,
+    procedures: &[ProcedureAst],
+    sizes: &mut Vec<Option<usize>>,
+) -> usize {
+    body.nodes()
+        .iter()
+        .map(|node| match node {
+            Node::Instruction(Instruction::ExecLocal(index) | Instruction::CallLocal(index)) => {
+                procedure_size(procedures, *index as usize, sizes)
+            }
+            Node::Instruction(_) => 1,
+            Node::IfElse {
+                true_case,
+                false_case,
+            } => assembled_size(true_case, procedures, sizes)
+                .saturating_add(assembled_size(false_case, procedures, sizes)),
+            Node::Repeat { times, body } => {
+                assembled_size(body, procedures, sizes).saturating_mul(*times as usize)
+            }
+            Node::While { body } => assembled_size(body, procedures, sizes),
+        })
+        .fold(0, usize::saturating_add)
+}
+
+fn procedure_size(
+    procedures: &[ProcedureAst],
+    index: usize,
+    sizes: &mut Vec<Option<usize>>,
+) -> usize {
+    let Some(procedure) = procedures.get(index) else {
+        return 1;
+    };
+    if let Some(size) = sizes[index] {
+        return size;
+    }
+    // Recursive calls can't be assembled, so they count as unbounded
+    sizes[index] = Some(usize::MAX);
+    let size = assembled_size(&procedure.body, procedures, sizes);
+    sizes[index] = Some(size);
+    size
+}
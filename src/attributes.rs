@@ -0,0 +1,181 @@
+//! Module for the `#[miden(...)]` attributes of Move functions.
+//!
+//! Move attribute names are plain identifiers, so all settings are grouped under
+//! a single `miden` attribute, e.g. `#[miden(max_cycles = 100_000)]` or
+//! `#[miden(export, inline(never))]`. Attributes are not kept in the bytecode;
+//! the frontend reads them from the parsed sources and hands them to the
+//! compiler along with the compiled modules.
+
+use {
+    move_compiler::parser::ast::{Attribute, AttributeValue_, Attribute_, Attributes, Value_},
+    move_core_types::language_storage::ModuleId,
+    move_ir_types::location::Loc,
+    std::collections::BTreeMap,
+};
+
+/// Name of the attribute holding all the settings.
+pub const ATTRIBUTE_NAME: &str = "miden";
+
+/// Settings of a function given by its `miden` attributes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionAttributes {
+    // Largest number of cycles a call of the function may take, set by `max_cycles = N`.
+    pub max_cycles: Option<u64>,
+    // Set by `inline`, `inline(always)` or `inline(never)`.
+    pub inline: Option<InlineHint>,
+    // Set by `export`, to export the procedure even if the function isn't public,
+    // e.g. for hooks called from other Miden code.
+    pub export: bool,
+    // Miden procedure implementing a native function, as `path::to::module::procedure`,
+    // set by `intrinsic = b"..."`. Takes precedence over built-in intrinsics.
+    pub intrinsic: Option<String>,
+    // Set by `foreign`, for native functions implemented by a procedure of another
+    // account, whose ID is the first parameter (see `fpi` module).
+    pub foreign: bool,
+}
+
+/// Whether calls to a function should be inlined (see `inline` module).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InlineHint {
+    Always,
+    Never,
+}
+
+/// Attributes of the functions of a module, by function name.
+/// Functions without `miden` attributes are left out.
+pub type ModuleAttributes = BTreeMap<String, FunctionAttributes>;
+
+/// Attributes of the functions of several modules, e.g. all modules of the sources.
+pub type PackageAttributes = BTreeMap<ModuleId, ModuleAttributes>;
+
+/// Invalid attribute, with its location in the sources.
+#[derive(Debug, Clone)]
+pub struct AttributeError {
+    pub loc: Loc,
+    pub message: String,
+}
+
+impl FunctionAttributes {
+    /// Reads the settings from the attributes of a function, ignoring attributes
+    /// other than `miden`.
+    pub fn parse(attributes: &[Attributes], is_native: bool) -> Result<Self, AttributeError> {
+        let mut result = Self::default();
+        let settings = attributes
+            .iter()
+            .flat_map(|attributes| &attributes.value)
+            .filter_map(|attribute| match &attribute.value {
+                Attribute_::Parameterized(name, settings)
+                    if name.value.as_str() == ATTRIBUTE_NAME =>
+                {
+                    Some(settings.value.iter())
+                }
+                _ => None,
+            })
+            .flatten();
+        for setting in settings {
+            result.set(setting, is_native)?;
+        }
+        Ok(result)
+    }
+
+    fn set(&mut self, setting: &Attribute, is_native: bool) -> Result<(), AttributeError> {
+        let error = |message: String| AttributeError {
+            loc: setting.loc,
+            message,
+        };
+        match &setting.value {
+            Attribute_::Assigned(name, value) if name.value.as_str() == "max_cycles" => {
+                let AttributeValue_::Value(value) = &value.value else {
+                    return Err(error("Expected a number of cycles".into()));
+                };
+                let Value_::Num(number) = &value.value else {
+                    return Err(error("Expected a number of cycles".into()));
+                };
+                self.max_cycles = Some(
+                    parse_number(number.as_str())
+                        .ok_or_else(|| error(format!("Invalid number of cycles {number}")))?,
+                );
+            }
+            Attribute_::Assigned(name, value) if name.value.as_str() == "intrinsic" => {
+                if !is_native {
+                    return Err(error(
+                        "Only native functions can be bound to intrinsics".into(),
+                    ));
+                }
+                if self.foreign {
+                    return Err(error(
+                        "Foreign functions can't be bound to intrinsics".into(),
+                    ));
+                }
+                let expected = "Expected a procedure path such as b\"std::math::u64::add\"";
+                let AttributeValue_::Value(value) = &value.value else {
+                    return Err(error(expected.into()));
+                };
+                let Value_::ByteString(path) = &value.value else {
+                    return Err(error(expected.into()));
+                };
+                if !path.as_str().contains("::") {
+                    return Err(error(format!("Invalid procedure path {path}")));
+                }
+                self.intrinsic = Some(path.to_string());
+            }
+            Attribute_::Name(name) if name.value.as_str() == "foreign" => {
+                if !is_native {
+                    return Err(error("Only native functions can be foreign".into()));
+                }
+                if self.intrinsic.is_some() {
+                    return Err(error(
+                        "Foreign functions can't be bound to intrinsics".into(),
+                    ));
+                }
+                self.foreign = true;
+            }
+            Attribute_::Name(name) if name.value.as_str() == "export" => {
+                self.export = true;
+            }
+            Attribute_::Name(name) if name.value.as_str() == "inline" => {
+                self.inline = Some(InlineHint::Always);
+            }
+            Attribute_::Parameterized(name, hints) if name.value.as_str() == "inline" => {
+                let hint = match hints.value.as_slice() {
+                    [hint] => match &hint.value {
+                        Attribute_::Name(hint) if hint.value.as_str() == "always" => {
+                            InlineHint::Always
+                        }
+                        Attribute_::Name(hint) if hint.value.as_str() == "never" => {
+                            InlineHint::Never
+                        }
+                        _ => {
+                            return Err(error(
+                                "Expected `inline(always)` or `inline(never)`".into(),
+                            ))
+                        }
+                    },
+                    _ => return Err(error("Expected `inline(always)` or `inline(never)`".into())),
+                };
+                self.inline = Some(hint);
+            }
+            Attribute_::Name(name)
+            | Attribute_::Assigned(name, _)
+            | Attribute_::Parameterized(name, _) => {
+                return Err(error(format!(
+                    "Unknown setting `{name}` in `miden` attribute"
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a Move integer literal, which may contain `_` separators and a type suffix.
+fn parse_number(literal: &str) -> Option<u64> {
+    let digits: String = literal.chars().filter(|c| *c != '_').collect();
+    let digits = ["u8", "u16", "u32", "u64", "u128", "u256"]
+        .iter()
+        .find_map(|suffix| digits.strip_suffix(suffix))
+        .unwrap_or(&digits);
+    match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => digits.parse().ok(),
+    }
+}
@@ -0,0 +1,208 @@
+//! Module for static checks over the call graph and the global storage accesses
+//! of a module, reporting patterns auditors should review.
+//!
+//! Move has no dynamic dispatch, so a function can only be re-entered through
+//! recursion within its module; recursive functions writing global storage are
+//! reported. Calls made one after the other by a function are also checked for
+//! read-after-write hazards, where a call accesses a resource an earlier call
+//! may have modified, so the order of the calls matters. Finally, arithmetic
+//! which may overflow, and is therefore checked at runtime, is listed (see
+//! `ranges` module).
+//! Findings are not errors: the module still compiles.
+
+use {
+    crate::{
+        analysis::{AnalysisResult, StorageAccess},
+        diagnostics::Severity,
+    },
+    move_binary_format::{
+        access::ModuleAccess,
+        file_format::{Bytecode, CodeOffset},
+    },
+    std::collections::{BTreeMap, BTreeSet},
+};
+
+/// A pattern found in a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    // Name of the function the finding is about.
+    pub function: String,
+    // Instruction the finding is about, if not the function as a whole.
+    pub offset: Option<CodeOffset>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Resources accessed by a call of every function of the module, including the
+/// functions it calls, by function name.
+pub fn storage_effects<'a>(
+    analysis: &'a AnalysisResult<'_>,
+) -> BTreeMap<&'a str, BTreeMap<String, StorageAccess>> {
+    let call_graph = analysis.call_graph();
+    let mut effects: BTreeMap<&str, BTreeMap<String, StorageAccess>> = analysis
+        .functions
+        .iter()
+        .map(|f| (f.name.as_str(), f.storage.clone()))
+        .collect();
+    // Propagate accesses to callers until nothing changes, which terminates as
+    // accesses only grow and are bounded by the resources of the module
+    loop {
+        let mut changed = false;
+        for (caller, callees) in &call_graph {
+            for callee in callees {
+                let callee_effects = effects[callee].clone();
+                let Some(caller_effects) = effects.get_mut(caller) else {
+                    continue;
+                };
+                for (resource, access) in callee_effects {
+                    if !matches!(caller_effects.get(&resource), Some(current) if *current >= access)
+                    {
+                        caller_effects.insert(resource, access);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    effects
+}
+
+/// Runs all checks on the analyzed module.
+pub fn audit(analysis: &AnalysisResult<'_>) -> Vec<Finding> {
+    let effects = storage_effects(analysis);
+    let mut findings = reentrancy(analysis, &effects);
+    findings.extend(read_after_write(analysis, &effects));
+    findings.extend(overflow_checks(analysis));
+    findings
+}
+
+/// Arithmetic which may overflow, and must be checked at runtime.
+fn overflow_checks(analysis: &AnalysisResult<'_>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (function, func_def) in analysis
+        .functions
+        .iter()
+        .zip(analysis.module.function_defs())
+    {
+        let Some(code) = &func_def.code else {
+            continue;
+        };
+        for offset in &function.overflow_checks {
+            let operation = match code.code.get(*offset as usize) {
+                Some(Bytecode::Sub) => "Subtraction may underflow",
+                Some(Bytecode::Mul) => "Multiplication may overflow",
+                _ => "Addition may overflow",
+            };
+            findings.push(Finding {
+                function: function.name.clone(),
+                offset: Some(*offset),
+                severity: Severity::Note,
+                message: format!("{operation} and is checked at runtime"),
+            });
+        }
+    }
+    findings
+}
+
+/// Recursive functions which write global storage.
+fn reentrancy(
+    analysis: &AnalysisResult<'_>,
+    effects: &BTreeMap<&str, BTreeMap<String, StorageAccess>>,
+) -> Vec<Finding> {
+    let call_graph = analysis.call_graph();
+    let mut findings = Vec::new();
+    for function in &analysis.functions {
+        let name = function.name.as_str();
+        if !reaches(&call_graph, name, name) {
+            continue;
+        }
+        let written: Vec<&str> = effects[name]
+            .iter()
+            .filter(|(_, access)| **access == StorageAccess::Write)
+            .map(|(resource, _)| resource.as_str())
+            .collect();
+        if written.is_empty() {
+            continue;
+        }
+        findings.push(Finding {
+            function: function.name.clone(),
+            offset: None,
+            severity: Severity::Warning,
+            message: format!(
+                "Potential re-entrancy: {name} can call itself while modifying {}",
+                written.join(", ")
+            ),
+        });
+    }
+    findings
+}
+
+/// Whether `to` can be called, directly or not, by a call of `from`.
+fn reaches(call_graph: &BTreeMap<&str, BTreeSet<&str>>, from: &str, to: &str) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut stack: Vec<&str> = call_graph[from].iter().copied().collect();
+    while let Some(function) = stack.pop() {
+        if function == to {
+            return true;
+        }
+        if visited.insert(function) {
+            stack.extend(call_graph[function].iter().copied());
+        }
+    }
+    false
+}
+
+/// Calls accessing a resource modified by an earlier call of the same function.
+/// Calls are taken in bytecode order, ignoring branches.
+fn read_after_write(
+    analysis: &AnalysisResult<'_>,
+    effects: &BTreeMap<&str, BTreeMap<String, StorageAccess>>,
+) -> Vec<Finding> {
+    let module = analysis.module;
+    let module_handle = module.self_handle_idx();
+    let mut findings = Vec::new();
+    for (function, func_def) in analysis.functions.iter().zip(module.function_defs()) {
+        let Some(code) = &func_def.code else {
+            continue;
+        };
+        // Resources written by the calls made so far, with the first call writing them
+        let mut written: BTreeMap<&str, &str> = BTreeMap::new();
+        let mut reported = BTreeSet::new();
+        for (offset, bytecode) in code.code.iter().enumerate() {
+            let Bytecode::Call(index) = bytecode else {
+                continue;
+            };
+            let handle = module.function_handle_at(*index);
+            if handle.module != module_handle {
+                continue;
+            }
+            let callee = module.identifier_at(handle.name).as_str();
+            let Some(accesses) = effects.get(callee) else {
+                continue;
+            };
+            for (resource, access) in accesses {
+                if let Some(writer) = written.get(resource.as_str()) {
+                    if reported.insert((resource.as_str(), callee)) {
+                        findings.push(Finding {
+                            function: function.name.clone(),
+                            offset: Some(offset as CodeOffset),
+                            severity: Severity::Note,
+                            message: format!(
+                                "Read after write: the call to {callee} in {} accesses {resource}, \
+                                 which the earlier call to {writer} may have modified",
+                                function.name
+                            ),
+                        });
+                    }
+                }
+                if *access == StorageAccess::Write {
+                    written.entry(resource).or_insert(callee);
+                }
+            }
+        }
+    }
+    findings
+}
@@ -0,0 +1,68 @@
+//! Module abstracting over the Miden assembler used to turn generated code into a program.
+//!
+//! Code generation always produces a `miden_assembly` 0.8 `ProgramAst`. Newer Miden
+//! releases replaced this AST with `MastForest` and a source-based `Assembler`; the
+//! `mast` feature enables a backend targeting that API, which is given the program as
+//! Miden assembly text. That backend is experimental and only used when asked for
+//! explicitly: generated code still uses the 0.8 syntax, e.g. `u32checked_shl`, which
+//! newer assemblers reject, and the VM executing programs is the one of 0.8. Program
+//! hashes recorded in bundles and checked when loading them are therefore always
+//! those of `DefaultBackend`, the AST backend.
+
+use {
+    miden_assembly::{ast::ProgramAst, Assembler},
+    miden_stdlib::StdLibrary,
+};
+
+pub trait MidenBackend {
+    /// Assembled program, in the representation of the targeted Miden version.
+    type Program;
+
+    fn assemble(&self, program: &ProgramAst) -> anyhow::Result<Self::Program>;
+
+    /// MAST root of the assembled program.
+    fn program_hash(&self, program: &Self::Program) -> [u8; 32];
+}
+
+/// Backend using the `ProgramAst` based assembler of `miden_assembly` 0.8.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AstBackend;
+
+impl MidenBackend for AstBackend {
+    type Program = miden_assembly::Program;
+
+    fn assemble(&self, program: &ProgramAst) -> anyhow::Result<Self::Program> {
+        let assembler = Assembler::default().with_library(&StdLibrary::default())?;
+        Ok(assembler.compile_ast(program)?)
+    }
+
+    fn program_hash(&self, program: &Self::Program) -> [u8; 32] {
+        program.hash().as_bytes()
+    }
+}
+
+/// Backend using the `MastForest` based assembler of newer Miden releases. Its
+/// program hashes differ from those of `AstBackend` for the same program.
+#[cfg(feature = "mast")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MastBackend;
+
+#[cfg(feature = "mast")]
+impl MidenBackend for MastBackend {
+    type Program = miden_assembly_mast::Program;
+
+    fn assemble(&self, program: &ProgramAst) -> anyhow::Result<Self::Program> {
+        let assembler = miden_assembly_mast::Assembler::default()
+            .with_library(miden_stdlib_mast::StdLibrary::default())
+            .map_err(|e| anyhow::anyhow!("Failed to load the Miden standard library: {e}"))?;
+        assembler
+            .assemble_program(program.to_string())
+            .map_err(|e| anyhow::anyhow!("Failed to assemble program: {e}"))
+    }
+
+    fn program_hash(&self, program: &Self::Program) -> [u8; 32] {
+        program.hash().as_bytes()
+    }
+}
+
+pub type DefaultBackend = AstBackend;
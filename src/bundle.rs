@@ -0,0 +1,371 @@
+//! Module for storing compiled programs on disk and comparing them.
+//!
+//! A bundle is a JSON file holding the Miden assembly of a compiled program,
+//! the hash of the assembled program and per-procedure details. Bundles created
+//! from a compiled module also hold its interface (ABI and storage layout),
+//! metadata, error map and build information, the location of every procedure in the Move sources,
+//! and optionally test vectors (see `verify` module) and a signature (see
+//! `signing` module).
+
+use {
+    crate::{
+        abi::{EntryAbi, StorageLayout},
+        artifact::{BuildInfo, CompiledArtifact, ModuleMetadata},
+        backend::{DefaultBackend, MidenBackend},
+        cost,
+        error_map::ErrorMap,
+    },
+    anyhow::Context,
+    miden_assembly::ast::ProgramAst,
+    serde::{Deserialize, Serialize},
+    similar::TextDiff,
+    std::{collections::BTreeMap, fmt::Write, path::Path},
+};
+
+/// Name used for the body of the program in the list of procedures.
+pub const MAIN_PROCEDURE: &str = "main";
+
+/// Version of this crate, recorded in bundles.
+pub const COMPILER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bundle {
+    // Hex encoded MAST root of the assembled program.
+    pub program_hash: String,
+    pub masm: String,
+    pub procedures: Vec<BundleProcedure>,
+    // Missing from bundles created before metadata was recorded.
+    #[serde(default)]
+    pub metadata: ModuleMetadata,
+    #[serde(default)]
+    pub error_map: ErrorMap,
+    // Only recorded for bundles created from a compiled module.
+    #[serde(default)]
+    pub abi: Option<EntryAbi>,
+    #[serde(default)]
+    pub layout: Option<StorageLayout>,
+    // Inputs of the entry function along with the outputs they produced at build time.
+    #[serde(default)]
+    pub test_vectors: Vec<TestVector>,
+    // Version of the compiler which produced the bundle; empty for older bundles.
+    #[serde(default)]
+    pub compiler_version: String,
+    #[serde(default)]
+    pub signature: Option<BundleSignature>,
+    // Only recorded for bundles created from a compiled module.
+    #[serde(default)]
+    pub build_info: Option<BuildInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleProcedure {
+    pub name: String,
+    pub masm: String,
+    pub cycles: u64,
+    // False if `cycles` only counts one iteration of the loops in the procedure.
+    pub bounded: bool,
+    // Definition of the Move function, for modules compiled from source.
+    #[serde(default)]
+    pub source: Option<SourceReference>,
+    // Resources acquired by the Move function (see `ProcedureInfo::acquires`).
+    #[serde(default)]
+    pub acquires: Vec<String>,
+}
+
+/// Byte range in the Move source file with the given hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceReference {
+    // Hex encoded SHA-256 of the file contents, as used in Move source maps.
+    pub file_hash: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Ed25519 signature of a bundle, hex encoded along with the public key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub inputs: Vec<u64>,
+    pub outputs: Vec<u64>,
+}
+
+impl Bundle {
+    pub fn new(program: &ProgramAst) -> anyhow::Result<Self> {
+        let backend = DefaultBackend::default();
+        let assembled = backend.assemble(program)?;
+        let masm = program.to_string();
+        let sections = split_procedures(&masm);
+        let mut procedures: Vec<BundleProcedure> = program
+            .procedures()
+            .iter()
+            .zip(sections.procedures)
+            .map(|(procedure, masm)| {
+                let cost = cost::estimate(&procedure.body);
+                BundleProcedure {
+                    name: procedure.name.to_string(),
+                    masm,
+                    cycles: cost.cycles,
+                    bounded: cost.bounded,
+                    source: None,
+                    acquires: Vec::new(),
+                }
+            })
+            .collect();
+        let cost = cost::estimate(program.body());
+        procedures.push(BundleProcedure {
+            name: MAIN_PROCEDURE.into(),
+            masm: sections.main,
+            cycles: cost.cycles,
+            bounded: cost.bounded,
+            source: None,
+            acquires: Vec::new(),
+        });
+        Ok(Self {
+            program_hash: to_hex(&backend.program_hash(&assembled)),
+            masm,
+            procedures,
+            metadata: ModuleMetadata::default(),
+            error_map: ErrorMap::default(),
+            abi: None,
+            layout: None,
+            test_vectors: Vec::new(),
+            compiler_version: COMPILER_VERSION.into(),
+            signature: None,
+            build_info: None,
+        })
+    }
+
+    /// Creates the bundle of a compiled module, including the module metadata,
+    /// error map and, when the module was compiled from source, source references.
+    pub fn from_artifact(artifact: &CompiledArtifact) -> anyhow::Result<Self> {
+        let mut bundle = Self {
+            metadata: artifact.metadata.clone(),
+            error_map: artifact.error_map.clone(),
+            abi: Some(artifact.abi.clone()),
+            layout: Some(artifact.layout.clone()),
+            build_info: Some(artifact.build_info.clone()),
+            ..Self::new(&artifact.program)?
+        };
+        let main = bundle.procedures.len() - 1;
+        for info in &artifact.procedures {
+            let index = info.procedure.map_or(main, |index| index as usize);
+            if let Some(procedure) = bundle.procedures.get_mut(index) {
+                procedure.acquires = info.acquires.clone();
+            }
+        }
+        if let Some(source_map) = &artifact.source_map {
+            for info in &artifact.procedures {
+                let Ok(function) = source_map.get_function_source_map(info.definition) else {
+                    continue;
+                };
+                let loc = function.definition_location;
+                let index = info.procedure.map_or(main, |index| index as usize);
+                if let Some(procedure) = bundle.procedures.get_mut(index) {
+                    procedure.source = Some(SourceReference {
+                        file_hash: to_hex(&loc.file_hash().0),
+                        start: loc.start(),
+                        end: loc.end(),
+                    });
+                }
+            }
+        }
+        Ok(bundle)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read bundle {}", path.display()))?;
+        let bundle = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse bundle {}", path.display()))?;
+        Ok(bundle)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, contents)
+            .with_context(|| format!("Failed to write bundle {}", path.display()))
+    }
+}
+
+/// Describes the differences between two bundles: the program hash, the
+/// procedures that were added, removed or changed (with a diff of their assembly),
+/// and the change in estimated cycles of every procedure.
+pub fn diff(old: &Bundle, new: &Bundle) -> String {
+    let mut report = String::new();
+    if old.program_hash == new.program_hash {
+        writeln!(report, "Program hash unchanged: {}", old.program_hash).ok();
+    } else {
+        writeln!(report, "Program hash changed:").ok();
+        writeln!(report, "  old: {}", old.program_hash).ok();
+        writeln!(report, "  new: {}", new.program_hash).ok();
+    }
+
+    let old_procs: BTreeMap<&str, &BundleProcedure> = old
+        .procedures
+        .iter()
+        .map(|p| (p.name.as_str(), p))
+        .collect();
+    let new_procs: BTreeMap<&str, &BundleProcedure> = new
+        .procedures
+        .iter()
+        .map(|p| (p.name.as_str(), p))
+        .collect();
+    let mut names: Vec<&str> = old_procs.keys().chain(new_procs.keys()).copied().collect();
+    names.sort();
+    names.dedup();
+
+    writeln!(report, "\nCycles per procedure:").ok();
+    for name in &names {
+        let cycles = |p: Option<&&BundleProcedure>| match p {
+            Some(p) if p.bounded => p.cycles.to_string(),
+            Some(p) => format!("{}+", p.cycles),
+            None => "-".into(),
+        };
+        let old_proc = old_procs.get(name);
+        let new_proc = new_procs.get(name);
+        let delta = match (old_proc, new_proc) {
+            (Some(o), Some(n)) => format!(" ({:+})", n.cycles as i64 - o.cycles as i64),
+            _ => String::new(),
+        };
+        writeln!(
+            report,
+            "  {name}: {} -> {}{delta}",
+            cycles(old_proc),
+            cycles(new_proc)
+        )
+        .ok();
+    }
+
+    for name in &names {
+        match (old_procs.get(name), new_procs.get(name)) {
+            (Some(_), None) => {
+                writeln!(report, "\nProcedure {name} removed").ok();
+            }
+            (None, Some(_)) => {
+                writeln!(report, "\nProcedure {name} added").ok();
+            }
+            (Some(o), Some(n)) if o.masm != n.masm => {
+                writeln!(report, "\nProcedure {name} changed:").ok();
+                let diff = TextDiff::from_lines(&o.masm, &n.masm);
+                write!(report, "{}", diff.unified_diff().header("old", "new")).ok();
+            }
+            _ => (),
+        }
+    }
+    report
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.len() % 2 != 0 {
+        anyhow::bail!("Odd number of hex digits");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).context("Invalid hex digit"))
+        .collect()
+}
+
+struct Sections {
+    procedures: Vec<String>,
+    main: String,
+}
+
+/// Splits formatted program assembly into its local procedures (in order) and its body.
+/// Every section starts with an unindented `proc.`, `export.` or `begin` line
+/// and ends with an unindented `end` line.
+fn split_procedures(masm: &str) -> Sections {
+    let mut sections = Sections {
+        procedures: Vec::new(),
+        main: String::new(),
+    };
+    let mut current: Option<String> = None;
+    for line in masm.lines() {
+        if let Some(section) = current.as_mut() {
+            section.push_str(line);
+            section.push('\n');
+            if line == "end" {
+                let section = current.take().unwrap_or_default();
+                if section.starts_with("begin") {
+                    sections.main = section;
+                } else {
+                    sections.procedures.push(section);
+                }
+            }
+        } else if line.starts_with("proc.") || line.starts_with("export.") || line == "begin" {
+            current = Some(format!("{line}\n"));
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_procedures() {
+        let masm = "proc.foo.0\n    add\nend\n\nproc.bar.1\n    if.true\n        drop\n    end\nend\n\nbegin\n    exec.foo\nend\n";
+        let sections = split_procedures(masm);
+        assert_eq!(
+            sections.procedures,
+            [
+                "proc.foo.0\n    add\nend\n",
+                "proc.bar.1\n    if.true\n        drop\n    end\nend\n"
+            ]
+        );
+        assert_eq!(sections.main, "begin\n    exec.foo\nend\n");
+    }
+
+    #[test]
+    fn test_diff_reports_changed_procedures() {
+        let procedure = |name: &str, masm: &str, cycles| BundleProcedure {
+            name: name.into(),
+            masm: masm.into(),
+            cycles,
+            bounded: true,
+            source: None,
+            acquires: Vec::new(),
+        };
+        let old = Bundle {
+            program_hash: "aa".into(),
+            masm: String::new(),
+            procedures: vec![procedure("foo", "add\n", 1), procedure("gone", "", 0)],
+            metadata: ModuleMetadata::default(),
+            error_map: ErrorMap::default(),
+            abi: None,
+            layout: None,
+            test_vectors: Vec::new(),
+            compiler_version: COMPILER_VERSION.into(),
+            signature: None,
+            build_info: None,
+        };
+        let new = Bundle {
+            program_hash: "bb".into(),
+            masm: String::new(),
+            procedures: vec![procedure("foo", "add\nmul\n", 2)],
+            metadata: ModuleMetadata::default(),
+            error_map: ErrorMap::default(),
+            abi: None,
+            layout: None,
+            test_vectors: Vec::new(),
+            compiler_version: COMPILER_VERSION.into(),
+            signature: None,
+            build_info: None,
+        };
+        let report = diff(&old, &new);
+        assert!(report.contains("Program hash changed"));
+        assert!(report.contains("foo: 1 -> 2 (+1)"));
+        assert!(report.contains("Procedure gone removed"));
+        assert!(report.contains("+mul"));
+    }
+}
@@ -0,0 +1,217 @@
+//! Module converting between byte-oriented Move values (addresses, hash
+//! outputs, `vector<u8>`) and the field elements and words of Miden.
+//!
+//! A byte order mismatch between two sides of a bridge doesn't fail, it
+//! silently produces another value, so the compiler and hosts convert values
+//! through this module only. The conventions are:
+//!
+//! - An element is 8 bytes in little-endian order, and must be below the field
+//!   modulus.
+//! - A word is its 4 elements in order, so 32 bytes. Digests given to the
+//!   compiler (e.g. note recipients or MAST roots) are encoded this way.
+//! - An address is the element of its value as a big-endian number, as Move
+//!   prints and compares addresses, so only addresses below the field modulus
+//!   have an element (see `accounts` module).
+//! - Wide integers are split into `u32` limbs of their little-endian bytes, the
+//!   low limb first, as they live on the stack (see `integers` module).
+//! - A byte vector hashed with the hashers of Miden, which take elements, is
+//!   packed into its length in bytes followed by its `u32` limbs. The last limb
+//!   is padded with zeros, so the length keeps vectors differing by trailing
+//!   zero bytes apart. Programs pack vectors the same way at runtime (see
+//!   `memory::pack_bytes`).
+
+use {
+    miden::math::{Felt, StarkField},
+    move_core_types::account_address::AccountAddress,
+};
+
+/// Number of bytes of an encoded element.
+pub const FELT_BYTES: usize = 8;
+
+/// Number of bytes of an encoded word.
+pub const WORD_BYTES: usize = 4 * FELT_BYTES;
+
+/// Element encoded as 8 bytes in little-endian order.
+pub fn felt_from_le_bytes(bytes: &[u8]) -> anyhow::Result<Felt> {
+    let bytes: [u8; FELT_BYTES] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Expected {FELT_BYTES} bytes, got {} bytes", bytes.len()))?;
+    let value = u64::from_le_bytes(bytes);
+    anyhow::ensure!(
+        value < Felt::MODULUS,
+        "Element {value:#x} is not a field element"
+    );
+    Ok(Felt::new(value))
+}
+
+/// Encoding of an element read by `felt_from_le_bytes`.
+pub fn felt_to_le_bytes(element: Felt) -> [u8; FELT_BYTES] {
+    element.as_int().to_le_bytes()
+}
+
+/// Word encoded as its 4 elements in order, 8 bytes each in little-endian order.
+pub fn word_from_le_bytes(bytes: &[u8]) -> anyhow::Result<[Felt; 4]> {
+    anyhow::ensure!(
+        bytes.len() == WORD_BYTES,
+        "Expected {WORD_BYTES} bytes, got {} bytes",
+        bytes.len()
+    );
+    let mut word = [Felt::new(0); 4];
+    for (element, chunk) in word.iter_mut().zip(bytes.chunks(FELT_BYTES)) {
+        *element = felt_from_le_bytes(chunk)?;
+    }
+    Ok(word)
+}
+
+/// Encoding of a word read by `word_from_le_bytes`.
+pub fn word_to_le_bytes(word: &[Felt; 4]) -> [u8; WORD_BYTES] {
+    let mut bytes = [0; WORD_BYTES];
+    for (chunk, element) in bytes.chunks_mut(FELT_BYTES).zip(word) {
+        chunk.copy_from_slice(&felt_to_le_bytes(*element));
+    }
+    bytes
+}
+
+/// Element holding an address, failing unless its value is below the field modulus.
+pub fn address_to_felt(address: &AccountAddress) -> anyhow::Result<Felt> {
+    let bytes = address.into_bytes();
+    let (high, low) = bytes.split_at(AccountAddress::LENGTH - FELT_BYTES);
+    let value = u64::from_be_bytes(low.try_into()?);
+    anyhow::ensure!(
+        high.iter().all(|b| *b == 0) && value < Felt::MODULUS,
+        "Address {} doesn't fit in a field element",
+        address.to_hex_literal()
+    );
+    Ok(Felt::new(value))
+}
+
+/// Address held by an element, as read by `address_to_felt`.
+pub fn address_from_felt(element: Felt) -> AccountAddress {
+    let mut bytes = [0; AccountAddress::LENGTH];
+    bytes[AccountAddress::LENGTH - FELT_BYTES..].copy_from_slice(&element.as_int().to_be_bytes());
+    AccountAddress::new(bytes)
+}
+
+/// `u32` limbs of a little-endian number, the low limb first. The last limb is
+/// padded with zeros when the number of bytes isn't a multiple of 4.
+pub fn limbs_from_le_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut limb = [0; 4];
+            limb[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(limb)
+        })
+        .collect()
+}
+
+/// Little-endian bytes of a number given as `u32` limbs, the low limb first.
+pub fn limbs_to_le_bytes(limbs: &[u32]) -> Vec<u8> {
+    limbs.iter().flat_map(|limb| limb.to_le_bytes()).collect()
+}
+
+/// Elements hashed for a byte vector: its length, then its bytes 4 per element.
+pub fn pack_bytes(bytes: &[u8]) -> anyhow::Result<Vec<Felt>> {
+    let length = u32::try_from(bytes.len())
+        .map_err(|_| anyhow::anyhow!("Byte vector of {} bytes is too long", bytes.len()))?;
+    Ok(std::iter::once(length)
+        .chain(limbs_from_le_bytes(bytes))
+        .map(|element| Felt::new(element as u64))
+        .collect())
+}
+
+/// Byte vector packed by `pack_bytes`, failing unless the elements are a
+/// length followed by as many `u32` limbs as it takes, padded with zeros.
+pub fn unpack_bytes(elements: &[Felt]) -> anyhow::Result<Vec<u8>> {
+    let (length, limbs) = elements
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("Packed byte vector without a length"))?;
+    let length = usize::try_from(length.as_int())?;
+    anyhow::ensure!(
+        limbs.len() == length.div_ceil(4),
+        "Packed byte vector of {length} bytes has {} limbs",
+        limbs.len()
+    );
+    let limbs = limbs
+        .iter()
+        .map(|limb| u32::try_from(limb.as_int()))
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| anyhow::anyhow!("Packed byte vector has limbs above 2^32"))?;
+    let mut bytes = limbs_to_le_bytes(&limbs);
+    anyhow::ensure!(
+        bytes[length..].iter().all(|b| *b == 0),
+        "Packed byte vector is not padded with zeros"
+    );
+    bytes.truncate(length);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_round_trip() {
+        let mut bytes = [0; WORD_BYTES];
+        bytes[0] = 1;
+        bytes[9] = 2;
+        bytes[31] = 0x3f;
+        let word = word_from_le_bytes(&bytes).unwrap();
+        assert_eq!(
+            word.map(|e| e.as_int()),
+            [1, 0x200, 0, 0x3f00_0000_0000_0000]
+        );
+        assert_eq!(word_to_le_bytes(&word), bytes);
+        // Every element must be below the modulus, and words are 32 bytes
+        assert!(word_from_le_bytes(&[0xff; WORD_BYTES]).is_err());
+        assert!(word_from_le_bytes(&bytes[1..]).is_err());
+        assert!(felt_from_le_bytes(&Felt::MODULUS.to_le_bytes()).is_err());
+        let largest = Felt::new(Felt::MODULUS - 1);
+        assert_eq!(
+            felt_from_le_bytes(&felt_to_le_bytes(largest)).unwrap(),
+            largest
+        );
+    }
+
+    #[test]
+    fn test_address_round_trip() {
+        let address = AccountAddress::from_hex_literal("0x1234").unwrap();
+        let element = address_to_felt(&address).unwrap();
+        assert_eq!(element.as_int(), 0x1234);
+        assert_eq!(address_from_felt(element), address);
+        // Addresses are big-endian numbers, unlike elements
+        assert_eq!(felt_to_le_bytes(element)[..2], [0x34, 0x12]);
+        assert!(address_to_felt(&AccountAddress::from_hex_literal("0x1").unwrap()).is_ok());
+        let mut wide = [0; AccountAddress::LENGTH];
+        wide[0] = 1;
+        assert!(address_to_felt(&AccountAddress::new(wide)).is_err());
+        assert!(address_to_felt(&AccountAddress::new([0xff; AccountAddress::LENGTH])).is_err());
+    }
+
+    #[test]
+    fn test_limbs_round_trip() {
+        let bytes: Vec<u8> = (1..=8).collect();
+        let limbs = limbs_from_le_bytes(&bytes);
+        assert_eq!(limbs, [0x0403_0201, 0x0807_0605]);
+        assert_eq!(limbs_to_le_bytes(&limbs), bytes);
+        // The high bytes of the last limb are zero
+        assert_eq!(limbs_from_le_bytes(&[1, 2, 3, 4, 5]), [0x0403_0201, 5]);
+    }
+
+    #[test]
+    fn test_packed_bytes() {
+        let packed = pack_bytes(&[1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(
+            packed.iter().map(|e| e.as_int()).collect::<Vec<_>>(),
+            [5, 0x0403_0201, 5]
+        );
+        assert_eq!(unpack_bytes(&packed).unwrap(), [1, 2, 3, 4, 5]);
+        // Trailing zero bytes change the length
+        assert_ne!(pack_bytes(&[1, 0]).unwrap(), pack_bytes(&[1]).unwrap());
+        assert_eq!(pack_bytes(&[]).unwrap(), [Felt::new(0)]);
+        assert!(unpack_bytes(&[]).is_err());
+        assert!(unpack_bytes(&[Felt::new(2), Felt::new(0x0100_0001)]).is_err());
+        assert!(unpack_bytes(&[Felt::new(1), Felt::new(1 << 32)]).is_err());
+        assert!(unpack_bytes(&[Felt::new(5), Felt::new(1)]).is_err());
+    }
+}
@@ -2,26 +2,14 @@
 
 use std::{
     cmp::{self, Ordering},
-    collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque},
+    collections::{BTreeSet, BinaryHeap},
     fmt, iter,
+    ops::Deref,
+    sync::Arc,
 };
 
 use move_binary_format::file_format::Bytecode;
 
-/// A block of bytecode without any control flow
-/// (i.e. no `BrTrue`, `BrFalse`, `Branch`).
-/// A block of bytecode is a node in the control flow graph.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub struct Block<'a> {
-    code: &'a [Bytecode],
-}
-
-impl<'a> Block<'a> {
-    pub fn new(code: &'a [Bytecode]) -> Self {
-        Self { code }
-    }
-}
-
 /// Labels for nodes in the control flow graph.
 /// Nodes (i.e. blocks) are one of: the entrypoint to the program,
 /// a specific offset in the overall array of bytecode, or the end
@@ -87,6 +75,10 @@ pub enum OutgoingEdge {
     // We will convert to `WhileTrue` by adding an extra `Not` instruction
     // during the compilation step.
     WhileFalse { body_start: Label, after: Label },
+    // Rotated loops, whose condition is checked at the end of the body, going
+    // back to the header with `BrTrue` (or `BrFalse`).
+    DoWhileTrue { header: Label, after: Label },
+    DoWhileFalse { header: Label, after: Label },
 }
 
 impl OutgoingEdge {
@@ -100,20 +92,59 @@ impl OutgoingEdge {
             OutgoingEdge::LoopBack { header } => [Some(header), None],
             OutgoingEdge::WhileTrue { body_start, after } => [Some(body_start), Some(after)],
             OutgoingEdge::WhileFalse { body_start, after } => [Some(body_start), Some(after)],
+            OutgoingEdge::DoWhileTrue { header, after } => [Some(header), Some(after)],
+            OutgoingEdge::DoWhileFalse { header, after } => [Some(header), Some(after)],
         };
         elems.into_iter().flatten()
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Index of a block in the arena of a `Graph`. Blocks are numbered in the order of
+/// their labels, so the entry is the first block and the exit the last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(u32);
+
+impl BlockId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Control flow graph of a function: the bytecode of the function and its `Graph`,
+/// which the CFG dereferences to.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Cfg<'a> {
-    blocks: BTreeMap<Label, Block<'a>>,
-    // Edges are directed as start -> end.
-    edges: BTreeMap<Label, OutgoingEdge>,
+    bytecode: &'a [Bytecode],
+    // The graph only depends on the bytecode, so it can be shared by the CFGs of
+    // functions with the same code (see `analysis::AnalysisCache`).
+    graph: Arc<Graph>,
+}
+
+/// Blocks of a control flow graph and their edges. Blocks are kept in an arena,
+/// as ranges of the bytecode of the function, with their outgoing edge and
+/// predecessors in vectors indexed by `BlockId`, so analyses can use dense tables
+/// rather than maps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Graph {
+    // Label of every block, in increasing order.
+    labels: Vec<Label>,
+    // Offsets of the first instruction of every block and of the one after it.
+    ranges: Vec<(u32, u32)>,
+    // Edge leaving every block, directed as start -> end. The exit has none.
+    edges: Vec<Option<OutgoingEdge>>,
+    predecessors: Vec<Vec<BlockId>>,
+    // Block ending with the loop condition, by loop header, for the loops whose
+    // condition spans several blocks (e.g. `while (f(x) && y < n)`).
+    loop_conditions: Vec<Option<BlockId>>,
+    // Block ending with the conditional branch back to the header, by header, for
+    // rotated loops.
+    rotated_loops: Vec<Option<BlockId>>,
 }
 
 impl<'a> Cfg<'a> {
-    pub fn new(bytecode: &'a [Bytecode]) -> Result<Self, CfgError> {
+    pub fn new(bytecode: &'a [Bytecode]) -> Result<Self, CfgFailure> {
+        let fail =
+            |error, branch, related: &[usize]| CfgFailure::new(error, branch, related, bytecode);
         // Locations that are destinations of a branch.
         let mut branch_dests = BTreeSet::new();
         branch_dests.insert(0); // 0 is the entry point of the function
@@ -126,7 +157,10 @@ impl<'a> Cfg<'a> {
             match b {
                 Bytecode::BrTrue(x) | Bytecode::BrFalse(x) => {
                     let x = *x as usize;
-                    validate_conditional_jump(x, i, bytecode)?;
+                    validate_conditional_jump(x, i, bytecode).map_err(|e| match e {
+                        CfgError::RepeatConditionalBranch => fail(e, i + 1, &[i]),
+                        _ => fail(e, i, &[x]),
+                    })?;
                     branch_origins.insert(i);
                     // Both x and i + 1 are branch destinations because we jump to x
                     // if the condition is met and simply go to the next bytecode otherwise.
@@ -135,7 +169,7 @@ impl<'a> Cfg<'a> {
                 }
                 Bytecode::Branch(x) => {
                     let x = *x as usize;
-                    validate_unconditional_jump(x, i, bytecode)?;
+                    validate_unconditional_jump(x, i, bytecode).map_err(|e| fail(e, i, &[x]))?;
                     branch_origins.insert(i);
                     branch_dests.insert(x);
                 }
@@ -145,37 +179,45 @@ impl<'a> Cfg<'a> {
 
         // Collect points into an ordered list
         let branch_points: Vec<usize> = branch_dests.union(&branch_origins).copied().collect();
-        let blocks: BTreeMap<Label, Block<'a>> = branch_points
+        let (mut labels, mut ranges): (Vec<_>, Vec<_>) = branch_points
             .iter()
             .zip(branch_points.iter().skip(1))
-            .filter_map(|(start, end)| {
-                if branch_origins.contains(start) {
-                    return None;
-                }
-                let start = *start;
-                let end = *end;
-                Some((Label::new(start), Block::new(&bytecode[start..end])))
-            })
-            .chain(iter::once((Label::Exit, Block::new(&[]))))
-            .collect();
+            .filter(|(start, _)| !branch_origins.contains(*start))
+            .map(|(start, end)| (Label::new(*start), (*start as u32, *end as u32)))
+            .unzip();
+        labels.push(Label::Exit);
+        ranges.push((bytecode.len() as u32, bytecode.len() as u32));
+        let count = labels.len();
+        let mut graph = Graph {
+            labels,
+            ranges,
+            edges: vec![None; count],
+            predecessors: vec![Vec::new(); count],
+            loop_conditions: vec![None; count],
+            rotated_loops: vec![None; count],
+        };
 
-        let mut edges = BTreeMap::new();
-        let mut current_label = None;
+        let mut next_block = 0;
+        let mut current = None;
         for (i, b) in bytecode.iter().enumerate() {
-            let maybe_label = Label::new(i);
-            if blocks.contains_key(&maybe_label) {
+            if graph.ranges[next_block].0 as usize == i {
                 // We have entered a new block.
                 // If we were already in a block then that
                 // block transition to the new one.
-                if let Some(l) = current_label {
-                    edges.insert(l, OutgoingEdge::Pass { next: maybe_label });
+                let id = BlockId(next_block as u32);
+                if let Some(previous) = current {
+                    let edge = OutgoingEdge::Pass {
+                        next: graph.label(id),
+                    };
+                    graph.insert_edge(previous, edge);
                 }
-                current_label = Some(maybe_label);
+                current = Some(id);
+                next_block += 1;
             }
-            let l = match current_label {
-                Some(l) => l,
-                None => continue,
+            let Some(id) = current else {
+                continue;
             };
+            let l = graph.label(id);
             match b {
                 Bytecode::BrTrue(x) => {
                     let x = *x as usize;
@@ -184,14 +226,23 @@ impl<'a> Cfg<'a> {
                         Some(Bytecode::Branch(x)) => Label::new(*x as usize),
                         _ => Label::new(i + 1),
                     };
-                    edges.insert(
-                        l,
+                    let edge = if x < i {
+                        if let Some(latch) = graph.set_rotated_loop(&true_case, id) {
+                            let previous = graph.ranges[latch.index()].1 as usize;
+                            return Err(fail(CfgError::ConditionalJumpBack, i, &[x, previous]));
+                        }
+                        OutgoingEdge::DoWhileTrue {
+                            header: true_case,
+                            after: false_case,
+                        }
+                    } else {
                         OutgoingEdge::If {
                             true_case,
                             false_case,
-                        },
-                    );
-                    current_label = None;
+                        }
+                    };
+                    graph.insert_edge(id, edge);
+                    current = None;
                 }
                 Bytecode::BrFalse(x) => {
                     let x = *x as usize;
@@ -200,100 +251,299 @@ impl<'a> Cfg<'a> {
                         Some(Bytecode::Branch(x)) => Label::new(*x as usize),
                         _ => Label::new(i + 1),
                     };
-                    edges.insert(
-                        l,
+                    let edge = if x < i {
+                        if let Some(latch) = graph.set_rotated_loop(&false_case, id) {
+                            let previous = graph.ranges[latch.index()].1 as usize;
+                            return Err(fail(CfgError::ConditionalJumpBack, i, &[x, previous]));
+                        }
+                        OutgoingEdge::DoWhileFalse {
+                            header: false_case,
+                            after: true_case,
+                        }
+                    } else {
                         OutgoingEdge::If {
                             true_case,
                             false_case,
-                        },
-                    );
-                    current_label = None;
+                        }
+                    };
+                    graph.insert_edge(id, edge);
+                    current = None;
                 }
                 Bytecode::Branch(x) => {
                     let x = *x as usize;
                     let dest_label = Label::new(x);
                     let edge = if x < i {
-                        // In the loop-back case we convert the if-else into a while loop
-                        let Some(OutgoingEdge::If {
-                            true_case,
-                            false_case,
-                        }) = edges.remove(&dest_label)
+                        // In the loop-back case we convert the if-else ending the
+                        // loop condition into a while loop
+                        let reaching = graph.reaching(id);
+                        let condition = graph
+                            .find_loop_condition(&reaching, dest_label, l)
+                            .map_err(|e| fail(e, i, &[x]))?;
+                        let (
+                            Some(condition),
+                            Some(header),
+                            Some(OutgoingEdge::If {
+                                true_case,
+                                false_case,
+                            }),
+                        ) = (
+                            graph.block_id(&condition),
+                            graph.block_id(&dest_label),
+                            graph.edge(&condition).ok().copied(),
+                        )
                         else {
-                            return Err(CfgError::InvalidLoopHeader);
+                            return Err(fail(CfgError::InvalidLoopHeader, i, &[x]));
                         };
+                        if condition != header {
+                            graph.loop_conditions[header.index()] = Some(condition);
+                        }
                         // Need to figure out if the true case or false case is the
                         // body of the loop. The body is the path which leads to
                         // the current label (since it is branching back up to the header).
-                        match (
-                            has_path(&edges, &true_case, &l),
-                            has_path(&edges, &false_case, &l),
+                        // The edge replacing the if-else has the same targets, so
+                        // the predecessors are unchanged.
+                        let edge = match (
+                            graph.reaches(&reaching, &true_case),
+                            graph.reaches(&reaching, &false_case),
                         ) {
                             // Exactly one path should get to this node; if none or both do then there is a problem
                             (true, true) | (false, false) => {
-                                return Err(CfgError::InvalidLoopHeader)
+                                return Err(fail(CfgError::InvalidLoopHeader, i, &[x]))
                             }
-                            (true, false) => edges.insert(
-                                dest_label,
-                                OutgoingEdge::WhileTrue {
-                                    body_start: true_case,
-                                    after: false_case,
-                                },
-                            ),
-                            (false, true) => edges.insert(
-                                dest_label,
-                                OutgoingEdge::WhileFalse {
-                                    body_start: false_case,
-                                    after: true_case,
-                                },
-                            ),
+                            (true, false) => OutgoingEdge::WhileTrue {
+                                body_start: true_case,
+                                after: false_case,
+                            },
+                            (false, true) => OutgoingEdge::WhileFalse {
+                                body_start: false_case,
+                                after: true_case,
+                            },
                         };
+                        graph.edges[condition.index()] = Some(edge);
                         OutgoingEdge::LoopBack { header: dest_label }
                     } else {
                         OutgoingEdge::Pass { next: dest_label }
                     };
-                    edges.insert(l, edge);
-                    current_label = None;
+                    graph.insert_edge(id, edge);
+                    current = None;
                 }
                 Bytecode::Abort | Bytecode::Ret => {
                     // Abort and Ret signify the end of the function
-                    edges.insert(l, OutgoingEdge::Pass { next: Label::Exit });
-                    current_label = None;
+                    graph.insert_edge(id, OutgoingEdge::Pass { next: Label::Exit });
+                    current = None;
                 }
                 _ => continue,
             }
         }
         // The last block exits the function
-        if let Some(l) = current_label {
-            edges.insert(l, OutgoingEdge::Pass { next: Label::Exit });
+        if let Some(id) = current {
+            graph.insert_edge(id, OutgoingEdge::Pass { next: Label::Exit });
         }
 
-        Ok(Self { blocks, edges })
+        Ok(Self {
+            bytecode,
+            graph: Arc::new(graph),
+        })
     }
 
-    pub fn edges(&self) -> &BTreeMap<Label, OutgoingEdge> {
-        &self.edges
+    pub fn block(&self, label: &Label) -> anyhow::Result<&'a [Bytecode]> {
+        let id = self
+            .block_id(label)
+            .ok_or_else(|| anyhow::Error::msg("CFG block not found"))?;
+        Ok(self.code(id))
     }
 
-    pub fn block(&self, label: &Label) -> anyhow::Result<&'a [Bytecode]> {
-        Ok(self
-            .blocks
-            .get(label)
-            .ok_or_else(|| anyhow::Error::msg("CFG block not found"))?
-            .code)
+    pub fn code(&self, id: BlockId) -> &'a [Bytecode] {
+        let (start, end) = self.graph.ranges[id.index()];
+        &self.bytecode[start as usize..end as usize]
+    }
+
+    /// The CFG of `bytecode` with an existing graph, built from the same bytecode.
+    pub fn with_graph(bytecode: &'a [Bytecode], graph: Arc<Graph>) -> Self {
+        Self { bytecode, graph }
+    }
+
+    pub fn graph(&self) -> &Arc<Graph> {
+        &self.graph
+    }
+}
+
+impl Deref for Cfg<'_> {
+    type Target = Graph;
+
+    fn deref(&self) -> &Graph {
+        &self.graph
+    }
+}
+
+impl Graph {
+    pub fn edges(&self) -> impl Iterator<Item = (Label, &OutgoingEdge)> + '_ {
+        self.labels
+            .iter()
+            .zip(&self.edges)
+            .filter_map(|(label, edge)| Some((*label, edge.as_ref()?)))
     }
 
     pub fn edge(&self, label: &Label) -> anyhow::Result<&OutgoingEdge> {
-        self.edges
-            .get(label)
+        self.block_id(label)
+            .and_then(|id| self.edges[id.index()].as_ref())
             .ok_or_else(|| anyhow::Error::msg("CFG edge not found"))
     }
+
+    /// The block ending with the condition of the loop starting at `header`, which
+    /// has the `WhileTrue` or `WhileFalse` edge. The blocks from `header` up to it
+    /// form the condition, evaluated again at the end of every iteration.
+    pub fn loop_condition(&self, header: &Label) -> Label {
+        self.block_id(header)
+            .and_then(|id| self.loop_conditions[id.index()])
+            .map_or(*header, |id| self.label(id))
+    }
+
+    /// The block ending with the `DoWhileTrue` or `DoWhileFalse` edge, if `header`
+    /// starts a rotated loop.
+    pub fn rotated_loop_latch(&self, header: &Label) -> Option<Label> {
+        let id = self.rotated_loops[self.block_id(header)?.index()]?;
+        Some(self.label(id))
+    }
+
+    /// Number of blocks, the exit included. Block ids are below it.
+    pub fn block_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn block_id(&self, label: &Label) -> Option<BlockId> {
+        let index = self.labels.binary_search(label).ok()?;
+        Some(BlockId(index as u32))
+    }
+
+    pub fn label(&self, id: BlockId) -> Label {
+        self.labels[id.index()]
+    }
+
+    pub fn successors(&self, id: BlockId) -> impl Iterator<Item = BlockId> + '_ {
+        self.edges[id.index()]
+            .iter()
+            .flat_map(|edge| edge.iter())
+            .filter_map(|label| self.block_id(label))
+    }
+
+    pub fn predecessors(&self, id: BlockId) -> &[BlockId] {
+        &self.predecessors[id.index()]
+    }
+
+    /// Finds the first label which is reachable from both `x` and `y`, ignoring
+    /// edges going back to loop headers.
+    pub fn first_common_ancestor(&self, x: &Label, y: &Label) -> Label {
+        let (Some(x), Some(y)) = (self.block_id(x), self.block_id(y)) else {
+            return Label::Exit;
+        };
+        let origins = [x, y];
+        // Blocks reached from `x` and from `y`
+        let mut visited = [
+            vec![false; self.block_count()],
+            vec![false; self.block_count()],
+        ];
+        // Blocks are visited in increasing order, from either side
+        let mut queue = BinaryHeap::from([cmp::Reverse((x, 0)), cmp::Reverse((y, 1))]);
+        while let Some(cmp::Reverse((id, side))) = queue.pop() {
+            visited[side][id.index()] = true;
+            if visited[1 - side][id.index()] {
+                return self.label(id);
+            }
+            for next in self.successors(id) {
+                // Ignore LoopBacks by enforcing next > origin
+                if !visited[side][next.index()] && next > origins[side] {
+                    queue.push(cmp::Reverse((next, side)));
+                }
+            }
+        }
+
+        // All nodes eventually reach the exit
+        Label::Exit
+    }
+
+    // Sets the edge leaving `id`, recording `id` as a predecessor of the blocks it
+    // leads to.
+    fn insert_edge(&mut self, id: BlockId, edge: OutgoingEdge) {
+        for target in edge.iter() {
+            if let Some(target) = self.block_id(target) {
+                self.predecessors[target.index()].push(id);
+            }
+        }
+        self.edges[id.index()] = Some(edge);
+    }
+
+    // Records `latch` as ending the rotated loop starting at `header`, returning
+    // the latch previously recorded, if any.
+    fn set_rotated_loop(&mut self, header: &Label, latch: BlockId) -> Option<BlockId> {
+        let header = self.block_id(header)?;
+        self.rotated_loops[header.index()].replace(latch)
+    }
+
+    // Blocks from which there is a path to `target`, `target` included, found with
+    // a single search backwards from it. Blocks are added in order, so only paths
+    // through the blocks before `target` are known when it's the latch of a loop.
+    fn reaching(&self, target: BlockId) -> Vec<bool> {
+        let mut visited = vec![false; self.block_count()];
+        visited[target.index()] = true;
+        let mut stack = vec![target];
+        while let Some(id) = stack.pop() {
+            for predecessor in self.predecessors(id) {
+                if !visited[predecessor.index()] {
+                    visited[predecessor.index()] = true;
+                    stack.push(*predecessor);
+                }
+            }
+        }
+        visited
+    }
+
+    fn reaches(&self, reaching: &[bool], label: &Label) -> bool {
+        self.block_id(label).is_some_and(|id| reaching[id.index()])
+    }
+
+    // Finds the block whose conditional branch leaves the loop starting at `header`
+    // and going back to it from `latch`. Branches before it where both cases stay in
+    // the loop are part of the loop condition (e.g. the short-circuit of `&&`), so the
+    // search continues from where they join.
+    fn find_loop_condition(
+        &self,
+        reaching: &[bool],
+        header: Label,
+        latch: Label,
+    ) -> Result<Label, CfgError> {
+        let mut label = header;
+        loop {
+            let edge = self.block_id(&label).and_then(|id| self.edges[id.index()]);
+            let next = match edge {
+                Some(OutgoingEdge::If {
+                    true_case,
+                    false_case,
+                }) => {
+                    if !(self.reaches(reaching, &true_case) && self.reaches(reaching, &false_case))
+                    {
+                        return Ok(label);
+                    }
+                    self.first_common_ancestor(&true_case, &false_case)
+                }
+                Some(OutgoingEdge::Pass { next }) => next,
+                _ => return Err(CfgError::InvalidLoopHeader),
+            };
+            // The condition must end before the end of the loop body
+            if next <= label || next >= latch {
+                return Err(CfgError::InvalidLoopHeader);
+            }
+            label = next;
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CfgError {
     // BrTrue, BrFalse and Branch are not allowed to jump to another such instruction.
     BranchToBranch,
-    // BrTrue and BrFalse are not allowed to jump to an earlier index in the bytecode.
+    // BrTrue and BrFalse may only jump to an earlier index in the bytecode to end a
+    // rotated loop, so at most one of them can jump back to a given index.
     ConditionalJumpBack,
     // BrTrue, BrFalse and Branch must branch to a value in [0, bytecode.len() - 1].
     BranchOutOfBounds,
@@ -307,20 +557,100 @@ pub enum CfgError {
 
 impl fmt::Display for CfgError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        let description = match self {
+            Self::BranchToBranch => "a branch jumps to another branch",
+            Self::ConditionalJumpBack => "several conditional branches jump back to the same loop",
+            Self::BranchOutOfBounds => "a branch jumps past the end of the function",
+            Self::SelfBranch => "a branch jumps to itself",
+            Self::RepeatConditionalBranch => "a conditional branch follows another",
+            Self::InvalidLoopHeader => "the loop has no single condition leaving it",
+        };
+        write!(f, "{self:?}: {description}")
     }
 }
 
 impl std::error::Error for CfgError {}
 
+/// A `CfgError` with the context needed to act on it: the offsets of the branches
+/// involved, the bytecode around them and, once known, the function name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CfgFailure {
+    pub error: CfgError,
+    // Offset of the branch found invalid.
+    pub branch: usize,
+    // Offsets of the offending instructions, in increasing order. For loops these
+    // are the header and the branch going back to it.
+    pub offsets: Vec<usize>,
+    pub function: Option<String>,
+    // Rendered instructions around the offsets, the offending ones marked with `>`.
+    pub excerpt: String,
+}
+
+impl CfgFailure {
+    // Instructions shown before and after each offending one.
+    const EXCERPT_CONTEXT: usize = 2;
+
+    fn new(error: CfgError, branch: usize, related: &[usize], bytecode: &[Bytecode]) -> Self {
+        let offsets: Vec<usize> = iter::once(branch)
+            .chain(related.iter().copied())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let mut excerpt = String::new();
+        let mut last = None;
+        for &offset in &offsets {
+            let start = offset.saturating_sub(Self::EXCERPT_CONTEXT);
+            let end = cmp::min(offset + Self::EXCERPT_CONTEXT + 1, bytecode.len());
+            let start = match last {
+                Some(last) if start <= last + 1 => last + 1,
+                Some(_) => {
+                    excerpt.push_str("    ...\n");
+                    start
+                }
+                None => start,
+            };
+            for (index, instruction) in bytecode.iter().enumerate().take(end).skip(start) {
+                let marker = if offsets.contains(&index) { '>' } else { ' ' };
+                excerpt.push_str(&format!("  {marker} {index:>4}: {instruction:?}\n"));
+                last = Some(index);
+            }
+        }
+        Self {
+            error,
+            branch,
+            offsets,
+            function: None,
+            excerpt,
+        }
+    }
+
+    pub fn with_function(self, name: impl Into<String>) -> Self {
+        Self {
+            function: Some(name.into()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for CfgFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unsupported control flow")?;
+        if let Some(function) = &self.function {
+            write!(f, " in function `{function}`")?;
+        }
+        let offsets: Vec<String> = self.offsets.iter().map(usize::to_string).collect();
+        writeln!(f, " at offsets {}: {}", offsets.join(", "), self.error)?;
+        write!(f, "{}", self.excerpt.trim_end())
+    }
+}
+
+impl std::error::Error for CfgFailure {}
+
 fn validate_conditional_jump(
     dest: usize,
     index: usize,
     bytecode: &[Bytecode],
 ) -> Result<(), CfgError> {
-    if dest < index {
-        return Err(CfgError::ConditionalJumpBack);
-    }
     if bytecode
         .get(index + 1)
         .filter(|b| Bytecode::is_conditional_branch(b))
@@ -348,86 +678,11 @@ fn validate_unconditional_jump(
     }
 }
 
-// Use BFS to see if there is a path from `start` to `target` using `edges`
-fn has_path(edges: &BTreeMap<Label, OutgoingEdge>, start: &Label, target: &Label) -> bool {
-    let mut visited = BTreeSet::new();
-    let mut queue = VecDeque::new();
-    queue.push_back(start);
-    while let Some(label) = queue.pop_front() {
-        visited.insert(label);
-        if label == target {
-            return true;
-        }
-        if let Some(edge) = edges.get(label) {
-            for l in edge.iter() {
-                if !visited.contains(l) {
-                    queue.push_back(l);
-                }
-            }
-        }
-    }
-    false
-}
-
-// Finds the first label which is reachable from both `x` and `y` using `edges`.
-pub fn first_common_ancestor(edges: &BTreeMap<Label, OutgoingEdge>, x: &Label, y: &Label) -> Label {
-    let mut x_visited = BTreeSet::new();
-    let mut y_visited = BTreeSet::new();
-    let mut queue = BinaryHeap::new();
-
-    queue.push(FCAQueueElem::new(x, x));
-    queue.push(FCAQueueElem::new(y, y));
-
-    while let Some(elem) = queue.pop() {
-        let origin = elem.origin();
-        let label = elem.label();
-
-        let (origin_visited, other_visited) = if origin == x {
-            (&mut x_visited, &y_visited)
-        } else {
-            (&mut y_visited, &x_visited)
-        };
-
-        origin_visited.insert(label);
-        if other_visited.contains(label) {
-            return *label;
-        }
-
-        if let Some(edge) = edges.get(label) {
-            for l in edge.iter() {
-                // Ignore LoopBacks by enforcing l > origin
-                if !origin_visited.contains(l) && l > origin {
-                    queue.push(FCAQueueElem::new(l, origin));
-                }
-            }
-        }
-    }
-
-    // All nodes eventually reach the exit
-    Label::Exit
-}
-
-/// Helper struct in the `first_common_ancestor` algorithm
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-struct FCAQueueElem<'a>(cmp::Reverse<&'a Label>, &'a Label);
-
-impl<'a> FCAQueueElem<'a> {
-    fn new(label: &'a Label, origin: &'a Label) -> Self {
-        Self(cmp::Reverse(label), origin)
-    }
-
-    fn label(&self) -> &'a Label {
-        self.0 .0
-    }
-
-    fn origin(&self) -> &'a Label {
-        self.1
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use {
+        super::*, move_binary_format::file_format::FunctionHandleIndex, std::collections::BTreeMap,
+    };
 
     #[test]
     fn test_trivial_cfg() {
@@ -630,7 +885,7 @@ mod tests {
         );
         assert_eq!(cfg, expected);
         assert_eq!(
-            first_common_ancestor(&cfg.edges, &Label::Point(8), &Label::Point(10)),
+            cfg.first_common_ancestor(&Label::Point(8), &Label::Point(10)),
             Label::Exit
         );
     }
@@ -726,24 +981,259 @@ mod tests {
         );
         assert_eq!(cfg, expected);
         assert_eq!(
-            first_common_ancestor(&cfg.edges, &Label::Point(13), &Label::Point(18)),
+            cfg.first_common_ancestor(&Label::Point(13), &Label::Point(18)),
             Label::Point(24)
         );
     }
 
-    fn build_expected_cfg<'a, B, E>(blocks: B, edges: E) -> Cfg<'a>
+    #[test]
+    fn test_loop_condition_blocks() {
+        // while (f(i) && i < n) { i = i + 1 }
+        let bytecode = vec![
+            Bytecode::LdU32(0), // Label::Entry
+            Bytecode::StLoc(1),
+            Bytecode::CopyLoc(1), // Label::Point(2)
+            Bytecode::Call(FunctionHandleIndex(0)),
+            Bytecode::BrFalse(10),
+            Bytecode::CopyLoc(1), // Label::Point(5)
+            Bytecode::CopyLoc(0),
+            Bytecode::Lt,
+            Bytecode::StLoc(2),
+            Bytecode::Branch(12),
+            Bytecode::LdFalse, // Label::Point(10)
+            Bytecode::StLoc(2),
+            Bytecode::MoveLoc(2), // Label::Point(12)
+            Bytecode::BrFalse(20),
+            Bytecode::Branch(15),
+            Bytecode::MoveLoc(1), // Label::Point(15)
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(1),
+            Bytecode::Branch(2),
+            Bytecode::MoveLoc(1), // Label::Point(20)
+            Bytecode::Ret,
+        ];
+        let cfg = Cfg::new(&bytecode).unwrap();
+        let mut expected = build_expected_cfg(
+            [
+                (Label::Entry, &bytecode[0..2]),
+                (Label::Point(2), &bytecode[2..4]),
+                (Label::Point(5), &bytecode[5..9]),
+                (Label::Point(10), &bytecode[10..12]),
+                (Label::Point(12), &bytecode[12..13]),
+                (Label::Point(15), &bytecode[15..19]),
+                (Label::Point(20), &bytecode[20..22]),
+                (Label::Exit, &[]),
+            ],
+            [
+                (
+                    Label::Entry,
+                    OutgoingEdge::Pass {
+                        next: Label::Point(2),
+                    },
+                ),
+                (
+                    Label::Point(2),
+                    OutgoingEdge::If {
+                        true_case: Label::Point(5),
+                        false_case: Label::Point(10),
+                    },
+                ),
+                (
+                    Label::Point(5),
+                    OutgoingEdge::Pass {
+                        next: Label::Point(12),
+                    },
+                ),
+                (
+                    Label::Point(10),
+                    OutgoingEdge::Pass {
+                        next: Label::Point(12),
+                    },
+                ),
+                (
+                    Label::Point(12),
+                    OutgoingEdge::WhileTrue {
+                        body_start: Label::Point(15),
+                        after: Label::Point(20),
+                    },
+                ),
+                (
+                    Label::Point(15),
+                    OutgoingEdge::LoopBack {
+                        header: Label::Point(2),
+                    },
+                ),
+                (Label::Point(20), OutgoingEdge::Pass { next: Label::Exit }),
+            ],
+        );
+        expected
+            .loop_conditions
+            .insert(Label::Point(2), Label::Point(12));
+        assert_eq!(cfg, expected);
+        assert_eq!(cfg.loop_condition(&Label::Point(2)), Label::Point(12));
+        assert_eq!(cfg.loop_condition(&Label::Point(15)), Label::Point(15));
+    }
+
+    #[test]
+    fn test_branch_heavy_loop() {
+        // while (true) { if (true) {} else {}; if (true) {} else {}; ... }
+        let diamonds = 200;
+        let end = 5 + 5 * diamonds;
+        let mut bytecode = vec![
+            Bytecode::Nop,    // Label::Entry
+            Bytecode::LdTrue, // Label::Point(1)
+            Bytecode::BrFalse(end as u16),
+        ];
+        for _ in 0..diamonds {
+            let start = bytecode.len() as u16;
+            bytecode.extend([
+                Bytecode::LdTrue,
+                Bytecode::BrFalse(start + 4),
+                Bytecode::Nop,
+                Bytecode::Branch(start + 5),
+                Bytecode::Nop,
+            ]);
+        }
+        bytecode.extend([Bytecode::Nop, Bytecode::Branch(1), Bytecode::Ret]);
+        assert_eq!(bytecode[end], Bytecode::Ret);
+        // Every diamond doubles the paths through the body, which must not be
+        // enumerated to find the loop
+        let cfg = Cfg::new(&bytecode).unwrap();
+        assert_eq!(
+            *cfg.edge(&Label::Point(1)).unwrap(),
+            OutgoingEdge::WhileTrue {
+                body_start: Label::Point(3),
+                after: Label::Point(end),
+            }
+        );
+    }
+
+    #[test]
+    fn test_rotated_loop() {
+        // Condition at the end of the body: do { i = i + 1 } while (i < n)
+        let bytecode = vec![
+            Bytecode::LdU32(0), // Label::Entry
+            Bytecode::StLoc(1),
+            Bytecode::MoveLoc(1), // Label::Point(2)
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(1),
+            Bytecode::CopyLoc(1),
+            Bytecode::CopyLoc(0),
+            Bytecode::Lt,
+            Bytecode::BrTrue(2),
+            Bytecode::MoveLoc(1), // Label::Point(10)
+            Bytecode::Ret,
+        ];
+        let cfg = Cfg::new(&bytecode).unwrap();
+        let mut expected = build_expected_cfg(
+            [
+                (Label::Entry, &bytecode[0..2]),
+                (Label::Point(2), &bytecode[2..9]),
+                (Label::Point(10), &bytecode[10..12]),
+                (Label::Exit, &[]),
+            ],
+            [
+                (
+                    Label::Entry,
+                    OutgoingEdge::Pass {
+                        next: Label::Point(2),
+                    },
+                ),
+                (
+                    Label::Point(2),
+                    OutgoingEdge::DoWhileTrue {
+                        header: Label::Point(2),
+                        after: Label::Point(10),
+                    },
+                ),
+                (Label::Point(10), OutgoingEdge::Pass { next: Label::Exit }),
+            ],
+        );
+        expected
+            .rotated_loops
+            .insert(Label::Point(2), Label::Point(2));
+        assert_eq!(cfg, expected);
+        assert_eq!(
+            cfg.rotated_loop_latch(&Label::Point(2)),
+            Some(Label::Point(2))
+        );
+        assert_eq!(cfg.rotated_loop_latch(&Label::Entry), None);
+
+        // Blocks are numbered in order, and the header is its own predecessor
+        assert_eq!(cfg.block_count(), 4);
+        let header = cfg.block_id(&Label::Point(2)).unwrap();
+        assert_eq!(header, BlockId(1));
+        assert_eq!(cfg.predecessors(header), [BlockId(0), header]);
+        assert_eq!(
+            cfg.successors(header).collect::<Vec<_>>(),
+            [header, BlockId(2)]
+        );
+
+        // Two conditional branches can't end the same loop
+        let mut bytecode = bytecode;
+        bytecode.splice(
+            6..6,
+            [Bytecode::CopyLoc(1), Bytecode::BrFalse(2), Bytecode::Nop],
+        );
+        let failure = Cfg::new(&bytecode).unwrap_err();
+        assert_eq!(failure.error, CfgError::ConditionalJumpBack);
+        assert_eq!(failure.offsets, [2, 7, 12]);
+        assert_eq!(failure.branch, 12);
+        assert!(failure.excerpt.contains(">   12: BrTrue(2)"));
+        let message = failure.with_function("main").to_string();
+        assert!(message.starts_with(
+            "Unsupported control flow in function `main` at offsets 2, 7, 12: ConditionalJumpBack"
+        ));
+    }
+
+    // The blocks and edges a `Cfg` is expected to have, by label.
+    #[derive(Debug)]
+    struct ExpectedCfg<'a> {
+        blocks: BTreeMap<Label, &'a [Bytecode]>,
+        edges: BTreeMap<Label, OutgoingEdge>,
+        loop_conditions: BTreeMap<Label, Label>,
+        rotated_loops: BTreeMap<Label, Label>,
+    }
+
+    impl<'a> PartialEq<ExpectedCfg<'a>> for Cfg<'a> {
+        fn eq(&self, expected: &ExpectedCfg<'a>) -> bool {
+            let blocks = (0..self.block_count() as u32)
+                .map(|id| (self.label(BlockId(id)), self.code(BlockId(id))))
+                .collect::<BTreeMap<_, _>>();
+            let edges = self
+                .edges()
+                .map(|(label, edge)| (label, *edge))
+                .collect::<BTreeMap<_, _>>();
+            let loop_conditions = self
+                .labels
+                .iter()
+                .filter(|header| self.loop_condition(header) != **header)
+                .map(|header| (*header, self.loop_condition(header)))
+                .collect::<BTreeMap<_, _>>();
+            let rotated_loops = self
+                .labels
+                .iter()
+                .filter_map(|header| Some((*header, self.rotated_loop_latch(header)?)))
+                .collect::<BTreeMap<_, _>>();
+            blocks == expected.blocks
+                && edges == expected.edges
+                && loop_conditions == expected.loop_conditions
+                && rotated_loops == expected.rotated_loops
+        }
+    }
+
+    fn build_expected_cfg<'a, B, E>(blocks: B, edges: E) -> ExpectedCfg<'a>
     where
         B: IntoIterator<Item = (Label, &'a [Bytecode])>,
         E: IntoIterator<Item = (Label, OutgoingEdge)>,
     {
-        let expected_blocks = blocks
-            .into_iter()
-            .map(|(l, code)| (l, Block::new(code)))
-            .collect();
-        let expected_edges = edges.into_iter().collect();
-        Cfg {
-            blocks: expected_blocks,
-            edges: expected_edges,
+        ExpectedCfg {
+            blocks: blocks.into_iter().collect(),
+            edges: edges.into_iter().collect(),
+            loop_conditions: BTreeMap::new(),
+            rotated_loops: BTreeMap::new(),
         }
     }
 }
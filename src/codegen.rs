@@ -0,0 +1,295 @@
+//! Module defining what is done with the code produced by the compiler.
+//!
+//! The compiler lowers each Move function to a Miden procedure and hands them,
+//! in order, to a `CodegenBackend`, followed by the body of the entry function.
+//! Backends decide what to build from them, so the lowering can be reused by
+//! consumers which do not need a `ProgramAst`.
+
+use {
+    crate::{integers, intrinsics, oracle, recursion},
+    anyhow::Context,
+    miden_assembly::{
+        ast::{CodeBody, Instruction, Node, ProcedureAst, ProgramAst},
+        LibraryPath, ProcedureId,
+    },
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        io::Write,
+    },
+};
+
+pub trait CodegenBackend {
+    type Output;
+
+    /// Called before any procedure with every library procedure, as
+    /// `path::to::module::procedure`, called by the code which isn't a
+    /// built-in intrinsic.
+    fn import(&mut self, _path: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every local procedure, in the order of their indices.
+    fn procedure(&mut self, procedure: ProcedureAst) -> anyhow::Result<()>;
+
+    /// Called once with the body of the program, after all procedures.
+    fn entry(&mut self, body: CodeBody) -> anyhow::Result<()>;
+
+    fn finish(self) -> anyhow::Result<Self::Output>;
+}
+
+/// Builds a `ProgramAst`.
+#[derive(Debug, Default)]
+pub struct AstSink {
+    procedures: Vec<ProcedureAst>,
+    body: Option<CodeBody>,
+}
+
+impl CodegenBackend for AstSink {
+    type Output = ProgramAst;
+
+    fn procedure(&mut self, procedure: ProcedureAst) -> anyhow::Result<()> {
+        self.procedures.push(procedure);
+        Ok(())
+    }
+
+    fn entry(&mut self, body: CodeBody) -> anyhow::Result<()> {
+        self.body = Some(body);
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<Self::Output> {
+        let body = self
+            .body
+            .ok_or_else(|| anyhow::Error::msg("No entry point defined"))?;
+        Ok(ProgramAst::new(body.nodes().to_vec(), self.procedures)?)
+    }
+}
+
+/// Produces the Miden assembly source of the program.
+#[derive(Debug, Default)]
+pub struct MasmSink {
+    ast: AstSink,
+}
+
+impl CodegenBackend for MasmSink {
+    type Output = String;
+
+    fn procedure(&mut self, procedure: ProcedureAst) -> anyhow::Result<()> {
+        self.ast.procedure(procedure)
+    }
+
+    fn entry(&mut self, body: CodeBody) -> anyhow::Result<()> {
+        self.ast.entry(body)
+    }
+
+    fn finish(self) -> anyhow::Result<Self::Output> {
+        Ok(self.ast.finish()?.to_string())
+    }
+}
+
+/// Writes the Miden assembly source of the program to `W` as it is generated.
+///
+/// Unlike `MasmSink`, procedures are formatted and written as soon as they are
+/// produced and then dropped; only their names are kept to resolve local calls.
+/// As the compiler hands procedures over as soon as they are lowered, keeping
+/// only the bodies of those whose calls are inlined (see `inline` module), peak
+/// memory doesn't grow with the code of every function of large modules.
+/// Every standard library module used by intrinsics or integer arithmetic is
+/// imported up front, along with the modules given to `import`.
+pub struct MasmWriter<W: Write> {
+    writer: W,
+    // Names of the procedures written so far, by index.
+    names: Vec<String>,
+    // Imported procedures, by id, as `module::procedure`.
+    imports: BTreeMap<ProcedureId, String>,
+    // Procedures passed to `import`, as library path and procedure name.
+    extra_imports: Vec<(String, String)>,
+    started: bool,
+    has_entry: bool,
+}
+
+impl<W: Write> MasmWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            names: Vec::new(),
+            imports: BTreeMap::new(),
+            extra_imports: Vec::new(),
+            started: false,
+            has_entry: false,
+        }
+    }
+
+    fn write_imports(&mut self) -> anyhow::Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        let mut paths = BTreeSet::new();
+        let extra_imports = self
+            .extra_imports
+            .iter()
+            .map(|(path, procedure)| (path.as_str(), procedure.as_str()));
+        let imports: Vec<(&str, &str)> = intrinsics::all()
+            .filter_map(|i| i.imported())
+            .chain(integers::imports())
+            .chain(oracle::imports())
+            .chain(recursion::imports())
+            .chain(extra_imports)
+            .collect();
+        for (path, procedure) in imports {
+            let library_path = LibraryPath::new(path)
+                .map_err(|e| anyhow::anyhow!("Invalid library path {path}: {e:?}"))?;
+            let module = library_path.last().to_string();
+            self.imports.insert(
+                ProcedureId::from_name(procedure, &library_path),
+                format!("{module}::{procedure}"),
+            );
+            paths.insert(path);
+        }
+        for path in &paths {
+            writeln!(self.writer, "use.{path}")?;
+        }
+        if !paths.is_empty() {
+            writeln!(self.writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_body(&mut self, body: &CodeBody, indent: usize) -> anyhow::Result<()> {
+        for node in body.nodes() {
+            self.write_node(node, indent)?;
+        }
+        Ok(())
+    }
+
+    fn write_node(&mut self, node: &Node, indent: usize) -> anyhow::Result<()> {
+        let pad = "    ".repeat(indent);
+        match node {
+            Node::Instruction(instruction) => {
+                let instruction = self.format_instruction(instruction)?;
+                writeln!(self.writer, "{pad}{instruction}")?;
+            }
+            Node::IfElse {
+                true_case,
+                false_case,
+            } => {
+                writeln!(self.writer, "{pad}if.true")?;
+                self.write_body(true_case, indent + 1)?;
+                if !false_case.nodes().is_empty() {
+                    writeln!(self.writer, "{pad}else")?;
+                    self.write_body(false_case, indent + 1)?;
+                }
+                writeln!(self.writer, "{pad}end")?;
+            }
+            Node::Repeat { times, body } => {
+                writeln!(self.writer, "{pad}repeat.{times}")?;
+                self.write_body(body, indent + 1)?;
+                writeln!(self.writer, "{pad}end")?;
+            }
+            Node::While { body } => {
+                writeln!(self.writer, "{pad}while.true")?;
+                self.write_body(body, indent + 1)?;
+                writeln!(self.writer, "{pad}end")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn format_instruction(&self, instruction: &Instruction) -> anyhow::Result<String> {
+        let local = |index: &u16| {
+            self.names
+                .get(*index as usize)
+                .with_context(|| format!("Call to unknown local procedure {index}"))
+        };
+        let imported = |id: &ProcedureId| {
+            self.imports
+                .get(id)
+                .with_context(|| format!("Call to unknown imported procedure {id:?}"))
+        };
+        Ok(match instruction {
+            Instruction::ExecLocal(index) => format!("exec.{}", local(index)?),
+            Instruction::CallLocal(index) => format!("call.{}", local(index)?),
+            Instruction::ExecImported(id) => format!("exec.{}", imported(id)?),
+            Instruction::CallImported(id) => format!("call.{}", imported(id)?),
+            instruction => instruction.to_string(),
+        })
+    }
+}
+
+impl<W: Write> CodegenBackend for MasmWriter<W> {
+    type Output = W;
+
+    fn import(&mut self, path: &str) -> anyhow::Result<()> {
+        if self.started {
+            anyhow::bail!("Imports must be given before any procedure");
+        }
+        let (module, procedure) = path
+            .rsplit_once("::")
+            .ok_or_else(|| anyhow::anyhow!("Invalid procedure path {path}"))?;
+        self.extra_imports
+            .push((module.to_string(), procedure.to_string()));
+        Ok(())
+    }
+
+    fn procedure(&mut self, procedure: ProcedureAst) -> anyhow::Result<()> {
+        self.write_imports()?;
+        let keyword = if procedure.is_export {
+            "export"
+        } else {
+            "proc"
+        };
+        writeln!(
+            self.writer,
+            "{keyword}.{}.{}",
+            procedure.name, procedure.num_locals
+        )?;
+        self.write_body(&procedure.body, 1)?;
+        writeln!(self.writer, "end\n")?;
+        self.names.push(procedure.name.to_string());
+        Ok(())
+    }
+
+    fn entry(&mut self, body: CodeBody) -> anyhow::Result<()> {
+        self.write_imports()?;
+        writeln!(self.writer, "begin")?;
+        self.write_body(&body, 1)?;
+        writeln!(self.writer, "end")?;
+        self.has_entry = true;
+        Ok(())
+    }
+
+    fn finish(mut self) -> anyhow::Result<Self::Output> {
+        if !self.has_entry {
+            anyhow::bail!("No entry point defined");
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Discards all code; only useful to check that a module can be compiled.
+#[derive(Debug, Default)]
+pub struct ValidationSink {
+    has_entry: bool,
+}
+
+impl CodegenBackend for ValidationSink {
+    type Output = ();
+
+    fn procedure(&mut self, _procedure: ProcedureAst) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn entry(&mut self, _body: CodeBody) -> anyhow::Result<()> {
+        self.has_entry = true;
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<Self::Output> {
+        if !self.has_entry {
+            anyhow::bail!("No entry point defined");
+        }
+        Ok(())
+    }
+}
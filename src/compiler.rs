@@ -1,74 +1,1671 @@
 use {
-    crate::cfg::{Cfg, Label, OutgoingEdge},
-    anyhow::Error,
-    miden_assembly::{
-        ast::{CodeBody, Instruction, Node, ProcedureAst, ProgramAst, SourceLocation},
-        ProcedureName,
+    crate::{
+        abi::{self, EntryAbi, StorageLayout},
+        analysis::{self, AnalysisCache, AnalysisResult, FunctionAnalysis},
+        artifact::{
+            self, BuildInfo, CompiledArtifact, CompilerOptions, EntryFunction, ModuleMetadata,
+            ProcedureInfo, Stats,
+        },
+        attributes::{FunctionAttributes, PackageAttributes},
+        bytes,
+        cfg::{Cfg, Label, OutgoingEdge},
+        codegen::{AstSink, CodegenBackend},
+        cost::{self, CostModel},
+        diagnostics::{self, Diagnostics, ErrorCode},
+        error_map::{ErrorCodes, ErrorMap},
+        footprint::{self, MemoryFootprint},
+        fpi,
+        inline::Inliner,
+        integers, intrinsics,
+        limits::{self, Limits},
+        mangle,
+        memory::{self, MemoryMap},
+        move_utils,
+        passes::{self, Pass, PassIr},
+        schedule,
+        types::{self, TypeStack},
     },
+    anyhow::{Context, Error},
+    miden::math::Felt,
+    miden_assembly::ast::{CodeBody, Instruction, Node, ProcedureAst, ProgramAst, SourceLocation},
     move_binary_format::{
         access::ModuleAccess,
-        file_format::{Bytecode, Constant, FunctionDefinition, FunctionDefinitionIndex, Signature},
+        file_format::{
+            Bytecode, CodeOffset, Constant, FunctionDefinition, FunctionDefinitionIndex,
+            FunctionInstantiationIndex, Signature, SignatureIndex, SignatureToken,
+            StructDefinitionIndex, StructFieldInformation, Visibility,
+        },
         CompiledModule,
     },
+    move_core_types::{language_storage::ModuleId, u256::U256, value::MoveValue},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fmt,
+        sync::{Arc, Mutex, RwLock},
+        time::Instant,
+    },
 };
 
-const MAIN_NAME_REPLACEMENT: &str = "dummy_name_in_place_of_main"; // TODO: remove after name mapping
-
-pub fn compile(module: &CompiledModule) -> anyhow::Result<ProgramAst> {
-    let mut local_procs = Vec::new();
-    let mut main_proc = None;
-    let mut state = CompilerState::default();
-    // Build up function details for compiler state
-    for (index, handle) in module.function_handles().iter().enumerate() {
-        let name = module.identifier_at(handle.name).to_string();
-        let params = module.signature_at(handle.parameters).to_owned();
-        let func_def = module.function_def_at(FunctionDefinitionIndex::new(index as u16));
-        let locals = match &func_def.code {
-            Some(code) => module.signature_at(code.locals).to_owned(),
-            None => Signature::default(),
+/// Compiles the module to a Miden program along with its metadata.
+pub fn compile(module: &CompiledModule) -> anyhow::Result<CompiledArtifact> {
+    let state = CompilerState::new(module)?;
+    compile_artifact(module, &state)
+}
+
+/// Like `compile`, making optimization decisions and cost estimates with `cost_model`.
+pub fn compile_with_cost_model(
+    module: &CompiledModule,
+    cost_model: Box<dyn CostModel>,
+) -> anyhow::Result<CompiledArtifact> {
+    let mut state = CompilerState::new(module)?;
+    state.cost_model = cost_model;
+    compile_artifact(module, &state)
+}
+
+/// Like `compile`, applying the `miden` attributes of the functions of the module
+/// and of the functions it calls in other modules.
+pub fn compile_with_attributes(
+    module: &CompiledModule,
+    attributes: &PackageAttributes,
+) -> anyhow::Result<CompiledArtifact> {
+    let mut state = CompilerState::new(module)?;
+    state.set_attributes(module, attributes);
+    compile_artifact(module, &state)
+}
+
+/// Compiles serialized bytecode from an untrusted source, e.g. contracts
+/// submitted to a rollup. The module must pass the Move bytecode verifier and
+/// stay within `limits`. The default cost model is used and there are no
+/// attributes, so the result only depends on the bytecode, and nothing is read
+/// from the file system. Panics in the compiler are reported as errors.
+pub fn compile_untrusted(bytes: &[u8], limits: &Limits) -> anyhow::Result<CompiledArtifact> {
+    limits.check_bytes(bytes)?;
+    let deadline = limits.deadline();
+    let result = std::panic::catch_unwind(|| {
+        let module = move_utils::parse_module(bytes)?;
+        // The size of the module is checked first, since verification takes longer
+        limits.check_module(&module)?;
+        move_bytecode_verifier::verify_module(&module)
+            .map_err(|e| Error::msg(format!("Module failed bytecode verification: {e}")))?;
+        let mut state = CompilerState::new(&module)?;
+        state.deadline = Some(deadline);
+        state.max_code = Some(limits.max_instructions);
+        compile_artifact(&module, &state)
+    });
+    let artifact = result.map_err(|_| Error::msg("The compiler panicked on the module"))??;
+    limits.check_artifact(&artifact)?;
+    Ok(artifact)
+}
+
+/// Options of code generation from an analyzed module (see `analysis` module).
+#[derive(Debug, Default)]
+pub struct CodegenOptions {
+    pub cost_model: Box<dyn CostModel>,
+    // `miden` attributes of the functions of the module and of the functions it calls.
+    pub attributes: PackageAttributes,
+    // Location of the definition of each function in the Move sources, recorded
+    // as the start of its procedure.
+    pub source_locations: BTreeMap<FunctionDefinitionIndex, SourceLocation>,
+    pub entry_mode: EntryMode,
+    // Whether to compute calls of pure functions (see `AnalysisResult::pure_functions`)
+    // made with the same constant arguments from several places only once, keeping
+    // the result in memory for the other calls.
+    pub memoize: bool,
+    pub opcode_policy: OpcodePolicy,
+    // Debug mode framing heap allocations with canary words, checked before the
+    // entry function returns (see `memory` module).
+    pub canaries: bool,
+    // Record the program after each compiler pass (see `passes` module).
+    pub emit_pass_ir: bool,
+    pub target: TargetContext,
+    // MAST roots of the procedures called by foreign functions, by `module::function`.
+    // Calls to foreign functions without a root fail to compile (see `fpi` module).
+    pub foreign_roots: BTreeMap<String, [Felt; 4]>,
+    // Addresses of the memory regions used by the program (see `memory` module).
+    pub memory: MemoryMap,
+    // Abort codes of the checks emitted by the compiler (see `error_map` module).
+    pub error_codes: ErrorCodes,
+}
+
+/// What to do with opcodes the compiler can't lower yet, e.g. ones added by newer
+/// Move toolchains.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpcodePolicy {
+    // Compilation fails with `diagnostics::UNSUPPORTED_OPCODE`.
+    #[default]
+    Error,
+    // The opcode is left out of the program, leaving the stack unchanged, and its
+    // location is recorded (see `CompiledArtifact::skipped_opcodes`).
+    Skip,
+}
+
+/// How the program hands the result of the entry function to its caller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntryMode {
+    // Return values are placed on the output stack (see `abi` module).
+    #[default]
+    Outputs,
+    // The entry function returns a bool, and the program fails unless it is true,
+    // e.g. for predicates used as note spend conditions. There are no outputs.
+    AssertTrue,
+    // The entry function returns a bool, and the program checks it returns true
+    // for a batch of instances of its inputs read from the advice stack, the
+    // number of instances being the only stack input, e.g. to validate many
+    // transactions of a rollup with one proof. There are no outputs.
+    Batch,
+}
+
+/// Where the program runs, which decides how native functions are lowered
+/// (see `intrinsics` module).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetContext {
+    // A program run on its own by the Miden VM, with only the standard library.
+    #[default]
+    Standalone,
+    // Account code, run by the Miden transaction kernel.
+    Account,
+    // A note script, run by the Miden transaction kernel when the note is consumed.
+    NoteScript,
+}
+
+impl TargetContext {
+    pub const ALL: [TargetContext; 3] = [Self::Standalone, Self::Account, Self::NoteScript];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Standalone => "standalone",
+            Self::Account => "account",
+            Self::NoteScript => "note-script",
+        }
+    }
+}
+
+impl fmt::Display for TargetContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for TargetContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|context| context.name() == s)
+            .ok_or_else(|| {
+                format!("Unknown target context `{s}`, expected standalone, account or note-script")
+            })
+    }
+}
+
+/// Generates the program of an analyzed module, as `compile` does.
+pub fn codegen(
+    analysis: &AnalysisResult<'_>,
+    options: CodegenOptions,
+) -> anyhow::Result<CompiledArtifact> {
+    let mut state = CompilerState::with_memory(analysis.module, options.memory)?;
+    state.cost_model = options.cost_model;
+    state.entry_mode = options.entry_mode;
+    state.opcode_policy = options.opcode_policy;
+    state.canaries = options.canaries;
+    state.emit_pass_ir = options.emit_pass_ir;
+    state.target = options.target;
+    state.foreign_roots = options.foreign_roots;
+    state.error_codes = options.error_codes;
+    if options.memoize {
+        state.plan_memoization(analysis)?;
+    }
+    if !options.attributes.is_empty() {
+        state.set_attributes(analysis.module, &options.attributes);
+    }
+    for function in &mut state.functions {
+        if let Some(location) = function
+            .definition
+            .and_then(|definition| options.source_locations.get(&definition))
+        {
+            function.start = *location;
+        }
+    }
+    generate_artifact(analysis, &state)
+}
+
+fn compile_artifact(
+    module: &CompiledModule,
+    state: &CompilerState,
+) -> anyhow::Result<CompiledArtifact> {
+    generate_artifact(&analysis::analyze(module)?, state)
+}
+
+fn generate_artifact(
+    analysis: &AnalysisResult<'_>,
+    state: &CompilerState,
+) -> anyhow::Result<CompiledArtifact> {
+    let module = analysis.module;
+    let program = generate(analysis, state, AstSink::default())?;
+    let mut procedures = Vec::new();
+    let mut entry = None;
+    for ((index, func_def), analyzed) in module
+        .function_defs()
+        .iter()
+        .enumerate()
+        .zip(&analysis.functions)
+    {
+        let function = module
+            .identifier_at(module.function_handle_at(func_def.function).name)
+            .to_string();
+        let (procedure, body) = if func_def.is_entry {
+            entry = Some(func_def);
+            (None, program.body())
+        } else {
+            let procedure = program
+                .procedures()
+                .get(index)
+                .ok_or_else(|| Error::msg("Missing procedure for function"))?;
+            (Some(index as u16), &procedure.body)
         };
-        state.functions.push(Function {
-            name,
-            params,
-            locals,
+        procedures.push(ProcedureInfo {
+            function,
+            definition: FunctionDefinitionIndex::new(index as u16),
+            procedure,
+            cost: cost::estimate_with(body, &*state.cost_model),
+            acquires: analyzed.acquires.iter().cloned().collect(),
         });
     }
-    state.constants = module.constant_pool.to_owned();
-    for function in module.function_defs() {
-        let mut proc = compile_function(function, &state)?;
+    let entry = entry.ok_or_else(|| diagnostics::ENTRY_POINT.error("No entry point defined"))?;
+    check_cycle_budgets(module, state, &program)?;
+    let entry_function = state.function_at(entry.function.0)?;
+    let definition = entry_function
+        .definition
+        .ok_or_else(|| Error::msg("Missing function definition"))?;
+    let metadata = ModuleMetadata::new(module);
+    let mut error_map = ErrorMap::from_metadata(module.self_id().name().as_str(), &metadata);
+    error_map.checks = state.error_codes;
+    check_abort_codes(module, state, &error_map)?;
+    let options = CompilerOptions {
+        cost_model: state.cost_model.parameters(),
+        attributes: state.attributes,
+        memoize: state.memoize,
+        skip_unsupported_opcodes: state.opcode_policy == OpcodePolicy::Skip,
+        canaries: state.canaries,
+        target: state.target,
+        memory: state.memory,
+        error_codes: state.error_codes,
+    };
+    let skipped_opcodes = std::mem::take(
+        &mut *state
+            .skipped_opcodes
+            .lock()
+            .map_err(|_| Error::msg("Skipped opcodes lock poisoned"))?,
+    );
+    let pass_ir = std::mem::take(
+        &mut *state
+            .pass_ir
+            .lock()
+            .map_err(|_| Error::msg("Pass IR lock poisoned"))?,
+    );
+    Ok(CompiledArtifact {
+        entry: EntryFunction {
+            module: module.self_id(),
+            function: entry_function.name.clone(),
+            definition,
+            procedure_name: entry_function.procedure_name.clone(),
+        },
+        abi: entry_abi(module, entry, state.entry_mode)?,
+        layout: StorageLayout::with_memory(module, state.data.size(), &state.memory),
+        stats: Stats {
+            memory: memory_footprints(analysis, state, &program)?,
+            ..Stats::new(&program, &*state.cost_model)
+        },
+        program,
+        procedures,
+        source_map: None,
+        diagnostics: Diagnostics::default(),
+        skipped_opcodes,
+        pass_ir,
+        metadata,
+        error_map,
+        build_info: BuildInfo::new(options),
+    })
+}
+
+/// Fails if the module aborts with a constant code, or declares an error, in
+/// the namespace of the checks emitted by the compiler, as failures with that
+/// code couldn't be told apart.
+fn check_abort_codes(
+    module: &CompiledModule,
+    state: &CompilerState,
+    error_map: &ErrorMap,
+) -> anyhow::Result<()> {
+    let aborts = module
+        .function_defs()
+        .iter()
+        .filter_map(|definition| definition.code.as_ref())
+        .flat_map(|code| code.code.windows(2))
+        .filter(|pair| pair[1] == Bytecode::Abort)
+        .filter_map(|pair| constant_abort_code(&pair[0], state));
+    for code in aborts.chain(error_map.errors.keys().copied()) {
+        if let Some(check) = state.error_codes.check(code) {
+            return Err(diagnostics::ABORT_CODE.error(format!(
+                "Abort code {code:#x} is the code of the {} check, from the namespace \
+                 starting at {:#x}",
+                check.name(),
+                state.error_codes.base()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn entry_abi(
+    module: &CompiledModule,
+    entry: &FunctionDefinition,
+    mode: EntryMode,
+) -> anyhow::Result<EntryAbi> {
+    let mut abi = EntryAbi::new(module, entry)?;
+    match mode {
+        EntryMode::Outputs => {}
+        EntryMode::AssertTrue => {
+            abi.output_width = 0;
+            abi.asserts_result = true;
+        }
+        EntryMode::Batch => {
+            abi.output_width = 0;
+            abi.asserts_result = true;
+            abi.batch = true;
+            // Every instance is read from the advice stack
+            abi.inputs.advice_width = abi.inputs.width();
+            abi.inputs.stack_width = 0;
+        }
+    }
+    Ok(abi)
+}
+
+/// Memory used by the functions of the module (see `footprint` module).
+fn memory_footprints(
+    analysis: &AnalysisResult<'_>,
+    state: &CompilerState,
+    program: &ProgramAst,
+) -> anyhow::Result<Vec<MemoryFootprint>> {
+    let allocation = |bytecode: &Bytecode| -> anyhow::Result<Option<u32>> {
+        Ok(match bytecode {
+            Bytecode::Pack(index) => Some(memory::allocation_size(
+                state.struct_at(*index)?.width()?,
+                state.canaries,
+            )),
+            Bytecode::Call(handle) => match native_intrinsic(handle.0, state)? {
+                Some(intrinsic) if intrinsic.allocates() => None,
+                _ => Some(0),
+            },
+            _ => Some(0),
+        })
+    };
+    footprint::footprints(
+        analysis,
+        program.procedures(),
+        &allocation,
+        state.data.size(),
+        &state.memory,
+    )
+}
+
+/// Fails if a function takes more cycles than allowed by its `max_cycles` attribute.
+fn check_cycle_budgets(
+    module: &CompiledModule,
+    state: &CompilerState,
+    program: &ProgramAst,
+) -> anyhow::Result<()> {
+    for (index, func_def) in module.function_defs().iter().enumerate() {
+        let function = state.function_at(func_def.function.0)?;
+        let Some(max_cycles) = function.attributes.max_cycles else {
+            continue;
+        };
+        let body = if func_def.is_entry {
+            program.body()
+        } else {
+            &program
+                .procedures()
+                .get(index)
+                .ok_or_else(|| Error::msg("Missing procedure for function"))?
+                .body
+        };
+        let cost = cost::estimate_with_calls(body, program.procedures(), &*state.cost_model);
+        let location = CodeLocation {
+            function: FunctionDefinitionIndex::new(index as u16),
+            offset: None,
+        };
+        if !cost.bounded {
+            return Err(diagnostics::CYCLE_BUDGET
+                .error(format!(
+                    "Function {} has loops or recursion without a constant bound, \
+                     so it can't be checked against its budget of {max_cycles} cycles",
+                    function.name
+                ))
+                .context(location));
+        }
+        if cost.cycles > max_cycles {
+            return Err(diagnostics::CYCLE_BUDGET
+                .error(format!(
+                    "Function {} takes up to {} cycles, exceeding its budget of {max_cycles}",
+                    function.name, cost.cycles
+                ))
+                .context(location));
+        }
+    }
+    Ok(())
+}
+
+/// Compiles the module to a bare Miden program, without any metadata.
+pub fn compile_program(module: &CompiledModule) -> anyhow::Result<ProgramAst> {
+    compile_with(module, AstSink::default())
+}
+
+/// Compiles the module, handing the generated code to `backend`.
+pub fn compile_with<B: CodegenBackend>(
+    module: &CompiledModule,
+    backend: B,
+) -> anyhow::Result<B::Output> {
+    let state = CompilerState::new(module)?;
+    compile_with_state(module, &state, backend)
+}
+
+fn compile_with_state<B: CodegenBackend>(
+    module: &CompiledModule,
+    state: &CompilerState,
+    backend: B,
+) -> anyhow::Result<B::Output> {
+    generate(&analysis::analyze(module)?, state, backend)
+}
+
+fn generate<B: CodegenBackend>(
+    analysis: &AnalysisResult<'_>,
+    state: &CompilerState,
+    mut backend: B,
+) -> anyhow::Result<B::Output> {
+    let module = analysis.module;
+    check_target_context(module, state)?;
+    state
+        .memory
+        .check(state.data.size())
+        .map_err(|e| diagnostics::MEMORY_LAYOUT.error(e.to_string()))?;
+    // Procedures are passed to the backend as soon as they are lowered, see
+    // `inline` module.
+    for path in state.imports() {
+        backend.import(&path)?;
+    }
+    let mut inliner = Inliner::new(&*state.cost_model);
+    let mut main_body = None;
+    let mut calls_helpers = false;
+    // Code after every pass, only kept when recording passes.
+    let mut lowered_body = None;
+    let mut lowered = Vec::new();
+    let mut scheduled = Vec::new();
+    let mut inlined = Vec::new();
+    let mut code_size = 0;
+    for (function, analyzed) in module.function_defs().iter().zip(&analysis.functions) {
+        limits::check_deadline(state.deadline)?;
+        let mut proc = lower_function(analyzed, function, state)?;
+        code_size += artifact::code_size(&proc.body);
+        limits::check_code_size(code_size, state.max_code)?;
+        let raw = state.emit_pass_ir.then(|| proc.clone());
+        proc.body = schedule::optimize(&proc.body, &*state.cost_model);
+        let entry = state.function_at(function.function.0)?;
         if function.is_entry {
-            if main_proc.is_some() {
-                anyhow::bail!("Cannot handle multiple entrypoints");
+            if main_body.is_some() {
+                return Err(diagnostics::ENTRY_POINT.error("Cannot handle multiple entrypoints"));
+            }
+            main_body = Some(entry_body(state, entry, &proc.body, true)?);
+            if let Some(raw) = raw {
+                lowered_body = Some(entry_body(state, entry, &raw.body, false)?);
+            }
+            // Keep an empty procedure named after the entry function, so the local
+            // procedure indices don't shift and the program records where its body comes from
+            proc = empty_proc(entry.procedure_name.clone())?;
+            proc.start = entry.start;
+            proc.docs = Some(format!(
+                "Entry function {}::{}, compiled into the body of the program",
+                module.self_id(),
+                entry.name
+            ));
+            if state.emit_pass_ir {
+                lowered.push(proc.clone());
             }
-            proc.name = ProcedureName::main();
-            main_proc = Some(proc);
-            // Add a dummy placeholder for main, so the local procedure indices don't shift
-            local_procs.push(empty_proc(MAIN_NAME_REPLACEMENT.into())?);
         } else {
-            local_procs.push(proc);
+            lowered.extend(raw);
+        }
+        if state.emit_pass_ir {
+            scheduled.push(proc.clone());
+        }
+        inliner.procedure(&mut proc, entry.attributes.inline);
+        // Inlining copies calls to helpers but never removes them, so checking
+        // the final code is enough.
+        calls_helpers |= integers::calls_helpers(proc.body.nodes(), state.helpers);
+        if state.emit_pass_ir {
+            inlined.push(proc.clone());
+        }
+        backend.procedure(proc)?;
+    }
+    let scheduled_body =
+        main_body.ok_or_else(|| diagnostics::ENTRY_POINT.error("No entry point defined"))?;
+    let main_body = inliner.body(&scheduled_body);
+    calls_helpers |= integers::calls_helpers(main_body.nodes(), state.helpers);
+    let helpers = if calls_helpers {
+        integers::helpers(&state.error_codes)?
+    } else {
+        Vec::new()
+    };
+    if let Some(lowered_body) = &lowered_body {
+        record_pass(
+            state,
+            Pass::Lower,
+            lowered.iter().chain(&helpers),
+            lowered_body,
+        )?;
+        record_pass(
+            state,
+            Pass::Schedule,
+            scheduled.iter().chain(&helpers),
+            &scheduled_body,
+        )?;
+        record_pass(
+            state,
+            Pass::Inline,
+            inlined.iter().chain(&helpers),
+            &main_body,
+        )?;
+    }
+    limits::check_deadline(state.deadline)?;
+    for helper in helpers {
+        backend.procedure(helper)?;
+    }
+    backend.entry(main_body)?;
+    backend.finish()
+}
+
+/// Fails on the first use of a feature the target context doesn't provide, such
+/// as global storage in a standalone program, which would otherwise only fail
+/// inside the transaction kernel, or not be reported precisely.
+fn check_target_context(module: &CompiledModule, state: &CompilerState) -> anyhow::Result<()> {
+    for (index, func_def) in module.function_defs().iter().enumerate() {
+        let Some(code) = &func_def.code else {
+            continue;
+        };
+        for (offset, bytecode) in code.code.iter().enumerate() {
+            let problem = match bytecode {
+                Bytecode::Call(handle) => {
+                    let callee = state.function_at(handle.0)?;
+                    match &callee.attributes.intrinsic {
+                        _ if callee.attributes.foreign => {
+                            (state.target == TargetContext::Standalone).then(|| {
+                                format!(
+                                    "Foreign procedure {}::{} requires account or note-script \
+                                     context, but the program is compiled for standalone context",
+                                    callee.module_name, callee.name
+                                )
+                            })
+                        }
+                        Some(path) => intrinsics::bound_context_error(path, state.target),
+                        None if callee.is_native
+                            || (callee.definition.is_none() && !callee.is_external) =>
+                        {
+                            intrinsics::context_error(
+                                &callee.module_name,
+                                &callee.name,
+                                state.target,
+                            )
+                        }
+                        None => None,
+                    }
+                }
+                Bytecode::MoveTo(_)
+                | Bytecode::MoveToGeneric(_)
+                | Bytecode::MoveFrom(_)
+                | Bytecode::MoveFromGeneric(_)
+                | Bytecode::ImmBorrowGlobal(_)
+                | Bytecode::ImmBorrowGlobalGeneric(_)
+                | Bytecode::MutBorrowGlobal(_)
+                | Bytecode::MutBorrowGlobalGeneric(_)
+                | Bytecode::Exists(_)
+                | Bytecode::ExistsGeneric(_)
+                    if state.target == TargetContext::Standalone =>
+                {
+                    Some(
+                        "Global storage requires account context, but the program is compiled \
+                         for standalone context"
+                            .to_string(),
+                    )
+                }
+                _ => None,
+            };
+            if let Some(message) = problem {
+                return Err(diagnostics::TARGET_CONTEXT
+                    .error(message)
+                    .context(CodeLocation {
+                        function: FunctionDefinitionIndex::new(index as u16),
+                        offset: Some(offset as CodeOffset),
+                    }));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Body of the program: the code of the entry function framed by the prologue
+/// and epilogue of the entry ABI, or run for every instance of a batch (see
+/// `abi` module). `optimize` is only unset
+/// to record the code before scheduling.
+fn entry_body(
+    state: &CompilerState,
+    entry: &Function,
+    body: &CodeBody,
+    optimize: bool,
+) -> anyhow::Result<CodeBody> {
+    let mut nodes = match state.entry_mode {
+        // Each instance reads its own inputs (see `abi::batch`)
+        EntryMode::Batch => Vec::new(),
+        _ => abi::prologue(&entry.params, &state.error_codes)?,
+    };
+    nodes.extend(state.data.init());
+    nodes.extend_from_slice(body.nodes());
+    if state.canaries {
+        nodes.extend(memory::check_canaries(state.data.size(), &state.memory));
+    }
+    let nodes = match state.entry_mode {
+        EntryMode::Outputs => {
+            nodes.extend(abi::epilogue(&entry.returns, &state.memory)?);
+            return Ok(CodeBody::new(nodes));
+        }
+        EntryMode::AssertTrue => {
+            nodes.extend(abi::assert_epilogue(&entry.returns)?);
+            nodes
+        }
+        EntryMode::Batch => abi::batch(&entry.params, &entry.returns, &nodes, &state.error_codes)?,
+    };
+    let body = CodeBody::new(nodes);
+    // Merges the assertion with the comparison computing the result, if any
+    Ok(if optimize {
+        schedule::optimize(&body, &*state.cost_model)
+    } else {
+        body
+    })
+}
+
+/// Records the program after a pass (see `passes` module).
+fn record_pass<'a>(
+    state: &CompilerState,
+    pass: Pass,
+    procedures: impl IntoIterator<Item = &'a ProcedureAst>,
+    body: &CodeBody,
+) -> anyhow::Result<()> {
+    let imports = state.imports();
+    let ir = passes::snapshot(pass, imports.iter().map(String::as_str), procedures, body)?;
+    state
+        .pass_ir
+        .lock()
+        .map_err(|_| Error::msg("Pass IR lock poisoned"))?
+        .push(ir);
+    Ok(())
+}
+
+/// Compiles all modules of a package that declare an entry function.
+///
+/// Metadata about the package (which functions are native) is built once and
+/// shared by all modules, so calls between modules of the package are reported
+/// precisely. Modules are compiled in parallel; artifacts are returned in the
+/// order of `modules`, skipping modules without an entry function.
+pub fn compile_package(modules: &[CompiledModule]) -> anyhow::Result<Vec<CompiledArtifact>> {
+    let package = PackageInfo::new(modules);
+    let entries: Vec<&CompiledModule> = modules
+        .iter()
+        .filter(|m| m.function_defs().iter().any(|f| f.is_entry))
+        .collect();
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = entries.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let package = &package;
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|module| {
+                            let state = CompilerState::new_in_package(module, package)?;
+                            compile_artifact(module, &state)
+                                .with_context(|| format!("Failed to compile {}", module.self_id()))
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        let mut artifacts = Vec::with_capacity(entries.len());
+        for handle in handles {
+            let chunk = handle
+                .join()
+                .map_err(|_| Error::msg("Compilation thread panicked"))??;
+            artifacts.extend(chunk);
+        }
+        Ok(artifacts)
+    })
+}
+
+/// Details about the modules of a package shared by all their compilations.
+#[derive(Debug, Default)]
+struct PackageInfo {
+    // Functions defined in the package, by module and function name.
+    functions: BTreeMap<(ModuleId, String), PackageFunction>,
+    // Modules allowed to call the `public(friend)` functions of every module.
+    friends: BTreeMap<ModuleId, BTreeSet<ModuleId>>,
+}
+
+#[derive(Debug)]
+struct PackageFunction {
+    is_native: bool,
+    visibility: Visibility,
+}
+
+impl PackageInfo {
+    fn new(modules: &[CompiledModule]) -> Self {
+        let functions = modules
+            .iter()
+            .flat_map(|module| {
+                module.function_defs().iter().map(move |func_def| {
+                    let name = module
+                        .identifier_at(module.function_handle_at(func_def.function).name)
+                        .to_string();
+                    let function = PackageFunction {
+                        is_native: func_def.code.is_none(),
+                        visibility: func_def.visibility,
+                    };
+                    ((module.self_id(), name), function)
+                })
+            })
+            .collect();
+        let friends = modules
+            .iter()
+            .map(|module| {
+                (
+                    module.self_id(),
+                    module.immediate_friends().into_iter().collect(),
+                )
+            })
+            .collect();
+        Self { functions, friends }
+    }
+
+    /// Checks that `caller` may call function `name` of module `callee`,
+    /// as the bytecode verifier does when modules are published together.
+    fn check_call(&self, caller: &ModuleId, callee: &ModuleId, name: &str) -> anyhow::Result<()> {
+        let Some(function) = self.functions.get(&(callee.clone(), name.to_string())) else {
+            return Ok(());
+        };
+        let reason = match function.visibility {
+            Visibility::Public => return Ok(()),
+            Visibility::Friend
+                if self
+                    .friends
+                    .get(callee)
+                    .is_some_and(|friends| friends.contains(caller)) =>
+            {
+                return Ok(())
+            }
+            Visibility::Friend => "public(friend) and the caller isn't a friend of its module",
+            Visibility::Private => "private",
+        };
+        Err(diagnostics::VISIBILITY.error(format!(
+            "{caller} calls {callee}::{name}, which is {reason}"
+        )))
+    }
+}
+
+/// A long-lived compiler holding the modules loaded into it.
+///
+/// Module details needed for compilation are computed once when a module is
+/// loaded, so repeated compilations of the same module (e.g. by a server) reuse them,
+/// as they reuse the analyses of functions with the same code in any loaded module.
+/// The compiler can be shared between threads.
+#[derive(Debug, Default)]
+pub struct Compiler {
+    modules: RwLock<BTreeMap<ModuleId, Arc<LoadedModule>>>,
+    analyses: AnalysisCache,
+}
+
+#[derive(Debug)]
+struct LoadedModule {
+    module: CompiledModule,
+    state: CompilerState,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a module, replacing any previously loaded module with the same id.
+    pub fn load(&self, module: CompiledModule) -> anyhow::Result<ModuleId> {
+        let id = module.self_id();
+        let state = CompilerState::new(&module)?;
+        self.modules
+            .write()
+            .map_err(|_| Error::msg("Compiler lock poisoned"))?
+            .insert(id.clone(), Arc::new(LoadedModule { module, state }));
+        Ok(id)
+    }
+
+    /// Deserializes and loads a module.
+    pub fn load_bytes(&self, bytes: &[u8]) -> anyhow::Result<ModuleId> {
+        self.load(move_utils::parse_module(bytes)?)
+    }
+
+    pub fn unload(&self, id: &ModuleId) -> anyhow::Result<bool> {
+        let removed = self
+            .modules
+            .write()
+            .map_err(|_| Error::msg("Compiler lock poisoned"))?
+            .remove(id);
+        Ok(removed.is_some())
+    }
+
+    /// Ids of all loaded modules.
+    pub fn modules(&self) -> anyhow::Result<Vec<ModuleId>> {
+        let modules = self
+            .modules
+            .read()
+            .map_err(|_| Error::msg("Compiler lock poisoned"))?;
+        Ok(modules.keys().cloned().collect())
+    }
+
+    /// Compiles a loaded module.
+    pub fn compile(&self, id: &ModuleId) -> anyhow::Result<CompiledArtifact> {
+        let loaded = self.loaded(id)?;
+        let analysis = analysis::analyze_cached(&loaded.module, &self.analyses)?;
+        generate_artifact(&analysis, &loaded.state)
+    }
+
+    /// Compiles a loaded module, handing the generated code to `backend`.
+    pub fn compile_with<B: CodegenBackend>(
+        &self,
+        id: &ModuleId,
+        backend: B,
+    ) -> anyhow::Result<B::Output> {
+        let loaded = self.loaded(id)?;
+        let analysis = analysis::analyze_cached(&loaded.module, &self.analyses)?;
+        generate(&analysis, &loaded.state, backend)
+    }
+
+    /// Analyses of functions shared by the compilations of all loaded modules.
+    pub fn analyses(&self) -> &AnalysisCache {
+        &self.analyses
+    }
+
+    fn loaded(&self, id: &ModuleId) -> anyhow::Result<Arc<LoadedModule>> {
+        // The lock is only held while looking up the module, not during compilation
+        self.modules
+            .read()
+            .map_err(|_| Error::msg("Compiler lock poisoned"))?
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Module {id} is not loaded"))
+    }
+}
+
+/// Outcome of compiling a single function of a module.
+#[derive(Debug)]
+pub struct FunctionResult {
+    pub name: String,
+    pub definition: FunctionDefinitionIndex,
+    pub procedure: anyhow::Result<ProcedureAst>,
+}
+
+/// Compiles every function of the module independently, so that a failure in
+/// one function does not prevent reporting on the others.
+/// Entry functions are compiled as regular procedures.
+pub fn compile_functions(
+    module: &CompiledModule,
+    attributes: &PackageAttributes,
+) -> anyhow::Result<Vec<FunctionResult>> {
+    let mut state = CompilerState::new(module)?;
+    state.set_attributes(module, attributes);
+    let results = module
+        .function_defs()
+        .iter()
+        .enumerate()
+        .map(|(index, func_def)| FunctionResult {
+            name: module
+                .identifier_at(module.function_handle_at(func_def.function).name)
+                .to_string(),
+            definition: FunctionDefinitionIndex::new(index as u16),
+            procedure: analysis::analyze_function(
+                module,
+                FunctionDefinitionIndex::new(index as u16),
+            )
+            .and_then(|analyzed| compile_function(&analyzed, func_def, &state)),
+        })
+        .collect();
+    Ok(results)
+}
+
+/// Location in the Move bytecode where compilation failed.
+/// Attached as context to compilation errors, so it can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeLocation {
+    pub function: FunctionDefinitionIndex,
+    // Offset of the failing bytecode, if the error is not about the function as a whole.
+    pub offset: Option<CodeOffset>,
+}
+
+impl fmt::Display for CodeLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "In function #{} at offset {offset}", self.function.0),
+            None => write!(f, "In function #{}", self.function.0),
         }
     }
-    let main_proc = main_proc.ok_or_else(|| Error::msg("No entry point defined"))?;
-    let result = ProgramAst::new(main_proc.body.nodes().to_vec(), local_procs)?;
-    Ok(result)
 }
 
 /// Struct definition of a module function.
 #[derive(Debug, Default)]
 struct Function {
     name: String,
+    module_name: String,
+    // Name of the generated procedure (see `mangle` module).
+    procedure_name: String,
     params: Signature,
+    returns: Signature,
     locals: Signature,
+    // Index of the definition if the function is defined in the module being compiled.
+    definition: Option<FunctionDefinitionIndex>,
+    is_native: bool,
+    // True if the function has bytecode in another module of the package.
+    is_external: bool,
+    attributes: FunctionAttributes,
+    // Location of the definition in the Move sources, if known.
+    start: SourceLocation,
+    frame: Frame,
+}
+
+/// Memory holding the locals of a function, parameters included, with one
+/// slot per stack element (see `types::stack_width`). Locals whose value is
+/// stored and moved right back out stay on the stack (see `compile_body`), so
+/// only the other locals get slots, and functions without any have no frame.
+#[derive(Debug, Default)]
+struct Frame {
+    // Slots of the locals going through the frame, by index.
+    slots: BTreeMap<u8, std::ops::Range<u16>>,
+    // Number of slots.
+    size: u16,
+    // Number of instructions at the start of the code moving the parameters in
+    // order, which are left out as the parameters are already on the stack in
+    // that order. Otherwise the parameters are stored on entry.
+    moved_params: usize,
+    // First address of the slots for the entry function, whose body is inlined
+    // into the program body, which has no procedure locals. Other functions use
+    // procedure locals.
+    address: Option<u32>,
+}
+
+impl Frame {
+    fn new(params: &Signature, locals: &Signature, code: &[Bytecode]) -> anyhow::Result<Self> {
+        let targets: BTreeSet<usize> = code
+            .iter()
+            .filter_map(|c| match c {
+                Bytecode::BrTrue(target) | Bytecode::BrFalse(target) | Bytecode::Branch(target) => {
+                    Some(*target as usize)
+                }
+                _ => None,
+            })
+            .collect();
+        let in_order = params.len() <= code.len()
+            && (0..params.len()).all(|index| {
+                code[index] == Bytecode::MoveLoc(index as u8) && !targets.contains(&index)
+            });
+        let moved_params = if in_order { params.len() } else { 0 };
+        let mut stored = BTreeSet::new();
+        if moved_params < params.len() {
+            stored.extend(0..params.len() as u8);
+        }
+        let mut i = moved_params;
+        while i < code.len() {
+            match &code[i..] {
+                [Bytecode::StLoc(x), Bytecode::MoveLoc(y), ..]
+                    if x == y && !targets.contains(&(i + 1)) =>
+                {
+                    i += 2;
+                    continue;
+                }
+                [Bytecode::MoveLoc(x)
+                | Bytecode::CopyLoc(x)
+                | Bytecode::StLoc(x)
+                | Bytecode::ImmBorrowLoc(x)
+                | Bytecode::MutBorrowLoc(x), ..] => {
+                    stored.insert(*x);
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+        let mut slots = BTreeMap::new();
+        let mut size: u16 = 0;
+        for index in stored {
+            let token = params
+                .0
+                .iter()
+                .chain(&locals.0)
+                .nth(index as usize)
+                .ok_or_else(|| Error::msg("Missing local index"))?;
+            let end = u16::try_from(types::stack_width(token)?)
+                .ok()
+                .and_then(|width| size.checked_add(width))
+                .ok_or_else(|| {
+                    Error::msg(format!(
+                        "Locals take more than {} slots, the maximum number of procedure locals",
+                        u16::MAX
+                    ))
+                })?;
+            slots.insert(index, size..end);
+            size = end;
+        }
+        Ok(Self {
+            slots,
+            size,
+            moved_params,
+            address: None,
+        })
+    }
+
+    /// Number of procedure locals of the function.
+    fn num_locals(&self) -> u16 {
+        match self.address {
+            Some(_) => 0,
+            None => self.size,
+        }
+    }
+
+    fn local(&self, index: u8) -> anyhow::Result<std::ops::Range<u16>> {
+        self.slots
+            .get(&index)
+            .cloned()
+            .ok_or_else(|| Error::msg("Missing local slots"))
+    }
+
+    /// Pushes the value of a local, its first element deepest.
+    fn load(&self, index: u8) -> anyhow::Result<Vec<Node>> {
+        Ok(self
+            .local(index)?
+            .map(|slot| {
+                Node::Instruction(match self.address {
+                    Some(address) => Instruction::MemLoadImm(address + slot as u32),
+                    None => Instruction::LocLoad(slot),
+                })
+            })
+            .collect())
+    }
+
+    /// Pops a value into a local, its last element being on top.
+    fn store(&self, index: u8) -> anyhow::Result<Vec<Node>> {
+        Ok(self
+            .local(index)?
+            .rev()
+            .map(|slot| {
+                Node::Instruction(match self.address {
+                    Some(address) => Instruction::MemStoreImm(address + slot as u32),
+                    None => Instruction::LocStore(slot),
+                })
+            })
+            .collect())
+    }
+
+    /// Pushes a reference to a local, the address of its first slot.
+    fn borrow(&self, index: u8) -> anyhow::Result<Node> {
+        let slot = self.local(index)?.start;
+        Ok(Node::Instruction(match self.address {
+            Some(address) => Instruction::PushU32(address + slot as u32),
+            None => Instruction::Locaddr(slot),
+        }))
+    }
+
+    /// Code storing the parameters on entry, unless they're moved in order.
+    fn prologue(&self, params: usize) -> anyhow::Result<Vec<Node>> {
+        let mut result = Vec::new();
+        if self.moved_params < params {
+            // The last parameter is on top
+            for index in (0..params).rev() {
+                result.extend(self.store(index as u8)?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Struct definition of a module struct.
+/// Structs are stored in memory with one address per stack element of their
+/// fields (see `memory` module).
+#[derive(Debug)]
+struct Struct {
+    handle: SignatureToken,
+    fields: Vec<SignatureToken>,
+}
+
+impl Struct {
+    /// Number of stack elements taken by the fields.
+    fn width(&self) -> anyhow::Result<u32> {
+        let mut width = 0;
+        for field in &self.fields {
+            width += types::stack_width(field)? as u32;
+        }
+        Ok(width)
+    }
+}
+
+/// How a constant from the constant pool is loaded.
+#[derive(Debug)]
+enum ConstantValue {
+    // Pushed as an immediate value.
+    Scalar(u32),
+    // Pushed as two u32 limbs (see `integers` module).
+    U64(u64),
+    // Pushed as four u32 limbs.
+    U128(u128),
+    // Pushed as eight u32 limbs.
+    U256(U256),
+    // Pushed as the element of the account ID (see `accounts` module).
+    Address(Felt),
+    // Pointer to the constant in the data section.
+    Data { address: u32 },
+    Unsupported(String),
+}
+
+impl ConstantValue {
+    fn new(constant: &Constant, data: &mut memory::DataSection) -> Self {
+        let Some(value) = constant.deserialize_constant() else {
+            return Self::Unsupported("Failed to deserialize constant".into());
+        };
+        match value {
+            MoveValue::Vector(elements) => {
+                let elements: Option<Vec<u32>> = elements.iter().map(scalar_value).collect();
+                match elements {
+                    Some(elements) => Self::Data {
+                        address: data.insert_vector(&elements),
+                    },
+                    None => Self::Unsupported(format!(
+                        "Can't handle constants of type {:?} yet",
+                        constant.type_
+                    )),
+                }
+            }
+            MoveValue::U64(x) => Self::U64(x),
+            MoveValue::U128(x) => Self::U128(x),
+            MoveValue::U256(x) => Self::U256(x),
+            MoveValue::Address(address) => match bytes::address_to_felt(&address) {
+                Ok(element) => Self::Address(element),
+                Err(e) => Self::Unsupported(e.to_string()),
+            },
+            value => match scalar_value(&value) {
+                Some(value) => Self::Scalar(value),
+                None => Self::Unsupported(format!(
+                    "Can't handle constants of type {:?} yet",
+                    constant.type_
+                )),
+            },
+        }
+    }
+}
+
+/// Value of a constant fitting in a single u32 stack element.
+fn scalar_value(value: &MoveValue) -> Option<u32> {
+    match value {
+        MoveValue::Bool(b) => Some(*b as u32),
+        MoveValue::U8(x) => Some(*x as u32),
+        MoveValue::U16(x) => Some(*x as u32),
+        MoveValue::U32(x) => Some(*x),
+        _ => None,
+    }
 }
 
 /// Struct carrying extra information needed during compilation.
 #[derive(Debug, Default)]
 struct CompilerState {
     constants: Vec<Constant>,
+    // Same order as `constants`.
+    constant_values: Vec<ConstantValue>,
+    // Vector constants, shared by all their uses.
+    data: memory::DataSection,
     functions: Vec<Function>,
+    structs: Vec<Struct>,
+    // Signature pool of the module, giving the element types of vector instructions.
+    signatures: Vec<Signature>,
+    // Instantiations of generic functions, as function handle and type arguments.
+    instantiations: Vec<(u16, SignatureIndex)>,
+    cost_model: Box<dyn CostModel>,
+    // Whether `miden` attributes were set.
+    attributes: bool,
+    // Time by which compilation must end, for untrusted modules.
+    deadline: Option<Instant>,
+    // Instructions of the generated code kept in memory, for untrusted modules.
+    max_code: Option<usize>,
+    entry_mode: EntryMode,
+    // Whether `plan_memoization` ran.
+    memoize: bool,
+    // Address of the memory holding the result of every memoized call, by function
+    // handle and constant arguments. The address before it is set once it is computed.
+    memoized: BTreeMap<(u16, Vec<u64>), u32>,
+    opcode_policy: OpcodePolicy,
+    // Opcodes left out under `OpcodePolicy::Skip`.
+    skipped_opcodes: Mutex<Vec<CodeLocation>>,
+    canaries: bool,
+    emit_pass_ir: bool,
+    target: TargetContext,
+    foreign_roots: BTreeMap<String, [Felt; 4]>,
+    memory: MemoryMap,
+    error_codes: ErrorCodes,
+    // Program after each pass, recorded when `emit_pass_ir` is set.
+    pass_ir: Mutex<Vec<PassIr>>,
+    // Index of the first u128 helper procedure, after the procedures of the
+    // module functions (see `integers` module).
+    helpers: u16,
+}
+
+impl CompilerState {
+    /// Builds up function and struct details of the module.
+    fn new(module: &CompiledModule) -> anyhow::Result<Self> {
+        Self::with_memory(module, MemoryMap::default())
+    }
+
+    /// Like `new`, laying out the data section according to the memory map.
+    fn with_memory(module: &CompiledModule, map: MemoryMap) -> anyhow::Result<Self> {
+        let mut state = Self {
+            memory: map,
+            data: memory::DataSection::new(map),
+            ..Self::default()
+        };
+        for handle in module.function_handles() {
+            let name = module.identifier_at(handle.name).to_string();
+            let module_handle = module.module_handle_at(handle.module);
+            let module_name = module.identifier_at(module_handle.name).to_string();
+            let procedure_name = mangle::mangle(
+                &module.module_id_for_handle(module_handle),
+                module.identifier_at(handle.name),
+                &[],
+            );
+            let params = module.signature_at(handle.parameters).to_owned();
+            let returns = module.signature_at(handle.return_).to_owned();
+            state.functions.push(Function {
+                name,
+                module_name,
+                procedure_name,
+                params,
+                returns,
+                ..Default::default()
+            });
+        }
+        for (index, func_def) in module.function_defs().iter().enumerate() {
+            let function = state
+                .functions
+                .get_mut(func_def.function.0 as usize)
+                .ok_or_else(|| Error::msg("Missing function handle index"))?;
+            function.definition = Some(FunctionDefinitionIndex::new(index as u16));
+            match &func_def.code {
+                Some(code) => {
+                    function.locals = module.signature_at(code.locals).to_owned();
+                    function.frame = Frame::new(&function.params, &function.locals, &code.code)?;
+                }
+                None => function.is_native = true,
+            }
+        }
+        for struct_def in module.struct_defs() {
+            let fields = match &struct_def.field_information {
+                StructFieldInformation::Declared(fields) => {
+                    fields.iter().map(|f| f.signature.0.clone()).collect()
+                }
+                StructFieldInformation::Native => Vec::new(),
+            };
+            state.structs.push(Struct {
+                handle: SignatureToken::Struct(struct_def.struct_handle),
+                fields,
+            });
+        }
+        state.signatures = module.signatures().to_vec();
+        state.instantiations = module
+            .function_instantiations()
+            .iter()
+            .map(|i| (i.handle.0, i.type_parameters))
+            .collect();
+        state.helpers = module.function_defs().len() as u16;
+        state.constants = module.constant_pool.to_owned();
+        for constant in &module.constant_pool {
+            let value = ConstantValue::new(constant, &mut state.data);
+            state.constant_values.push(value);
+        }
+        for func_def in module.function_defs().iter().filter(|f| f.is_entry) {
+            let frame = &mut state
+                .functions
+                .get_mut(func_def.function.0 as usize)
+                .ok_or_else(|| Error::msg("Missing function handle index"))?
+                .frame;
+            if frame.size > 0 {
+                frame.address = Some(state.data.reserve(frame.size as u32));
+            }
+        }
+        Ok(state)
+    }
+
+    /// Like `new`, also recording which functions called from other modules
+    /// of the package are native.
+    fn new_in_package(module: &CompiledModule, package: &PackageInfo) -> anyhow::Result<Self> {
+        let mut state = Self::new(module)?;
+        for (handle, function) in module.function_handles().iter().zip(&mut state.functions) {
+            if function.definition.is_some() {
+                continue;
+            }
+            let id = module.module_id_for_handle(module.module_handle_at(handle.module));
+            package.check_call(&module.self_id(), &id, &function.name)?;
+            match package.functions.get(&(id, function.name.clone())) {
+                Some(f) if f.is_native => function.is_native = true,
+                Some(_) => function.is_external = true,
+                None => (),
+            }
+        }
+        Ok(state)
+    }
+
+    /// Applies the attributes of the functions used by the module.
+    fn set_attributes(&mut self, module: &CompiledModule, attributes: &PackageAttributes) {
+        self.attributes = true;
+        for (handle, function) in module.function_handles().iter().zip(&mut self.functions) {
+            let id = module.module_id_for_handle(module.module_handle_at(handle.module));
+            if let Some(attributes) = attributes.get(&id).and_then(|m| m.get(&function.name)) {
+                function.attributes = attributes.clone();
+            }
+        }
+    }
+
+    /// Reserves memory for the calls of pure functions with a single integer or
+    /// bool return value made with the same constant arguments more than once.
+    fn plan_memoization(&mut self, analysis: &AnalysisResult<'_>) -> anyhow::Result<()> {
+        self.memoize = true;
+        let pure = analysis.pure_functions();
+        let mut counts: BTreeMap<(u16, Vec<u64>), usize> = BTreeMap::new();
+        for func_def in analysis.module.function_defs() {
+            let Some(code) = &func_def.code else {
+                continue;
+            };
+            for start in 0..code.code.len() {
+                let Some(((handle, args), _)) = constant_call(&code.code[start..]) else {
+                    continue;
+                };
+                let callee = self.function_at(handle)?;
+                let memoizable = callee.definition.is_some()
+                    && pure.contains(callee.name.as_str())
+                    && callee.params.len() == args.len()
+                    && matches!(
+                        callee.returns.0.as_slice(),
+                        [SignatureToken::Bool
+                            | SignatureToken::U8
+                            | SignatureToken::U16
+                            | SignatureToken::U32]
+                    );
+                if memoizable {
+                    *counts.entry((handle, args)).or_default() += 1;
+                }
+            }
+        }
+        for (key, count) in counts {
+            if count > 1 {
+                let address = self.data.reserve(2) + 1;
+                self.memoized.insert(key, address);
+            }
+        }
+        Ok(())
+    }
+
+    /// Library procedures called by the code which aren't imported by every
+    /// program: procedures bound to native functions with `intrinsic` attributes,
+    /// and transaction kernel procedures lowering native functions.
+    fn imports(&self) -> Vec<String> {
+        let mut imports = Vec::new();
+        for function in &self.functions {
+            if function.attributes.foreign {
+                let (path, procedure) = fpi::KERNEL_PROCEDURE;
+                imports.push(format!("{path}::{procedure}"));
+            } else if let Some(path) = &function.attributes.intrinsic {
+                imports.push(path.clone());
+            } else if (function.definition.is_none() && !function.is_external) || function.is_native
+            {
+                let kernel = intrinsics::find(&function.module_name, &function.name, self.target)
+                    .and_then(|intrinsic| intrinsic.kernel_procedure());
+                if let Some((path, procedure)) = kernel {
+                    imports.push(format!("{path}::{procedure}"));
+                }
+            }
+        }
+        imports
+    }
+
+    fn struct_at(&self, index: StructDefinitionIndex) -> anyhow::Result<&Struct> {
+        self.structs
+            .get(index.0 as usize)
+            .ok_or_else(|| Error::msg("Missing struct definition index"))
+    }
+
+    fn function_at(&self, index: u16) -> anyhow::Result<&Function> {
+        self.functions
+            .get(index as usize)
+            .ok_or_else(|| Error::msg("Missing function handle index"))
+    }
+
+    /// Element type of a vector instruction.
+    fn element_type(&self, index: SignatureIndex) -> anyhow::Result<&SignatureToken> {
+        match self
+            .signatures
+            .get(index.0 as usize)
+            .map(|s| s.0.as_slice())
+        {
+            Some([element]) => Ok(element),
+            _ => Err(Error::msg("Invalid vector instruction signature")),
+        }
+    }
+
+    /// Function called by a generic call, with its parameter and return types
+    /// instantiated.
+    fn instantiation_at(
+        &self,
+        index: FunctionInstantiationIndex,
+    ) -> anyhow::Result<(&Function, Vec<SignatureToken>, Vec<SignatureToken>)> {
+        let (handle, type_args) = self
+            .instantiations
+            .get(index.0 as usize)
+            .ok_or_else(|| Error::msg("Missing function instantiation index"))?;
+        let type_args = &self
+            .signatures
+            .get(type_args.0 as usize)
+            .ok_or_else(|| Error::msg("Missing signature index"))?
+            .0;
+        let function = self.function_at(*handle)?;
+        let instantiate = |tokens: &[SignatureToken]| -> anyhow::Result<Vec<SignatureToken>> {
+            tokens
+                .iter()
+                .map(|token| types::instantiate(token, type_args))
+                .collect()
+        };
+        let params = instantiate(&function.params.0)?;
+        let returns = instantiate(&function.returns.0)?;
+        Ok((function, params, returns))
+    }
+
+    /// Updates the types on the stack to reflect the execution of `bytecode`.
+    fn update_types(
+        &self,
+        types: &mut TypeStack,
+        bytecode: &Bytecode,
+        function: &Function,
+    ) -> anyhow::Result<()> {
+        match bytecode {
+            Bytecode::LdU8(_) => types.push(SignatureToken::U8),
+            Bytecode::LdU16(_) => types.push(SignatureToken::U16),
+            Bytecode::LdU32(_) => types.push(SignatureToken::U32),
+            Bytecode::LdU64(_) => types.push(SignatureToken::U64),
+            Bytecode::LdU128(_) => types.push(SignatureToken::U128),
+            Bytecode::LdU256(_) => types.push(SignatureToken::U256),
+            Bytecode::LdTrue | Bytecode::LdFalse => types.push(SignatureToken::Bool),
+            Bytecode::LdConst(index) => {
+                let constant = self
+                    .constants
+                    .get(index.0 as usize)
+                    .ok_or_else(|| Error::msg("Missing constant index"))?;
+                types.push(constant.type_.clone());
+            }
+            Bytecode::CopyLoc(index) | Bytecode::MoveLoc(index) => {
+                types.push(function.local_type(*index)?.clone())
+            }
+            Bytecode::StLoc(_)
+            | Bytecode::Pop
+            | Bytecode::Abort
+            | Bytecode::BrTrue(_)
+            | Bytecode::BrFalse(_) => {
+                types.pop()?;
+            }
+            Bytecode::Add
+            | Bytecode::Sub
+            | Bytecode::Mul
+            | Bytecode::Div
+            | Bytecode::Mod
+            | Bytecode::BitOr
+            | Bytecode::BitAnd
+            | Bytecode::Xor
+            | Bytecode::Shl
+            | Bytecode::Shr => {
+                // The result has the type of the left operand
+                types.pop()?;
+            }
+            Bytecode::Lt
+            | Bytecode::Gt
+            | Bytecode::Le
+            | Bytecode::Ge
+            | Bytecode::Eq
+            | Bytecode::Neq
+            | Bytecode::Or
+            | Bytecode::And => {
+                types.pop_n(2)?;
+                types.push(SignatureToken::Bool);
+            }
+            Bytecode::Not => {
+                types.pop()?;
+                types.push(SignatureToken::Bool);
+            }
+            Bytecode::CastU8 => cast(types, SignatureToken::U8)?,
+            Bytecode::CastU16 => cast(types, SignatureToken::U16)?,
+            Bytecode::CastU32 => cast(types, SignatureToken::U32)?,
+            Bytecode::CastU64 => cast(types, SignatureToken::U64)?,
+            Bytecode::CastU128 => cast(types, SignatureToken::U128)?,
+            Bytecode::CastU256 => cast(types, SignatureToken::U256)?,
+            Bytecode::Ret => types.clear(),
+            Bytecode::Branch(_) | Bytecode::Nop => (),
+            Bytecode::Call(index) => {
+                let callee = self.function_at(index.0)?;
+                types.pop_n(callee.params.len())?;
+                for token in &callee.returns.0 {
+                    types.push(token.clone());
+                }
+            }
+            Bytecode::CallGeneric(index) => {
+                let (_, params, returns) = self.instantiation_at(*index)?;
+                types.pop_n(params.len())?;
+                for token in returns {
+                    types.push(token);
+                }
+            }
+            Bytecode::Pack(index) => {
+                let s = self.struct_at(*index)?;
+                types.pop_n(s.fields.len())?;
+                types.push(s.handle.clone());
+            }
+            Bytecode::Unpack(index) => {
+                types.pop()?;
+                for token in &self.struct_at(*index)?.fields {
+                    types.push(token.clone());
+                }
+            }
+            Bytecode::VecImmBorrow(index) => {
+                types.pop_n(2)?;
+                let element = self.element_type(*index)?.clone();
+                types.push(SignatureToken::Reference(Box::new(element)));
+            }
+            Bytecode::VecMutBorrow(index) => {
+                types.pop_n(2)?;
+                let element = self.element_type(*index)?.clone();
+                types.push(SignatureToken::MutableReference(Box::new(element)));
+            }
+            Bytecode::ImmBorrowLoc(index) => {
+                let local = function.local_type(*index)?.clone();
+                types.push(SignatureToken::Reference(Box::new(local)));
+            }
+            Bytecode::MutBorrowLoc(index) => {
+                let local = function.local_type(*index)?.clone();
+                types.push(SignatureToken::MutableReference(Box::new(local)));
+            }
+            Bytecode::ReadRef => match types.pop()? {
+                SignatureToken::Reference(inner) | SignatureToken::MutableReference(inner) => {
+                    types.push(*inner)
+                }
+                token => return Err(Error::msg(format!("ReadRef of a value of type {token:?}"))),
+            },
+            Bytecode::WriteRef => {
+                types.pop_n(2)?;
+            }
+            Bytecode::FreezeRef => match types.pop()? {
+                SignatureToken::MutableReference(inner) => {
+                    types.push(SignatureToken::Reference(inner))
+                }
+                token => {
+                    return Err(Error::msg(format!(
+                        "FreezeRef of a value of type {token:?}"
+                    )))
+                }
+            },
+            _ => {
+                return Err(diagnostics::UNSUPPORTED_OPCODE
+                    .error(format!("Type tracking is not implemented for {bytecode:?}")))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn cast(types: &mut TypeStack, to: SignatureToken) -> anyhow::Result<()> {
+    types.pop()?;
+    types.push(to);
+    Ok(())
+}
+
+impl Function {
+    /// Type of the local at `index`, where parameters come before the declared locals.
+    fn local_type(&self, index: u8) -> anyhow::Result<&SignatureToken> {
+        self.params
+            .0
+            .iter()
+            .chain(self.locals.0.iter())
+            .nth(index as usize)
+            .ok_or_else(|| Error::msg("Missing local index"))
+    }
 }
 
 fn compile_function(
+    analysis: &FunctionAnalysis<'_>,
+    func_def: &FunctionDefinition,
+    state: &CompilerState,
+) -> anyhow::Result<ProcedureAst> {
+    let mut procedure = lower_function(analysis, func_def, state)?;
+    procedure.body = schedule::optimize(&procedure.body, &*state.cost_model);
+    Ok(procedure)
+}
+
+/// Lowers a function on its own, before any optimization.
+fn lower_function(
+    analysis: &FunctionAnalysis<'_>,
     func_def: &FunctionDefinition,
     state: &CompilerState,
 ) -> anyhow::Result<ProcedureAst> {
@@ -76,28 +1673,70 @@ fn compile_function(
         .functions
         .get(func_def.function.0 as usize)
         .ok_or_else(|| Error::msg("Missing function handle index"))?;
-    let code = match &func_def.code {
-        Some(code) => code,
-        None => return empty_proc(function.name.clone()),
+    // Exported procedures can be called by any Miden code, ignoring friend lists
+    if function.attributes.export && func_def.visibility == Visibility::Friend {
+        return Err(diagnostics::VISIBILITY.error(format!(
+            "{} is public(friend) and can't be exported, as that would make it callable \
+             from any module",
+            function.name
+        )));
+    }
+    let Some(cfg) = &analysis.cfg else {
+        return empty_proc(function.procedure_name.clone());
+    };
+    // Longer loops stay `while` loops, since `repeat` blocks are unrolled
+    let loops: BTreeMap<Label, u32> = analysis
+        .counted_loops
+        .iter()
+        .filter(|(_, times)| **times as u64 <= state.cost_model.max_repeat())
+        .map(|(header, times)| (*header, *times))
+        .collect();
+    let lowering = Lowering {
+        loops,
+        overflow_checks: &analysis.overflow_checks,
     };
-    let _locals = &function.locals;
-    let cfg = Cfg::new(&code.code)?;
-    let body = compile_with_cfg(&cfg, state, Label::Entry, Label::Exit)?;
+    let mut nodes = function.frame.prologue(function.params.len())?;
+    let body = compile_with_cfg(cfg, &lowering, state, function, Label::Entry, Label::Exit)?;
+    nodes.extend_from_slice(body.nodes());
     let result = ProcedureAst {
-        name: function.name.as_str().try_into().map_err(Error::msg)?,
+        name: function
+            .procedure_name
+            .as_str()
+            .try_into()
+            .map_err(Error::msg)?,
         docs: None,
-        num_locals: 0, // TODO: use `locals` from function definition
-        body,
-        start: SourceLocation::default(),
-        is_export: false,
+        num_locals: function.frame.num_locals(),
+        body: CodeBody::new(nodes),
+        start: function.start,
+        is_export: function.attributes.export,
     };
     Ok(result)
 }
 
+/// Offset of the first bytecode of the block with the given label.
+fn offset(label: &Label) -> usize {
+    match label {
+        Label::Entry => 0,
+        Label::Point(x) => *x,
+        // The exit block is always empty
+        Label::Exit => 0,
+    }
+}
+
+/// Results of the analysis of a function used while lowering its code.
+struct Lowering<'a> {
+    // Loops compiled to `repeat` blocks, by header.
+    loops: BTreeMap<Label, u32>,
+    // Arithmetic instructions whose result must be checked (see `ranges` module).
+    overflow_checks: &'a BTreeSet<CodeOffset>,
+}
+
 // TODO: rewrite without recursion
 fn compile_with_cfg(
     cfg: &Cfg<'_>,
+    lowering: &Lowering<'_>,
     state: &CompilerState,
+    function: &Function,
     current_label: Label,
     target_label: Label,
 ) -> anyhow::Result<CodeBody> {
@@ -105,99 +1744,695 @@ fn compile_with_cfg(
     if current_label == target_label {
         return Ok(CodeBody::new(nodes));
     }
+    // Counted loops don't need to evaluate their condition (see `loops` module)
+    if let (Some(times), OutgoingEdge::WhileTrue { body_start, after }) = (
+        lowering.loops.get(&current_label),
+        cfg.edge(&current_label)?,
+    ) {
+        if *times > 0 {
+            let body = compile_with_cfg(cfg, lowering, state, function, *body_start, target_label)?;
+            nodes.push(Node::Repeat {
+                times: *times,
+                body,
+            });
+        }
+        let remainder = compile_with_cfg(cfg, lowering, state, function, *after, target_label)?;
+        nodes.extend_from_slice(remainder.nodes());
+        return Ok(CodeBody::new(nodes));
+    }
+    // Rotated loops run their body once before checking the condition at its end,
+    // unless this is the body being compiled
+    match cfg.rotated_loop_latch(&current_label) {
+        Some(latch) if latch != target_label => {
+            let mut body = compile_with_cfg(cfg, lowering, state, function, current_label, latch)?
+                .nodes()
+                .to_vec();
+            let condition = cfg.block(&latch)?;
+            compile_body(
+                condition,
+                offset(&latch),
+                lowering,
+                state,
+                function,
+                &mut body,
+            )?;
+            let after = match cfg.edge(&latch)? {
+                OutgoingEdge::DoWhileTrue { after, .. } => after,
+                OutgoingEdge::DoWhileFalse { after, .. } => {
+                    body.push(Node::Instruction(Instruction::Not));
+                    after
+                }
+                _ => return Err(Error::msg("Invalid rotated loop")),
+            };
+            nodes.push(Node::Instruction(Instruction::PushU32(1)));
+            nodes.push(Node::While {
+                body: CodeBody::new(body),
+            });
+            let remainder = compile_with_cfg(cfg, lowering, state, function, *after, target_label)?;
+            nodes.extend_from_slice(remainder.nodes());
+            return Ok(CodeBody::new(nodes));
+        }
+        _ => (),
+    }
     let body = cfg.block(&current_label)?;
-    compile_body(body, state, &mut nodes)?;
+    compile_body(
+        body,
+        offset(&current_label),
+        lowering,
+        state,
+        function,
+        &mut nodes,
+    )?;
     match cfg.edge(&current_label)? {
         OutgoingEdge::Pass { next } => {
-            let next = compile_with_cfg(cfg, state, *next, target_label)?;
+            let next = compile_with_cfg(cfg, lowering, state, function, *next, target_label)?;
             nodes.extend_from_slice(next.nodes());
         }
         OutgoingEdge::If {
             true_case,
             false_case,
         } => {
-            let new_target = crate::cfg::first_common_ancestor(cfg.edges(), true_case, false_case);
-            let true_case = compile_with_cfg(cfg, state, *true_case, new_target)?;
-            let false_case = compile_with_cfg(cfg, state, *false_case, new_target)?;
+            let new_target = cfg.first_common_ancestor(true_case, false_case);
+            let true_case =
+                compile_with_cfg(cfg, lowering, state, function, *true_case, new_target)?;
+            let false_case =
+                compile_with_cfg(cfg, lowering, state, function, *false_case, new_target)?;
             nodes.push(Node::IfElse {
                 true_case,
                 false_case,
             });
+            // Continue after the branches join, unless they leave the current region
+            // (e.g. a `break` joining after the loop)
+            if new_target < target_label {
+                let join =
+                    compile_with_cfg(cfg, lowering, state, function, new_target, target_label)?;
+                nodes.extend_from_slice(join.nodes());
+            }
         }
+        OutgoingEdge::LoopBack { header } if lowering.loops.contains_key(header) => (),
         OutgoingEdge::LoopBack { header } => {
-            let body = cfg.block(header)?;
-            compile_body(body, state, &mut nodes)?;
-            if let OutgoingEdge::WhileFalse { .. } = cfg.edge(header)? {
+            // Evaluate the loop condition again, which may span several blocks
+            let condition = cfg.loop_condition(header);
+            let leading = compile_with_cfg(cfg, lowering, state, function, *header, condition)?;
+            nodes.extend_from_slice(leading.nodes());
+            let body = cfg.block(&condition)?;
+            compile_body(
+                body,
+                offset(&condition),
+                lowering,
+                state,
+                function,
+                &mut nodes,
+            )?;
+            if let OutgoingEdge::WhileFalse { .. } = cfg.edge(&condition)? {
                 nodes.push(Node::Instruction(Instruction::Not));
             }
         }
         OutgoingEdge::WhileTrue { body_start, after } => {
-            let body = compile_with_cfg(cfg, state, *body_start, target_label)?;
+            let body = compile_with_cfg(cfg, lowering, state, function, *body_start, target_label)?;
             nodes.push(Node::While { body });
-            let remainder = compile_with_cfg(cfg, state, *after, target_label)?;
+            let remainder = compile_with_cfg(cfg, lowering, state, function, *after, target_label)?;
             nodes.extend_from_slice(remainder.nodes());
         }
         OutgoingEdge::WhileFalse { body_start, after } => {
             nodes.push(Node::Instruction(Instruction::Not));
-            let body = compile_with_cfg(cfg, state, *body_start, target_label)?;
+            let body = compile_with_cfg(cfg, lowering, state, function, *body_start, target_label)?;
             nodes.push(Node::While { body });
-            let remainder = compile_with_cfg(cfg, state, *after, target_label)?;
+            let remainder = compile_with_cfg(cfg, lowering, state, function, *after, target_label)?;
             nodes.extend_from_slice(remainder.nodes());
         }
+        // Compiled with the header of the loop
+        OutgoingEdge::DoWhileTrue { .. } | OutgoingEdge::DoWhileFalse { .. } => (),
     };
     Ok(CodeBody::new(nodes))
 }
 
 fn compile_body(
     bytecode: &[Bytecode],
+    start: usize,
+    lowering: &Lowering<'_>,
     state: &CompilerState,
+    function: &Function,
     result: &mut Vec<Node>,
 ) -> anyhow::Result<()> {
-    for c in bytecode {
-        let node = match c {
-            Bytecode::Add => Node::Instruction(Instruction::Add),
-            Bytecode::Sub => Node::Instruction(Instruction::Sub),
-            Bytecode::Mul => Node::Instruction(Instruction::Mul),
-            Bytecode::Div => Node::Instruction(Instruction::U32Div),
-            Bytecode::Mod => Node::Instruction(Instruction::U32Mod),
-            Bytecode::LdU32(x) => Node::Instruction(Instruction::PushU32(*x)),
-            Bytecode::LdU64(x) => {
-                let x = *x;
-                if x <= u32::MAX as u64 {
-                    Node::Instruction(Instruction::PushU32(x as u32))
-                } else {
-                    // TODO: handle u64 numbers
-                    anyhow::bail!("Can't handle u64 numbers yet");
-                }
-            }
-            Bytecode::Eq => Node::Instruction(Instruction::Eq),
-            Bytecode::Pop => Node::Instruction(Instruction::Drop), // TODO: type validation
-            Bytecode::MoveLoc(_) => continue,                      // TODO: properly handle locals
-            Bytecode::Ret => continue, // TODO: properly handle function return
-            Bytecode::Abort => {
-                // TODO: figure out how to use error code
-                result.push(Node::Instruction(Instruction::Drop));
-                result.push(Node::Instruction(Instruction::PushU32(1)));
-                result.push(Node::Instruction(Instruction::Assertz));
+    let def_index = function
+        .definition
+        .ok_or_else(|| Error::msg("Missing function definition"))?;
+    let mut types = TypeStack::default();
+    let mut i = 0;
+    while i < bytecode.len() {
+        let location = || CodeLocation {
+            function: def_index,
+            offset: Some((start + i) as CodeOffset),
+        };
+        // Parameters moved in order on entry are already on the stack (see `Frame`)
+        if start + i < function.frame.moved_params {
+            state
+                .update_types(&mut types, &bytecode[i], function)
+                .with_context(location)?;
+            i += 1;
+            continue;
+        }
+        // Storing a value into a local and moving it right back out leaves the stack
+        // unchanged, so the value stays on the stack instead of going through the local.
+        if let [store @ Bytecode::StLoc(x), load @ Bytecode::MoveLoc(y), ..] = &bytecode[i..] {
+            if x == y {
+                state
+                    .update_types(&mut types, store, function)
+                    .with_context(location)?;
+                state
+                    .update_types(&mut types, load, function)
+                    .with_context(location)?;
+                i += 2;
                 continue;
             }
-            Bytecode::Call(index) => {
-                let _name = &state
-                    .functions
-                    .get(index.0 as usize)
-                    .ok_or_else(|| Error::msg("Missing function handle index"))?
-                    .name;
-                // TODO: use the name to figure out what to call.
-                Node::Instruction(Instruction::ExecLocal(index.0))
+        }
+        // Kernel intrinsics taking a constant u32 and byte vector as last arguments
+        if let [Bytecode::LdU32(x), Bytecode::LdConst(c), Bytecode::Call(f), ..] = &bytecode[i..] {
+            if let Some(nodes) = fold_kernel_call(*x, c.0, f.0, state).with_context(location)? {
+                for code in &bytecode[i..i + 3] {
+                    state
+                        .update_types(&mut types, code, function)
+                        .with_context(location)?;
+                }
+                result.extend(nodes);
+                i += 3;
+                continue;
             }
-            Bytecode::BrFalse(_) | Bytecode::BrTrue(_) | Bytecode::Branch(_) => {
-                unreachable!("Control flow handled by CFG");
+        }
+        if let [Bytecode::LdConst(constant), call @ Bytecode::Call(callee), ..] = &bytecode[i..] {
+            if let Some(nodes) = fold_call(constant.0, callee.0, state).with_context(location)? {
+                state
+                    .update_types(&mut types, &bytecode[i], function)
+                    .with_context(location)?;
+                state
+                    .update_types(&mut types, call, function)
+                    .with_context(location)?;
+                result.extend(nodes);
+                i += 2;
+                continue;
             }
-            // TODO: other bytecodes
-            _ => anyhow::bail!("Unimplemented opcode {c:?}"),
+        }
+        // Aborts with a constant code fail with that code (see `error_map` module)
+        if let [load, abort @ Bytecode::Abort, ..] = &bytecode[i..] {
+            if let Some(nodes) = abort_with_code(load, state) {
+                state
+                    .update_types(&mut types, load, function)
+                    .with_context(location)?;
+                state
+                    .update_types(&mut types, abort, function)
+                    .with_context(location)?;
+                result.extend(nodes);
+                i += 2;
+                continue;
+            }
+        }
+        // Arithmetic on u8, u16 and u32 values which may overflow is checked, as Move
+        // aborts on overflow
+        if lowering
+            .overflow_checks
+            .contains(&((start + i) as CodeOffset))
+        {
+            let checked = types
+                .peek()
+                .and_then(|top| integers::checked_op(top, &bytecode[i], &state.error_codes));
+            if let Some(nodes) = checked {
+                state
+                    .update_types(&mut types, &bytecode[i], function)
+                    .with_context(location)?;
+                result.extend(nodes);
+                i += 1;
+                continue;
+            }
+        }
+        if let Some((key, length)) = constant_call(&bytecode[i..]) {
+            if let Some(address) = state.memoized.get(&key) {
+                let mut call = Vec::new();
+                for c in &bytecode[i..i + length] {
+                    compile_bytecode(c, &mut types, state, function, &mut call)
+                        .with_context(location)?;
+                }
+                result.extend(memoize(call, *address));
+                i += length;
+                continue;
+            }
+        }
+        let length = result.len();
+        let previous_types = (state.opcode_policy == OpcodePolicy::Skip).then(|| types.clone());
+        if let Err(error) = compile_bytecode(&bytecode[i], &mut types, state, function, result) {
+            match previous_types {
+                Some(previous)
+                    if error.downcast_ref::<ErrorCode>()
+                        == Some(&diagnostics::UNSUPPORTED_OPCODE) =>
+                {
+                    types = previous;
+                    result.truncate(length);
+                    state
+                        .skipped_opcodes
+                        .lock()
+                        .map_err(|_| Error::msg("Skipped opcodes lock poisoned"))?
+                        .push(location());
+                }
+                _ => return Err(error.context(location())),
+            }
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Function handle and arguments of the call made by the start of `bytecode`
+/// if all its arguments are constants, along with the number of instructions
+/// of the call.
+fn constant_call(bytecode: &[Bytecode]) -> Option<((u16, Vec<u64>), usize)> {
+    let mut args = Vec::new();
+    for (length, c) in bytecode.iter().enumerate() {
+        let arg = match c {
+            Bytecode::LdU8(x) => *x as u64,
+            Bytecode::LdU16(x) => *x as u64,
+            Bytecode::LdU32(x) => *x as u64,
+            Bytecode::LdU64(x) => *x,
+            Bytecode::LdTrue => 1,
+            Bytecode::LdFalse => 0,
+            Bytecode::Call(index) => return Some(((index.0, args), length + 1)),
+            _ => return None,
         };
-        result.push(node);
+        args.push(arg);
     }
+    None
+}
+
+/// Runs `call` only the first time, keeping its result at `address` for later runs.
+/// The address before it holds 1 once the result is stored.
+fn memoize(call: Vec<Node>, address: u32) -> Vec<Node> {
+    let mut compute = call;
+    compute.extend([
+        Node::Instruction(Instruction::Dup0),
+        Node::Instruction(Instruction::MemStoreImm(address)),
+        Node::Instruction(Instruction::PushU32(1)),
+        Node::Instruction(Instruction::MemStoreImm(address - 1)),
+    ]);
+    vec![
+        Node::Instruction(Instruction::MemLoadImm(address - 1)),
+        Node::IfElse {
+            true_case: CodeBody::new(vec![Node::Instruction(Instruction::MemLoadImm(address))]),
+            false_case: CodeBody::new(compute),
+        },
+    ]
+}
+
+/// Lowers a call to a foreign function, whose first parameter is the ID of the
+/// account providing its procedure (see `fpi` module).
+fn foreign_call(callee: &Function, state: &CompilerState) -> anyhow::Result<Vec<Node>> {
+    let name = format!("{}::{}", callee.module_name, callee.name);
+    let Some((SignatureToken::Address, params)) = callee.params.0.split_first() else {
+        return Err(diagnostics::UNSUPPORTED_NATIVE.error(format!(
+            "Foreign function {name} must take the ID of the account as first parameter, \
+             of type address"
+        )));
+    };
+    let mut input_width = 0;
+    for param in params {
+        input_width += types::stack_width(param)?;
+    }
+    let mut output_width = 0;
+    for value in &callee.returns.0 {
+        output_width += types::stack_width(value)?;
+    }
+    let root = state.foreign_roots.get(&name).ok_or_else(|| {
+        diagnostics::UNSUPPORTED_NATIVE.error(format!(
+            "No MAST root is configured for foreign function {name}, so the procedure it \
+             calls isn't known"
+        ))
+    })?;
+    fpi::call(&name, input_width, output_width, root)
+}
+
+/// Code calling an intrinsic with a constant byte vector, generated at compile time.
+fn fold_call(
+    constant: u16,
+    callee: u16,
+    state: &CompilerState,
+) -> anyhow::Result<Option<Vec<Node>>> {
+    let Some(intrinsic) = native_intrinsic(callee, state)? else {
+        return Ok(None);
+    };
+    constant_bytes(constant, state)?
+        .and_then(|bytes| intrinsic.fold(&bytes))
+        .transpose()
+}
+
+/// Abort code loaded by `load`, if it is a constant.
+fn constant_abort_code(load: &Bytecode, state: &CompilerState) -> Option<u64> {
+    match load {
+        Bytecode::LdU64(code) => Some(*code),
+        Bytecode::LdConst(index) => match state.constant_values.get(index.0 as usize)? {
+            ConstantValue::U64(code) => Some(*code),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Code aborting with the abort code loaded by `load`, if it is a constant
+/// fitting in a Miden error code.
+fn abort_with_code(load: &Bytecode, state: &CompilerState) -> Option<Vec<Node>> {
+    let code = u32::try_from(constant_abort_code(load, state)?).ok()?;
+    Some(vec![
+        Node::Instruction(Instruction::PushU32(1)),
+        Node::Instruction(Instruction::AssertzWithError(code)),
+    ])
+}
+
+/// Code calling a kernel intrinsic whose last two arguments, a u32 and a
+/// byte vector, are constants (see `intrinsics::Lowering::KernelFold`).
+fn fold_kernel_call(
+    immediate: u32,
+    constant: u16,
+    callee: u16,
+    state: &CompilerState,
+) -> anyhow::Result<Option<Vec<Node>>> {
+    let Some(intrinsic) = native_intrinsic(callee, state)? else {
+        return Ok(None);
+    };
+    constant_bytes(constant, state)?
+        .and_then(|bytes| intrinsic.fold_kernel(immediate, &bytes, &state.error_codes))
+        .transpose()
+}
+
+/// Intrinsic of the callee, if it is a native function with one in the target context.
+fn native_intrinsic(
+    callee: u16,
+    state: &CompilerState,
+) -> anyhow::Result<Option<&'static intrinsics::Intrinsic>> {
+    let callee = state.function_at(callee)?;
+    if callee.definition.is_some() && !callee.is_native {
+        return Ok(None);
+    }
+    Ok(intrinsics::find(
+        &callee.module_name,
+        &callee.name,
+        state.target,
+    ))
+}
+
+/// Value of the constant, if it is a byte vector.
+fn constant_bytes(constant: u16, state: &CompilerState) -> anyhow::Result<Option<Vec<u8>>> {
+    let constant = state
+        .constants
+        .get(constant as usize)
+        .ok_or_else(|| Error::msg("Missing constant index"))?;
+    Ok(match constant.deserialize_constant() {
+        Some(MoveValue::Vector(elements)) => elements
+            .iter()
+            .map(|e| match e {
+                MoveValue::U8(b) => Some(*b),
+                _ => None,
+            })
+            .collect::<Option<Vec<u8>>>(),
+        _ => None,
+    })
+}
+
+fn compile_bytecode(
+    c: &Bytecode,
+    types: &mut TypeStack,
+    state: &CompilerState,
+    function: &Function,
+    result: &mut Vec<Node>,
+) -> anyhow::Result<()> {
+    let top = types.peek().cloned();
+    state.update_types(types, c, function)?;
+    if matches!(c, Bytecode::Div | Bytecode::Mod) {
+        let divisor = top
+            .as_ref()
+            .ok_or_else(|| Error::msg("Division of an empty stack"))?;
+        // Constant divisors are pushed right before the division
+        let nonzero_constant = types::stack_width(divisor)? == 1
+            && matches!(result.last(), Some(Node::Instruction(Instruction::PushU32(x))) if *x != 0);
+        if !nonzero_constant {
+            result.extend(integers::check_divisor(divisor, &state.error_codes)?);
+        }
+    }
+    // The shift amount is on top, so the shifted value has the type of the result
+    if let Some(value) = types.peek() {
+        if let Some(nodes) = integers::shift(value, c, &state.error_codes)? {
+            result.extend(nodes);
+            return Ok(());
+        }
+    }
+    if top == Some(SignatureToken::U64) {
+        if let Some(nodes) = integers::u64_binary_op(c, &state.error_codes)? {
+            result.extend(nodes);
+            return Ok(());
+        }
+    }
+    if let Some(top) = &top {
+        if let Some(nodes) = integers::wide_binary_op(top, c, state.helpers, &state.error_codes) {
+            result.extend(nodes);
+            return Ok(());
+        }
+        if let Some(nodes) = integers::bitwise_op(top, c)? {
+            result.extend(nodes);
+            return Ok(());
+        }
+    }
+    let node = match c {
+        Bytecode::Add => Node::Instruction(Instruction::Add),
+        Bytecode::Sub => Node::Instruction(Instruction::Sub),
+        Bytecode::Mul => Node::Instruction(Instruction::Mul),
+        Bytecode::Div => Node::Instruction(Instruction::U32Div),
+        Bytecode::Mod => Node::Instruction(Instruction::U32Mod),
+        Bytecode::LdU8(x) => Node::Instruction(Instruction::PushU32(*x as u32)),
+        Bytecode::LdU16(x) => Node::Instruction(Instruction::PushU32(*x as u32)),
+        Bytecode::LdU32(x) => Node::Instruction(Instruction::PushU32(*x)),
+        // Bools are 0 or 1, as conditional branches and loops require
+        Bytecode::LdTrue => Node::Instruction(Instruction::PushU32(1)),
+        Bytecode::LdFalse => Node::Instruction(Instruction::PushU32(0)),
+        Bytecode::LdU64(x) => {
+            result.extend(integers::push_u64(*x));
+            return Ok(());
+        }
+        Bytecode::LdU128(x) => {
+            result.extend(integers::push_u128(*x));
+            return Ok(());
+        }
+        Bytecode::LdU256(x) => {
+            result.extend(integers::push_u256(x));
+            return Ok(());
+        }
+        Bytecode::LdConst(index) => {
+            let value = state
+                .constant_values
+                .get(index.0 as usize)
+                .ok_or_else(|| Error::msg("Missing constant index"))?;
+            match value {
+                ConstantValue::Scalar(x) => Node::Instruction(Instruction::PushU32(*x)),
+                ConstantValue::U64(x) => {
+                    result.extend(integers::push_u64(*x));
+                    return Ok(());
+                }
+                ConstantValue::U128(x) => {
+                    result.extend(integers::push_u128(*x));
+                    return Ok(());
+                }
+                ConstantValue::U256(x) => {
+                    result.extend(integers::push_u256(x));
+                    return Ok(());
+                }
+                ConstantValue::Address(element) => {
+                    Node::Instruction(Instruction::PushFelt(*element))
+                }
+                ConstantValue::Data { address } => {
+                    Node::Instruction(Instruction::PushU32(*address))
+                }
+                ConstantValue::Unsupported(reason) => {
+                    return Err(diagnostics::UNSUPPORTED_TYPE.error(reason))
+                }
+            }
+        }
+        // Values narrower than u64 fit in a single element, and integers among
+        // them are u32s, so they're ordered by the cheaper u32 comparisons
+        Bytecode::Eq => Node::Instruction(Instruction::Eq),
+        Bytecode::Neq => Node::Instruction(Instruction::Neq),
+        Bytecode::Lt => Node::Instruction(Instruction::U32Lt),
+        Bytecode::Gt => Node::Instruction(Instruction::U32Gt),
+        Bytecode::Le => Node::Instruction(Instruction::U32Lte),
+        Bytecode::Ge => Node::Instruction(Instruction::U32Gte),
+        // Bools are 0 or 1, as the boolean instructions also require. A negated loop
+        // condition negated again for `while` (see `compile_with_cfg`) is left out
+        // by scheduling.
+        Bytecode::Not => Node::Instruction(Instruction::Not),
+        Bytecode::And => Node::Instruction(Instruction::And),
+        Bytecode::Or => Node::Instruction(Instruction::Or),
+        Bytecode::CastU8
+        | Bytecode::CastU16
+        | Bytecode::CastU32
+        | Bytecode::CastU64
+        | Bytecode::CastU128
+        | Bytecode::CastU256 => {
+            let from = top.ok_or_else(|| Error::msg("Cast of an empty stack"))?;
+            let to = types
+                .peek()
+                .ok_or_else(|| Error::msg("Missing cast result type"))?;
+            result.extend(integers::cast(&from, to, &state.error_codes)?);
+            return Ok(());
+        }
+        Bytecode::Pop => {
+            // Memory behind popped aggregates is not reclaimed by the bump
+            // allocator. Wide integers are dropped a word at a time.
+            let top = top.ok_or_else(|| Error::msg("Pop from an empty stack"))?;
+            let width = types::stack_width(&top)?;
+            for _ in 0..width / 4 {
+                result.push(Node::Instruction(Instruction::DropW));
+            }
+            for _ in 0..width % 4 {
+                result.push(Node::Instruction(Instruction::Drop));
+            }
+            return Ok(());
+        }
+        // Both kinds of references are the same address
+        Bytecode::Nop | Bytecode::FreezeRef => return Ok(()),
+        Bytecode::MoveLoc(index) | Bytecode::CopyLoc(index) => {
+            result.extend(function.frame.load(*index)?);
+            return Ok(());
+        }
+        Bytecode::StLoc(index) => {
+            result.extend(function.frame.store(*index)?);
+            return Ok(());
+        }
+        Bytecode::Ret => return Ok(()), // TODO: properly handle function return
+        Bytecode::Abort => {
+            // Error codes are immediates, so codes only known at runtime are lost.
+            // The code is a u64, taking two elements
+            result.push(Node::Instruction(Instruction::Drop));
+            result.push(Node::Instruction(Instruction::Drop));
+            result.push(Node::Instruction(Instruction::PushU32(1)));
+            result.push(Node::Instruction(Instruction::Assertz));
+            return Ok(());
+        }
+        Bytecode::Call(index) => {
+            let callee = state.function_at(index.0)?;
+            match callee.definition {
+                Some(def_index) if !callee.is_native => {
+                    Node::Instruction(Instruction::ExecLocal(def_index.0))
+                }
+                None if callee.is_external => {
+                    return Err(diagnostics::CROSS_MODULE_CALL.error(format!(
+                        "Calls to functions of other modules are not supported yet: {}::{}",
+                        callee.module_name, callee.name
+                    )))
+                }
+                _ => {
+                    if callee.attributes.foreign {
+                        result.extend(foreign_call(callee, state)?);
+                        return Ok(());
+                    }
+                    if let Some(path) = &callee.attributes.intrinsic {
+                        if let Some(message) = intrinsics::bound_context_error(path, state.target) {
+                            return Err(diagnostics::TARGET_CONTEXT.error(message));
+                        }
+                        result.extend(intrinsics::bind(path)?);
+                        return Ok(());
+                    }
+                    let intrinsic =
+                        intrinsics::lookup(&callee.module_name, &callee.name, state.target)?;
+                    result.extend(intrinsic.lower(
+                        &state.memory,
+                        state.canaries,
+                        &state.error_codes,
+                    )?);
+                    return Ok(());
+                }
+            }
+        }
+        // Only native functions which drop their arguments can be generic, as
+        // code isn't generated for every instantiation of a function.
+        Bytecode::CallGeneric(index) => {
+            let (callee, params, _) = state.instantiation_at(*index)?;
+            let unsupported = || {
+                diagnostics::UNSUPPORTED_OPCODE.error(format!(
+                    "Calls to generic function {}::{} are not supported",
+                    callee.module_name, callee.name
+                ))
+            };
+            if (callee.definition.is_some() && !callee.is_native)
+                || callee.is_external
+                || callee.attributes.foreign
+                || callee.attributes.intrinsic.is_some()
+            {
+                return Err(unsupported());
+            }
+            let intrinsic = intrinsics::lookup(&callee.module_name, &callee.name, state.target)?;
+            let width = params
+                .iter()
+                .map(types::stack_width)
+                .sum::<anyhow::Result<usize>>()?;
+            result.extend(intrinsic.discard(width).ok_or_else(unsupported)?);
+            return Ok(());
+        }
+        Bytecode::Pack(index) => {
+            let width = state.struct_at(*index)?.width()?;
+            result.extend(memory::pack(width, &state.memory, state.canaries));
+            return Ok(());
+        }
+        Bytecode::Unpack(index) => {
+            result.extend(memory::unpack(state.struct_at(*index)?.width()?));
+            return Ok(());
+        }
+        Bytecode::VecImmBorrow(index) | Bytecode::VecMutBorrow(index) => {
+            let element = state.element_type(*index)?;
+            result.extend(memory::borrow_element(
+                types::is_aggregate(element),
+                &state.error_codes,
+            ));
+            return Ok(());
+        }
+        Bytecode::ImmBorrowLoc(index) | Bytecode::MutBorrowLoc(index) => {
+            // A reference to an aggregate is its pointer
+            if types::is_aggregate(function.local_type(*index)?) {
+                result.extend(function.frame.load(*index)?);
+                return Ok(());
+            }
+            function.frame.borrow(*index)?
+        }
+        Bytecode::ReadRef => {
+            match top {
+                Some(
+                    SignatureToken::Reference(inner) | SignatureToken::MutableReference(inner),
+                ) if !types::is_aggregate(&inner) => match types::stack_width(&inner)? {
+                    1 => Node::Instruction(Instruction::MemLoad),
+                    width => {
+                        result.extend(memory::unpack(width as u32));
+                        return Ok(());
+                    }
+                },
+                _ => {
+                    return Err(diagnostics::UNSUPPORTED_TYPE
+                        .error("Can't read references to aggregates yet"))
+                }
+            }
+        }
+        Bytecode::WriteRef => match top {
+            Some(SignatureToken::MutableReference(inner)) if !types::is_aggregate(&inner) => {
+                result.extend(memory::store(types::stack_width(&inner)? as u32));
+                Node::Instruction(Instruction::Drop)
+            }
+            _ => {
+                return Err(
+                    diagnostics::UNSUPPORTED_TYPE.error("Can't write references to aggregates yet")
+                )
+            }
+        },
+        Bytecode::BrFalse(_) | Bytecode::BrTrue(_) | Bytecode::Branch(_) => {
+            unreachable!("Control flow handled by CFG");
+        }
+        // TODO: other bytecodes
+        _ => {
+            return Err(diagnostics::UNSUPPORTED_OPCODE.error(format!("Unimplemented opcode {c:?}")))
+        }
+    };
+    result.push(node);
     Ok(())
 }
 
@@ -0,0 +1,268 @@
+//! Module for estimating the number of cycles needed to execute generated Miden code.
+//!
+//! Estimates are driven by a `CostModel`, which is also consulted by optimizations
+//! that trade code size for speed (e.g. compiling loops to `repeat` blocks or
+//! rewriting stack manipulation). `DefaultCostModel` approximates the Miden VM;
+//! `MeasuredCostModel` lets users plug in cycle counts measured on their own setup.
+
+use {
+    anyhow::Context,
+    miden_assembly::ast::{CodeBody, Instruction, Node, ProcedureAst},
+    std::{collections::BTreeMap, fmt},
+};
+
+pub trait CostModel: fmt::Debug + Send + Sync {
+    /// Cycles needed to execute `instruction`. Calls only count the call itself,
+    /// not the called procedure.
+    fn instruction(&self, instruction: &Instruction) -> u64;
+
+    /// Largest number of iterations of a loop compiled to a `repeat` block,
+    /// which is unrolled when the program is assembled.
+    fn max_repeat(&self) -> u64 {
+        256
+    }
+
+    /// Largest estimated cost of a procedure whose calls are inlined
+    /// without an explicit hint.
+    fn max_inline_cycles(&self) -> u64 {
+        20
+    }
+
+    /// Settings of the model, in the format read by `MeasuredCostModel::from_json`,
+    /// so the options a program was compiled with can be recorded.
+    fn parameters(&self) -> BTreeMap<String, u64> {
+        [
+            ("max_repeat".to_string(), self.max_repeat()),
+            ("max_inline_cycles".to_string(), self.max_inline_cycles()),
+        ]
+        .into_iter()
+        .collect()
+    }
+}
+
+/// Approximate cycle counts of the Miden VM.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultCostModel;
+
+impl CostModel for DefaultCostModel {
+    fn instruction(&self, instruction: &Instruction) -> u64 {
+        match instruction {
+            // Hashing
+            Instruction::HMerge => 16,
+            Instruction::HPerm => 1,
+            Instruction::MTreeGet => 9,
+            Instruction::MTreeSet => 29,
+            Instruction::MTreeVerify => 1,
+            // Memory
+            Instruction::MemLoad | Instruction::MemLoadW => 1,
+            Instruction::MemLoadImm(_) | Instruction::MemLoadWImm(_) => 2,
+            Instruction::MemStore => 2,
+            Instruction::MemStoreImm(_) => 4,
+            Instruction::MemStoreW => 1,
+            Instruction::MemStoreWImm(_) => 2,
+            Instruction::LocLoad(_) => 3,
+            Instruction::LocStore(_) => 4,
+            Instruction::Locaddr(_) => 2,
+            // Field and u32 arithmetic
+            Instruction::Neq => 2,
+            Instruction::Lt | Instruction::Lte | Instruction::Gt | Instruction::Gte => 15,
+            Instruction::U32Lt | Instruction::U32Gt => 3,
+            Instruction::U32Lte | Instruction::U32Gte => 5,
+            Instruction::U32CheckedAdd | Instruction::U32CheckedSub => 4,
+            Instruction::U32CheckedMul => 4,
+            Instruction::U32Div | Instruction::U32Mod => 3,
+            Instruction::Assertz | Instruction::AssertzWithError(_) | Instruction::AssertEq => 2,
+            // Stack manipulation of whole words
+            Instruction::DropW => 4,
+            Instruction::AdvPush(n) => *n as u64,
+            _ => 1,
+        }
+    }
+}
+
+/// Cycle counts measured by users, keyed by instruction mnemonic (e.g. `mem_store`,
+/// `u32checked_add`), falling back to `DefaultCostModel` for other instructions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MeasuredCostModel {
+    pub cycles: BTreeMap<String, u64>,
+    pub max_repeat: Option<u64>,
+    pub max_inline_cycles: Option<u64>,
+}
+
+impl MeasuredCostModel {
+    /// Parses a JSON object mapping mnemonics to cycles, with optional
+    /// `max_repeat` and `max_inline_cycles` entries.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let mut cycles: BTreeMap<String, u64> =
+            serde_json::from_str(json).context("Invalid cost model")?;
+        let max_repeat = cycles.remove("max_repeat");
+        let max_inline_cycles = cycles.remove("max_inline_cycles");
+        Ok(Self {
+            cycles,
+            max_repeat,
+            max_inline_cycles,
+        })
+    }
+}
+
+impl CostModel for MeasuredCostModel {
+    fn instruction(&self, instruction: &Instruction) -> u64 {
+        let text = instruction.to_string();
+        let mnemonic = text.split('.').next().unwrap_or_default();
+        self.cycles
+            .get(mnemonic)
+            .copied()
+            .unwrap_or_else(|| DefaultCostModel.instruction(instruction))
+    }
+
+    fn max_repeat(&self) -> u64 {
+        self.max_repeat
+            .unwrap_or_else(|| DefaultCostModel.max_repeat())
+    }
+
+    fn max_inline_cycles(&self) -> u64 {
+        self.max_inline_cycles
+            .unwrap_or_else(|| DefaultCostModel.max_inline_cycles())
+    }
+
+    fn parameters(&self) -> BTreeMap<String, u64> {
+        let mut parameters = self.cycles.clone();
+        parameters.insert("max_repeat".into(), self.max_repeat());
+        parameters.insert("max_inline_cycles".into(), self.max_inline_cycles());
+        parameters
+    }
+}
+
+impl Default for Box<dyn CostModel> {
+    fn default() -> Self {
+        Box::new(DefaultCostModel)
+    }
+}
+
+/// Estimated cost of executing a piece of code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cost {
+    pub cycles: u64,
+    // False if the code contains loops, in which case `cycles` only counts one iteration.
+    pub bounded: bool,
+}
+
+impl Cost {
+    /// Cost of running either `self` or `other`.
+    pub fn max(self, other: Self) -> Self {
+        Self {
+            cycles: self.cycles.max(other.cycles),
+            bounded: self.bounded && other.bounded,
+        }
+    }
+
+    /// Cost of running `self` followed by `other`.
+    pub fn then(self, other: Self) -> Self {
+        Self {
+            cycles: self.cycles + other.cycles,
+            bounded: self.bounded && other.bounded,
+        }
+    }
+}
+
+/// Estimates the worst-case cost of executing `body` with the default cost model.
+/// Calls to other procedures only count the call itself.
+pub fn estimate(body: &CodeBody) -> Cost {
+    estimate_with(body, &DefaultCostModel)
+}
+
+/// Estimates the worst-case cost of executing `body` with the given cost model.
+pub fn estimate_with(body: &CodeBody, model: &dyn CostModel) -> Cost {
+    Estimator {
+        model,
+        procedures: &[],
+        calls: Vec::new(),
+    }
+    .body(body)
+}
+
+/// Like `estimate_with`, also counting the cost of the local procedures called,
+/// which are looked up in `procedures`. Recursive calls make the cost unbounded.
+pub fn estimate_with_calls(
+    body: &CodeBody,
+    procedures: &[ProcedureAst],
+    model: &dyn CostModel,
+) -> Cost {
+    Estimator {
+        model,
+        procedures,
+        calls: Vec::new(),
+    }
+    .body(body)
+}
+
+struct Estimator<'a> {
+    model: &'a dyn CostModel,
+    // Procedures whose cost is added to calls to them; empty to only count the calls.
+    procedures: &'a [ProcedureAst],
+    // Procedures being estimated, to detect recursion.
+    calls: Vec<u16>,
+}
+
+impl Estimator<'_> {
+    fn body(&mut self, body: &CodeBody) -> Cost {
+        body.nodes().iter().fold(
+            Cost {
+                cycles: 0,
+                bounded: true,
+            },
+            |acc, node| acc.then(self.node(node)),
+        )
+    }
+
+    fn node(&mut self, node: &Node) -> Cost {
+        match node {
+            Node::Instruction(instruction) => {
+                let cost = Cost {
+                    cycles: self.model.instruction(instruction),
+                    bounded: true,
+                };
+                match instruction {
+                    Instruction::ExecLocal(index) | Instruction::CallLocal(index) => {
+                        cost.then(self.call(*index))
+                    }
+                    _ => cost,
+                }
+            }
+            Node::IfElse {
+                true_case,
+                false_case,
+            } => self.body(true_case).max(self.body(false_case)),
+            Node::Repeat { times, body } => {
+                let body = self.body(body);
+                Cost {
+                    cycles: body.cycles * *times as u64,
+                    bounded: body.bounded,
+                }
+            }
+            Node::While { body } => Cost {
+                bounded: false,
+                ..self.body(body)
+            },
+        }
+    }
+
+    fn call(&mut self, index: u16) -> Cost {
+        let Some(procedure) = self.procedures.get(index as usize) else {
+            return Cost {
+                cycles: 0,
+                bounded: true,
+            };
+        };
+        if self.calls.contains(&index) {
+            return Cost {
+                cycles: 0,
+                bounded: false,
+            };
+        }
+        self.calls.push(index);
+        let cost = self.body(&procedure.body);
+        self.calls.pop();
+        cost
+    }
+}
@@ -0,0 +1,430 @@
+//! Module for reporting problems found while compiling Move code.
+//!
+//! Diagnostics carry the location in the original source (when known) so they
+//! can be rendered with the offending snippet, in the same style as the Move compiler.
+
+use {
+    codespan_reporting::{
+        diagnostic::{Diagnostic as CodespanDiagnostic, Label as CodespanLabel},
+        files::SimpleFiles,
+        term::{self, termcolor::NoColor},
+    },
+    std::{collections::BTreeMap, fmt, ops::Range},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+/// A region of a source file.
+/// `line` and `column` are 1-based and refer to the start of the region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub bytes: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // Code of the problem in the registry (see `ErrorCode`), if it is a known one.
+    pub code: Option<&'static str>,
+    pub message: String,
+    // Primary location of the problem along with a short description.
+    pub primary: Option<(Span, String)>,
+    // Other locations related to the problem.
+    pub secondary: Vec<(Span, String)>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn with_primary(mut self, span: Span, label: impl Into<String>) -> Self {
+        self.primary = Some((span, label.into()));
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Tags the diagnostic with a registered code, pointing to its explanation.
+    pub fn with_code(mut self, code: &ErrorCode) -> Self {
+        self.code = Some(code.code);
+        self.with_note(format!(
+            "{}; run `move2miden explain {}` for details",
+            code.title, code.code
+        ))
+    }
+}
+
+/// A class of problems preventing Move code from being compiled to Miden,
+/// with an explanation of its cause and the known workarounds.
+///
+/// Codes are attached as context to compilation errors (see `ErrorCode::error`),
+/// so they can be recovered with `downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub explanation: &'static str,
+    pub workarounds: &'static [&'static str],
+}
+
+impl ErrorCode {
+    /// Creates an error with the given message, tagged with this code.
+    pub fn error(self, message: impl fmt::Display) -> anyhow::Error {
+        anyhow::Error::msg(message.to_string()).context(self)
+    }
+
+    /// Full description of the problem, as printed by `move2miden explain`.
+    pub fn explain(&self) -> String {
+        let mut text = format!("{}: {}\n\n{}\n", self.code, self.title, self.explanation);
+        if !self.workarounds.is_empty() {
+            text.push_str("\nWorkarounds:\n");
+            for workaround in self.workarounds {
+                text.push_str(&format!("  - {workaround}\n"));
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.title)
+    }
+}
+
+pub const UNSUPPORTED_OPCODE: ErrorCode = ErrorCode {
+    code: "M2M001",
+    title: "Move instruction not supported",
+    explanation: "The function uses a Move bytecode instruction the compiler cannot lower to \
+Miden assembly yet. Generic functions, references, vectors and global storage \
+instructions are not implemented.",
+    workarounds: &[
+        "Replace generic functions with monomorphic copies for the types used.",
+        "Pass values instead of references.",
+        "Keep state in struct values returned from the entry function instead of global storage.",
+    ],
+};
+
+pub const UNSUPPORTED_TYPE: ErrorCode = ErrorCode {
+    code: "M2M002",
+    title: "Type not supported",
+    explanation: "Values of this type cannot be represented on the Miden stack yet. \
+Field elements can't hold every 64 bit value, so u64, u128 and u256 values take two, \
+four and eight u32 limbs; type parameters are not supported, nor are references to \
+these integers or vector constants of them.",
+    workarounds: &[
+        "Use u32 values, or split wider values into several u32 limbs.",
+        "Replace generic type parameters with concrete types.",
+    ],
+};
+
+pub const UNSUPPORTED_NATIVE: ErrorCode = ErrorCode {
+    code: "M2M003",
+    title: "Native function not supported",
+    explanation: "Native functions have no bytecode, so calls to them must be mapped onto \
+Miden code by an intrinsic. This function has no intrinsic, or its intrinsic relies on \
+functionality Miden does not provide.",
+    workarounds: &[
+        "Implement the function in Move.",
+        "Enable the `aptos` feature for Aptos framework functions.",
+    ],
+};
+
+pub const CROSS_MODULE_CALL: ErrorCode = ErrorCode {
+    code: "M2M004",
+    title: "Call to another module",
+    explanation: "Each Move module is compiled to a separate Miden program, so functions \
+defined in other modules cannot be called yet.",
+    workarounds: &["Move the called function into the module being compiled."],
+};
+
+pub const ENTRY_POINT: ErrorCode = ErrorCode {
+    code: "M2M005",
+    title: "Invalid entry point",
+    explanation: "A Miden program has exactly one body, which is compiled from the entry \
+function of the module. The module must declare exactly one `entry` function.",
+    workarounds: &[
+        "Mark the function to run as `entry`.",
+        "Split modules with several entry functions.",
+    ],
+};
+
+pub const ABI_LIMIT: ErrorCode = ErrorCode {
+    code: "M2M006",
+    title: "Entry function signature too wide",
+    explanation: "The Miden output stack holds 16 elements, so the return values of the \
+entry function must fit in 16 field elements. Inputs wider than 16 elements are \
+passed through the advice stack.",
+    workarounds: &["Return fewer values, or a struct pointer to values stored in memory."],
+};
+
+/// All registered error codes.
+pub const CYCLE_BUDGET: ErrorCode = ErrorCode {
+    code: "M2M007",
+    title: "Cycle budget exceeded",
+    explanation: "The function is annotated with `#[miden(max_cycles = N)]`, but the \
+worst-case number of cycles estimated for a call of it, including the functions it calls, \
+is larger than N. Loops whose number of iterations isn't a constant, and recursive calls, \
+can't be bounded, so functions containing them always exceed their budget.",
+    workarounds: &[
+        "Raise the budget if the extra proving cost is acceptable.",
+        "Use loops with constant bounds, so their cost can be estimated.",
+    ],
+};
+
+pub const RESOURCE_LIMIT: ErrorCode = ErrorCode {
+    code: "M2M008",
+    title: "Resource limit exceeded",
+    explanation: "The module was compiled with `compile_untrusted`, which rejects modules \
+whose size, number of functions or generated code exceeds the configured `Limits`, and \
+stops compilation once its time limit is reached.",
+    workarounds: &[
+        "Split the module into smaller modules.",
+        "Raise the limits if the module comes from a trusted source.",
+    ],
+};
+
+pub const VISIBILITY: ErrorCode = ErrorCode {
+    code: "M2M009",
+    title: "Function visibility violated",
+    explanation: "A module of the package calls a function of another module which is not \
+visible to it: private functions can only be called from their own module, and \
+`public(friend)` functions only from the modules listed as friends of their module. \
+The `export` attribute can't be used on `public(friend)` functions either, since \
+exported procedures can be called by any Miden code.",
+    workarounds: &[
+        "Make the called function `public`, or add the calling module to the friends \
+of its module.",
+        "Export a `public` wrapper checking its caller instead of the friend function.",
+    ],
+};
+
+pub const TARGET_CONTEXT: ErrorCode = ErrorCode {
+    code: "M2M010",
+    title: "Feature not available in the target context",
+    explanation: "Global storage, notes and assets are managed by the Miden transaction \
+kernel, which only runs account code and note scripts. Programs compiled for the \
+`standalone` context, the default, can't use them, and some kernel procedures are \
+reserved to account code or to note scripts. Such uses are rejected at compile time, \
+since the program would otherwise only fail inside the kernel.",
+    workarounds: &[
+        "Compile account code with `--target account`, and note scripts with \
+`--target note-script`.",
+        "Pass the values read from the kernel as inputs of the entry function.",
+    ],
+};
+
+pub const INVALID_NOTE: ErrorCode = ErrorCode {
+    code: "M2M011",
+    title: "Invalid note encoding",
+    explanation: "The tag and recipient of notes created with `note::create` are checked at \
+compile time. The recipient must be a 32 byte digest, encoding 4 field elements in \
+little-endian order, and can't be zero. Notes are created private, so their tag can't \
+request network execution, which requires public notes: the most significant bit of the \
+tag must be set.",
+    workarounds: &[
+        "Compute the recipient from the serial number, script and inputs of the note, and \
+declare it as a `vector<u8>` constant.",
+        "Use a tag for local execution, such as one derived from the ID of the target account \
+with the most significant bits set to `0b11`.",
+    ],
+};
+
+pub const MEMORY_LAYOUT: ErrorCode = ErrorCode {
+    code: "M2M012",
+    title: "Invalid memory layout",
+    explanation: "The program keeps its heap pointer, the return values of the entry \
+function, its data section and the scratch memory of intrinsics at the addresses given \
+by its memory map. Regions given by a custom map must not overlap, nor extend past the \
+end of memory, or the program would overwrite its own values.",
+    workarounds: &[
+        "Move the overlapping regions apart, keeping the heap below the regions reserved \
+by other code.",
+        "Use the default memory map unless other code reserves the addresses it uses.",
+    ],
+};
+
+pub const ABORT_CODE: ErrorCode = ErrorCode {
+    code: "M2M013",
+    title: "Abort code reserved by the compiler",
+    explanation: "The runtime checks emitted by the compiler, such as divisions by zero or \
+arithmetic overflows, abort with codes allocated from a namespace, by default starting at \
+0x20000. A module aborting with a constant code in that namespace, or declaring an error \
+with such a code, would fail in ways that can't be told apart from the checks.",
+    workarounds: &[
+        "Move the namespace of the checks past the codes of the module with `--check-codes`.",
+        "Use abort codes outside the namespace.",
+    ],
+};
+
+pub const ERROR_CODES: &[ErrorCode] = &[
+    UNSUPPORTED_OPCODE,
+    UNSUPPORTED_TYPE,
+    UNSUPPORTED_NATIVE,
+    CROSS_MODULE_CALL,
+    ENTRY_POINT,
+    ABI_LIMIT,
+    CYCLE_BUDGET,
+    RESOURCE_LIMIT,
+    VISIBILITY,
+    TARGET_CONTEXT,
+    INVALID_NOTE,
+    MEMORY_LAYOUT,
+    ABORT_CODE,
+];
+
+/// Looks up a registered error code, ignoring case.
+pub fn find_code(code: &str) -> Option<&'static ErrorCode> {
+    ERROR_CODES
+        .iter()
+        .find(|c| c.code.eq_ignore_ascii_case(code))
+}
+
+/// Contents of the source files diagnostics may refer to, keyed by file name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceFiles {
+    files: BTreeMap<String, String>,
+}
+
+impl SourceFiles {
+    pub fn insert(&mut self, name: String, contents: String) {
+        self.files.insert(name, contents);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.files.get(name).map(String::as_str)
+    }
+
+    /// Builds the span of `bytes` in file `name`, computing its line and column.
+    pub fn span(&self, name: &str, bytes: Range<usize>) -> Option<Span> {
+        let (line, column) = self.position(name, bytes.start)?;
+        Some(Span {
+            file: name.into(),
+            bytes,
+            line,
+            column,
+        })
+    }
+
+    /// 1-based line and column of the byte at `offset` in file `name`.
+    pub fn position(&self, name: &str, offset: usize) -> Option<(usize, usize)> {
+        let before = self.get(name)?.get(..offset)?;
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = before[line_start..].chars().count() + 1;
+        Some((line, column))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub diagnostics: Vec<Diagnostic>,
+    pub files: SourceFiles,
+}
+
+impl Diagnostics {
+    pub fn new(files: SourceFiles) -> Self {
+        Self {
+            diagnostics: Vec::new(),
+            files,
+        }
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+    }
+
+    /// Renders all diagnostics with source snippets, without colors.
+    pub fn render(&self) -> String {
+        let mut files = SimpleFiles::new();
+        let ids: BTreeMap<&str, usize> = self
+            .files
+            .files
+            .iter()
+            .map(|(name, contents)| (name.as_str(), files.add(name.as_str(), contents.as_str())))
+            .collect();
+        let mut writer = NoColor::new(Vec::new());
+        let config = term::Config::default();
+        for diagnostic in &self.diagnostics {
+            let to_label = |span: &Span, message: &str, primary: bool| {
+                let id = *ids.get(span.file.as_str())?;
+                let label = if primary {
+                    CodespanLabel::primary(id, span.bytes.clone())
+                } else {
+                    CodespanLabel::secondary(id, span.bytes.clone())
+                };
+                Some(label.with_message(message))
+            };
+            let labels = diagnostic
+                .primary
+                .iter()
+                .filter_map(|(span, message)| to_label(span, message, true))
+                .chain(
+                    diagnostic
+                        .secondary
+                        .iter()
+                        .filter_map(|(span, message)| to_label(span, message, false)),
+                )
+                .collect();
+            let mut rendered = CodespanDiagnostic::new(diagnostic.severity.into());
+            if let Some(code) = diagnostic.code {
+                rendered = rendered.with_code(code);
+            }
+            let rendered = rendered
+                .with_message(&diagnostic.message)
+                .with_labels(labels)
+                .with_notes(diagnostic.notes.clone());
+            // Writing to a `Vec` cannot fail and all file ids are valid
+            term::emit(&mut writer, &config, &files, &rendered).ok();
+        }
+        String::from_utf8_lossy(&writer.into_inner()).into_owned()
+    }
+}
+
+impl From<Severity> for codespan_reporting::diagnostic::Severity {
+    fn from(value: Severity) -> Self {
+        match value {
+            Severity::Note => Self::Note,
+            Severity::Warning => Self::Warning,
+            Severity::Error => Self::Error,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
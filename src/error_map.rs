@@ -0,0 +1,380 @@
+//! Module mapping Move abort codes to the error constants they come from.
+//!
+//! Aptos modules declare abort reasons as documented `const E_NAME: u64 = N;`
+//! constants, and the Aptos compiler records them as an error map in the
+//! `aptos::metadata_v0` or `aptos::metadata_v1` metadata entry. The map is read
+//! from the metadata when present, and from the constants of modules compiled
+//! from source otherwise. It is embedded in artifacts and bundles, so failures
+//! can be reported by name rather than by number.
+//!
+//! Move aborts with a constant code fail the program with that code when it
+//! fits in 32 bits, the size of Miden error codes. The runtime checks emitted
+//! by the compiler (see `Check`) abort with codes of their own, allocated from
+//! a namespace starting at `ErrorCodes::base`, by default the code of
+//! `std::vector::EINDEX_OUT_OF_BOUNDS`. Move codes inside the namespace can't
+//! be told apart from the checks, so modules aborting with such codes fail to
+//! compile until the namespace is moved elsewhere (see
+//! `compiler::CodegenOptions`), and the namespace is recorded in the error map.
+//! Debug builds also abort with `memory::ECANARY_OVERWRITTEN`, and allocations
+//! past the end of the heap with `memory::EOUT_OF_MEMORY`, which are fixed.
+
+use {
+    crate::{artifact::ModuleMetadata, memory},
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+/// Metadata keys of the Aptos runtime metadata, which starts with the error map.
+const APTOS_METADATA_KEYS: &[&[u8]] = &[b"aptos::metadata_v1", b"aptos::metadata_v0"];
+
+/// Default first code of the namespace of the checks emitted by the compiler.
+pub const DEFAULT_CHECKS_BASE: u32 = 0x20000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorMap {
+    pub errors: BTreeMap<u64, ErrorDescription>,
+    // Codes of the checks emitted by the compiler, described before `errors`.
+    #[serde(default)]
+    pub checks: ErrorCodes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorDescription {
+    pub module: String,
+    // Name of the constant, e.g. `E_NOT_OWNER`.
+    pub name: String,
+    // Doc comment of the constant, if any.
+    pub doc: String,
+}
+
+/// Runtime checks emitted by the compiler, each aborting with its own code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    // Vector access out of bounds, as in Move's `std::vector` module.
+    IndexOutOfBounds,
+    // Entry parameter which is not a value of its type (see `abi::prologue`).
+    InvalidInput,
+    DivisionByZero,
+    // Note created with an amount above the maximum amount of a fungible asset.
+    InvalidNoteAmount,
+    // Shift by at least the number of bits of the shifted integer.
+    InvalidShift,
+    // Arithmetic result which doesn't fit in its type.
+    Overflow,
+    // Narrowing cast of a value which doesn't fit in the narrower type.
+    CastOutOfRange,
+}
+
+impl Check {
+    /// Checks in the order of their codes.
+    pub const ALL: [Check; 7] = [
+        Self::IndexOutOfBounds,
+        Self::InvalidInput,
+        Self::DivisionByZero,
+        Self::InvalidNoteAmount,
+        Self::InvalidShift,
+        Self::Overflow,
+        Self::CastOutOfRange,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::IndexOutOfBounds => "EINDEX_OUT_OF_BOUNDS",
+            Self::InvalidInput => "EINVALID_INPUT",
+            Self::DivisionByZero => "EDIVISION_BY_ZERO",
+            Self::InvalidNoteAmount => "EINVALID_NOTE_AMOUNT",
+            Self::InvalidShift => "EINVALID_SHIFT",
+            Self::Overflow => "EOVERFLOW",
+            Self::CastOutOfRange => "ECAST_OUT_OF_RANGE",
+        }
+    }
+
+    pub fn doc(self) -> &'static str {
+        match self {
+            Self::IndexOutOfBounds => "Vector index out of bounds",
+            Self::InvalidInput => "Entry parameter is not a value of its type",
+            Self::DivisionByZero => "Division by zero",
+            Self::InvalidNoteAmount => "Note amount above the maximum amount of an asset",
+            Self::InvalidShift => "Shift by at least the number of bits of the integer",
+            Self::Overflow => "Arithmetic overflow",
+            Self::CastOutOfRange => "Cast of a value out of the range of the type",
+        }
+    }
+}
+
+/// Namespace of the abort codes of the checks emitted by the compiler, the
+/// code of a check being `base` plus its position in `Check::ALL`. Namespaces
+/// are only built by `ErrorCodes::new`, including when deserialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawErrorCodes")]
+pub struct ErrorCodes {
+    base: u32,
+}
+
+/// Serialized form of `ErrorCodes`, validated when deserialized.
+#[derive(Deserialize)]
+struct RawErrorCodes {
+    base: u32,
+}
+
+impl TryFrom<RawErrorCodes> for ErrorCodes {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawErrorCodes) -> anyhow::Result<Self> {
+        Self::new(raw.base)
+    }
+}
+
+impl Default for ErrorCodes {
+    fn default() -> Self {
+        Self {
+            base: DEFAULT_CHECKS_BASE,
+        }
+    }
+}
+
+impl ErrorCodes {
+    /// Namespace starting at `base`, failing unless it fits in 32 bits and
+    /// leaves out 0, the code of assertions without one, and the fixed codes.
+    pub fn new(base: u32) -> anyhow::Result<Self> {
+        let end = base.checked_add(Check::ALL.len() as u32);
+        let fixed = [memory::ECANARY_OVERWRITTEN, memory::EOUT_OF_MEMORY];
+        anyhow::ensure!(
+            base != 0
+                && end.is_some_and(|end| !fixed.iter().any(|code| (base..end).contains(code))),
+            "The {} check codes from {base:#x} must fit in 32 bits and leave out 0, {:#x} and \
+             {:#x}",
+            Check::ALL.len(),
+            memory::ECANARY_OVERWRITTEN,
+            memory::EOUT_OF_MEMORY
+        );
+        Ok(Self { base })
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    pub fn code(&self, check: Check) -> u32 {
+        self.base + check as u32
+    }
+
+    /// Check aborting with `code`, if any.
+    pub fn check(&self, code: u64) -> Option<Check> {
+        let offset = code.checked_sub(self.base as u64)?;
+        Check::ALL.get(usize::try_from(offset).ok()?).copied()
+    }
+}
+
+impl ErrorMap {
+    /// Reads the error map from the Aptos metadata of `module`, if any.
+    pub fn from_metadata(module: &str, metadata: &ModuleMetadata) -> Self {
+        let mut map = Self::default();
+        let Some(bytes) = APTOS_METADATA_KEYS.iter().find_map(|key| metadata.get(key)) else {
+            return map;
+        };
+        // Metadata which can't be decoded is ignored, as it only improves error messages
+        read_error_map(&mut BcsReader { bytes }, module, &mut map);
+        map
+    }
+
+    /// Records an error, keeping any existing description of the code.
+    pub fn insert(&mut self, code: u64, module: &str, name: String, doc: String) {
+        self.errors.entry(code).or_insert(ErrorDescription {
+            module: module.to_string(),
+            name,
+            doc,
+        });
+    }
+
+    pub fn extend(&mut self, other: ErrorMap) {
+        for (code, error) in other.errors {
+            self.errors.entry(code).or_insert(error);
+        }
+    }
+
+    /// Looks up an abort code. Codes built with the Aptos `error` module hold the
+    /// category in bits 16 and up, so the reason in the low 16 bits is tried too.
+    pub fn get(&self, code: u64) -> Option<&ErrorDescription> {
+        self.errors
+            .get(&code)
+            .or_else(|| self.errors.get(&(code & 0xffff)))
+    }
+
+    /// Human readable description of an abort code.
+    pub fn describe(&self, code: u64) -> String {
+        if let Some(check) = self.checks.check(code) {
+            return format!(
+                "Aborted with code {code} ({}): {}",
+                check.name(),
+                check.doc()
+            );
+        }
+        match self.get(code) {
+            Some(error) if error.doc.is_empty() => {
+                format!(
+                    "Aborted with code {code} ({}::{})",
+                    error.module, error.name
+                )
+            }
+            Some(error) => format!(
+                "Aborted with code {code} ({}::{}): {}",
+                error.module, error.name, error.doc
+            ),
+            None => format!("Aborted with code {code}"),
+        }
+    }
+}
+
+/// Whether a constant name follows the convention for error constants,
+/// e.g. `E_NOT_OWNER` or `ENOT_OWNER`.
+pub fn is_error_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next() == Some('E')
+        && chars
+            .next()
+            .is_some_and(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+/// Doc comment (`///` lines) right before the declaration of constant `name` in `source`.
+pub fn constant_doc(source: &str, name: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let Some(declaration) = lines.iter().position(|line| {
+        line.trim_start()
+            .strip_prefix("const ")
+            .is_some_and(|rest| rest.trim_start().starts_with(&format!("{name}:")))
+    }) else {
+        return String::new();
+    };
+    let doc: Vec<&str> = lines[..declaration]
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with("///"))
+        .map(|line| line.trim_start_matches('/').trim())
+        .collect();
+    doc.into_iter().rev().collect::<Vec<_>>().join(" ")
+}
+
+/// Decodes the BCS encoded `BTreeMap<u64, ErrorDescription>` at the start of
+/// the Aptos runtime metadata, adding its errors to `map`.
+fn read_error_map(reader: &mut BcsReader<'_>, module: &str, map: &mut ErrorMap) -> Option<()> {
+    for _ in 0..reader.uleb128()? {
+        let code = reader.u64()?;
+        let name = reader.string()?;
+        let doc = reader.string()?;
+        map.insert(code, module, name, doc);
+    }
+    Some(())
+}
+
+/// Reader for the subset of BCS used by the error map.
+struct BcsReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl BcsReader<'_> {
+    fn take(&mut self, count: usize) -> Option<&[u8]> {
+        if self.bytes.len() < count {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.uleb128()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::artifact::MetadataEntry};
+
+    #[test]
+    fn test_error_map_from_aptos_metadata() {
+        let mut value = vec![1];
+        value.extend(3u64.to_le_bytes());
+        value.push(11);
+        value.extend(b"E_NOT_OWNER");
+        value.push(9);
+        value.extend(b"Not owner");
+        let metadata = ModuleMetadata {
+            bytecode_version: 6,
+            entries: vec![MetadataEntry {
+                key: b"aptos::metadata_v1".to_vec(),
+                value,
+            }],
+        };
+        let map = ErrorMap::from_metadata("coin", &metadata);
+        assert_eq!(map.get(3).unwrap().name, "E_NOT_OWNER");
+        // `error::permission_denied(E_NOT_OWNER)`
+        assert_eq!(
+            map.describe(0x50003),
+            "Aborted with code 327683 (coin::E_NOT_OWNER): Not owner"
+        );
+        assert_eq!(map.describe(4), "Aborted with code 4");
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let codes = ErrorCodes::new(0x100).unwrap();
+        assert_eq!(codes.code(Check::DivisionByZero), 0x102);
+        assert_eq!(codes.check(0x104), Some(Check::InvalidShift));
+        assert_eq!(codes.check(0x106), Some(Check::CastOutOfRange));
+        assert_eq!(codes.check(0x107), None);
+        assert_eq!(codes.check(0xff), None);
+        // The checks are described before the errors of the module
+        let mut map = ErrorMap {
+            checks: codes,
+            ..ErrorMap::default()
+        };
+        map.insert(0x102, "coin", "E_ZERO".into(), String::new());
+        assert_eq!(
+            map.describe(0x102),
+            "Aborted with code 258 (EDIVISION_BY_ZERO): Division by zero"
+        );
+        assert_eq!(
+            ErrorMap::default().describe(DEFAULT_CHECKS_BASE as u64),
+            "Aborted with code 131072 (EINDEX_OUT_OF_BOUNDS): Vector index out of bounds"
+        );
+        assert!(ErrorCodes::new(0).is_err());
+        assert!(ErrorCodes::new(u32::MAX - 2).is_err());
+        assert!(ErrorCodes::new(memory::ECANARY_OVERWRITTEN - 1).is_err());
+        assert!(ErrorCodes::new(memory::EOUT_OF_MEMORY).is_err());
+        // Deserialized namespaces are validated too
+        let codes: ErrorCodes = serde_json::from_str(r#"{"base":256}"#).unwrap();
+        assert_eq!(codes.base(), 0x100);
+        assert!(serde_json::from_str::<ErrorCodes>(r#"{"base":0}"#).is_err());
+    }
+
+    #[test]
+    fn test_constant_doc() {
+        let source = "module a::b {\n    /// The value\n    /// is too large.\n    const E_TOO_LARGE: u64 = 1;\n    const E_OTHER: u64 = 2;\n}\n";
+        assert_eq!(
+            constant_doc(source, "E_TOO_LARGE"),
+            "The value is too large."
+        );
+        assert_eq!(constant_doc(source, "E_OTHER"), "");
+        assert!(is_error_name("E_TOO_LARGE") && is_error_name("ENOT_OWNER"));
+        assert!(!is_error_name("MAX_VALUE") && !is_error_name("Eq"));
+    }
+}
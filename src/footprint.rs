@@ -0,0 +1,222 @@
+//! Module estimating the memory used by the functions of a program.
+//!
+//! Procedure locals live from `memory::LOCALS_START`, the locals of every call
+//! following those of its caller, constant aggregates live in the data section,
+//! and structs are allocated on the heap, which is never freed (see `memory`
+//! module). The heap addresses allocated by a call are known at compile time
+//! unless the function allocates in loops without a constant bound, in
+//! recursive calls, or as much as the length of a vector (e.g. packing bytes
+//! for hashing). Functions whose memory may reach a reserved region of the
+//! memory map, e.g. a heap growing into the scratch region, are flagged, since
+//! writing there would silently corrupt other values.
+
+use {
+    crate::{
+        analysis::{self, AnalysisResult},
+        cfg::Label,
+        cost::Cost,
+        memory::{self, MemoryMap},
+    },
+    miden_assembly::ast::ProcedureAst,
+    move_binary_format::{access::ModuleAccess, file_format::Bytecode},
+    std::collections::BTreeMap,
+};
+
+/// Memory used by a call to a function, including the functions it calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    pub function: String,
+    // Procedure locals of the deepest chain of calls, or `None` if calls are recursive.
+    pub locals: Option<u64>,
+    // Size of the data section, written before the entry function runs.
+    pub data: u32,
+    // Worst-case number of heap addresses allocated by a call, or `None` if it
+    // allocates in loops without a constant bound, in recursive calls or as
+    // much as the length of a vector.
+    pub heap: Option<u64>,
+    // Reserved regions a call may write into (see `MemoryMap::reserved`), along
+    // with "procedure locals" for a heap growing into them.
+    pub collisions: Vec<&'static str>,
+}
+
+/// Memory used by a call, in addresses.
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    locals: Cost,
+    heap: Cost,
+}
+
+const NONE: Cost = Cost {
+    cycles: 0,
+    bounded: true,
+};
+
+const UNBOUNDED: Cost = Cost {
+    cycles: 0,
+    bounded: false,
+};
+
+/// Footprints of the functions of the analyzed module, in definition order.
+/// `procedures` are the generated procedures, by function definition index,
+/// and `allocation` gives the heap addresses allocated by an instruction, if
+/// known at compile time.
+pub fn footprints(
+    analysis: &AnalysisResult<'_>,
+    procedures: &[ProcedureAst],
+    allocation: &dyn Fn(&Bytecode) -> anyhow::Result<Option<u32>>,
+    data_size: u32,
+    map: &MemoryMap,
+) -> anyhow::Result<Vec<MemoryFootprint>> {
+    let mut estimator = Estimator {
+        analysis,
+        procedures,
+        allocation,
+        usages: BTreeMap::new(),
+        calls: Vec::new(),
+    };
+    let mut result = Vec::with_capacity(analysis.functions.len());
+    for (index, function) in analysis.functions.iter().enumerate() {
+        let usage = estimator.function(index)?;
+        let bound = |cost: Cost| cost.bounded.then_some(cost.cycles);
+        let (locals, heap) = (bound(usage.locals), bound(usage.heap));
+        result.push(MemoryFootprint {
+            function: function.name.clone(),
+            locals,
+            data: data_size,
+            heap,
+            collisions: collisions(map, data_size, locals, heap),
+        });
+    }
+    Ok(result)
+}
+
+/// Reserved regions reached by locals and heap allocations of the given
+/// sizes, `None` being unbounded.
+fn collisions(
+    map: &MemoryMap,
+    data_size: u32,
+    locals: Option<u64>,
+    heap: Option<u64>,
+) -> Vec<&'static str> {
+    // Address ranges as start and end, `None` being unbounded
+    let heap = (
+        map.heap_start as u64,
+        heap.map(|heap| map.heap_start as u64 + data_size as u64 + heap),
+    );
+    let locals = (
+        memory::LOCALS_START as u64,
+        locals.map(|locals| memory::LOCALS_START as u64 + locals),
+    );
+    let overlaps = |(start, end): (u64, Option<u64>), region_start: u32, size: u32| {
+        let region_start = region_start as u64;
+        end != Some(start)
+            && region_start + size as u64 > start
+            && end.map_or(true, |end| region_start < end)
+    };
+    let mut result: Vec<&'static str> = map
+        .reserved()
+        .into_iter()
+        .filter(|(_, start, size)| overlaps(heap, *start, *size) || overlaps(locals, *start, *size))
+        .map(|(name, ..)| name)
+        .collect();
+    if locals.1 != Some(locals.0) && overlaps(heap, memory::LOCALS_START, 1) {
+        result.push("procedure locals");
+    }
+    result
+}
+
+struct Estimator<'a> {
+    analysis: &'a AnalysisResult<'a>,
+    procedures: &'a [ProcedureAst],
+    allocation: &'a dyn Fn(&Bytecode) -> anyhow::Result<Option<u32>>,
+    // Usage of the functions estimated so far, by definition index.
+    usages: BTreeMap<usize, Usage>,
+    // Functions being estimated, to detect recursion.
+    calls: Vec<usize>,
+}
+
+impl Estimator<'_> {
+    fn function(&mut self, index: usize) -> anyhow::Result<Usage> {
+        if let Some(usage) = self.usages.get(&index) {
+            return Ok(*usage);
+        }
+        if self.calls.contains(&index) {
+            return Ok(Usage {
+                locals: UNBOUNDED,
+                heap: UNBOUNDED,
+            });
+        }
+        let module = self.analysis.module;
+        let function = &self.analysis.functions[index];
+        let (Some(cfg), Some(code)) = (&function.cfg, &module.function_defs()[index].code) else {
+            // Native functions
+            return Ok(Usage {
+                locals: NONE,
+                heap: NONE,
+            });
+        };
+        // Callees first, so the usage of every call is known when walking the code
+        self.calls.push(index);
+        let mut callees = BTreeMap::new();
+        for bytecode in &code.code {
+            if let Some(callee) = self.callee(bytecode) {
+                let usage = self.function(callee)?;
+                callees.insert(callee, usage);
+            }
+        }
+        self.calls.pop();
+        let deepest = callees
+            .values()
+            .fold(NONE, |deepest, usage| deepest.max(usage.locals));
+        let own = self
+            .procedures
+            .get(index)
+            .map_or(0, |procedure| procedure.num_locals as u64);
+        let locals = Cost {
+            cycles: own,
+            bounded: true,
+        }
+        .then(deepest);
+        let weight = |block: &[Bytecode]| -> anyhow::Result<Cost> {
+            let mut heap = NONE;
+            for bytecode in block {
+                let allocated = match self.callee(bytecode) {
+                    Some(callee) => callees[&callee].heap,
+                    None => match (self.allocation)(bytecode)? {
+                        Some(size) => Cost {
+                            cycles: size as u64,
+                            bounded: true,
+                        },
+                        None => UNBOUNDED,
+                    },
+                };
+                heap = heap.then(allocated);
+            }
+            Ok(heap)
+        };
+        let heap = analysis::weighted_cost(
+            cfg,
+            &function.counted_loops,
+            Label::Entry,
+            Label::Exit,
+            &weight,
+        )?;
+        let usage = Usage { locals, heap };
+        self.usages.insert(index, usage);
+        Ok(usage)
+    }
+
+    /// Definition index of the function of the module called by the instruction.
+    fn callee(&self, bytecode: &Bytecode) -> Option<usize> {
+        let module = self.analysis.module;
+        let handle = match bytecode {
+            Bytecode::Call(index) => *index,
+            Bytecode::CallGeneric(index) => module.function_instantiation_at(*index).handle,
+            _ => return None,
+        };
+        module
+            .function_defs()
+            .iter()
+            .position(|definition| definition.function == handle)
+    }
+}
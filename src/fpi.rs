@@ -0,0 +1,81 @@
+//! Module lowering calls to procedures of other on-chain accounts.
+//!
+//! A procedure of another account is declared in Move as a native function
+//! marked `#[miden(foreign)]`, whose first parameter is the ID of the account.
+//! Calls to it go through foreign procedure invocation: the transaction kernel
+//! loads the foreign account and runs the procedure in its context. The kernel
+//! needs the MAST root of the procedure, which must be given at compile time
+//! (see `CodegenOptions::foreign_roots`): a root read from advice would let the
+//! prover run any procedure of the foreign account in its place.
+//!
+//! The kernel hands 16 elements of inputs to the procedure and returns 16
+//! elements of outputs, so the arguments are padded with zeros and the outputs
+//! truncated to the width of the return values.
+//!
+//! `miden::tx::execute_foreign_procedure` is provided by the transaction kernel
+//! library of miden-base (`miden-lib`) from version 0.6, which runs on Miden VM
+//! 0.10 and later. This crate assembles and executes programs with Miden 0.8,
+//! whose libraries don't have it, so programs calling foreign procedures only
+//! assemble against that kernel library, and no test here executes them.
+
+use {
+    crate::{abi, bundle, bytes, diagnostics, intrinsics},
+    miden::math::Felt,
+    miden_assembly::ast::{Instruction, Node},
+};
+
+/// Transaction kernel procedure running foreign procedures.
+pub const KERNEL_PROCEDURE: (&str, &str) = ("miden::tx", "execute_foreign_procedure");
+
+/// Number of elements of inputs and outputs of a foreign procedure.
+const FRAME_WIDTH: usize = 16;
+
+/// Code calling the foreign procedure `name`, whose MAST root is `root`, with
+/// the account ID and arguments of `input_width` elements on the stack, the
+/// account ID deepest, leaving its return values of `output_width` elements.
+pub fn call(
+    name: &str,
+    input_width: usize,
+    output_width: usize,
+    root: &[Felt; 4],
+) -> anyhow::Result<Vec<Node>> {
+    // The account ID is moved above the arguments and the padding below them
+    if input_width > FRAME_WIDTH - 2 {
+        return Err(diagnostics::UNSUPPORTED_NATIVE.error(format!(
+            "Foreign procedure {name} takes {input_width} elements of arguments, but at most {} \
+             can be passed",
+            FRAME_WIDTH - 2
+        )));
+    }
+    if output_width >= FRAME_WIDTH {
+        return Err(diagnostics::UNSUPPORTED_NATIVE.error(format!(
+            "Foreign procedure {name} returns {output_width} elements, but at most {} can be \
+             returned",
+            FRAME_WIDTH - 1
+        )));
+    }
+    // [inputs, account_id] -> [account_id, inputs, pad]
+    let mut result: Vec<Instruction> = abi::movup(input_width).into_iter().collect();
+    for _ in input_width..FRAME_WIDTH {
+        result.push(Instruction::PushU32(0));
+        result.extend(abi::movdn(input_width + 1));
+    }
+    // -> [account_id, ROOT, inputs, pad]
+    result.extend(root.iter().map(|e| Instruction::PushFelt(*e)));
+    result.push(Instruction::MovUp4);
+    let mut nodes: Vec<Node> = result.into_iter().map(Node::Instruction).collect();
+    let (path, procedure) = KERNEL_PROCEDURE;
+    nodes.push(intrinsics::exec_imported(path, procedure)?);
+    // [outputs, pad] -> [outputs]
+    for _ in output_width..FRAME_WIDTH {
+        nodes.extend(abi::movup(output_width).map(Node::Instruction));
+        nodes.push(Node::Instruction(Instruction::Drop));
+    }
+    Ok(nodes)
+}
+
+/// Parses a MAST root given as the hex encoding of its 4 elements, in
+/// little-endian order, 8 bytes each.
+pub fn parse_root(hex: &str) -> anyhow::Result<[Felt; 4]> {
+    bytes::word_from_le_bytes(&bundle::from_hex(hex)?)
+}
@@ -0,0 +1,617 @@
+//! Module for compiling Move source files into Miden programs.
+//!
+//! Both the errors reported by the Move compiler and the errors found while
+//! lowering to Miden are returned as `Diagnostics` pointing into the sources.
+//! The `miden` attributes of functions (see `attributes` module) are read from
+//! the parsed sources, since they are not kept in the bytecode.
+
+use {
+    crate::{
+        analysis,
+        artifact::CompiledArtifact,
+        attributes::{self, FunctionAttributes, ModuleAttributes, PackageAttributes},
+        audit,
+        compiler::{self, CodeLocation, CodegenOptions, EntryMode, OpcodePolicy, TargetContext},
+        diagnostics::{self, Diagnostic, Diagnostics, ErrorCode, Severity, SourceFiles, Span},
+        error_map::{self, ErrorCodes, ErrorMap},
+        memory::MemoryMap,
+    },
+    miden::math::Felt,
+    miden_assembly::ast::{ProcedureAst, SourceLocation},
+    move_binary_format::{
+        access::ModuleAccess, file_format::FunctionDefinitionIndex, CompiledModule,
+    },
+    move_bytecode_source_map::source_map::SourceMap,
+    move_command_line_common::files::FileHash,
+    move_compiler::{
+        compiled_unit::{CompiledUnit, NamedCompiledModule},
+        diagnostics::Diagnostics as MoveDiagnostics,
+        parser::ast::{
+            Definition, FunctionBody_, LeadingNameAccess, LeadingNameAccess_, ModuleMember,
+        },
+        shared::NumericalAddress,
+        Compiler, Flags, PASS_PARSER,
+    },
+    move_core_types::{
+        account_address::AccountAddress, identifier::Identifier, language_storage::ModuleId,
+        value::MoveValue,
+    },
+    move_ir_types::location::Loc,
+    std::collections::{BTreeMap, BTreeSet, HashMap},
+};
+
+/// A module compiled from source, along with the mapping back to its source.
+#[derive(Debug)]
+pub struct SourceModule {
+    pub module: CompiledModule,
+    pub source_map: SourceMap,
+}
+
+/// Result of running the Move compiler.
+#[derive(Debug)]
+pub struct CompiledSources {
+    pub modules: Vec<SourceModule>,
+    pub files: SourceFiles,
+    pub warnings: Diagnostics,
+    // Attributes of the functions of every module in the sources.
+    pub attributes: PackageAttributes,
+    // Files are identified by hash in Move locations.
+    file_names: HashMap<[u8; 32], String>,
+}
+
+/// Parses named addresses given as strings, e.g. by bindings or RPC clients.
+pub fn parse_named_addresses(
+    named_addresses: BTreeMap<String, String>,
+) -> anyhow::Result<BTreeMap<String, NumericalAddress>> {
+    named_addresses
+        .into_iter()
+        .map(|(name, address)| {
+            let address = NumericalAddress::parse_str(&address)
+                .map_err(|e| anyhow::anyhow!("Invalid address for {name}: {e}"))?;
+            Ok((name, address))
+        })
+        .collect()
+}
+
+/// Compiles the given Move source files to bytecode.
+pub fn compile_sources(
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+) -> Result<CompiledSources, Diagnostics> {
+    let known_attributes = known_attributes();
+    let compiler = Compiler::from_files(
+        paths.to_vec(),
+        Vec::new(),
+        named_addresses.clone(),
+        Flags::empty(),
+        &known_attributes,
+    );
+    let (files, result) = compiler
+        .build()
+        .map_err(|e| single_error(format!("Failed to run the Move compiler: {e:#}")))?;
+    let mut compiled = CompiledSources {
+        modules: Vec::new(),
+        files: SourceFiles::default(),
+        warnings: Diagnostics::default(),
+        attributes: PackageAttributes::new(),
+        file_names: HashMap::new(),
+    };
+    for (hash, (name, contents)) in files {
+        compiled.files.insert(name.to_string(), contents);
+        compiled.file_names.insert(hash.0, name.to_string());
+    }
+    let (units, warnings) = match result {
+        Ok(result) => result,
+        Err(errors) => return Err(compiled.convert_move_diagnostics(errors)),
+    };
+    let warnings = compiled.convert_move_diagnostics(warnings);
+    if warnings.has_errors() {
+        return Err(warnings);
+    }
+    compiled.warnings = warnings;
+    for unit in units {
+        match unit.into_compiled_unit() {
+            CompiledUnit::Module(NamedCompiledModule {
+                module, source_map, ..
+            }) => compiled.modules.push(SourceModule { module, source_map }),
+            CompiledUnit::Script(_) => {
+                let mut diagnostics = Diagnostics::new(compiled.files);
+                diagnostics.push(Diagnostic::error("Move scripts are not supported"));
+                return Err(diagnostics);
+            }
+        }
+    }
+    compiled.read_attributes(paths, named_addresses)?;
+    Ok(compiled)
+}
+
+fn known_attributes() -> BTreeSet<String> {
+    [attributes::ATTRIBUTE_NAME.to_string()]
+        .into_iter()
+        .collect()
+}
+
+/// Compiles the given Move source files to a Miden program.
+/// Exactly one of the compiled modules must contain an entry function.
+/// Warnings from the Move compiler are included in the artifact diagnostics.
+pub fn compile_to_miden(
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+) -> Result<CompiledArtifact, Diagnostics> {
+    compile_with_options(paths, named_addresses, BuildOptions::default())
+}
+
+/// Compiles Move source files to a Miden program asserting that the entry
+/// function, which must return a `bool`, returns true (see `EntryMode::AssertTrue`).
+pub fn compile_predicate(
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+) -> Result<CompiledArtifact, Diagnostics> {
+    let options = BuildOptions {
+        entry_mode: EntryMode::AssertTrue,
+        ..BuildOptions::default()
+    };
+    compile_with_options(paths, named_addresses, options)
+}
+
+/// Compiles Move source files to a Miden program asserting that the entry
+/// function, which must return a `bool`, returns true for every instance of
+/// a batch of inputs (see `EntryMode::Batch`).
+pub fn compile_batch(
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+) -> Result<CompiledArtifact, Diagnostics> {
+    let options = BuildOptions {
+        entry_mode: EntryMode::Batch,
+        ..BuildOptions::default()
+    };
+    compile_with_options(paths, named_addresses, options)
+}
+
+/// Code generation settings of `compile_with_options` (see `CodegenOptions`).
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub entry_mode: EntryMode,
+    pub memoize: bool,
+    pub opcode_policy: OpcodePolicy,
+    pub canaries: bool,
+    pub emit_pass_ir: bool,
+    pub target: TargetContext,
+    pub foreign_roots: BTreeMap<String, [Felt; 4]>,
+    pub memory: MemoryMap,
+    pub error_codes: ErrorCodes,
+}
+
+/// Compiles Move source files to a Miden program with the given settings.
+pub fn compile_with_options(
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+    options: BuildOptions,
+) -> Result<CompiledArtifact, Diagnostics> {
+    compile_sources(paths, named_addresses)?.compile_entry_module(options)
+}
+
+/// Compiles prebuilt module bytecode to a Miden program, using the source map
+/// written by the Move compiler along with it (see `move_utils::load_source_map`)
+/// to locate diagnostics and procedures in the given Move source files. The
+/// `miden` attributes of the functions are read from the sources.
+pub fn compile_bytecode(
+    module: CompiledModule,
+    source_map: SourceMap,
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+    options: BuildOptions,
+) -> Result<CompiledArtifact, Diagnostics> {
+    let mut compiled = CompiledSources {
+        modules: vec![SourceModule { module, source_map }],
+        files: SourceFiles::default(),
+        warnings: Diagnostics::default(),
+        attributes: PackageAttributes::new(),
+        file_names: HashMap::new(),
+    };
+    for path in paths {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| single_error(format!("Failed to read {path}: {e}")))?;
+        compiled
+            .file_names
+            .insert(FileHash::new(&contents).0, path.clone());
+        compiled.files.insert(path.clone(), contents);
+    }
+    if !paths.is_empty() {
+        compiled.read_attributes(paths, named_addresses)?;
+    }
+    compiled.compile_entry_module(options)
+}
+
+/// Compilation status of a single function, as reported by `check_sources`.
+#[derive(Debug)]
+pub struct FunctionStatus {
+    pub module: String,
+    pub function: String,
+    // Location of the function definition in the sources.
+    pub span: Option<Span>,
+    // The compiled procedure, or `None` if the function could not be compiled.
+    pub procedure: Option<ProcedureAst>,
+}
+
+/// Result of compiling sources in recovery mode.
+#[derive(Debug)]
+pub struct CheckResult {
+    pub functions: Vec<FunctionStatus>,
+    pub diagnostics: Diagnostics,
+}
+
+/// Compiles the given Move source files as far as possible, for use by IDE integrations.
+/// Instead of stopping at the first error, each function is compiled independently;
+/// the result reports the status of every function along with all diagnostics found.
+/// If the Move compiler itself fails, no functions are reported.
+pub fn check_sources(
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+) -> CheckResult {
+    let sources = match compile_sources(paths, named_addresses) {
+        Ok(sources) => sources,
+        Err(diagnostics) => {
+            return CheckResult {
+                functions: Vec::new(),
+                diagnostics,
+            }
+        }
+    };
+    let mut result = CheckResult {
+        functions: Vec::new(),
+        diagnostics: Diagnostics::new(sources.files.clone()),
+    };
+    for module in &sources.modules {
+        let module_name = module.module.self_id().name().to_string();
+        let functions = match compiler::compile_functions(&module.module, &sources.attributes) {
+            Ok(functions) => functions,
+            Err(e) => {
+                result
+                    .diagnostics
+                    .push(sources.backend_diagnostic(module, &e));
+                continue;
+            }
+        };
+        for function in functions {
+            let span = module
+                .source_map
+                .get_function_source_map(function.definition)
+                .ok()
+                .and_then(|f| sources.span(&f.definition_location));
+            let procedure = match function.procedure {
+                Ok(procedure) => Some(procedure),
+                Err(e) => {
+                    result
+                        .diagnostics
+                        .push(sources.backend_diagnostic(module, &e));
+                    None
+                }
+            };
+            result.functions.push(FunctionStatus {
+                module: module_name.clone(),
+                function: function.name,
+                span,
+                procedure,
+            });
+        }
+    }
+    result
+}
+
+/// Runs the checks of the `audit` module on every module of the sources.
+/// Findings are reported as warnings and notes on the functions they are about.
+pub fn audit_sources(
+    paths: &[String],
+    named_addresses: BTreeMap<String, NumericalAddress>,
+) -> Diagnostics {
+    let sources = match compile_sources(paths, named_addresses) {
+        Ok(sources) => sources,
+        Err(diagnostics) => return diagnostics,
+    };
+    let mut diagnostics = Diagnostics::new(sources.files.clone());
+    for module in &sources.modules {
+        let analysis = match analysis::analyze(&module.module) {
+            Ok(analysis) => analysis,
+            Err(e) => {
+                diagnostics.push(sources.backend_diagnostic(module, &e));
+                continue;
+            }
+        };
+        for finding in audit::audit(&analysis) {
+            let mut diagnostic = Diagnostic::new(finding.severity, finding.message);
+            let definition = analysis
+                .functions
+                .iter()
+                .find(|f| f.name == finding.function)
+                .map(|f| f.definition);
+            let loc = definition.and_then(|definition| match finding.offset {
+                Some(offset) => module.source_map.get_code_location(definition, offset).ok(),
+                None => module
+                    .source_map
+                    .get_function_source_map(definition)
+                    .ok()
+                    .map(|f| f.definition_location),
+            });
+            if let Some(span) = loc.and_then(|loc| sources.span(&loc)) {
+                let label = match finding.offset {
+                    Some(_) => "here",
+                    None => "in this function",
+                };
+                diagnostic = diagnostic.with_primary(span, label);
+            }
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics
+}
+
+impl CompiledSources {
+    /// Compiles the only module with an entry function to a Miden program.
+    fn compile_entry_module(
+        mut self,
+        build_options: BuildOptions,
+    ) -> Result<CompiledArtifact, Diagnostics> {
+        let entry_modules: Vec<&SourceModule> = self
+            .modules
+            .iter()
+            .filter(|m| m.module.function_defs.iter().any(|f| f.is_entry))
+            .collect();
+        let module = match entry_modules.as_slice() {
+            [module] => *module,
+            [] => return Err(self.error("No module with an entry function found")),
+            _ => return Err(self.error("Multiple modules with entry functions found")),
+        };
+        let options = CodegenOptions {
+            attributes: self.attributes.clone(),
+            source_locations: self.function_locations(module),
+            entry_mode: build_options.entry_mode,
+            memoize: build_options.memoize,
+            opcode_policy: build_options.opcode_policy,
+            canaries: build_options.canaries,
+            emit_pass_ir: build_options.emit_pass_ir,
+            target: build_options.target,
+            foreign_roots: build_options.foreign_roots,
+            memory: build_options.memory,
+            error_codes: build_options.error_codes,
+            ..CodegenOptions::default()
+        };
+        let mut artifact = analysis::analyze(&module.module)
+            .and_then(|analysis| compiler::codegen(&analysis, options))
+            .map_err(|e| self.backend_error(module, &e))?;
+        artifact.source_map = Some(module.source_map.clone());
+        artifact.error_map.extend(self.error_map(module));
+        let mut diagnostics = std::mem::take(&mut self.warnings);
+        for location in &artifact.skipped_opcodes {
+            diagnostics.push(self.skipped_opcode_warning(module, location));
+        }
+        artifact.diagnostics = diagnostics;
+        Ok(artifact)
+    }
+
+    /// Parses the sources again to read the attributes of their functions.
+    fn read_attributes(
+        &mut self,
+        paths: &[String],
+        named_addresses: BTreeMap<String, NumericalAddress>,
+    ) -> Result<(), Diagnostics> {
+        let known_attributes = known_attributes();
+        let compiler = Compiler::from_files(
+            paths.to_vec(),
+            Vec::new(),
+            named_addresses.clone(),
+            Flags::empty(),
+            &known_attributes,
+        );
+        let (_, result) = compiler
+            .run::<PASS_PARSER>()
+            .map_err(|e| single_error(format!("Failed to run the Move parser: {e:#}")))?;
+        let (_, program) = result
+            .map_err(|errors| self.convert_move_diagnostics(errors))?
+            .into_ast();
+        let mut diagnostics = Diagnostics::new(self.files.clone());
+        for package in &program.source_definitions {
+            let (address, definitions) = match &package.def {
+                Definition::Module(module) => (None, std::slice::from_ref(module)),
+                Definition::Address(definition) => {
+                    (Some(&definition.addr), &definition.modules[..])
+                }
+                Definition::Script(_) => continue,
+            };
+            for definition in definitions {
+                let Some(address) = definition
+                    .address
+                    .as_ref()
+                    .or(address)
+                    .and_then(|address| resolve_address(address, &named_addresses))
+                else {
+                    continue;
+                };
+                let Ok(name) = Identifier::new(definition.name.0.value.as_str()) else {
+                    continue;
+                };
+                let mut module_attributes = ModuleAttributes::new();
+                for member in &definition.members {
+                    let ModuleMember::Function(function) = member else {
+                        continue;
+                    };
+                    let is_native = matches!(function.body.value, FunctionBody_::Native);
+                    match FunctionAttributes::parse(&function.attributes, is_native) {
+                        Ok(attributes) if attributes == FunctionAttributes::default() => (),
+                        Ok(attributes) => {
+                            module_attributes.insert(function.name.0.value.to_string(), attributes);
+                        }
+                        Err(e) => {
+                            let mut diagnostic = Diagnostic::error(e.message);
+                            if let Some(span) = self.span(&e.loc) {
+                                diagnostic = diagnostic.with_primary(span, "invalid attribute");
+                            }
+                            diagnostics.push(diagnostic);
+                        }
+                    }
+                }
+                self.attributes
+                    .insert(ModuleId::new(address, name), module_attributes);
+            }
+        }
+        if diagnostics.has_errors() {
+            return Err(diagnostics);
+        }
+        Ok(())
+    }
+
+    /// Finds the span in the sources corresponding to a Move location.
+    pub fn span(&self, loc: &Loc) -> Option<Span> {
+        let name = self.file_names.get(&loc.file_hash().0)?;
+        self.files
+            .span(name, loc.start() as usize..loc.end() as usize)
+    }
+
+    /// Line and column of the definition of every function of the module.
+    pub fn function_locations(
+        &self,
+        module: &SourceModule,
+    ) -> BTreeMap<FunctionDefinitionIndex, SourceLocation> {
+        (0..module.module.function_defs.len())
+            .filter_map(|index| {
+                let definition = FunctionDefinitionIndex::new(index as u16);
+                let function = module.source_map.get_function_source_map(definition).ok()?;
+                let span = self.span(&function.definition_location)?;
+                let location = SourceLocation::new(span.line as u32, span.column as u32);
+                Some((definition, location))
+            })
+            .collect()
+    }
+
+    /// Error map built from the error constants declared in the module.
+    pub fn error_map(&self, module: &SourceModule) -> ErrorMap {
+        let mut map = ErrorMap::default();
+        let module_name = module.module.self_id().name().to_string();
+        let source = self
+            .file_names
+            .get(&module.source_map.definition_location.file_hash().0)
+            .and_then(|name| self.files.get(name))
+            .unwrap_or_default();
+        for (name, index) in &module.source_map.constant_map {
+            let name = name.0.as_str();
+            if !error_map::is_error_name(name) {
+                continue;
+            }
+            let value = module
+                .module
+                .constant_pool
+                .get(*index as usize)
+                .and_then(|constant| constant.deserialize_constant());
+            if let Some(MoveValue::U64(code)) = value {
+                let doc = error_map::constant_doc(source, name);
+                map.insert(code, &module_name, name.to_string(), doc);
+            }
+        }
+        map
+    }
+
+    fn error(&self, message: &str) -> Diagnostics {
+        let mut diagnostics = Diagnostics::new(self.files.clone());
+        diagnostics.push(Diagnostic::error(message));
+        diagnostics
+    }
+
+    /// Converts an error from the Miden backend, locating it in the sources when possible.
+    pub fn backend_error(&self, module: &SourceModule, error: &anyhow::Error) -> Diagnostics {
+        let mut diagnostics = Diagnostics::new(self.files.clone());
+        diagnostics.push(self.backend_diagnostic(module, error));
+        diagnostics
+    }
+
+    /// Warning about an opcode left out under `OpcodePolicy::Skip`.
+    fn skipped_opcode_warning(&self, module: &SourceModule, location: &CodeLocation) -> Diagnostic {
+        let opcode = location.offset.and_then(|offset| {
+            let code = module
+                .module
+                .function_def_at(location.function)
+                .code
+                .as_ref()?;
+            code.code.get(offset as usize)
+        });
+        let message = match opcode {
+            Some(opcode) => format!("Skipped unimplemented opcode {opcode:?}"),
+            None => "Skipped unimplemented opcode".to_string(),
+        };
+        let error = diagnostics::UNSUPPORTED_OPCODE
+            .error(message)
+            .context(*location);
+        Diagnostic {
+            severity: Severity::Warning,
+            ..self.backend_diagnostic(module, &error)
+        }
+    }
+
+    fn backend_diagnostic(&self, module: &SourceModule, error: &anyhow::Error) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(error.root_cause().to_string());
+        if let Some(code) = error.downcast_ref::<ErrorCode>() {
+            diagnostic = diagnostic.with_code(code);
+        }
+        if let Some(location) = error.downcast_ref::<CodeLocation>() {
+            let loc = match location.offset {
+                Some(offset) => module
+                    .source_map
+                    .get_code_location(location.function, offset)
+                    .ok(),
+                None => module
+                    .source_map
+                    .get_function_source_map(location.function)
+                    .ok()
+                    .map(|f| f.definition_location),
+            };
+            match loc.and_then(|loc| self.span(&loc)) {
+                Some(span) => {
+                    diagnostic = diagnostic.with_primary(span, "cannot be compiled to Miden")
+                }
+                None => diagnostic = diagnostic.with_note(location.to_string()),
+            }
+        }
+        diagnostic
+    }
+
+    fn convert_move_diagnostics(&self, move_diagnostics: MoveDiagnostics) -> Diagnostics {
+        let mut diagnostics = Diagnostics::new(self.files.clone());
+        for (severity, message, (loc, label), secondary, notes) in
+            move_diagnostics.into_codespan_format()
+        {
+            let severity = match severity {
+                codespan_reporting::diagnostic::Severity::Bug
+                | codespan_reporting::diagnostic::Severity::Error => Severity::Error,
+                codespan_reporting::diagnostic::Severity::Warning => Severity::Warning,
+                codespan_reporting::diagnostic::Severity::Note
+                | codespan_reporting::diagnostic::Severity::Help => Severity::Note,
+            };
+            let mut diagnostic = Diagnostic::new(severity, message);
+            diagnostic.primary = self.span(&loc).map(|span| (span, label));
+            diagnostic.secondary = secondary
+                .into_iter()
+                .filter_map(|(loc, label)| Some((self.span(&loc)?, label)))
+                .collect();
+            diagnostic.notes = notes;
+            diagnostics.push(diagnostic);
+        }
+        diagnostics
+    }
+}
+
+fn resolve_address(
+    address: &LeadingNameAccess,
+    named_addresses: &BTreeMap<String, NumericalAddress>,
+) -> Option<AccountAddress> {
+    match &address.value {
+        LeadingNameAccess_::AnonymousAddress(address) => Some(address.into_inner()),
+        LeadingNameAccess_::Name(name) => named_addresses
+            .get(name.value.as_str())
+            .map(|address| address.into_inner()),
+    }
+}
+
+fn single_error(message: String) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    diagnostics.push(Diagnostic::error(message));
+    diagnostics
+}
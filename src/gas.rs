@@ -0,0 +1,167 @@
+//! Module comparing the cost of entry functions on the Move VM, in gas units,
+//! with their cost on Miden, in VM cycles, to help predict the proving cost of
+//! contracts ported to Miden.
+//!
+//! Functions run on the Move VM with the gas schedule of the Move VM test
+//! utilities and without native functions, so functions calling natives can't
+//! be measured. Arguments are decoded from the flattened inputs of the entry
+//! function (see `abi` module), so both VMs run the same call.
+
+use {
+    crate::{
+        abi::AbiType, accounts::AccountId, artifact::CompiledArtifact, bytes, compiler, runner,
+    },
+    anyhow::Context,
+    move_binary_format::CompiledModule,
+    move_core_types::{identifier::Identifier, u256::U256, value::MoveValue},
+    move_vm_runtime::move_vm::MoveVM,
+    move_vm_test_utils::{
+        gas_schedule::{Gas, GasStatus, INITIAL_COST_SCHEDULE},
+        InMemoryStorage,
+    },
+    std::fmt::Write,
+};
+
+/// Gas available to a call on the Move VM.
+const GAS_BUDGET: u64 = 1_000_000_000;
+
+/// Cost of a call of an entry function on both VMs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    // As `module::function`.
+    pub function: String,
+    pub inputs: Vec<u64>,
+    pub gas: u64,
+    pub cycles: usize,
+}
+
+/// Runs the entry function of the artifact, compiled from `module`, on the
+/// Move VM and on Miden with the given flattened parameter values.
+pub fn measure(
+    module: &CompiledModule,
+    artifact: &CompiledArtifact,
+    values: &[u64],
+) -> anyhow::Result<Measurement> {
+    let entry = artifact.entry();
+    let function = format!("{}::{}", entry.module.name(), entry.function);
+    let (_, cycles) = runner::run_with_cycles(artifact, values)
+        .with_context(|| format!("Failed to run {function} on Miden"))?;
+    let args = move_args(&artifact.abi.params, values)?;
+    let gas = move_vm_gas(module, &entry.function, args)
+        .with_context(|| format!("Failed to run {function} on the Move VM"))?;
+    Ok(Measurement {
+        function,
+        inputs: values.to_vec(),
+        gas,
+        cycles,
+    })
+}
+
+/// Measures the entry function of every module of a package declaring one
+/// (see `compiler::compile_package`) with each of the given inputs.
+pub fn compare(
+    modules: &[CompiledModule],
+    inputs: &[Vec<u64>],
+) -> anyhow::Result<Vec<Measurement>> {
+    let artifacts = compiler::compile_package(modules)?;
+    let mut measurements = Vec::new();
+    for artifact in &artifacts {
+        let entry = artifact.entry();
+        let module = modules
+            .iter()
+            .find(|m| m.self_id() == entry.module)
+            .context("No module for the artifact")?;
+        for values in inputs {
+            measurements.push(measure(module, artifact, values)?);
+        }
+    }
+    Ok(measurements)
+}
+
+/// Markdown table comparing the measurements.
+pub fn render(measurements: &[Measurement]) -> String {
+    let mut report = String::new();
+    report.push_str("| Function | Inputs | Move VM gas | Miden cycles | Cycles per gas unit |\n");
+    report.push_str("|---|---|---|---|---|\n");
+    for m in measurements {
+        let inputs: Vec<String> = m.inputs.iter().map(u64::to_string).collect();
+        let ratio = match m.gas {
+            0 => "-".to_string(),
+            gas => format!("{:.2}", m.cycles as f64 / gas as f64),
+        };
+        // Writing to a string can't fail
+        let _ = writeln!(
+            report,
+            "| `{}` | {} | {} | {} | {ratio} |",
+            m.function,
+            inputs.join(", "),
+            m.gas,
+            m.cycles
+        );
+    }
+    report
+}
+
+/// Gas used by a call of the function on the Move VM.
+fn move_vm_gas(module: &CompiledModule, function: &str, args: Vec<Vec<u8>>) -> anyhow::Result<u64> {
+    let mut bytes = Vec::new();
+    module.serialize(&mut bytes)?;
+    let mut storage = InMemoryStorage::new();
+    storage.publish_or_overwrite_module(module.self_id(), bytes);
+    let vm = MoveVM::new(vec![])?;
+    let mut session = vm.new_session(&storage);
+    let mut gas = GasStatus::new(&INITIAL_COST_SCHEDULE, Gas::new(GAS_BUDGET));
+    let function = Identifier::new(function)?;
+    session.execute_function_bypass_visibility(
+        &module.self_id(),
+        &function,
+        Vec::new(),
+        args,
+        &mut gas,
+    )?;
+    Ok(GAS_BUDGET - u64::from(gas.remaining_gas()))
+}
+
+/// Serialized Move values of the parameters, given their flattened values.
+fn move_args(params: &[AbiType], values: &[u64]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut values = values.iter().copied();
+    let mut next = |count: usize| -> anyhow::Result<Vec<u64>> {
+        let elements: Vec<u64> = values.by_ref().take(count).collect();
+        anyhow::ensure!(elements.len() == count, "Not enough input values");
+        Ok(elements)
+    };
+    params
+        .iter()
+        .map(|param| {
+            let value = match param {
+                AbiType::Bool => MoveValue::Bool(next(1)?[0] != 0),
+                AbiType::U8 => MoveValue::U8(next(1)?[0].try_into()?),
+                AbiType::U16 => MoveValue::U16(next(1)?[0].try_into()?),
+                AbiType::U32 => MoveValue::U32(next(1)?[0].try_into()?),
+                AbiType::U64 => MoveValue::U64(join_limbs(&next(2)?) as u64),
+                AbiType::U128 => MoveValue::U128(join_limbs(&next(4)?)),
+                AbiType::U256 => {
+                    let limbs: Vec<u32> = next(8)?.into_iter().map(|limb| limb as u32).collect();
+                    let bytes: [u8; 32] = bytes::limbs_to_le_bytes(&limbs)
+                        .try_into()
+                        .map_err(|_| anyhow::anyhow!("Invalid u256 limbs"))?;
+                    MoveValue::U256(U256::from_le_bytes(&bytes))
+                }
+                AbiType::Address => MoveValue::Address(AccountId::new(next(1)?[0])?.to_address()),
+                AbiType::Signer => MoveValue::Signer(AccountId::new(next(1)?[0])?.to_address()),
+                ty => anyhow::bail!("Can't pass a value of type {ty} to the Move VM"),
+            };
+            value
+                .simple_serialize()
+                .with_context(|| format!("Failed to serialize {value:?}"))
+        })
+        .collect()
+}
+
+/// Integer made of 32-bit limbs, the low limb first.
+fn join_limbs(limbs: &[u64]) -> u128 {
+    limbs
+        .iter()
+        .rev()
+        .fold(0, |value, limb| (value << 32) | (*limb as u32) as u128)
+}
@@ -0,0 +1,180 @@
+//! Module replacing calls to small procedures with their bodies.
+//!
+//! An `exec` of a local procedure costs cycles on top of the procedure body, and
+//! keeps stack rewrites (see `schedule` module) from seeing across the call.
+//! Calls are inlined when the called procedure is marked `#[miden(inline)]`, or
+//! when it isn't marked `#[miden(inline(never))]` and its body is estimated by
+//! the cost model to take at most `CostModel::max_inline_cycles`.
+//! Procedures with locals are never inlined, since their locals would be taken
+//! from the caller's, and neither are recursive calls. Inlined procedures are
+//! kept, so procedure indices don't change.
+//!
+//! Procedures are given to the `Inliner` in the order of their indices, as they
+//! are lowered, and can be passed on to the backend right after: only calls to
+//! procedures given before are inlined, so their bodies are final. Only the
+//! bodies of procedures calls are inlined to are kept, for later callers and
+//! the body of the program.
+
+use {
+    crate::{attributes::InlineHint, cost, cost::CostModel, schedule},
+    miden_assembly::ast::{CodeBody, Instruction, Node, ProcedureAst},
+};
+
+pub struct Inliner<'a> {
+    model: &'a dyn CostModel,
+    // Bodies of the procedures given so far, by index, if calls to them are inlined.
+    bodies: Vec<Option<CodeBody>>,
+}
+
+impl<'a> Inliner<'a> {
+    pub fn new(model: &'a dyn CostModel) -> Self {
+        Self {
+            model,
+            bodies: Vec::new(),
+        }
+    }
+
+    /// Inlines calls to the procedures given so far in `procedure`, the
+    /// procedure with the next index, whose inline hint is `hint`.
+    pub fn procedure(&mut self, procedure: &mut ProcedureAst, hint: Option<InlineHint>) {
+        procedure.body = self.body(&procedure.body);
+        let inlined = procedure.num_locals == 0
+            && match hint {
+                Some(InlineHint::Never) => false,
+                Some(InlineHint::Always) => true,
+                None => {
+                    let cost = cost::estimate_with(&procedure.body, self.model);
+                    cost.bounded && cost.cycles <= self.model.max_inline_cycles()
+                }
+            };
+        self.bodies.push(inlined.then(|| procedure.body.clone()));
+    }
+
+    /// Inlines calls to the procedures given so far in `body`.
+    pub fn body(&self, body: &CodeBody) -> CodeBody {
+        let nodes = self.nodes(body.nodes());
+        if nodes == body.nodes() {
+            return body.clone();
+        }
+        // Stack manipulation around the inlined code may now cancel out
+        schedule::optimize(&CodeBody::new(nodes), self.model)
+    }
+
+    /// Number of procedure bodies kept to be inlined.
+    pub fn kept(&self) -> usize {
+        self.bodies.iter().flatten().count()
+    }
+
+    fn nodes(&self, nodes: &[Node]) -> Vec<Node> {
+        let mut result = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            match node {
+                Node::Instruction(Instruction::ExecLocal(index)) => {
+                    match self.bodies.get(*index as usize) {
+                        Some(Some(body)) => result.extend_from_slice(body.nodes()),
+                        _ => result.push(node.clone()),
+                    }
+                }
+                Node::Instruction(_) => result.push(node.clone()),
+                Node::IfElse {
+                    true_case,
+                    false_case,
+                } => result.push(Node::IfElse {
+                    true_case: CodeBody::new(self.nodes(true_case.nodes())),
+                    false_case: CodeBody::new(self.nodes(false_case.nodes())),
+                }),
+                Node::Repeat { times, body } => result.push(Node::Repeat {
+                    times: *times,
+                    body: CodeBody::new(self.nodes(body.nodes())),
+                }),
+                Node::While { body } => result.push(Node::While {
+                    body: CodeBody::new(self.nodes(body.nodes())),
+                }),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::cost::DefaultCostModel, miden_assembly::ast::SourceLocation};
+
+    fn procedure(name: &str, nodes: Vec<Node>) -> ProcedureAst {
+        ProcedureAst {
+            name: name.try_into().unwrap(),
+            docs: None,
+            num_locals: 0,
+            body: CodeBody::new(nodes),
+            start: SourceLocation::default(),
+            is_export: false,
+        }
+    }
+
+    #[test]
+    fn test_inline_hints() {
+        let small = vec![Node::Instruction(Instruction::Add)];
+        let mut procedures = vec![
+            procedure("small", small.clone()),
+            procedure("never", small.clone()),
+            procedure(
+                "large",
+                vec![Node::Instruction(Instruction::U32CheckedDiv); 100],
+            ),
+            procedure(
+                "forced",
+                vec![Node::Instruction(Instruction::U32CheckedDiv); 100],
+            ),
+        ];
+        let mut inliner = Inliner::new(&DefaultCostModel);
+        let hints = [
+            None,
+            Some(InlineHint::Never),
+            None,
+            Some(InlineHint::Always),
+        ];
+        for (procedure, hint) in procedures.iter_mut().zip(hints) {
+            inliner.procedure(procedure, hint);
+        }
+        // Only the bodies of `small` and `forced` are kept
+        assert_eq!(inliner.kept(), 2);
+        let calls: Vec<Node> = (0..4)
+            .map(|index| Node::Instruction(Instruction::ExecLocal(index)))
+            .collect();
+        let body = inliner.body(&CodeBody::new(calls));
+
+        let mut expected = small;
+        expected.push(Node::Instruction(Instruction::ExecLocal(1)));
+        expected.push(Node::Instruction(Instruction::ExecLocal(2)));
+        expected.extend(vec![Node::Instruction(Instruction::U32CheckedDiv); 100]);
+        assert_eq!(body.nodes(), expected);
+    }
+
+    #[test]
+    fn test_recursive_calls_are_kept() {
+        let call = Node::Instruction(Instruction::ExecLocal(0));
+        let mut procedures = vec![procedure("loop", vec![call.clone()])];
+        let mut inliner = Inliner::new(&DefaultCostModel);
+        inliner.procedure(&mut procedures[0], None);
+        let body = inliner.body(&CodeBody::new(vec![call.clone()]));
+        assert_eq!(procedures[0].body.nodes(), [call.clone()]);
+        assert_eq!(body.nodes(), [call]);
+    }
+
+    #[test]
+    fn test_calls_to_later_procedures_are_kept() {
+        let add = vec![Node::Instruction(Instruction::Add)];
+        let call = Node::Instruction(Instruction::ExecLocal(1));
+        let mut procedures = vec![
+            procedure("caller", vec![call.clone()]),
+            procedure("callee", add.clone()),
+        ];
+        let mut inliner = Inliner::new(&DefaultCostModel);
+        for procedure in &mut procedures {
+            inliner.procedure(procedure, None);
+        }
+        // The caller may have been passed on before the callee was lowered
+        assert_eq!(procedures[0].body.nodes(), [call.clone()]);
+        assert_eq!(inliner.body(&CodeBody::new(vec![call])).nodes(), add);
+    }
+}
@@ -0,0 +1,592 @@
+//! Module for lowering Move integers which don't fit in a u32.
+//!
+//! Field elements can't hold every `u64`, so a `u64` takes two stack elements,
+//! its high and low 32-bit limbs, the high limb on top, as expected by the
+//! `std::math::u64` module of the Miden standard library. Arithmetic and
+//! comparisons call the procedures of that module, and the program aborts with
+//! the code of `Check::Overflow` when the result overflows, as Move aborts on
+//! overflow. Limbs are always u32s, as they are checked by the entry prologue
+//! and produced by u32 instructions.
+//!
+//! A `u128` takes four limbs and a `u256` eight, the most significant on top.
+//! The standard library has no arithmetic on them, so it is implemented by
+//! helper procedures shipped with the compiler (see `masm/u128.masm` and
+//! `masm/u256.masm`). They are appended to the local procedures of programs
+//! calling them, after the procedures of the module, so each helper is
+//! emitted once however often it is used.
+//!
+//! Divisions and remainders abort with `Check::DivisionByZero` when the divisor is
+//! zero, whatever the width of the integers, as the Move VM does, rather than
+//! failing inside a Miden instruction or procedure.
+//!
+//! Casts add zero high limbs when widening, and abort with the code of
+//! `Check::CastOutOfRange` when narrowing a value which doesn't fit, as in Move.
+//!
+//! `u8` and `u16` values take a single element like `u32` values. Arithmetic on
+//! them which may overflow (see `ranges` module) uses overflowing u32
+//! instructions whose overflow flag must be zero, followed by a check of the
+//! result against the largest value of the type.
+//!
+//! Shifts abort with `Check::InvalidShift` when the shift amount is at least the
+//! number of bits of the type, as in Move, and left shifts drop the bits
+//! shifted out. Shifts of `u128` and `u256` values aren't supported yet.
+//!
+//! Bitwise operations apply the u32 instruction to each pair of limbs, except
+//! on `u64` values, for which the standard library has procedures.
+//!
+//! Flattened into a list of elements, such as the inputs and outputs of the
+//! entry function, integers start with their low limb, e.g. a `u64` is its
+//! low limb followed by its high limb.
+
+use {
+    crate::{
+        abi, bytes, diagnostics,
+        error_map::{Check, ErrorCodes},
+        intrinsics,
+    },
+    miden::math::Felt,
+    miden_assembly::ast::{CodeBody, Instruction, ModuleAst, Node, ProcedureAst},
+    move_binary_format::file_format::{Bytecode, SignatureToken},
+    move_core_types::u256::U256,
+};
+
+/// Standard library module implementing u64 arithmetic.
+const U64_MODULE: &str = "std::math::u64";
+
+/// Procedures of `U64_MODULE` lowering u64 instructions, with the steps run on
+/// their results. Arithmetic procedures leave an overflow flag (or the high
+/// limbs of the full product) on top, which must be zero.
+const U64_OPS: &[(Bytecode, &str, &[Step])] = &[
+    (Bytecode::Add, "overflowing_add", &[Overflow]),
+    (Bytecode::Sub, "overflowing_sub", &[Overflow]),
+    (
+        Bytecode::Mul,
+        "overflowing_mul",
+        &[Inline(Instruction::Add), Overflow],
+    ),
+    (Bytecode::Div, "div", &[]),
+    (Bytecode::Mod, "mod", &[]),
+    (Bytecode::Lt, "lt", &[]),
+    (Bytecode::Gt, "gt", &[]),
+    (Bytecode::Le, "lte", &[]),
+    (Bytecode::Ge, "gte", &[]),
+    (Bytecode::Eq, "eq", &[]),
+    (Bytecode::Neq, "neq", &[]),
+    (Bytecode::BitAnd, "and", &[]),
+    (Bytecode::BitOr, "or", &[]),
+    (Bytecode::Xor, "xor", &[]),
+    (Bytecode::Shl, "shl", &[]),
+    (Bytecode::Shr, "shr", &[]),
+];
+
+/// Standard library procedures which may be called by generated code, as
+/// library path and procedure name.
+pub fn imports() -> impl Iterator<Item = (&'static str, &'static str)> {
+    U64_OPS
+        .iter()
+        .map(|(_, procedure, _)| (U64_MODULE, *procedure))
+}
+
+/// Pushes the limbs of a u64 constant.
+pub fn push_u64(x: u64) -> Vec<Node> {
+    vec![
+        Node::Instruction(Instruction::PushU32(x as u32)),
+        Node::Instruction(Instruction::PushU32((x >> 32) as u32)),
+    ]
+}
+
+/// Lowering of an arithmetic or comparison instruction on two u64s, or `None`
+/// for other instructions.
+pub fn u64_binary_op(bytecode: &Bytecode, codes: &ErrorCodes) -> anyhow::Result<Option<Vec<Node>>> {
+    let Some((_, procedure, after)) = U64_OPS.iter().find(|(op, _, _)| op == bytecode) else {
+        return Ok(None);
+    };
+    let mut result = vec![intrinsics::exec_imported(U64_MODULE, procedure)?];
+    result.extend(after.iter().map(|step| step.lower(0, codes)));
+    Ok(Some(result))
+}
+
+/// Sources of the helper procedures, in the order they are appended.
+const HELPER_SOURCES: &[&str] = &[
+    include_str!("masm/u128.masm"),
+    include_str!("masm/u256.masm"),
+];
+
+/// Names of the helper procedures, in the order of `HELPER_SOURCES`.
+const HELPER_NAMES: &[&str] = &[
+    U128_ADD,
+    U128_SUB,
+    U128_MUL,
+    U128_DIVMOD,
+    U256_ADD,
+    U256_SUB,
+    U256_MUL,
+    U256_DIVMOD,
+    U256_EQ,
+];
+
+const U128_ADD: &str = "u128_overflowing_add";
+const U128_SUB: &str = "u128_overflowing_sub";
+const U128_MUL: &str = "u128_checked_mul";
+const U128_DIVMOD: &str = "u128_divmod";
+const U256_ADD: &str = "u256_overflowing_add";
+const U256_SUB: &str = "u256_overflowing_sub";
+const U256_MUL: &str = "u256_checked_mul";
+const U256_DIVMOD: &str = "u256_divmod";
+const U256_EQ: &str = "u256_eq";
+
+#[derive(Debug, Clone)]
+enum Step {
+    // Call the helper procedure of the given name.
+    Helper(&'static str),
+    Inline(Instruction),
+    // Abort with the code of `Check::Overflow` unless the flag on top is zero.
+    Overflow,
+}
+
+use Step::{Helper, Inline, Overflow};
+
+impl Step {
+    /// Node of the step, given the index of the first helper procedure.
+    fn lower(&self, helpers: u16, codes: &ErrorCodes) -> Node {
+        Node::Instruction(match self {
+            Helper(name) => {
+                let index = HELPER_NAMES
+                    .iter()
+                    .position(|helper| helper == name)
+                    .expect("Unknown integer helper");
+                Instruction::ExecLocal(helpers + index as u16)
+            }
+            Inline(instruction) => instruction.clone(),
+            Overflow => Instruction::AssertzWithError(codes.code(Check::Overflow)),
+        })
+    }
+}
+
+/// Lowerings of u128 instructions. The overflow flags of addition and
+/// subtraction must be zero, and a < b when a - b borrows.
+const U128_OPS: &[(Bytecode, &[Step])] = &[
+    (Bytecode::Add, &[Helper(U128_ADD), Overflow]),
+    (Bytecode::Sub, &[Helper(U128_SUB), Overflow]),
+    (Bytecode::Mul, &[Helper(U128_MUL)]),
+    // The remainder is left on top of the quotient
+    (
+        Bytecode::Div,
+        &[Helper(U128_DIVMOD), Inline(Instruction::DropW)],
+    ),
+    (
+        Bytecode::Mod,
+        &[
+            Helper(U128_DIVMOD),
+            Inline(Instruction::SwapW),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Lt,
+        &[
+            Helper(U128_SUB),
+            Inline(Instruction::MovDn4),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Gt,
+        &[
+            Inline(Instruction::SwapW),
+            Helper(U128_SUB),
+            Inline(Instruction::MovDn4),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Le,
+        &[
+            Inline(Instruction::SwapW),
+            Helper(U128_SUB),
+            Inline(Instruction::MovDn4),
+            Inline(Instruction::DropW),
+            Inline(Instruction::Not),
+        ],
+    ),
+    (
+        Bytecode::Ge,
+        &[
+            Helper(U128_SUB),
+            Inline(Instruction::MovDn4),
+            Inline(Instruction::DropW),
+            Inline(Instruction::Not),
+        ],
+    ),
+    (
+        Bytecode::Eq,
+        &[
+            Inline(Instruction::Eqw),
+            Inline(Instruction::MovDn8),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Neq,
+        &[
+            Inline(Instruction::Eqw),
+            Inline(Instruction::Not),
+            Inline(Instruction::MovDn8),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+        ],
+    ),
+];
+
+/// Lowerings of u256 instructions, as for u128s with two words per value.
+const U256_OPS: &[(Bytecode, &[Step])] = &[
+    (Bytecode::Add, &[Helper(U256_ADD), Overflow]),
+    (Bytecode::Sub, &[Helper(U256_SUB), Overflow]),
+    (Bytecode::Mul, &[Helper(U256_MUL)]),
+    (
+        Bytecode::Div,
+        &[
+            Helper(U256_DIVMOD),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Mod,
+        &[
+            Helper(U256_DIVMOD),
+            Inline(Instruction::SwapDw),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Lt,
+        &[
+            Helper(U256_SUB),
+            Inline(Instruction::MovDn8),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Gt,
+        &[
+            Inline(Instruction::SwapDw),
+            Helper(U256_SUB),
+            Inline(Instruction::MovDn8),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+        ],
+    ),
+    (
+        Bytecode::Le,
+        &[
+            Inline(Instruction::SwapDw),
+            Helper(U256_SUB),
+            Inline(Instruction::MovDn8),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+            Inline(Instruction::Not),
+        ],
+    ),
+    (
+        Bytecode::Ge,
+        &[
+            Helper(U256_SUB),
+            Inline(Instruction::MovDn8),
+            Inline(Instruction::DropW),
+            Inline(Instruction::DropW),
+            Inline(Instruction::Not),
+        ],
+    ),
+    (Bytecode::Eq, &[Helper(U256_EQ)]),
+    (Bytecode::Neq, &[Helper(U256_EQ), Inline(Instruction::Not)]),
+];
+
+/// Pushes 32-bit limbs, the low limb first.
+fn push_limbs(limbs: impl IntoIterator<Item = u32>) -> Vec<Node> {
+    limbs
+        .into_iter()
+        .map(|limb| Node::Instruction(Instruction::PushU32(limb)))
+        .collect()
+}
+
+/// Pushes the limbs of a u128 constant.
+pub fn push_u128(x: u128) -> Vec<Node> {
+    push_limbs((0..4).map(|i| (x >> (32 * i)) as u32))
+}
+
+/// Pushes the limbs of a u256 constant.
+pub fn push_u256(x: &U256) -> Vec<Node> {
+    push_limbs(bytes::limbs_from_le_bytes(&x.to_le_bytes()))
+}
+
+/// Lowering of an arithmetic or comparison instruction on two u128s or u256s
+/// of type `token`, or `None` for other instructions and types. `helpers` is
+/// the index of the first helper procedure.
+pub fn wide_binary_op(
+    token: &SignatureToken,
+    bytecode: &Bytecode,
+    helpers: u16,
+    codes: &ErrorCodes,
+) -> Option<Vec<Node>> {
+    let ops = match token {
+        SignatureToken::U128 => U128_OPS,
+        SignatureToken::U256 => U256_OPS,
+        _ => return None,
+    };
+    let (_, steps) = ops.iter().find(|(op, _)| op == bytecode)?;
+    Some(
+        steps
+            .iter()
+            .map(|step| step.lower(helpers, codes))
+            .collect(),
+    )
+}
+
+/// Number of limbs of an integer type, and the largest value of its low limb
+/// when it is the only one.
+fn limbs(token: &SignatureToken) -> anyhow::Result<(usize, u32)> {
+    Ok(match token {
+        SignatureToken::U8 => (1, u8::MAX as u32),
+        SignatureToken::U16 => (1, u16::MAX as u32),
+        SignatureToken::U32 => (1, u32::MAX),
+        SignatureToken::U64 => (2, u32::MAX),
+        SignatureToken::U128 => (4, u32::MAX),
+        SignatureToken::U256 => (8, u32::MAX),
+        token => anyhow::bail!("Cast of a value of type {token:?}"),
+    })
+}
+
+/// Lowering of a cast between integer types, aborting with the code of
+/// `Check::CastOutOfRange` when the value doesn't fit in the narrower type.
+pub fn cast(
+    from: &SignatureToken,
+    to: &SignatureToken,
+    codes: &ErrorCodes,
+) -> anyhow::Result<Vec<Node>> {
+    let (from_limbs, from_max) = limbs(from)?;
+    let (to_limbs, to_max) = limbs(to)?;
+    let code = codes.code(Check::CastOutOfRange);
+    let mut result = Vec::new();
+    for _ in to_limbs..from_limbs {
+        // High limbs must be zero
+        result.push(Node::Instruction(Instruction::AssertzWithError(code)));
+    }
+    for _ in from_limbs..to_limbs {
+        result.push(Node::Instruction(Instruction::PushU32(0)));
+    }
+    if to_max < from_max {
+        // The low limb must fit in the narrower type
+        result.extend(range_check(to_max, code));
+    }
+    Ok(result)
+}
+
+/// Aborts with the code of `Check::DivisionByZero` if the integer of the given
+/// type on top of the stack, the divisor of a `Div` or `Mod`, is zero. Limbs are
+/// u32s, so the integer is zero exactly when the sum of its limbs is.
+pub fn check_divisor(token: &SignatureToken, codes: &ErrorCodes) -> anyhow::Result<Vec<Node>> {
+    let (limbs, _) = limbs(token)?;
+    let mut result: Vec<Node> = (0..limbs)
+        .map(|_| Node::Instruction(abi::dup(limbs - 1)))
+        .collect();
+    result.extend((1..limbs).map(|_| Node::Instruction(Instruction::Add)));
+    result.extend([
+        Node::Instruction(Instruction::NeqImm(Felt::new(0))),
+        Node::Instruction(Instruction::AssertWithError(
+            codes.code(Check::DivisionByZero),
+        )),
+    ]);
+    Ok(result)
+}
+
+/// Checked `Add`, `Sub` or `Mul` of integers taking a single element, aborting
+/// with the code of `Check::Overflow` when the result doesn't fit in their type.
+pub fn checked_op(
+    token: &SignatureToken,
+    bytecode: &Bytecode,
+    codes: &ErrorCodes,
+) -> Option<Vec<Node>> {
+    let max = match token {
+        SignatureToken::U8 => u8::MAX as u32,
+        SignatureToken::U16 => u16::MAX as u32,
+        SignatureToken::U32 => u32::MAX,
+        _ => return None,
+    };
+    // The operands are u32s, and the overflow flag, borrow or high limb of the
+    // product is left on top of the result
+    let instruction = match bytecode {
+        Bytecode::Add => Instruction::U32OverflowingAdd,
+        Bytecode::Sub => Instruction::U32OverflowingSub,
+        Bytecode::Mul => Instruction::U32OverflowingMul,
+        _ => return None,
+    };
+    let code = codes.code(Check::Overflow);
+    let mut result = vec![
+        Node::Instruction(instruction),
+        Node::Instruction(Instruction::AssertzWithError(code)),
+    ];
+    // A difference is never larger than the value it is subtracted from
+    if max < u32::MAX && *bytecode != Bytecode::Sub {
+        result.extend(range_check(max, code));
+    }
+    Some(result)
+}
+
+/// Lowering of `BitAnd`, `BitOr` or `Xor` on two integers of type `token`
+/// other than `u64` (see `u64_binary_op`), or `None` for other instructions.
+pub fn bitwise_op(
+    token: &SignatureToken,
+    bytecode: &Bytecode,
+) -> anyhow::Result<Option<Vec<Node>>> {
+    let instruction = match bytecode {
+        Bytecode::BitAnd => Instruction::U32And,
+        Bytecode::BitOr => Instruction::U32Or,
+        Bytecode::Xor => Instruction::U32Xor,
+        _ => return Ok(None),
+    };
+    let (limbs, _) = limbs(token)?;
+    // Starting from the top limbs, the result limb goes below the limbs left
+    // and above the result limbs already computed, keeping the limb order
+    let mut result = Vec::new();
+    for left in (1..=limbs).rev() {
+        // The low limbs end up on top, in either order as the operations commute
+        if left > 1 {
+            result.extend(abi::movup(left));
+        }
+        result.push(instruction.clone());
+        result.extend(abi::movdn(2 * (left - 1) + limbs - left));
+    }
+    Ok(Some(result.into_iter().map(Node::Instruction).collect()))
+}
+
+/// Lowering of `Shl` or `Shr` of an integer of type `token` by the `u8` on top
+/// of it, or `None` for other instructions.
+pub fn shift(
+    token: &SignatureToken,
+    bytecode: &Bytecode,
+    codes: &ErrorCodes,
+) -> anyhow::Result<Option<Vec<Node>>> {
+    if !matches!(bytecode, Bytecode::Shl | Bytecode::Shr) {
+        return Ok(None);
+    }
+    let (bits, max) = match token {
+        SignatureToken::U8 => (8, u8::MAX as u32),
+        SignatureToken::U16 => (16, u16::MAX as u32),
+        SignatureToken::U32 => (32, u32::MAX),
+        SignatureToken::U64 => (64, u32::MAX),
+        token => {
+            return Err(diagnostics::UNSUPPORTED_TYPE
+                .error(format!("Can't shift values of type {token:?} yet")))
+        }
+    };
+    let mut result = vec![
+        Node::Instruction(Instruction::Dup0),
+        Node::Instruction(Instruction::PushU32(bits)),
+        Node::Instruction(Instruction::Lt),
+        Node::Instruction(Instruction::AssertWithError(
+            codes.code(Check::InvalidShift),
+        )),
+    ];
+    if *token == SignatureToken::U64 {
+        result.extend(u64_binary_op(bytecode, codes)?.unwrap_or_default());
+        return Ok(Some(result));
+    }
+    if *bytecode == Bytecode::Shr {
+        result.push(Node::Instruction(Instruction::U32CheckedShr));
+        return Ok(Some(result));
+    }
+    result.push(Node::Instruction(Instruction::U32CheckedShl));
+    if max < u32::MAX {
+        // Bits shifted out of narrower types are still in the u32 result
+        result.extend([
+            Node::Instruction(Instruction::PushU32(max)),
+            Node::Instruction(Instruction::U32And),
+        ]);
+    }
+    Ok(Some(result))
+}
+
+/// Aborts with `code` unless the element on top of the stack is at most `max`.
+fn range_check(max: u32, code: u32) -> [Node; 4] {
+    [
+        Node::Instruction(Instruction::Dup0),
+        Node::Instruction(Instruction::PushU32(max)),
+        Node::Instruction(Instruction::Lte),
+        Node::Instruction(Instruction::AssertWithError(code)),
+    ]
+}
+
+/// The helper procedures, to be appended to the local procedures. Their
+/// assertions abort with the code of `Check::DivisionByZero` in divisions,
+/// and of `Check::Overflow` otherwise.
+pub fn helpers(codes: &ErrorCodes) -> anyhow::Result<Vec<ProcedureAst>> {
+    let mut procedures = Vec::new();
+    for source in HELPER_SOURCES {
+        let module = ModuleAst::parse(source)
+            .map_err(|e| anyhow::anyhow!("Failed to parse integer helpers: {e}"))?;
+        procedures.extend_from_slice(module.procs());
+    }
+    let names: Vec<String> = procedures.iter().map(|p| p.name.to_string()).collect();
+    anyhow::ensure!(
+        names == HELPER_NAMES,
+        "Unexpected integer helpers {names:?}"
+    );
+    for (procedure, name) in procedures.iter_mut().zip(HELPER_NAMES) {
+        let check = if [U128_DIVMOD, U256_DIVMOD].contains(name) {
+            Check::DivisionByZero
+        } else {
+            Check::Overflow
+        };
+        let body = with_error_code(procedure.body.nodes(), codes.code(check));
+        procedure.body = CodeBody::new(body);
+    }
+    Ok(procedures)
+}
+
+/// Copy of the code whose assertions abort with `code`.
+fn with_error_code(nodes: &[Node], code: u32) -> Vec<Node> {
+    let body = |body: &CodeBody| CodeBody::new(with_error_code(body.nodes(), code));
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Instruction(Instruction::Assert) => {
+                Node::Instruction(Instruction::AssertWithError(code))
+            }
+            Node::Instruction(Instruction::Assertz) => {
+                Node::Instruction(Instruction::AssertzWithError(code))
+            }
+            Node::Instruction(_) => node.clone(),
+            Node::IfElse {
+                true_case,
+                false_case,
+            } => Node::IfElse {
+                true_case: body(true_case),
+                false_case: body(false_case),
+            },
+            Node::Repeat { times, body: inner } => Node::Repeat {
+                times: *times,
+                body: body(inner),
+            },
+            Node::While { body: inner } => Node::While { body: body(inner) },
+        })
+        .collect()
+}
+
+/// Whether the code calls a helper procedure, given the index of the first one.
+pub fn calls_helpers(nodes: &[Node], helpers: u16) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Instruction(Instruction::ExecLocal(index)) => *index >= helpers,
+        Node::Instruction(_) => false,
+        Node::IfElse {
+            true_case,
+            false_case,
+        } => {
+            calls_helpers(true_case.nodes(), helpers) || calls_helpers(false_case.nodes(), helpers)
+        }
+        Node::Repeat { body, .. } | Node::While { body } => calls_helpers(body.nodes(), helpers),
+    })
+}
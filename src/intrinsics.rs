@@ -0,0 +1,588 @@
+//! Module for lowering Move native functions to Miden procedures.
+//!
+//! Native functions have no bytecode, so calls to them must be mapped onto
+//! an existing Miden procedure. Intrinsics are identified by the name of the
+//! Move module declaring them and the function name; the address of the
+//! module is not taken into account.
+//!
+//! The `aptos` feature adds intrinsics for Aptos framework functions, so that
+//! contracts written against the framework can be compiled with few changes.
+//! Module authors can also bind native functions to Miden procedures in the
+//! sources, with `#[miden(intrinsic = b"path::to::procedure")]`.
+//!
+//! Account code and note scripts run inside the Miden transaction kernel,
+//! whose procedures (such as reading the account ID) aren't available to
+//! standalone programs. Intrinsics calling the kernel are only used when
+//! compiling for a context providing it (see `compiler::TargetContext`); an
+//! intrinsic may also have a pure MASM lowering for standalone programs, listed
+//! after its kernel lowerings, and calls to natives only available in other
+//! contexts fail to compile.
+//!
+//! Output notes are created with `note::create(faucet, amount, tag, recipient)`,
+//! whose tag and recipient must be constants. Their encodings are checked at
+//! compile time, since the kernel would only reject a malformed note when the
+//! transaction is executed, or accept a note no account can ever consume.
+
+use {
+    crate::{
+        bytes,
+        compiler::TargetContext,
+        diagnostics,
+        error_map::{Check, ErrorCodes},
+        memory::{self, MemoryMap},
+        merkle, oracle, recursion,
+    },
+    anyhow::Context,
+    miden::{
+        crypto::Rpo256,
+        math::{Felt, StarkField},
+    },
+    miden_assembly::{
+        ast::{Instruction, Node},
+        LibraryPath, ProcedureId,
+    },
+};
+
+/// A Move native function with a known Miden lowering.
+#[derive(Debug, Clone, Copy)]
+pub struct Intrinsic {
+    pub module: &'static str,
+    pub function: &'static str,
+    pub lowering: Lowering,
+}
+
+/// How a call to an intrinsic is turned into Miden code.
+/// Arguments are expected on the stack exactly as the Miden procedure
+/// expects them, and results are left on the stack as the procedure produces them.
+#[derive(Debug, Clone, Copy)]
+pub enum Lowering {
+    // Execute a procedure from the Miden standard library.
+    StdLib {
+        path: &'static str,
+        procedure: &'static str,
+    },
+    // Emit the given instructions inline.
+    Inline(&'static [Instruction]),
+    // Emit code generated at compile time from the memory map and error codes of
+    // the program, e.g. calling a procedure of the standard library between other
+    // instructions.
+    Generated(fn(&MemoryMap, &ErrorCodes) -> anyhow::Result<Vec<Node>>),
+    // Like `Generated`, for code allocating memory whose size is only known at
+    // runtime, given whether allocations are framed by canaries.
+    Allocating(fn(&MemoryMap, bool) -> Vec<Node>),
+    // Execute a procedure of the transaction kernel library, followed by the given
+    // instructions, when compiling for one of the given contexts.
+    Kernel {
+        path: &'static str,
+        procedure: &'static str,
+        then: &'static [Instruction],
+        contexts: &'static [TargetContext],
+    },
+    // The last argument is only accepted as a constant byte vector, turned into
+    // code at compile time, e.g. pushing the result directly.
+    Fold(fn(&[u8]) -> anyhow::Result<Vec<Node>>),
+    // Like `Kernel`, with the last two arguments, a u32 and a byte vector, only
+    // accepted as constants; they are validated at compile time by `fold`, which
+    // returns the code preparing the inputs of the procedure.
+    KernelFold {
+        path: &'static str,
+        procedure: &'static str,
+        fold: fn(u32, &[u8], &ErrorCodes) -> anyhow::Result<Vec<Node>>,
+        contexts: &'static [TargetContext],
+    },
+    // Drop the arguments, the function being generic so their width is only
+    // known at the call.
+    Discard,
+    // There is no Miden implementation available for this intrinsic.
+    Unsupported {
+        reason: &'static str,
+    },
+}
+
+const INTRINSICS: &[Intrinsic] = &[
+    // Digests are passed as 8 u32 limbs both as input and output.
+    Intrinsic {
+        module: "hash",
+        function: "keccak256",
+        lowering: Lowering::StdLib {
+            path: "std::crypto::hashes::keccak256",
+            procedure: "hash",
+        },
+    },
+    // rpo256(data: vector<u8>): (u64, u64, u64, u64), the last digest element on top,
+    // each element split into u32 limbs like any u64. The bytes are hashed packed
+    // 4 per element (see `bytes::pack_bytes`).
+    Intrinsic {
+        module: "hash",
+        function: "rpo256",
+        lowering: Lowering::Fold(rpo256),
+    },
+    // pack(data: vector<u8>): vector<u32>, the elements hashed for the bytes
+    Intrinsic {
+        module: "hash",
+        function: "pack",
+        lowering: Lowering::Allocating(memory::pack_bytes),
+    },
+    // read(feed: u32, key: vector<u8>): (u64, u64), the value and timestamp of
+    // the latest report of the feed signed by the oracle (see `oracle` module)
+    Intrinsic {
+        module: "oracle",
+        function: "read",
+        lowering: Lowering::Fold(oracle::read),
+    },
+    // verify(num_queries: u32, grinding: u32), verifying a proof provided by the
+    // host, whose stack inputs and outputs are then read by input(index: u32): u64
+    // and output(index: u32): u64 (see `recursion` module)
+    Intrinsic {
+        module: "recursion",
+        function: "verify",
+        lowering: Lowering::Generated(recursion::verify),
+    },
+    Intrinsic {
+        module: "recursion",
+        function: "input",
+        lowering: Lowering::Generated(recursion::read_input),
+    },
+    Intrinsic {
+        module: "recursion",
+        function: "output",
+        lowering: Lowering::Generated(recursion::read_output),
+    },
+    // Out of scope until the standard library provides blake2b: a bundled MASM
+    // implementation would have to be audited and kept in sync by this crate.
+    Intrinsic {
+        module: "hash",
+        function: "blake2b_256",
+        lowering: Lowering::Unsupported {
+            reason: "the Miden standard library does not provide blake2b",
+        },
+    },
+    // verify(r0, r1, r2, r3: u64, index: u64, depth: u8, l0, l1, l2, l3: u64),
+    // failing unless the leaf is part of the tree committed to by the root,
+    // whose path is provided by the host (see `merkle` module)
+    Intrinsic {
+        module: "merkle",
+        function: "verify",
+        lowering: Lowering::Generated(merkle::verify),
+    },
+];
+
+/// Library of the procedures of the transaction kernel.
+const KERNEL_LIBRARY: &str = "miden";
+
+/// Transaction kernel procedures. Account IDs are single elements, like the
+/// addresses they map to (see `accounts` module).
+const KERNEL_INTRINSICS: &[Intrinsic] = &[
+    Intrinsic {
+        module: "account",
+        function: "get_id",
+        lowering: Lowering::Kernel {
+            path: "miden::account",
+            procedure: "get_id",
+            then: &[],
+            contexts: &[TargetContext::Account, TargetContext::NoteScript],
+        },
+    },
+    // incr_nonce(value: u32)
+    Intrinsic {
+        module: "account",
+        function: "incr_nonce",
+        lowering: Lowering::Kernel {
+            path: "miden::account",
+            procedure: "incr_nonce",
+            then: &[],
+            contexts: &[TargetContext::Account],
+        },
+    },
+    // get_balance(faucet: address): u64, balances being below 2^63
+    Intrinsic {
+        module: "account",
+        function: "get_balance",
+        lowering: Lowering::Kernel {
+            path: "miden::account",
+            procedure: "get_balance",
+            then: &[Instruction::U32Split],
+            contexts: &[TargetContext::Account, TargetContext::NoteScript],
+        },
+    },
+    Intrinsic {
+        module: "note",
+        function: "get_sender",
+        lowering: Lowering::Kernel {
+            path: "miden::note",
+            procedure: "get_sender",
+            then: &[],
+            contexts: &[TargetContext::NoteScript],
+        },
+    },
+    // get_block_number(): u32
+    Intrinsic {
+        module: "tx",
+        function: "get_block_number",
+        lowering: Lowering::Kernel {
+            path: "miden::tx",
+            procedure: "get_block_number",
+            then: &[],
+            contexts: &[TargetContext::Account, TargetContext::NoteScript],
+        },
+    },
+    // Standalone programs are given the block number by the host.
+    Intrinsic {
+        module: "tx",
+        function: "get_block_number",
+        lowering: Lowering::Inline(&[Instruction::AdvPush(1), Instruction::U32Assert]),
+    },
+    // create(faucet: address, amount: u64, tag: u32, recipient: vector<u8>): u32,
+    // creating a private note holding `amount` of the fungible asset of `faucet`
+    // and returning the pointer to the note
+    Intrinsic {
+        module: "note",
+        function: "create",
+        lowering: Lowering::KernelFold {
+            path: "miden::tx",
+            procedure: "create_note",
+            fold: create_note,
+            contexts: &[TargetContext::Account, TargetContext::NoteScript],
+        },
+    },
+];
+
+/// Note type of the notes created by `note::create`: only their hash is
+/// published on chain.
+const NOTE_TYPE_PRIVATE: u32 = 2;
+
+/// Validates the tag and recipient of a note and turns the arguments of
+/// `note::create` into the inputs of the kernel's `create_note`,
+/// `[ASSET, tag, note_type, RECIPIENT]`.
+///
+/// The recipient is the digest committing to the serial number, script and
+/// inputs of the note, encoded as its 4 elements in little-endian order, 8
+/// bytes each. The two most significant bits of the tag give the execution
+/// mode of the note, and network execution (`0b00` and `0b01`) requires public
+/// notes. Notes with an amount above the maximum amount of a fungible asset,
+/// 2^63 - 1, abort with the code of `Check::InvalidNoteAmount`.
+fn create_note(tag: u32, recipient: &[u8], codes: &ErrorCodes) -> anyhow::Result<Vec<Node>> {
+    let recipient = bytes::word_from_le_bytes(recipient)
+        .map_err(|e| diagnostics::INVALID_NOTE.error(format!("Invalid note recipient: {e}")))?;
+    if recipient.iter().all(|e| e.as_int() == 0) {
+        return Err(diagnostics::INVALID_NOTE
+            .error("Note recipient is zero, so the note could never be consumed"));
+    }
+    if tag >> 31 == 0 {
+        return Err(diagnostics::INVALID_NOTE.error(format!(
+            "Note tag {tag:#010x} requests network execution, which requires public notes, \
+             but notes are created private"
+        )));
+    }
+    // [amount_hi, amount_lo, faucet]
+    let mut nodes = vec![
+        Instruction::Dup0,
+        Instruction::PushU32(1 << 31),
+        Instruction::Lt,
+        Instruction::AssertWithError(codes.code(Check::InvalidNoteAmount)),
+        Instruction::MulImm(Felt::new(1 << 32)),
+        Instruction::Add,
+    ];
+    // [amount, faucet] -> [tag, note_type, RECIPIENT, amount, faucet]
+    nodes.extend(recipient.into_iter().map(Instruction::PushFelt));
+    nodes.extend([
+        Instruction::PushU32(NOTE_TYPE_PRIVATE),
+        Instruction::PushU32(tag),
+        // The fungible asset word is [faucet, 0, 0, amount]
+        Instruction::MovUp7,
+        Instruction::MovUp7,
+        Instruction::Swap1,
+        Instruction::PushU32(0),
+        Instruction::PushU32(0),
+        Instruction::MovUp2,
+    ]);
+    Ok(nodes.into_iter().map(Node::Instruction).collect())
+}
+
+fn rpo256(bytes: &[u8]) -> anyhow::Result<Vec<Node>> {
+    Ok(Rpo256::hash_elements(&bytes::pack_bytes(bytes)?)
+        .as_elements()
+        .iter()
+        .flat_map(|felt| {
+            [
+                Node::Instruction(Instruction::PushFelt(*felt)),
+                Node::Instruction(Instruction::U32Split),
+            ]
+        })
+        .collect())
+}
+
+/// Aptos framework functions. Values only known to the chain (such as the
+/// current time) are provided by the host through the advice stack, as a
+/// single element split into the limbs of a `u64`. Nothing checks these values:
+/// `timestamp::now_seconds` and `now_microseconds` return whatever time the
+/// prover chooses, so proofs don't attest to when the program ran.
+/// Coins and accounts live in the Miden rollup kernel, which is not part of
+/// the standard library, so they cannot be lowered yet.
+#[cfg(feature = "aptos")]
+const APTOS_INTRINSICS: &[Intrinsic] = &[
+    Intrinsic {
+        module: "timestamp",
+        function: "now_seconds",
+        lowering: Lowering::Inline(&[Instruction::AdvPush(1), Instruction::U32Split]),
+    },
+    Intrinsic {
+        module: "timestamp",
+        function: "now_microseconds",
+        lowering: Lowering::Inline(&[Instruction::AdvPush(1), Instruction::U32Split]),
+    },
+    // emit<T>(msg: T). Miden has no event log; events are discarded so emitting
+    // code still runs.
+    Intrinsic {
+        module: "event",
+        function: "emit",
+        lowering: Lowering::Discard,
+    },
+    Intrinsic {
+        module: "account",
+        function: "exists_at",
+        lowering: Lowering::Unsupported {
+            reason: "accounts require the Miden rollup kernel",
+        },
+    },
+    Intrinsic {
+        module: "account",
+        function: "get_sequence_number",
+        lowering: Lowering::Unsupported {
+            reason: "accounts require the Miden rollup kernel",
+        },
+    },
+    Intrinsic {
+        module: "coin",
+        function: "balance",
+        lowering: Lowering::Unsupported {
+            reason: "assets require the Miden rollup kernel",
+        },
+    },
+    Intrinsic {
+        module: "coin",
+        function: "transfer",
+        lowering: Lowering::Unsupported {
+            reason: "assets require the Miden rollup kernel",
+        },
+    },
+];
+
+#[cfg(not(feature = "aptos"))]
+const APTOS_INTRINSICS: &[Intrinsic] = &[];
+
+/// Looks up the intrinsic for the native function `module::function` in the
+/// given context.
+pub fn find(module: &str, function: &str, context: TargetContext) -> Option<&'static Intrinsic> {
+    all().find(|i| i.module == module && i.function == function && i.available_in(context))
+}
+
+/// Like `find`, failing if the native function has no intrinsic in the context.
+pub fn lookup(
+    module: &str,
+    function: &str,
+    context: TargetContext,
+) -> anyhow::Result<&'static Intrinsic> {
+    if let Some(intrinsic) = find(module, function, context) {
+        return Ok(intrinsic);
+    }
+    if let Some(message) = context_error(module, function, context) {
+        return Err(diagnostics::TARGET_CONTEXT.error(message));
+    }
+    Err(diagnostics::UNSUPPORTED_NATIVE.error(format!(
+        "Call to unknown external function {module}::{function}"
+    )))
+}
+
+/// Why the native function `module::function` can't be called in the context,
+/// if it only has intrinsics in other contexts.
+pub fn context_error(module: &str, function: &str, context: TargetContext) -> Option<String> {
+    if find(module, function, context).is_some() {
+        return None;
+    }
+    let contexts: Vec<String> = TargetContext::ALL
+        .into_iter()
+        .filter(|other| find(module, function, *other).is_some())
+        .map(|other| other.to_string())
+        .collect();
+    (!contexts.is_empty()).then(|| {
+        format!(
+            "Native function {module}::{function} requires {} context, but the program is \
+             compiled for {context} context",
+            contexts.join(" or ")
+        )
+    })
+}
+
+/// Why the procedure at `path`, bound to a native function by an `intrinsic`
+/// attribute, can't be called in the context, if it belongs to the transaction
+/// kernel library and the context has no kernel.
+pub fn bound_context_error(path: &str, context: TargetContext) -> Option<String> {
+    let in_kernel = path
+        .split("::")
+        .next()
+        .is_some_and(|library| library == KERNEL_LIBRARY);
+    (in_kernel && context == TargetContext::Standalone).then(|| {
+        format!(
+            "Procedure {path} of the transaction kernel requires account or note-script \
+             context, but the program is compiled for {context} context"
+        )
+    })
+}
+
+/// Lowers a call to a native function bound to the Miden procedure at `path`
+/// (e.g. `std::math::u64::wrapping_add`) by an `intrinsic` attribute.
+pub fn bind(path: &str) -> anyhow::Result<Vec<Node>> {
+    let (module, procedure) = path
+        .rsplit_once("::")
+        .ok_or_else(|| anyhow::anyhow!("Invalid procedure path {path}"))?;
+    Ok(vec![exec_imported(module, procedure)?])
+}
+
+pub fn exec_imported(path: &str, procedure: &str) -> anyhow::Result<Node> {
+    let library_path = LibraryPath::new(path)
+        .map_err(|e| anyhow::anyhow!("Invalid library path {path}: {e:?}"))?;
+    let id = ProcedureId::from_name(procedure, &library_path);
+    Ok(Node::Instruction(Instruction::ExecImported(id)))
+}
+
+/// All intrinsics, in no particular order.
+pub fn all() -> impl Iterator<Item = &'static Intrinsic> {
+    INTRINSICS
+        .iter()
+        .chain(KERNEL_INTRINSICS)
+        .chain(APTOS_INTRINSICS)
+}
+
+impl Intrinsic {
+    /// Code calling the intrinsic in a program with the given memory map, its
+    /// allocations being framed by canaries if `canaries` is set.
+    pub fn lower(
+        &self,
+        memory: &MemoryMap,
+        canaries: bool,
+        codes: &ErrorCodes,
+    ) -> anyhow::Result<Vec<Node>> {
+        match self.lowering {
+            Lowering::StdLib { path, procedure } => Ok(vec![exec_imported(path, procedure)
+                .with_context(|| format!("Invalid intrinsic {}", self.name()))?]),
+            Lowering::Inline(instructions) => Ok(instructions
+                .iter()
+                .cloned()
+                .map(Node::Instruction)
+                .collect()),
+            Lowering::Generated(generate) => generate(memory, codes)
+                .with_context(|| format!("Invalid intrinsic {}", self.name())),
+            Lowering::Allocating(generate) => Ok(generate(memory, canaries)),
+            Lowering::Kernel {
+                path,
+                procedure,
+                then,
+                ..
+            } => {
+                let call = exec_imported(path, procedure)
+                    .with_context(|| format!("Invalid intrinsic {}", self.name()))?;
+                Ok([call]
+                    .into_iter()
+                    .chain(then.iter().cloned().map(Node::Instruction))
+                    .collect())
+            }
+            Lowering::Fold(_) => Err(diagnostics::UNSUPPORTED_NATIVE.error(format!(
+                "Native function {} can only be called with a constant argument",
+                self.name()
+            ))),
+            Lowering::KernelFold { .. } => Err(diagnostics::UNSUPPORTED_NATIVE.error(format!(
+                "Native function {} can only be called with constant last two arguments",
+                self.name()
+            ))),
+            Lowering::Discard => Err(diagnostics::UNSUPPORTED_NATIVE.error(format!(
+                "Native function {} can only be called with type arguments",
+                self.name()
+            ))),
+            Lowering::Unsupported { reason } => Err(diagnostics::UNSUPPORTED_NATIVE.error(
+                format!("Native function {} is not supported: {reason}", self.name()),
+            )),
+        }
+    }
+
+    /// Whether calls allocate a number of addresses only known at runtime.
+    pub fn allocates(&self) -> bool {
+        matches!(self.lowering, Lowering::Allocating(_))
+    }
+
+    /// Code calling the intrinsic with a constant byte vector as last argument,
+    /// if it is folded at compile time.
+    pub fn fold(&self, bytes: &[u8]) -> Option<anyhow::Result<Vec<Node>>> {
+        match self.lowering {
+            Lowering::Fold(fold) => Some(fold(bytes)),
+            _ => None,
+        }
+    }
+
+    /// Code calling the generic intrinsic with arguments taking `width` stack
+    /// elements, if it only drops them.
+    pub fn discard(&self, width: usize) -> Option<Vec<Node>> {
+        match self.lowering {
+            Lowering::Discard => Some(vec![Node::Instruction(Instruction::Drop); width]),
+            _ => None,
+        }
+    }
+
+    /// Code calling the intrinsic with the constants `immediate` and `bytes` as
+    /// last arguments, if they are folded at compile time.
+    pub fn fold_kernel(
+        &self,
+        immediate: u32,
+        bytes: &[u8],
+        codes: &ErrorCodes,
+    ) -> Option<anyhow::Result<Vec<Node>>> {
+        let Lowering::KernelFold {
+            path,
+            procedure,
+            fold,
+            ..
+        } = self.lowering
+        else {
+            return None;
+        };
+        Some(fold(immediate, bytes, codes).and_then(|mut nodes| {
+            nodes.push(exec_imported(path, procedure)?);
+            Ok(nodes)
+        }))
+    }
+
+    /// Transaction kernel procedure called by this intrinsic, if any. Unlike
+    /// standard library procedures, these are only imported by programs using them.
+    pub fn kernel_procedure(&self) -> Option<(&'static str, &'static str)> {
+        match self.lowering {
+            Lowering::Kernel {
+                path, procedure, ..
+            }
+            | Lowering::KernelFold {
+                path, procedure, ..
+            } => Some((path, procedure)),
+            _ => None,
+        }
+    }
+
+    fn available_in(&self, context: TargetContext) -> bool {
+        match self.lowering {
+            Lowering::Kernel { contexts, .. } | Lowering::KernelFold { contexts, .. } => {
+                contexts.contains(&context)
+            }
+            _ => true,
+        }
+    }
+
+    /// Standard library procedure called by this intrinsic, if any.
+    pub fn imported(&self) -> Option<(&'static str, &'static str)> {
+        match self.lowering {
+            Lowering::StdLib { path, procedure } => Some((path, procedure)),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{}::{}", self.module, self.function)
+    }
+}
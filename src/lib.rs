@@ -0,0 +1,59 @@
+pub mod abi;
+pub mod abi_doc;
+pub mod accounts;
+pub mod analysis;
+pub mod artifact;
+pub mod attributes;
+pub mod audit;
+pub mod backend;
+pub mod bundle;
+pub mod bytes;
+pub mod cfg;
+pub mod codegen;
+pub mod compiler;
+pub mod cost;
+pub mod diagnostics;
+pub mod error_map;
+pub mod footprint;
+pub mod fpi;
+#[cfg(feature = "host")]
+pub mod frontend;
+#[cfg(feature = "move-vm")]
+pub mod gas;
+mod inline;
+mod integers;
+mod intrinsics;
+pub mod limits;
+mod loops;
+#[cfg(feature = "host")]
+pub mod lsp;
+pub mod mangle;
+pub mod memory;
+pub mod merkle;
+pub mod move_utils;
+#[cfg(feature = "node")]
+mod node;
+pub mod oracle;
+pub mod passes;
+#[cfg(feature = "python")]
+mod python;
+mod ranges;
+pub mod recursion;
+pub mod reduce;
+#[cfg(feature = "host")]
+pub mod runner;
+mod schedule;
+#[cfg(feature = "host")]
+pub mod server;
+#[cfg(feature = "host")]
+pub mod signing;
+#[cfg(feature = "host")]
+pub mod simulator;
+pub mod state;
+mod types;
+#[cfg(feature = "host")]
+pub mod verify;
+pub mod witness;
+
+#[cfg(all(test, feature = "host"))]
+mod tests;
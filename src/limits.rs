@@ -0,0 +1,115 @@
+//! Module for the resource limits applied to modules from untrusted sources.
+//!
+//! Compilation work and memory grow with the size of the module and of its
+//! functions, so both are checked before compiling; the size of the generated
+//! program is checked after. Time is checked between functions, so a single
+//! function can overrun the limit by the time taken to compile it, which is
+//! bounded by `max_function_code`. The code generated so far is also checked
+//! against `max_instructions` after every function, which bounds the memory
+//! taken by compilation. Programs of untrusted modules are executed
+//! with at most `max_cycles` VM cycles, which bounds both the time taken and
+//! the size of the execution trace.
+
+use {
+    crate::{artifact::CompiledArtifact, diagnostics},
+    move_binary_format::{access::ModuleAccess, CompiledModule},
+    std::time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    // Size of the serialized module, in bytes.
+    pub max_module_bytes: usize,
+    pub max_functions: usize,
+    // Bytecode instructions of a single function.
+    pub max_function_code: usize,
+    // Miden instructions of the generated program, once assembled, when `repeat`
+    // bodies and called procedures are copied (see `artifact::Stats`).
+    pub max_instructions: usize,
+    // Time spent compiling.
+    pub timeout: Duration,
+    // VM cycles of an execution of the program.
+    pub max_cycles: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_module_bytes: 256 * 1024,
+            max_functions: 1024,
+            max_function_code: 16 * 1024,
+            max_instructions: 1_000_000,
+            timeout: Duration::from_secs(10),
+            max_cycles: 1 << 20,
+        }
+    }
+}
+
+impl Limits {
+    pub fn check_bytes(&self, bytes: &[u8]) -> anyhow::Result<()> {
+        if bytes.len() > self.max_module_bytes {
+            return Err(diagnostics::RESOURCE_LIMIT.error(format!(
+                "Module of {} bytes exceeds the limit of {}",
+                bytes.len(),
+                self.max_module_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn check_module(&self, module: &CompiledModule) -> anyhow::Result<()> {
+        let functions = module.function_defs().len();
+        if functions > self.max_functions {
+            return Err(diagnostics::RESOURCE_LIMIT.error(format!(
+                "Module defines {functions} functions, exceeding the limit of {}",
+                self.max_functions
+            )));
+        }
+        for function in module.function_defs() {
+            let size = function.code.as_ref().map_or(0, |code| code.code.len());
+            if size > self.max_function_code {
+                let name = module.identifier_at(module.function_handle_at(function.function).name);
+                return Err(diagnostics::RESOURCE_LIMIT.error(format!(
+                    "Function {name} has {size} instructions, exceeding the limit of {}",
+                    self.max_function_code
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_artifact(&self, artifact: &CompiledArtifact) -> anyhow::Result<()> {
+        if artifact.stats.instructions > self.max_instructions {
+            return Err(diagnostics::RESOURCE_LIMIT.error(format!(
+                "Generated program has {} instructions, exceeding the limit of {}",
+                artifact.stats.instructions, self.max_instructions
+            )));
+        }
+        Ok(())
+    }
+
+    /// Time after which compilation started now must stop.
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+}
+
+/// Fails if the generated code, of `size` instructions, exceeds the maximum, if any.
+/// Checked while generating code, so memory is bounded before the program is complete.
+pub fn check_code_size(size: usize, max: Option<usize>) -> anyhow::Result<()> {
+    match max {
+        Some(max) if size > max => Err(diagnostics::RESOURCE_LIMIT
+            .error(format!("Generated code has more than {max} instructions"))),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if the deadline, if any, has passed.
+pub fn check_deadline(deadline: Option<Instant>) -> anyhow::Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => {
+            Err(diagnostics::RESOURCE_LIMIT.error("Compilation exceeded its time limit"))
+        }
+        _ => Ok(()),
+    }
+}
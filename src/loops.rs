@@ -0,0 +1,161 @@
+//! Module recognizing counted loops, so they can be compiled to Miden `repeat` blocks.
+//!
+//! A counted loop has the shape Move produces for
+//! `let i = start; while (i < end) { ...; i = i + step }` (or `<=`), where `start`,
+//! `end` and `step` are constants, and the counter is only written by the final
+//! increment. Its number of iterations is known at compile time, so the loop can
+//! run as `repeat` without evaluating the condition on every iteration.
+//!
+//! Loops whose bound is only known at run time keep their condition: comparing
+//! `u32`s is already a single instruction, so there is no cheaper form of it to
+//! substitute.
+
+use {
+    crate::cfg::{Cfg, Label, OutgoingEdge},
+    move_binary_format::file_format::Bytecode,
+    std::collections::BTreeMap,
+};
+
+/// Number of iterations of every counted loop of the function, by loop header.
+/// Loops with more than `max_repeat` iterations are left out and kept as `while`
+/// loops, since `repeat` blocks are unrolled when the program is assembled
+/// (see `cost::CostModel::max_repeat`).
+pub fn counted_loops(
+    cfg: &Cfg<'_>,
+    bytecode: &[Bytecode],
+    max_repeat: u64,
+) -> BTreeMap<Label, u32> {
+    cfg.edges()
+        .filter(|(_, edge)| matches!(edge, OutgoingEdge::WhileTrue { .. }))
+        .filter_map(|(header, _)| {
+            let trips = trip_count(cfg, bytecode, &header)?;
+            (trips <= max_repeat).then_some((header, trips as u32))
+        })
+        .collect()
+}
+
+fn trip_count(cfg: &Cfg<'_>, bytecode: &[Bytecode], header: &Label) -> Option<u64> {
+    let Label::Point(header_start) = *header else {
+        return None;
+    };
+    // Header: `CopyLoc(i); Ld(end); Lt | Le`, followed by the conditional branch
+    let (counter, end, inclusive) = match cfg.block(header).ok()? {
+        [Bytecode::CopyLoc(i), end, Bytecode::Lt] => (*i, constant(end)?, false),
+        [Bytecode::CopyLoc(i), end, Bytecode::Le] => (*i, constant(end)?, true),
+        _ => return None,
+    };
+
+    // The header must only be reached by falling through from the initialization
+    // and from the end of the body.
+    let mut back_edges = cfg.edges().filter(|(_, edge)| match edge {
+        OutgoingEdge::LoopBack { header: h } => h == header,
+        _ => false,
+    });
+    let (back_edge, _) = back_edges.next()?;
+    if back_edges.next().is_some() {
+        return None;
+    }
+    let Label::Point(latch_start) = back_edge else {
+        return None;
+    };
+    let latch = cfg.block(&back_edge).ok()?;
+    let branch_back = latch_start + latch.len();
+
+    // Initialization: `Ld(start); StLoc(i)` right before the header
+    let start = match bytecode.get(header_start.checked_sub(2)?..header_start)? {
+        [value, Bytecode::StLoc(i)] if *i == counter => constant(value)?,
+        _ => return None,
+    };
+    // Increment: `CopyLoc(i) | MoveLoc(i); Ld(step); Add; StLoc(i)` ending the body
+    let step = match latch {
+        [.., Bytecode::CopyLoc(i) | Bytecode::MoveLoc(i), step, Bytecode::Add, Bytecode::StLoc(j)]
+            if *i == counter && *j == counter =>
+        {
+            constant(step)?
+        }
+        _ => return None,
+    };
+    if step == 0 {
+        return None;
+    }
+
+    // The body must not write the counter or leave the loop other than through the header
+    let exit_branch = header_start + 3;
+    let body = bytecode.get(header_start..branch_back - 4)?;
+    for (offset, instruction) in body.iter().enumerate() {
+        let offset = header_start + offset;
+        let leaves = match instruction {
+            Bytecode::StLoc(i) | Bytecode::MutBorrowLoc(i) => *i == counter,
+            Bytecode::Branch(x) | Bytecode::BrTrue(x) | Bytecode::BrFalse(x) => {
+                offset != exit_branch && !(header_start..=branch_back).contains(&(*x as usize))
+            }
+            Bytecode::Ret | Bytecode::Abort => true,
+            _ => false,
+        };
+        if leaves {
+            return None;
+        }
+    }
+
+    // The counter must not overflow when computing the value ending the loop
+    end.checked_add(step).filter(|x| *x <= u32::MAX as u64)?;
+    let trips = match (inclusive, start) {
+        (false, start) if start >= end => 0,
+        (false, start) => (end - start).div_ceil(step),
+        (true, start) if start > end => 0,
+        (true, start) => (end - start) / step + 1,
+    };
+    Some(trips)
+}
+
+fn constant(bytecode: &Bytecode) -> Option<u64> {
+    match bytecode {
+        Bytecode::LdU8(x) => Some(*x as u64),
+        Bytecode::LdU16(x) => Some(*x as u64),
+        Bytecode::LdU32(x) => Some(*x as u64),
+        Bytecode::LdU64(x) => Some(*x),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_counted_loop() {
+        // let total = 0; let i = 1; while (i <= 5) { total = total + i; i = i + 1 }; total
+        let bytecode = vec![
+            Bytecode::LdU32(0),
+            Bytecode::StLoc(1),
+            Bytecode::LdU32(1),
+            Bytecode::StLoc(0),
+            Bytecode::CopyLoc(0),
+            Bytecode::LdU32(5),
+            Bytecode::Le,
+            Bytecode::BrFalse(18),
+            Bytecode::Branch(9),
+            Bytecode::MoveLoc(1),
+            Bytecode::CopyLoc(0),
+            Bytecode::Add,
+            Bytecode::StLoc(1),
+            Bytecode::MoveLoc(0),
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(0),
+            Bytecode::Branch(4),
+            Bytecode::MoveLoc(1),
+            Bytecode::Ret,
+        ];
+        let cfg = Cfg::new(&bytecode).unwrap();
+        let loops = counted_loops(&cfg, &bytecode, 256);
+        assert_eq!(loops.get(&Label::Point(4)), Some(&5));
+        assert!(counted_loops(&cfg, &bytecode, 4).is_empty());
+
+        // The bound is a parameter, so the number of iterations is unknown
+        let mut bytecode = bytecode;
+        bytecode[5] = Bytecode::CopyLoc(2);
+        let cfg = Cfg::new(&bytecode).unwrap();
+        assert!(counted_loops(&cfg, &bytecode, 256).is_empty());
+    }
+}
@@ -0,0 +1,328 @@
+//! Minimal Language Server Protocol server over stdio.
+//!
+//! Each open document is compiled on its own in recovery mode (see `frontend::check_sources`).
+//! Diagnostics are published whenever a document is opened or changed, and the
+//! estimated cost of every compiled function is offered as an inlay hint.
+//! Named addresses can be given on the command line or through the
+//! `namedAddresses` initialization option (a map from name to address).
+//!
+//! Malformed messages are answered with JSON-RPC errors, or logged to the
+//! client for notifications, and the server keeps running; only failures to
+//! read from or write to the client stop it.
+
+use {
+    crate::{
+        cost,
+        diagnostics::{Severity, SourceFiles, Span},
+        frontend::{self, CheckResult},
+    },
+    anyhow::{Context, Error},
+    move_compiler::shared::NumericalAddress,
+    serde_json::{json, Value},
+    std::{
+        collections::{hash_map::DefaultHasher, BTreeMap},
+        hash::{Hash, Hasher},
+        io::{BufRead, Read, Write},
+        path::PathBuf,
+    },
+};
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// LSP message type of errors logged with `window/logMessage`.
+const LOG_ERROR: u64 = 1;
+
+/// Runs the server until the client sends `exit`.
+pub fn run<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    named_addresses: BTreeMap<String, NumericalAddress>,
+) -> anyhow::Result<()> {
+    let mut server = Server {
+        named_addresses,
+        documents: BTreeMap::new(),
+    };
+    while let Some(body) = read_message(&mut input)? {
+        let message: Value = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                let error = error_response(Value::Null, PARSE_ERROR, &e.to_string());
+                write_message(&mut output, &error)?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            return Ok(());
+        }
+        let id = message.get("id").cloned();
+        let mut notifications = Vec::new();
+        let response = match server.handle(method, &message["params"], &mut notifications) {
+            Ok(Handled::Result(result)) => {
+                id.map(|id| json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+            }
+            Ok(Handled::Notification) => None,
+            Ok(Handled::Unknown) => id.map(|id| {
+                error_response(id, METHOD_NOT_FOUND, &format!("Unknown method {method}"))
+            }),
+            // Notifications have no response, so their errors are logged
+            Err(e) => match id {
+                Some(id) => Some(error_response(id, INVALID_PARAMS, &format!("{e:#}"))),
+                None => {
+                    notifications.push(json!({
+                        "jsonrpc": "2.0",
+                        "method": "window/logMessage",
+                        "params": { "type": LOG_ERROR, "message": format!("{method}: {e:#}") },
+                    }));
+                    None
+                }
+            },
+        };
+        for message in notifications.iter().chain(&response) {
+            write_message(&mut output, message)?;
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of a message handled by the server.
+enum Handled {
+    // Result of a request.
+    Result(Value),
+    // Notification, which has no result.
+    Notification,
+    // Method the server doesn't implement.
+    Unknown,
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+struct Document {
+    // Path of the copy of the document given to the Move compiler.
+    path: String,
+    result: CheckResult,
+}
+
+struct Server {
+    named_addresses: BTreeMap<String, NumericalAddress>,
+    documents: BTreeMap<String, Document>,
+}
+
+impl Server {
+    /// Handles a message, adding the notifications it sends to `notifications`.
+    fn handle(
+        &mut self,
+        method: &str,
+        params: &Value,
+        notifications: &mut Vec<Value>,
+    ) -> anyhow::Result<Handled> {
+        Ok(match method {
+            "initialize" => {
+                self.initialize(params)?;
+                Handled::Result(json!({
+                    "capabilities": {
+                        // Full document sync
+                        "textDocumentSync": 1,
+                        "inlayHintProvider": true,
+                    },
+                    "serverInfo": { "name": "move2miden" },
+                }))
+            }
+            "shutdown" => Handled::Result(Value::Null),
+            "initialized" => Handled::Notification,
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                let uri = string_field(document, "uri")?;
+                let text = string_field(document, "text")?;
+                notifications.push(self.update(uri, text)?);
+                Handled::Notification
+            }
+            "textDocument/didChange" => {
+                let uri = string_field(&params["textDocument"], "uri")?;
+                // With full sync, the last change holds the whole document
+                let changes = params["contentChanges"]
+                    .as_array()
+                    .ok_or_else(|| Error::msg("Missing content changes"))?;
+                if let Some(change) = changes.last() {
+                    notifications.push(self.update(uri, string_field(change, "text")?)?);
+                }
+                Handled::Notification
+            }
+            "textDocument/didClose" => {
+                let uri = string_field(&params["textDocument"], "uri")?;
+                if let Some(document) = self.documents.remove(uri) {
+                    // The scratch copy is only read while the document is open
+                    let _ = std::fs::remove_file(&document.path);
+                }
+                Handled::Notification
+            }
+            "textDocument/inlayHint" => {
+                let uri = string_field(&params["textDocument"], "uri")?;
+                Handled::Result(self.inlay_hints(uri))
+            }
+            _ => Handled::Unknown,
+        })
+    }
+
+    fn initialize(&mut self, params: &Value) -> anyhow::Result<()> {
+        let options = &params["initializationOptions"]["namedAddresses"];
+        if let Some(options) = options.as_object() {
+            for (name, address) in options {
+                let address = address
+                    .as_str()
+                    .ok_or_else(|| Error::msg("Named addresses must be strings"))?;
+                let address = NumericalAddress::parse_str(address).map_err(Error::msg)?;
+                self.named_addresses.insert(name.clone(), address);
+            }
+        }
+        Ok(())
+    }
+
+    /// Recompiles the document and returns the diagnostics notification for it.
+    fn update(&mut self, uri: &str, text: &str) -> anyhow::Result<Value> {
+        // The Move compiler only reads from disk, so compile a copy of the unsaved buffer.
+        let path = match self.documents.get(uri) {
+            Some(document) => PathBuf::from(&document.path),
+            None => scratch_path(uri),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create LSP scratch directory")?;
+        }
+        std::fs::write(&path, text).context("Failed to write LSP scratch file")?;
+        let path = path.to_string_lossy().into_owned();
+        let result = frontend::check_sources(&[path.clone()], self.named_addresses.clone());
+
+        let files = &result.diagnostics.files;
+        let diagnostics: Vec<Value> = result
+            .diagnostics
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let range = d
+                    .primary
+                    .as_ref()
+                    .filter(|(span, _)| span.file == path)
+                    .and_then(|(span, _)| range(files, span))
+                    .unwrap_or_else(|| range_at(1, 1, 1, 1));
+                let severity = match d.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                    Severity::Note => 3,
+                };
+                json!({
+                    "range": range,
+                    "severity": severity,
+                    "source": "move2miden",
+                    "message": d.message,
+                })
+            })
+            .collect();
+        self.documents
+            .insert(uri.to_string(), Document { path, result });
+        Ok(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn inlay_hints(&self, uri: &str) -> Value {
+        let Some(document) = self.documents.get(uri) else {
+            return json!([]);
+        };
+        let files = &document.result.diagnostics.files;
+        let hints: Vec<Value> = document
+            .result
+            .functions
+            .iter()
+            .filter_map(|f| {
+                let procedure = f.procedure.as_ref()?;
+                let span = f.span.as_ref()?;
+                let (line, column) = files.position(&span.file, span.bytes.end)?;
+                let cost = cost::estimate(&procedure.body);
+                let bound = if cost.bounded { "" } else { "+" };
+                Some(json!({
+                    "position": position(line, column),
+                    "label": format!(" ~{}{bound} cycles", cost.cycles),
+                    "paddingLeft": true,
+                }))
+            })
+            .collect();
+        json!(hints)
+    }
+}
+
+/// Path of the scratch copy of a document, unique to the URI and to this
+/// process, so documents and servers running side by side don't share copies.
+fn scratch_path(uri: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    uri.hash(&mut hasher);
+    let file_name = uri.rsplit('/').next().unwrap_or("document.move");
+    std::env::temp_dir()
+        .join("move2miden-lsp")
+        .join(format!("{}-{:016x}", std::process::id(), hasher.finish()))
+        .join(file_name)
+}
+
+fn range(files: &SourceFiles, span: &Span) -> Option<Value> {
+    let (end_line, end_column) = files.position(&span.file, span.bytes.end)?;
+    Some(range_at(span.line, span.column, end_line, end_column))
+}
+
+/// LSP range from 1-based lines and columns.
+fn range_at(line: usize, column: usize, end_line: usize, end_column: usize) -> Value {
+    json!({
+        "start": position(line, column),
+        "end": position(end_line, end_column),
+    })
+}
+
+/// LSP positions are 0-based.
+fn position(line: usize, column: usize) -> Value {
+    json!({ "line": line - 1, "character": column - 1 })
+}
+
+fn string_field<'a>(value: &'a Value, field: &str) -> anyhow::Result<&'a str> {
+    value[field]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("Missing field {field}"))
+}
+
+/// Reads the body of a message framed with a `Content-Length` header; returns
+/// `None` at end of input.
+fn read_message<R: BufRead>(input: &mut R) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let length = length.ok_or_else(|| Error::msg("Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> anyhow::Result<()> {
+    let body = serde_json::to_string(message)?;
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()?;
+    Ok(())
+}
@@ -1,10 +1,480 @@
-mod cfg;
-mod compiler;
-mod move_utils;
+use {
+    anyhow::Context,
+    clap::{Args, Parser, Subcommand},
+    miden::math::Felt,
+    move2miden::{
+        abi_doc,
+        bundle::{self, Bundle},
+        compiler::{Compiler, EntryMode, OpcodePolicy, TargetContext},
+        diagnostics,
+        error_map::ErrorCodes,
+        fpi, frontend, lsp, move_utils, passes, server, signing, verify,
+    },
+    move_compiler::shared::NumericalAddress,
+    std::{
+        collections::BTreeMap,
+        net::TcpListener,
+        path::{Path, PathBuf},
+        process::ExitCode,
+        sync::Arc,
+    },
+};
 
-#[cfg(test)]
-mod tests;
+#[derive(Debug, Parser)]
+#[command(version, about = "Compiles Move to Miden assembly")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Compile Move source files, or prebuilt bytecode, into a Miden program.
+    Build(BuildArgs),
+    /// Compare two bundles produced by `build --bundle`.
+    Diff { old: PathBuf, new: PathBuf },
+    /// Check that a bundle produced by `build --bundle` assembles to its program hash,
+    /// reproduces its test vectors and refers to the given sources.
+    VerifyArtifact {
+        bundle: PathBuf,
+        /// Move source files the bundle was built from.
+        #[arg(long = "source")]
+        sources: Vec<PathBuf>,
+        /// Hex encoded public key allowed to sign the bundle. If given, the
+        /// bundle must be signed by one of these keys. May be repeated.
+        #[arg(long = "trusted-key")]
+        trusted_keys: Vec<String>,
+    },
+    /// Describe the interface of a bundle produced by `build --bundle`:
+    /// entry point types, input and output encoding, and memory layout.
+    AbiDoc { bundle: PathBuf },
+    /// Report potential re-entrancy, storage access ordering hazards and
+    /// arithmetic checked at runtime in Move source files, for review by auditors.
+    Audit {
+        #[arg(required = true)]
+        sources: Vec<String>,
+        /// Named addresses used by the sources, as `name=address`.
+        #[arg(long = "named-address", value_parser = parse_named_address)]
+        named_addresses: Vec<(String, NumericalAddress)>,
+    },
+    /// Run a Language Server Protocol server over stdio.
+    Lsp {
+        /// Named addresses used by the sources, as `name=address`.
+        #[arg(long = "named-address", value_parser = parse_named_address)]
+        named_addresses: Vec<(String, NumericalAddress)>,
+    },
+    /// Explain why a construct can't be compiled, given the code of its diagnostic.
+    Explain {
+        /// Diagnostic code, such as `M2M001`; lists all codes if not given.
+        code: Option<String>,
+    },
+    /// Describe an abort code using the error map of a bundle produced by `build --bundle`.
+    Abort {
+        bundle: PathBuf,
+        /// Abort code, in decimal or as `0x` prefixed hex.
+        #[arg(value_parser = parse_code)]
+        code: u64,
+    },
+    /// Compare the Move VM gas cost of the entry functions of Move source files
+    /// with their cost in Miden VM cycles.
+    #[cfg(feature = "move-vm")]
+    CompareGas {
+        #[arg(required = true)]
+        sources: Vec<String>,
+        /// Named addresses used by the sources, as `name=address`.
+        #[arg(long = "named-address", value_parser = parse_named_address)]
+        named_addresses: Vec<(String, NumericalAddress)>,
+        /// Entry function inputs, as comma separated values, to measure each
+        /// entry function with. May be repeated.
+        #[arg(long = "input", value_delimiter = ',', required = true)]
+        inputs: Vec<Vec<u64>>,
+    },
+    /// Run a JSON-RPC server sharing a compiler cache between requests.
+    Serve {
+        /// Address to listen on.
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: String,
+    },
+}
+
+#[derive(Debug, Args)]
+struct BuildArgs {
+    /// Move source files to compile. With `--bytecode`, the sources the module
+    /// was built from, used to locate diagnostics and read attributes.
+    #[arg(required_unless_present = "bytecode")]
+    sources: Vec<String>,
+    /// Named addresses used by the sources, as `name=address`.
+    #[arg(long = "named-address", value_parser = parse_named_address)]
+    named_addresses: Vec<(String, NumericalAddress)>,
+    /// Compile this module bytecode (`.mv` file) instead of the sources.
+    #[arg(long, requires = "source_map")]
+    bytecode: Option<PathBuf>,
+    /// Source map of the bytecode (`.mvsm` file), as written by the Move compiler.
+    #[arg(long, requires = "bytecode")]
+    source_map: Option<PathBuf>,
+    /// File to write the Miden assembly to; printed to stdout if not given.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Also write a bundle (for use with `diff`) to this file.
+    #[arg(long)]
+    bundle: Option<PathBuf>,
+    /// Entry function inputs, as comma separated values, to run and record
+    /// in the bundle along with their outputs. May be repeated.
+    #[arg(long = "test-vector", value_delimiter = ',', requires = "bundle")]
+    test_vectors: Vec<Vec<u64>>,
+    /// Compile the entry function, which must return a bool, into a program
+    /// failing unless it returns true, with no outputs (e.g. for note spend conditions).
+    #[arg(long)]
+    assert_result: bool,
+    /// Compile the entry function, which must return a bool, into a program
+    /// failing unless it returns true for every instance of a batch of inputs
+    /// read from the advice stack, the number of instances being the stack input.
+    #[arg(long, conflicts_with = "assert_result")]
+    batch: bool,
+    /// Compute calls of pure functions repeated with the same constant arguments
+    /// only once, keeping the result in memory.
+    #[arg(long)]
+    memoize: bool,
+    /// Leave opcodes the compiler can't lower out of the program with a warning,
+    /// instead of failing, e.g. for modules built by newer Move toolchains.
+    #[arg(long)]
+    skip_unsupported_opcodes: bool,
+    /// Debug build checking that no heap allocation was written out of bounds
+    /// before the program returns.
+    #[arg(long)]
+    canaries: bool,
+    /// Sign the bundle with the hex encoded Ed25519 secret key in this file.
+    #[arg(long, requires = "bundle")]
+    sign_key: Option<PathBuf>,
+    /// Context the program runs in: `standalone`, `account` or `note-script`.
+    /// Decides which native functions are available and how they are lowered.
+    #[arg(long, default_value = "standalone")]
+    target: TargetContext,
+    /// Record the program after each compiler pass. If this is a directory,
+    /// each pass is written to its own file; otherwise a report listing what
+    /// every pass changed is written to this file.
+    #[arg(long)]
+    emit_pass_ir: Option<PathBuf>,
+    /// MAST root of the procedure called by a foreign function, as
+    /// `module::function=root`, the root being hex encoded. Every foreign
+    /// function called needs one. May be repeated.
+    #[arg(long = "foreign-root", value_parser = parse_foreign_root)]
+    foreign_roots: Vec<(String, [Felt; 4])>,
+    /// First abort code of the checks emitted by the compiler, in decimal or as
+    /// `0x` prefixed hex, for modules whose own abort codes are in the default
+    /// range from 0x20000.
+    #[arg(long, value_parser = parse_check_codes)]
+    check_codes: Option<ErrorCodes>,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Build(args) => build(args),
+        Command::VerifyArtifact {
+            bundle,
+            sources,
+            trusted_keys,
+        } => verify_artifact(&bundle, &sources, &trusted_keys),
+        Command::Diff { old, new } => {
+            let bundles = Bundle::load(&old).and_then(|old| Ok((old, Bundle::load(&new)?)));
+            match bundles {
+                Ok((old, new)) => {
+                    print!("{}", bundle::diff(&old, &new));
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::AbiDoc { bundle } => match Bundle::load(&bundle).and_then(|b| abi_doc::render(&b))
+        {
+            Ok(report) => {
+                print!("{report}");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{e:#}");
+                ExitCode::FAILURE
+            }
+        },
+        Command::Audit {
+            sources,
+            named_addresses,
+        } => {
+            let diagnostics =
+                frontend::audit_sources(&sources, named_addresses.into_iter().collect());
+            eprint!("{}", diagnostics.render());
+            if diagnostics.has_errors() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Command::Lsp { named_addresses } => {
+            let stdin = std::io::stdin().lock();
+            let stdout = std::io::stdout().lock();
+            match lsp::run(stdin, stdout, named_addresses.into_iter().collect()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("Language server failed: {e:#}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Explain { code: None } => {
+            for code in diagnostics::ERROR_CODES {
+                println!("{}: {}", code.code, code.title);
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Explain { code: Some(code) } => match diagnostics::find_code(&code) {
+            Some(code) => {
+                print!("{}", code.explain());
+                ExitCode::SUCCESS
+            }
+            None => {
+                eprintln!("Unknown diagnostic code {code}; run `move2miden explain` to list them");
+                ExitCode::FAILURE
+            }
+        },
+        Command::Abort { bundle, code } => match Bundle::load(&bundle) {
+            Ok(bundle) => {
+                println!("{}", bundle.error_map.describe(code));
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{e:#}");
+                ExitCode::FAILURE
+            }
+        },
+        #[cfg(feature = "move-vm")]
+        Command::CompareGas {
+            sources,
+            named_addresses,
+            inputs,
+        } => {
+            let compiled =
+                match frontend::compile_sources(&sources, named_addresses.into_iter().collect()) {
+                    Ok(compiled) => compiled,
+                    Err(diagnostics) => {
+                        eprint!("{}", diagnostics.render());
+                        return ExitCode::FAILURE;
+                    }
+                };
+            let modules: Vec<_> = compiled.modules.into_iter().map(|m| m.module).collect();
+            match move2miden::gas::compare(&modules, &inputs) {
+                Ok(measurements) => {
+                    print!("{}", move2miden::gas::render(&measurements));
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Command::Serve { listen } => {
+            let result = TcpListener::bind(&listen)
+                .map_err(anyhow::Error::from)
+                .and_then(|listener| server::serve(listener, Arc::new(Compiler::new())));
+            match result {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("Server failed: {e:#}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+    }
+}
+
+fn build(args: BuildArgs) -> ExitCode {
+    let BuildArgs {
+        sources,
+        named_addresses,
+        bytecode,
+        source_map,
+        output,
+        bundle,
+        test_vectors,
+        assert_result,
+        batch,
+        memoize,
+        skip_unsupported_opcodes,
+        canaries,
+        sign_key,
+        target,
+        emit_pass_ir,
+        foreign_roots,
+        check_codes,
+    } = args;
+    let named_addresses: BTreeMap<_, _> = named_addresses.into_iter().collect();
+    let options = frontend::BuildOptions {
+        entry_mode: if batch {
+            EntryMode::Batch
+        } else if assert_result {
+            EntryMode::AssertTrue
+        } else {
+            EntryMode::Outputs
+        },
+        memoize,
+        opcode_policy: if skip_unsupported_opcodes {
+            OpcodePolicy::Skip
+        } else {
+            OpcodePolicy::Error
+        },
+        canaries,
+        emit_pass_ir: emit_pass_ir.is_some(),
+        target,
+        foreign_roots: foreign_roots.into_iter().collect(),
+        error_codes: check_codes.unwrap_or_default(),
+        ..frontend::BuildOptions::default()
+    };
+    let result = match (bytecode, source_map) {
+        (Some(bytecode), Some(source_map)) => {
+            let loaded = std::fs::read(&bytecode)
+                .with_context(|| format!("Failed to read {}", bytecode.display()))
+                .and_then(|bytes| move_utils::parse_module(&bytes))
+                .and_then(|module| Ok((module, move_utils::load_source_map(&source_map)?)));
+            match loaded {
+                Ok((module, source_map)) => frontend::compile_bytecode(
+                    module,
+                    source_map,
+                    &sources,
+                    named_addresses,
+                    options,
+                ),
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        _ => frontend::compile_with_options(&sources, named_addresses, options),
+    };
+    let artifact = match result {
+        Ok(artifact) => artifact,
+        Err(diagnostics) => {
+            eprint!("{}", diagnostics.render());
+            return ExitCode::FAILURE;
+        }
+    };
+    if !artifact.diagnostics.diagnostics.is_empty() {
+        eprint!("{}", artifact.diagnostics.render());
+    }
+    if let Some(path) = bundle {
+        let result = Bundle::from_artifact(&artifact).and_then(|mut bundle| {
+            for inputs in test_vectors {
+                verify::record_test_vector(&mut bundle, &artifact, inputs)?;
+            }
+            if let Some(key) = &sign_key {
+                signing::sign(&mut bundle, &signing::load_signing_key(key)?)?;
+            }
+            bundle.save(&path)
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to create bundle: {e:#}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(path) = emit_pass_ir {
+        let result = if path.is_dir() {
+            passes::write_files(&artifact.pass_ir, &path)
+        } else {
+            std::fs::write(&path, passes::report(&artifact.pass_ir))
+                .with_context(|| format!("Failed to write {}", path.display()))
+        };
+        if let Err(e) = result {
+            eprintln!("{e:#}");
+            return ExitCode::FAILURE;
+        }
+    }
+    let masm = artifact.masm();
+    match output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, masm) {
+                eprintln!("Failed to write {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{masm}"),
+    }
+    ExitCode::SUCCESS
+}
+
+fn verify_artifact(path: &Path, source_paths: &[PathBuf], trusted_keys: &[String]) -> ExitCode {
+    let bundle = match Bundle::load(path) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut sources = Vec::new();
+    for source in source_paths {
+        match std::fs::read_to_string(source) {
+            Ok(contents) => sources.push(contents),
+            Err(e) => {
+                eprintln!("Failed to read {}: {e}", source.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let mut problems = verify::verify(&bundle, &sources);
+    if !trusted_keys.is_empty() {
+        let result = trusted_keys
+            .iter()
+            .map(|key| signing::parse_verifying_key(key))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .and_then(|keys| signing::verify_signature(&bundle, &keys));
+        if let Err(e) = result {
+            problems.push(format!("{e:#}"));
+        }
+    }
+    if problems.is_empty() {
+        println!(
+            "Bundle verified: program hash {}, {} test vectors",
+            bundle.program_hash,
+            bundle.test_vectors.len()
+        );
+        return ExitCode::SUCCESS;
+    }
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    ExitCode::FAILURE
+}
+
+fn parse_named_address(s: &str) -> Result<(String, NumericalAddress), String> {
+    let (name, address) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected `name=address`, got `{s}`"))?;
+    let address = NumericalAddress::parse_str(address)?;
+    Ok((name.to_string(), address))
+}
+
+fn parse_foreign_root(s: &str) -> Result<(String, [Felt; 4]), String> {
+    let (function, root) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected `module::function=root`, got `{s}`"))?;
+    let root = fpi::parse_root(root).map_err(|e| format!("Invalid MAST root `{root}`: {e}"))?;
+    Ok((function.to_string(), root))
+}
+
+fn parse_check_codes(s: &str) -> Result<ErrorCodes, String> {
+    let base = parse_code(s)?;
+    let base =
+        u32::try_from(base).map_err(|_| format!("Abort code `{s}` doesn't fit in 32 bits"))?;
+    ErrorCodes::new(base).map_err(|e| e.to_string())
+}
 
-fn main() {
-    println!("Hello, world!");
+fn parse_code(s: &str) -> Result<u64, String> {
+    let result = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    result.map_err(|e| format!("Invalid abort code `{s}`: {e}"))
 }
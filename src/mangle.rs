@@ -0,0 +1,229 @@
+//! Module defining how Move functions are named in the generated Miden assembly.
+//!
+//! Procedure names must be valid Miden identifiers, so the fully qualified name
+//! of a function is encoded as follows:
+//!
+//! ```text
+//! name     := "mv" address "_" ident ident [generics]
+//! address  := lowercase hex digits of the address, without leading zeros ("0" for zero)
+//! ident    := decimal length, followed by the identifier
+//! generics := "G" decimal count "_" type*
+//! type     := "b" (bool) | "h" (u8) | "t" (u16) | "j" (u32) | "m" (u64) | "o" (u128)
+//!           | "y" (u256) | "a" (address) | "s" (signer) | "v" type (vector)
+//!           | "S" address "_" ident ident [generics] (struct)
+//! ```
+//!
+//! For example `0x1::coin::balance<u64>` is `mv1_4coin7balanceG1_m`.
+//! Names longer than `MAX_NAME_LEN` are truncated and suffixed with a hash of the
+//! full name; such names cannot be demangled.
+
+use {
+    move_core_types::{
+        account_address::AccountAddress,
+        identifier::{IdentStr, Identifier},
+        language_storage::{ModuleId, StructTag, TypeTag},
+    },
+    std::fmt::Write,
+};
+
+/// Maximum length of a Miden procedure name.
+pub const MAX_NAME_LEN: usize = 100;
+
+const PREFIX: &str = "mv";
+
+/// A Move function identified by a mangled procedure name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MangledFunction {
+    pub module: ModuleId,
+    pub function: Identifier,
+    pub type_args: Vec<TypeTag>,
+}
+
+/// Name of the procedure generated for `module::function<type_args>`.
+pub fn mangle(module: &ModuleId, function: &IdentStr, type_args: &[TypeTag]) -> String {
+    let mut name = String::from(PREFIX);
+    write_path(
+        &mut name,
+        module.address(),
+        module.name().as_str(),
+        function.as_str(),
+    );
+    write_generics(&mut name, type_args);
+    if name.len() > MAX_NAME_LEN {
+        let hash = format!("_h{:016x}", fnv1a(name.as_bytes()));
+        name.truncate(MAX_NAME_LEN - hash.len());
+        name.push_str(&hash);
+    }
+    name
+}
+
+/// Recovers the Move function from a procedure name produced by `mangle`.
+pub fn demangle(name: &str) -> Option<MangledFunction> {
+    let mut parser = Parser {
+        rest: name.strip_prefix(PREFIX)?,
+    };
+    let (address, module, function) = parser.path()?;
+    let type_args = parser.generics()?;
+    if !parser.rest.is_empty() {
+        return None;
+    }
+    Some(MangledFunction {
+        module: ModuleId::new(address, module),
+        function,
+        type_args,
+    })
+}
+
+fn write_path(name: &mut String, address: &AccountAddress, module: &str, item: &str) {
+    let hex = address.to_hex();
+    let hex = hex.trim_start_matches('0');
+    let hex = if hex.is_empty() { "0" } else { hex };
+    write!(name, "{hex}_{}{module}{}{item}", module.len(), item.len()).ok();
+}
+
+fn write_generics(name: &mut String, type_args: &[TypeTag]) {
+    if type_args.is_empty() {
+        return;
+    }
+    write!(name, "G{}_", type_args.len()).ok();
+    for type_arg in type_args {
+        write_type(name, type_arg);
+    }
+}
+
+fn write_type(name: &mut String, type_tag: &TypeTag) {
+    match type_tag {
+        TypeTag::Bool => name.push('b'),
+        TypeTag::U8 => name.push('h'),
+        TypeTag::U16 => name.push('t'),
+        TypeTag::U32 => name.push('j'),
+        TypeTag::U64 => name.push('m'),
+        TypeTag::U128 => name.push('o'),
+        TypeTag::U256 => name.push('y'),
+        TypeTag::Address => name.push('a'),
+        TypeTag::Signer => name.push('s'),
+        TypeTag::Vector(inner) => {
+            name.push('v');
+            write_type(name, inner);
+        }
+        TypeTag::Struct(tag) => {
+            name.push('S');
+            write_path(name, &tag.address, tag.module.as_str(), tag.name.as_str());
+            write_generics(name, &tag.type_params);
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn path(&mut self) -> Option<(AccountAddress, Identifier, Identifier)> {
+        let (hex, rest) = self.rest.split_once('_')?;
+        self.rest = rest;
+        let address = AccountAddress::from_hex_literal(&format!("0x{hex}")).ok()?;
+        let module = self.ident()?;
+        let item = self.ident()?;
+        Some((address, module, item))
+    }
+
+    fn ident(&mut self) -> Option<Identifier> {
+        let digits = self.rest.len()
+            - self
+                .rest
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let len: usize = self.rest[..digits].parse().ok()?;
+        let ident = self.rest.get(digits..digits + len)?;
+        self.rest = &self.rest[digits + len..];
+        Identifier::new(ident).ok()
+    }
+
+    fn generics(&mut self) -> Option<Vec<TypeTag>> {
+        let Some(rest) = self.rest.strip_prefix('G') else {
+            return Some(Vec::new());
+        };
+        let (count, rest) = rest.split_once('_')?;
+        self.rest = rest;
+        let count: usize = count.parse().ok()?;
+        (0..count).map(|_| self.type_tag()).collect()
+    }
+
+    fn type_tag(&mut self) -> Option<TypeTag> {
+        let code = self.rest.chars().next()?;
+        self.rest = &self.rest[1..];
+        Some(match code {
+            'b' => TypeTag::Bool,
+            'h' => TypeTag::U8,
+            't' => TypeTag::U16,
+            'j' => TypeTag::U32,
+            'm' => TypeTag::U64,
+            'o' => TypeTag::U128,
+            'y' => TypeTag::U256,
+            'a' => TypeTag::Address,
+            's' => TypeTag::Signer,
+            'v' => TypeTag::Vector(Box::new(self.type_tag()?)),
+            'S' => {
+                let (address, module, name) = self.path()?;
+                let type_params = self.generics()?;
+                TypeTag::Struct(Box::new(StructTag {
+                    address,
+                    module,
+                    name,
+                    type_params,
+                }))
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// FNV-1a hash; stable across platforms and releases, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mangle_roundtrip() {
+        let module = ModuleId::new(AccountAddress::ONE, Identifier::new("coin").unwrap());
+        let function = Identifier::new("balance").unwrap();
+        let name = mangle(&module, &function, &[TypeTag::U64]);
+        assert_eq!(name, "mv1_4coin7balanceG1_m");
+
+        let coin = TypeTag::Struct(Box::new(StructTag {
+            address: AccountAddress::ONE,
+            module: Identifier::new("aptos_coin").unwrap(),
+            name: Identifier::new("AptosCoin").unwrap(),
+            type_params: Vec::new(),
+        }));
+        let type_args = [TypeTag::Vector(Box::new(coin)), TypeTag::Bool];
+        let name = mangle(&module, &function, &type_args);
+        assert_eq!(
+            demangle(&name),
+            Some(MangledFunction {
+                module,
+                function,
+                type_args: type_args.to_vec(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_long_names_are_hashed() {
+        let module = ModuleId::new(
+            AccountAddress::from_hex_literal(&format!("0x{}", "ab".repeat(32))).unwrap(),
+            Identifier::new("module").unwrap(),
+        );
+        let function = Identifier::new("f".repeat(40)).unwrap();
+        let name = mangle(&module, &function, &[]);
+        assert_eq!(name.len(), MAX_NAME_LEN);
+        assert_eq!(demangle(&name), None);
+    }
+}
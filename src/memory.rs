@@ -0,0 +1,591 @@
+//! Module describing how aggregate values (structs and vectors) live in Miden memory.
+//!
+//! Aggregates never live on the operand stack. Instead, the stack holds a single
+//! element which is the address of the aggregate in memory (its "pointer").
+//! This is also how aggregates cross procedure boundaries: arguments and return
+//! values of aggregate type are passed as pointers, and the memory behind a
+//! pointer is always allocated by whoever created the value (e.g. the callee
+//! for a function returning a struct it packed). The caller takes ownership of
+//! the pointed-to memory on return.
+//!
+//! Memory is allocated with a simple bump allocator. The next free offset is
+//! stored at the heap pointer address and allocations start at the heap start,
+//! both given by the `MemoryMap` of the program (by default `HEAP_POINTER_ADDR`
+//! and `HEAP_START`). Allocations which would extend past the end of the heap
+//! (see `MemoryMap::heap_end`) abort with `EOUT_OF_MEMORY` instead of
+//! overwriting the region above it.
+//! Each field occupies one memory address per stack element, so a `u64` field
+//! takes two. Vectors are stored as their length followed by their elements,
+//! one address per element.
+//!
+//! Byte vectors are packed 4 bytes per element for hashing (see `bytes` module)
+//! into a newly allocated `vector<u32>`, the only allocation whose size is only
+//! known at runtime (see `pack_bytes`).
+//!
+//! A reference to an aggregate is its pointer, while a reference to a scalar
+//! value, such as a vector element or a borrowed local, is the address holding
+//! it, or the address of its low limb for a wide integer.
+//!
+//! Vector constants live in a data section at the start of the heap, which is
+//! written by the entry function before anything else is allocated. The data
+//! section also holds the results of memoized calls (see `compiler::CodegenOptions`).
+//!
+//! In debug builds (see `compiler::CodegenOptions::canaries`), every allocation
+//! is framed by `CANARY` words, with its size after the first one, and the entry
+//! function checks all of them before returning, so lowering bugs writing out
+//! of bounds fail the program instead of silently corrupting other values.
+
+use {
+    crate::{
+        abi,
+        error_map::{Check, ErrorCodes},
+    },
+    miden_assembly::ast::{CodeBody, Instruction, Node},
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+/// Address holding the offset (relative to `HEAP_START`) of the next free address.
+pub const HEAP_POINTER_ADDR: u32 = 0;
+
+/// Start of the region used by the entry function epilogue to hold return values
+/// while the output stack is being cleared.
+pub const OUTPUT_BUFFER_ADDR: u32 = 1;
+
+/// First address available for allocations.
+pub const HEAP_START: u32 = OUTPUT_BUFFER_ADDR + abi::MAX_OUTPUTS as u32;
+
+/// Start of the procedure locals, where the VM starts its frame pointer. Locals
+/// of nested calls follow the locals of their callers.
+pub const LOCALS_START: u32 = 1 << 30;
+
+/// Default start of the region used by intrinsics needing fixed addresses, far
+/// above the heap and the procedure locals, and below the memory used by the
+/// standard library's STARK verifier (from 3 * 2^30).
+pub const SCRATCH_START: u32 = 1 << 31;
+
+/// Number of addresses of the scratch region.
+pub const SCRATCH_SIZE: u32 = 64;
+
+/// Where a program keeps its values in memory, configurable so compiled code
+/// can coexist with hand-written MASM or kernel conventions reserving specific
+/// address ranges. Locals live on the operand stack or in procedure locals,
+/// except those of the entry function, which live in the data section.
+/// The heap extends from its start up to the next region above it, the
+/// procedure locals from `LOCALS_START` included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryMap {
+    // Address holding the offset (relative to `heap_start`) of the next free address.
+    pub heap_pointer: u32,
+    // First of the `abi::MAX_OUTPUTS` addresses used by the entry function epilogue.
+    pub output_buffer: u32,
+    // First address of the heap, which starts with the data section.
+    pub heap_start: u32,
+    // First of the `SCRATCH_SIZE` addresses used by intrinsics, e.g. to hold the
+    // public inputs of verified proofs (see `recursion` module).
+    pub scratch: u32,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            heap_pointer: HEAP_POINTER_ADDR,
+            output_buffer: OUTPUT_BUFFER_ADDR,
+            heap_start: HEAP_START,
+            scratch: SCRATCH_START,
+        }
+    }
+}
+
+impl MemoryMap {
+    /// Regions with fixed sizes, as name, start and size.
+    pub fn reserved(&self) -> [(&'static str, u32, u32); 3] {
+        [
+            ("heap pointer", self.heap_pointer, 1),
+            ("output buffer", self.output_buffer, abi::MAX_OUTPUTS as u32),
+            ("scratch region", self.scratch, SCRATCH_SIZE),
+        ]
+    }
+
+    /// First address above the heap: the start of the next region above the
+    /// heap start, or the end of memory if there is none.
+    pub fn heap_end(&self) -> u32 {
+        self.reserved()
+            .into_iter()
+            .map(|(_, start, _)| start)
+            .chain([LOCALS_START])
+            .filter(|start| *start > self.heap_start)
+            .min()
+            .unwrap_or(u32::MAX)
+    }
+
+    /// Fails if two regions overlap, the heap holding at least the data
+    /// section of `data_size` addresses. Procedure locals grow from
+    /// `LOCALS_START` as calls nest, so no region may contain that address.
+    pub fn check(&self, data_size: u32) -> anyhow::Result<()> {
+        let mut regions = self.reserved().to_vec();
+        regions.push(("data section", self.heap_start, data_size.max(1)));
+        regions.push(("procedure locals", LOCALS_START, 1));
+        let mut ranges = Vec::with_capacity(regions.len());
+        for (name, start, size) in regions {
+            let end = start.checked_add(size).ok_or_else(|| {
+                anyhow::anyhow!("The {name} at {start} extends past the end of memory")
+            })?;
+            ranges.push((name, start, end));
+        }
+        for (index, (name, start, end)) in ranges.iter().enumerate() {
+            for (other, other_start, other_end) in &ranges[index + 1..] {
+                if start < other_end && other_start < end {
+                    anyhow::bail!("The {name} at {start} overlaps the {other} at {other_start}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Word written before and after every allocation in debug builds.
+pub const CANARY: u32 = 0xC0FF_EE00;
+
+/// Abort code of a canary found overwritten.
+pub const ECANARY_OVERWRITTEN: u32 = 0xC0FF_EE01;
+
+/// Abort code of an allocation extending past the end of the heap.
+pub const EOUT_OF_MEMORY: u32 = 0xC0FF_EE02;
+
+/// Addresses taken by the canaries of an allocation and its size.
+const CANARY_OVERHEAD: u32 = 3;
+
+/// Addresses taken on the heap by an allocation of `size` addresses.
+pub fn allocation_size(size: u32, canaries: bool) -> u32 {
+    if canaries {
+        size + CANARY_OVERHEAD
+    } else {
+        size
+    }
+}
+
+/// Allocates `size` addresses, leaving the address of the allocation on the stack.
+/// With `canaries`, the allocation is framed as `[CANARY, size, ..., CANARY]`.
+pub fn alloc(size: u32, memory: &MemoryMap, canaries: bool) -> Vec<Node> {
+    if !canaries {
+        return alloc_unchecked(size, memory);
+    }
+    let mut result = alloc_unchecked(size + CANARY_OVERHEAD, memory);
+    // [start, ..] -> [start, ..], writing the leading canary, the size and the trailing canary
+    for (offset, value) in [(0, CANARY), (1, size), (size + 2, CANARY)] {
+        result.extend([
+            Node::Instruction(Instruction::PushU32(value)),
+            Node::Instruction(Instruction::Dup1),
+            Node::Instruction(Instruction::PushU32(offset)),
+            Node::Instruction(Instruction::Add),
+            Node::Instruction(Instruction::MemStore),
+        ]);
+    }
+    result.extend([
+        Node::Instruction(Instruction::PushU32(2)),
+        Node::Instruction(Instruction::Add),
+    ]);
+    result
+}
+
+/// Checks the canaries of all allocations made so far, which start after a data
+/// section of `data_size` addresses, aborting with `ECANARY_OVERWRITTEN` if one
+/// was overwritten. The stack is left unchanged.
+pub fn check_canaries(data_size: u32, memory: &MemoryMap) -> Vec<Node> {
+    // [p, ..] -> [p < end of the heap, p, ..]
+    let before_end = [
+        Node::Instruction(Instruction::Dup0),
+        Node::Instruction(Instruction::MemLoadImm(memory.heap_pointer)),
+        Node::Instruction(Instruction::PushU32(memory.heap_start)),
+        Node::Instruction(Instruction::Add),
+        Node::Instruction(Instruction::Lt),
+    ];
+    // [address, ..] -> [address, ..], failing unless it holds the canary
+    let check = [
+        Node::Instruction(Instruction::Dup0),
+        Node::Instruction(Instruction::MemLoad),
+        Node::Instruction(Instruction::PushU32(CANARY)),
+        Node::Instruction(Instruction::Eq),
+        Node::Instruction(Instruction::AssertWithError(ECANARY_OVERWRITTEN)),
+    ];
+    let mut body = check.to_vec();
+    body.extend([
+        // [p, ..] -> [p + size + 2, p, ..]
+        Node::Instruction(Instruction::Dup0),
+        Node::Instruction(Instruction::PushU32(1)),
+        Node::Instruction(Instruction::Add),
+        Node::Instruction(Instruction::MemLoad),
+        Node::Instruction(Instruction::Dup1),
+        Node::Instruction(Instruction::Add),
+        Node::Instruction(Instruction::PushU32(2)),
+        Node::Instruction(Instruction::Add),
+    ]);
+    body.extend(check);
+    body.extend([
+        // [p + size + 2, p, ..] -> [p + size + 3, ..]
+        Node::Instruction(Instruction::PushU32(1)),
+        Node::Instruction(Instruction::Add),
+        Node::Instruction(Instruction::Swap1),
+        Node::Instruction(Instruction::Drop),
+    ]);
+    body.extend(before_end.clone());
+    let mut result = vec![Node::Instruction(Instruction::PushU32(
+        memory.heap_start + data_size,
+    ))];
+    result.extend(before_end);
+    result.push(Node::While {
+        body: CodeBody::new(body),
+    });
+    result.push(Node::Instruction(Instruction::Drop));
+    result
+}
+
+/// Like `alloc`, with the number of addresses on top of the stack instead of
+/// known at compile time.
+fn alloc_dynamic(memory: &MemoryMap, canaries: bool) -> Vec<Node> {
+    // [size, ..] -> [start, ..]
+    let mut unchecked = vec![
+        Instruction::MemLoadImm(memory.heap_pointer),
+        Instruction::Dup0,
+        Instruction::MovUp2,
+        Instruction::Add,
+    ];
+    unchecked.extend(check_heap_end(memory));
+    unchecked.extend([
+        Instruction::MemStoreImm(memory.heap_pointer),
+        Instruction::PushU32(memory.heap_start),
+        Instruction::Add,
+    ]);
+    if !canaries {
+        return unchecked.into_iter().map(Node::Instruction).collect();
+    }
+    let mut result = vec![
+        Instruction::Dup0,
+        Instruction::PushU32(CANARY_OVERHEAD),
+        Instruction::Add,
+    ];
+    result.extend(unchecked);
+    // [start, size, ..] -> [start, size, ..], writing the size and both canaries
+    result.extend([
+        Instruction::Dup1,
+        Instruction::Dup1,
+        Instruction::PushU32(1),
+        Instruction::Add,
+        Instruction::MemStore,
+        Instruction::PushU32(CANARY),
+        Instruction::Dup1,
+        Instruction::MemStore,
+        Instruction::PushU32(CANARY),
+        Instruction::Dup1,
+        Instruction::Dup3,
+        Instruction::Add,
+        Instruction::PushU32(2),
+        Instruction::Add,
+        Instruction::MemStore,
+        // [start, size, ..] -> [start + 2, ..]
+        Instruction::PushU32(2),
+        Instruction::Add,
+        Instruction::Swap1,
+        Instruction::Drop,
+    ]);
+    result.into_iter().map(Node::Instruction).collect()
+}
+
+fn alloc_unchecked(size: u32, memory: &MemoryMap) -> Vec<Node> {
+    let mut result = vec![
+        Instruction::MemLoadImm(memory.heap_pointer),
+        Instruction::Dup0,
+        Instruction::PushU32(size),
+        Instruction::Add,
+    ];
+    result.extend(check_heap_end(memory));
+    result.extend([
+        Instruction::MemStoreImm(memory.heap_pointer),
+        Instruction::PushU32(memory.heap_start),
+        Instruction::Add,
+    ]);
+    result.into_iter().map(Node::Instruction).collect()
+}
+
+/// Checks the new heap offset on top of the stack is within the heap, aborting
+/// with `EOUT_OF_MEMORY` otherwise. Offsets are sums of u32s, so they can't wrap
+/// around the field.
+fn check_heap_end(memory: &MemoryMap) -> [Instruction; 4] {
+    [
+        Instruction::Dup0,
+        Instruction::PushU32(memory.heap_end() - memory.heap_start),
+        Instruction::Lte,
+        Instruction::AssertWithError(EOUT_OF_MEMORY),
+    ]
+}
+
+/// Moves the `width` stack elements of the fields of a struct into newly allocated
+/// memory, leaving a pointer to it on the stack. The last field is expected on top of the stack.
+pub fn pack(width: u32, memory: &MemoryMap, canaries: bool) -> Vec<Node> {
+    let mut result = alloc(width, memory, canaries);
+    result.extend(store(width));
+    result
+}
+
+/// Moves the `width` stack elements under the pointer on top of the stack into
+/// the addresses it points to, keeping the pointer. The last element is
+/// expected right under the pointer, and is stored at the last address.
+pub fn store(width: u32) -> Vec<Node> {
+    let mut result = Vec::new();
+    for i in (0..width).rev() {
+        // [ptr, f_i, ..] -> [ptr + i, f_i, ptr, ..]
+        result.push(Node::Instruction(Instruction::Dup0));
+        result.push(Node::Instruction(Instruction::PushU32(i)));
+        result.push(Node::Instruction(Instruction::Add));
+        result.push(Node::Instruction(Instruction::MovUp2));
+        result.push(Node::Instruction(Instruction::Swap1));
+        result.push(Node::Instruction(Instruction::MemStore));
+    }
+    result
+}
+
+/// Replaces the pointer on top of the stack with the `width` stack elements of
+/// the fields it points to. The last field ends up on top of the stack.
+pub fn unpack(width: u32) -> Vec<Node> {
+    let mut result = Vec::new();
+    for i in 0..width {
+        // [ptr, ..] -> [ptr, f_i, ..]
+        result.push(Node::Instruction(Instruction::Dup0));
+        result.push(Node::Instruction(Instruction::PushU32(i)));
+        result.push(Node::Instruction(Instruction::Add));
+        result.push(Node::Instruction(Instruction::MemLoad));
+        result.push(Node::Instruction(Instruction::Swap1));
+    }
+    result.push(Node::Instruction(Instruction::Drop));
+    result
+}
+
+/// Replaces the pointer to a `vector<u8>` on top of the stack with a pointer to
+/// a newly allocated `vector<u32>` of its packed elements, its length in bytes
+/// followed by its bytes 4 per element, the last one padded with zeros, as
+/// `bytes::pack_bytes` packs them.
+pub fn pack_bytes(memory: &MemoryMap, canaries: bool) -> Vec<Node> {
+    let mut result: Vec<Node> = [
+        // [src, ..] -> [limbs, len, src, ..]
+        Instruction::Dup0,
+        Instruction::MemLoad,
+        Instruction::Dup0,
+        Instruction::PushU32(3),
+        Instruction::Add,
+        Instruction::PushU32(4),
+        Instruction::U32Div,
+        // The vector holds its length, then the length in bytes and the limbs
+        Instruction::Dup0,
+        Instruction::PushU32(2),
+        Instruction::Add,
+    ]
+    .map(Node::Instruction)
+    .to_vec();
+    result.extend(alloc_dynamic(memory, canaries));
+    result.extend(
+        [
+            // [dst, limbs, len, src, ..] -> [dst, limbs, len, src, ..], writing the lengths
+            Instruction::Dup1,
+            Instruction::PushU32(1),
+            Instruction::Add,
+            Instruction::Dup1,
+            Instruction::MemStore,
+            Instruction::Dup2,
+            Instruction::Dup1,
+            Instruction::PushU32(1),
+            Instruction::Add,
+            Instruction::MemStore,
+            // [k, dst, limbs, len, src, ..], looping while k < limbs
+            Instruction::PushU32(0),
+            Instruction::Dup0,
+            Instruction::Dup3,
+            Instruction::U32Lt,
+        ]
+        .map(Node::Instruction),
+    );
+    // [k, dst, limbs, len, src, ..] -> [limb, k, dst, limbs, len, src, ..], adding
+    // the bytes from the last one, and zero for bytes past the end
+    let mut body = vec![Node::Instruction(Instruction::PushU32(0))];
+    for j in (0..4).rev() {
+        body.extend(
+            [
+                Instruction::PushU32(256),
+                Instruction::Mul,
+                // [i, acc, k, ..], where i = 4k + j
+                Instruction::Dup1,
+                Instruction::PushU32(4),
+                Instruction::Mul,
+                Instruction::PushU32(j),
+                Instruction::Add,
+                Instruction::Dup0,
+                Instruction::Dup6,
+                Instruction::U32Lt,
+            ]
+            .map(Node::Instruction),
+        );
+        body.push(Node::IfElse {
+            true_case: CodeBody::new(
+                [
+                    Instruction::Dup6,
+                    Instruction::Add,
+                    Instruction::PushU32(1),
+                    Instruction::Add,
+                    Instruction::MemLoad,
+                ]
+                .map(Node::Instruction)
+                .to_vec(),
+            ),
+            false_case: CodeBody::new(
+                [Instruction::Drop, Instruction::PushU32(0)]
+                    .map(Node::Instruction)
+                    .to_vec(),
+            ),
+        });
+        body.push(Node::Instruction(Instruction::Add));
+    }
+    body.extend(
+        [
+            // [limb, k, dst, ..] -> [k, dst, ..], writing the limb after the lengths
+            Instruction::Dup2,
+            Instruction::Dup2,
+            Instruction::Add,
+            Instruction::PushU32(2),
+            Instruction::Add,
+            Instruction::MemStore,
+            Instruction::PushU32(1),
+            Instruction::Add,
+            Instruction::Dup0,
+            Instruction::Dup3,
+            Instruction::U32Lt,
+        ]
+        .map(Node::Instruction),
+    );
+    result.push(Node::While {
+        body: CodeBody::new(body),
+    });
+    // [k, dst, limbs, len, src, ..] -> [dst, ..]
+    result.extend(
+        [
+            Instruction::Drop,
+            Instruction::Swap1,
+            Instruction::Drop,
+            Instruction::Swap1,
+            Instruction::Drop,
+            Instruction::Swap1,
+            Instruction::Drop,
+        ]
+        .map(Node::Instruction),
+    );
+    result
+}
+
+/// Replaces the vector pointer and the `u64` index on top of the stack with a
+/// reference to the element at that index, aborting with the code of
+/// `Check::IndexOutOfBounds` if the index is not less than the length of the
+/// vector. Elements which are aggregates are referenced by their own pointer.
+pub fn borrow_element(aggregate: bool, codes: &ErrorCodes) -> Vec<Node> {
+    let out_of_bounds = codes.code(Check::IndexOutOfBounds);
+    let mut result = vec![
+        // [i_hi, i_lo, ptr, ..] -> [i, ptr, ..], as vectors are shorter than 2^32
+        Node::Instruction(Instruction::AssertzWithError(out_of_bounds)),
+        // [i, ptr, ..] -> [len > i, i, ptr, ..]
+        Node::Instruction(Instruction::Dup1),
+        Node::Instruction(Instruction::MemLoad),
+        Node::Instruction(Instruction::Dup1),
+        Node::Instruction(Instruction::Gt),
+        Node::Instruction(Instruction::AssertWithError(out_of_bounds)),
+        // The elements follow the length
+        Node::Instruction(Instruction::Add),
+        Node::Instruction(Instruction::PushU32(1)),
+        Node::Instruction(Instruction::Add),
+    ];
+    if aggregate {
+        result.push(Node::Instruction(Instruction::MemLoad));
+    }
+    result
+}
+
+/// Constant aggregates of a module, laid out at the start of the heap, along
+/// with zeroed addresses reserved for other static data.
+/// Identical constants are stored once.
+#[derive(Debug, Default)]
+pub struct DataSection {
+    memory: MemoryMap,
+    values: Vec<u32>,
+    // Offset of every stored entry, by contents.
+    offsets: BTreeMap<Vec<u32>, u32>,
+}
+
+impl DataSection {
+    pub fn new(memory: MemoryMap) -> Self {
+        Self {
+            memory,
+            ..Self::default()
+        }
+    }
+
+    /// Adds a vector constant, returning its address.
+    pub fn insert_vector(&mut self, elements: &[u32]) -> u32 {
+        let mut entry = vec![elements.len() as u32];
+        entry.extend_from_slice(elements);
+        if let Some(offset) = self.offsets.get(&entry) {
+            return self.memory.heap_start + offset;
+        }
+        let offset = self.values.len() as u32;
+        self.values.extend_from_slice(&entry);
+        self.offsets.insert(entry, offset);
+        self.memory.heap_start + offset
+    }
+
+    /// Reserves `size` zeroed addresses, returning the first one.
+    pub fn reserve(&mut self, size: u32) -> u32 {
+        let offset = self.values.len() as u32;
+        self.values.resize((offset + size) as usize, 0);
+        self.memory.heap_start + offset
+    }
+
+    /// Number of addresses taken by the data section.
+    pub fn size(&self) -> u32 {
+        self.values.len() as u32
+    }
+
+    /// Writes the data section to memory and reserves it in the allocator.
+    /// Must run before any allocation.
+    pub fn init(&self) -> Vec<Node> {
+        if self.values.is_empty() {
+            return Vec::new();
+        }
+        let mut result = Vec::new();
+        for (offset, value) in self.values.iter().enumerate() {
+            result.push(Node::Instruction(Instruction::PushU32(*value)));
+            result.push(Node::Instruction(Instruction::MemStoreImm(
+                self.memory.heap_start + offset as u32,
+            )));
+        }
+        result.push(Node::Instruction(Instruction::PushU32(
+            self.values.len() as u32
+        )));
+        result.push(Node::Instruction(Instruction::MemStoreImm(
+            self.memory.heap_pointer,
+        )));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_section_deduplicates_constants() {
+        let mut data = DataSection::default();
+        let first = data.insert_vector(&[1, 2, 3]);
+        let second = data.insert_vector(&[4]);
+        assert_eq!(first, HEAP_START);
+        assert_eq!(second, HEAP_START + 4);
+        assert_eq!(data.insert_vector(&[1, 2, 3]), first);
+        // 2 instructions per stored value, then 2 to reserve the section
+        assert_eq!(data.init().len(), 2 * 6 + 2);
+    }
+}
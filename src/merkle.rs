@@ -0,0 +1,73 @@
+//! Module for checking Merkle paths inside a program, so Move code can
+//! validate commitments to external state, e.g. a storage root published by
+//! another chain.
+//!
+//! Move code checks that a leaf is the node at `index` and `depth` of the tree
+//! committed to by a root with `merkle::verify(root, index, depth, leaf)`,
+//! declared by the native module in `move/merkle.move`. Roots and leaves are
+//! words, passed as their 4 elements like the digests of `hash::rpo256`, the
+//! last one on top, and elements and indices are `u64`s split into limbs. The
+//! path itself is provided by the host in the Merkle store of the advice
+//! provider, and the program fails unless the store holds a path from the
+//! leaf to the root.
+
+use {
+    crate::{
+        abi,
+        error_map::{Check, ErrorCodes},
+        memory::MemoryMap,
+    },
+    miden::math::Felt,
+    miden_assembly::ast::{Instruction, Node},
+};
+
+/// Arguments of `merkle::verify` from the top of the stack, as whether they
+/// are `u64`s rather than single elements: the leaf, the `u8` depth, the index
+/// and the root.
+const ARGUMENTS: [bool; 10] = [true, true, true, true, false, true, true, true, true, true];
+
+/// Lowering of `merkle::verify`. Every `u64` is joined into the element it
+/// holds, aborting with the code of `Check::CastOutOfRange` unless it is below
+/// the field modulus, so the stack becomes `[leaf, depth, index, root]` as
+/// `mtree_verify` expects.
+pub fn verify(_map: &MemoryMap, codes: &ErrorCodes) -> anyhow::Result<Vec<Node>> {
+    let mut result = Vec::new();
+    // The arguments joined so far stay on top, in order
+    for (joined, wide) in ARGUMENTS.into_iter().enumerate() {
+        if !wide {
+            continue;
+        }
+        // [.. joined, hi, lo] -> [hi, lo, .. joined]
+        if joined > 0 {
+            result.extend(abi::movup(joined));
+            result.extend(abi::movup(joined + 1));
+            result.push(Instruction::Swap1);
+        }
+        result.extend(join_limbs(codes));
+        result.extend(abi::movdn(joined));
+    }
+    result.extend([
+        Instruction::MTreeVerify,
+        // Move's `verify` has no return values
+        Instruction::DropW,
+        Instruction::Drop,
+        Instruction::Drop,
+        Instruction::DropW,
+    ]);
+    Ok(result.into_iter().map(Node::Instruction).collect())
+}
+
+/// Joins the limbs of the `u64` on top of the stack into an element. The
+/// largest element has a high limb of `u32::MAX` and a zero low limb.
+fn join_limbs(codes: &ErrorCodes) -> [Instruction; 8] {
+    [
+        Instruction::Dup1,
+        Instruction::EqImm(Felt::new(0)),
+        Instruction::Dup1,
+        Instruction::NeqImm(Felt::new(u32::MAX as u64)),
+        Instruction::Or,
+        Instruction::AssertWithError(codes.code(Check::CastOutOfRange)),
+        Instruction::MulImm(Felt::new(1 << 32)),
+        Instruction::Add,
+    ]
+}
@@ -0,0 +1,16 @@
+/// Verification of Merkle paths, e.g. to validate commitments to external state.
+/// Add this file to the sources of a package to use it; the address of the
+/// module doesn't matter, as intrinsics are looked up by module name.
+module miden::merkle {
+    /// Checks that the word `(l0, l1, l2, l3)` is the node at `index` and
+    /// `depth` of the tree whose root is `(r0, r1, r2, r3)`, words being given
+    /// by their elements like the digests of `hash::rpo256`. The host provides
+    /// the path, and the program fails unless it leads from the leaf to the
+    /// root, or if an element or the index is not a field element.
+    native public fun verify(
+        r0: u64, r1: u64, r2: u64, r3: u64,
+        index: u64,
+        depth: u8,
+        l0: u64, l1: u64, l2: u64, l3: u64,
+    );
+}
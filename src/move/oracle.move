@@ -0,0 +1,10 @@
+/// Signed external data, such as price feeds, read from an oracle.
+/// Add this file to the sources of a package to use it; the address of the
+/// module doesn't matter, as intrinsics are looked up by module name.
+module miden::oracle {
+    /// Value and timestamp of the latest report of `feed`. `key` is the
+    /// commitment to the public key of the oracle, as 32 bytes, and must be a
+    /// constant. The host provides the report and the signature of the oracle,
+    /// which the program verifies, failing unless the oracle signed the report.
+    native public fun read(feed: u32, key: vector<u8>): (u64, u64);
+}
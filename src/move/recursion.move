@@ -0,0 +1,18 @@
+/// Verification of proofs of other Miden programs, e.g. to aggregate their results.
+/// Add this file to the sources of a package to use it; the address of the
+/// module doesn't matter, as intrinsics are looked up by module name.
+module miden::recursion {
+    /// Verifies a proof of the execution of a Miden program, with `num_queries`
+    /// FRI queries and `grinding` bits of proof of work. The host provides the
+    /// proof and its public inputs, and the program fails unless it is valid.
+    native public fun verify(num_queries: u32, grinding: u32);
+
+    /// Stack input at `index` of the execution proven by the last verified
+    /// proof, the first one being the top of the stack. Aborts unless `index`
+    /// is below 16, and is zero if no proof was verified.
+    native public fun input(index: u32): u64;
+
+    /// Stack output at `index` of the execution proven by the last verified
+    /// proof, as `input` does.
+    native public fun output(index: u32): u64;
+}
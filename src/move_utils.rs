@@ -1,6 +1,18 @@
-use move_binary_format::file_format::CompiledModule;
+use {
+    anyhow::Context,
+    move_binary_format::file_format::CompiledModule,
+    move_bytecode_source_map::{source_map::SourceMap, utils::source_map_from_file},
+    std::path::Path,
+};
 
 pub fn parse_module(bytes: &[u8]) -> anyhow::Result<CompiledModule> {
     let module = CompiledModule::deserialize(bytes)?;
     Ok(module)
 }
+
+/// Reads a source map written by the Move compiler next to the bytecode of a
+/// module (a `.mvsm` file in `build/<package>/source_maps`).
+pub fn load_source_map(path: &Path) -> anyhow::Result<SourceMap> {
+    source_map_from_file(path)
+        .with_context(|| format!("Failed to read source map {}", path.display()))
+}
@@ -0,0 +1,65 @@
+//! Node.js bindings, enabled by the `node` feature.
+//!
+//! The crate builds as a native addon with napi-rs; functions are exported
+//! in camel case (e.g. `programHash`). Errors are thrown as JavaScript errors.
+
+use {
+    crate::{
+        artifact::CompiledArtifact,
+        backend::{DefaultBackend, MidenBackend},
+        bundle, compiler, move_utils,
+    },
+    napi::bindgen_prelude::Buffer,
+    napi_derive::napi,
+};
+
+#[napi(object)]
+pub struct CompileOutput {
+    pub masm: String,
+    // Hex encoded MAST root of the assembled program.
+    pub program_hash: String,
+    // ABI of the entry function, as JSON (see `abi::EntryAbi`).
+    pub abi: String,
+}
+
+/// Compiles serialized module bytecode.
+#[napi]
+pub fn compile(module: Buffer) -> napi::Result<CompileOutput> {
+    let artifact = compile_bytes(&module)?;
+    Ok(CompileOutput {
+        masm: artifact.masm(),
+        program_hash: program_hash_of(&artifact)?,
+        abi: abi_of(&artifact)?,
+    })
+}
+
+/// ABI of the entry function of serialized module bytecode, as JSON.
+#[napi]
+pub fn abi(module: Buffer) -> napi::Result<String> {
+    abi_of(&compile_bytes(&module)?)
+}
+
+/// Hex encoded hash of the program compiled from serialized module bytecode.
+#[napi]
+pub fn program_hash(module: Buffer) -> napi::Result<String> {
+    program_hash_of(&compile_bytes(&module)?)
+}
+
+fn compile_bytes(bytes: &[u8]) -> napi::Result<CompiledArtifact> {
+    let module = move_utils::parse_module(bytes).map_err(to_napi_err)?;
+    compiler::compile(&module).map_err(to_napi_err)
+}
+
+fn abi_of(artifact: &CompiledArtifact) -> napi::Result<String> {
+    serde_json::to_string(&artifact.abi).map_err(|e| to_napi_err(e.into()))
+}
+
+fn program_hash_of(artifact: &CompiledArtifact) -> napi::Result<String> {
+    let backend = DefaultBackend::default();
+    let program = backend.assemble(&artifact.program).map_err(to_napi_err)?;
+    Ok(bundle::to_hex(&backend.program_hash(&program)))
+}
+
+fn to_napi_err(error: anyhow::Error) -> napi::Error {
+    napi::Error::from_reason(format!("{error:#}"))
+}
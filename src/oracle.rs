@@ -0,0 +1,165 @@
+//! Module for reading signed external data, such as price feeds, from an oracle.
+//!
+//! Move code reads the latest report of a feed with `oracle::read(feed, key)`,
+//! declared by the native module in `move/oracle.move`, `key` being the
+//! commitment to the public key of the oracle, given as a constant (see
+//! `bytes::word_from_le_bytes`). The value and timestamp of the report are
+//! read from the advice stack, and the program verifies the Falcon signature of
+//! the report by the oracle, so it fails unless the oracle signed the report.
+//!
+//! The oracle publishes its reports with their signatures (see `Report::sign`).
+//! The host only passes them on: signatures are looked up in the advice map by
+//! the hash of the message and public key, and moved to the advice stack for
+//! the verifier, so whoever runs the prover can't sign reports of their own.
+//! `advice_inputs` builds the advice inputs of the signed reports a program
+//! reads.
+
+use {
+    crate::{bytes, intrinsics},
+    miden::{
+        crypto::{dsa::rpo_falcon512::KeyPair, Rpo256, RpoDigest},
+        math::{Felt, StarkField},
+        AdviceInputs, Word,
+    },
+    miden_assembly::ast::{AdviceInjectorNode, Instruction, Node},
+    miden_processor::{AdviceProvider, MemAdviceProvider, SignatureKind},
+};
+
+/// Standard library procedure verifying the signature of a message by the key
+/// committed to, as `[PK, MSG]` with the public key on top.
+const VERIFY: (&str, &str) = ("std::crypto::dsa::rpo_falcon512", "verify");
+
+/// Value of a feed reported by the oracle at the given time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub feed: u32,
+    pub value: u64,
+    pub timestamp: u64,
+}
+
+impl Report {
+    /// Message signed by the oracle, the hash of the word
+    /// `[value, timestamp, feed, 0]`.
+    pub fn message(&self) -> anyhow::Result<Word> {
+        let elements = [self.value, self.timestamp, self.feed as u64, 0];
+        for value in [self.value, self.timestamp] {
+            anyhow::ensure!(
+                value < Felt::MODULUS,
+                "Reported value {value} is not a field element"
+            );
+        }
+        Ok(Rpo256::hash_elements(&elements.map(Felt::new)).into())
+    }
+
+    /// Signs the report with the key pair of the oracle, for oracles publishing
+    /// their reports.
+    pub fn sign(&self, key_pair: &KeyPair) -> anyhow::Result<SignedReport> {
+        let message = self.message()?;
+        let public_key: Word = key_pair.public_key().into();
+        let secret_key = key_pair
+            .to_bytes()
+            .iter()
+            .map(|byte| Felt::new(*byte as u64))
+            .collect();
+        // The advice provider signs with the secret key found under the public key
+        let provider = MemAdviceProvider::from(
+            AdviceInputs::default()
+                .with_map([(RpoDigest::from(public_key).as_bytes(), secret_key)]),
+        );
+        let mut signature = provider
+            .get_signature(SignatureKind::RpoFalcon512, public_key, message)
+            .map_err(|e| anyhow::anyhow!("Failed to sign the report: {e}"))?;
+        // It returns the elements last to first, as it pushes them one by one
+        signature.reverse();
+        Ok(SignedReport {
+            report: *self,
+            signature,
+        })
+    }
+}
+
+/// A report with the signature of the oracle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedReport {
+    pub report: Report,
+    // Elements the verifier reads from the advice stack, in order.
+    pub signature: Vec<Felt>,
+}
+
+/// Standard library procedures which may be called by generated code, as
+/// library path and procedure name.
+pub fn imports() -> impl Iterator<Item = (&'static str, &'static str)> {
+    [VERIFY].into_iter()
+}
+
+/// Lowering of `oracle::read` with the given public key commitment, the feed
+/// being on top of the stack. Leaves the value of the report below its
+/// timestamp, both split into limbs like any `u64`.
+pub fn read(key: &[u8]) -> anyhow::Result<Vec<Node>> {
+    let key = bytes::word_from_le_bytes(key)
+        .map_err(|e| anyhow::anyhow!("Invalid oracle public key commitment: {e}"))?;
+    // [feed] -> [MSG, timestamp, value], MSG being the hash of the word
+    // [value, timestamp, feed, 0]
+    let mut result = vec![
+        Instruction::AdvPush(2),
+        Instruction::Dup1,
+        Instruction::Dup1,
+        Instruction::MovUp4,
+        Instruction::PushU32(0),
+        Instruction::Hash,
+    ];
+    // [MSG, timestamp, value] -> [PK, MSG, timestamp, value]
+    result.extend(key.into_iter().map(Instruction::PushFelt));
+    // Moves the signature to the advice stack, from the advice map under the
+    // hash of MSG and PK
+    result.extend([
+        Instruction::DupW1,
+        Instruction::DupW1,
+        Instruction::HMerge,
+        Instruction::AdvInject(AdviceInjectorNode::PushMapVal),
+        Instruction::DropW,
+    ]);
+    let mut nodes: Vec<Node> = result.into_iter().map(Node::Instruction).collect();
+    let (path, procedure) = VERIFY;
+    nodes.push(intrinsics::exec_imported(path, procedure)?);
+    // [timestamp, value] -> [timestamp_hi, timestamp_lo, value_hi, value_lo]
+    nodes.extend(
+        [
+            Instruction::Swap1,
+            Instruction::U32Split,
+            Instruction::MovUp2,
+            Instruction::U32Split,
+        ]
+        .map(Node::Instruction),
+    );
+    Ok(nodes)
+}
+
+/// Advice inputs providing the signed reports, in the order the program reads
+/// them, for the oracle with the given public key.
+pub fn advice_inputs(reports: &[SignedReport], public_key: Word) -> anyhow::Result<AdviceInputs> {
+    let mut stack = Vec::new();
+    let mut signatures = Vec::new();
+    for signed in reports {
+        let report = &signed.report;
+        let message = report.message()?;
+        stack.extend([report.value, report.timestamp]);
+        signatures.push((signature_key(public_key, message), signed.signature.clone()));
+    }
+    let advice = AdviceInputs::default()
+        .with_stack_values(stack)?
+        .with_map(signatures);
+    Ok(advice)
+}
+
+/// Key of the signature of a message in the advice map, the hash of the message
+/// and the public key, as `read` computes it.
+pub fn signature_key(public_key: Word, message: Word) -> [u8; 32] {
+    Rpo256::merge(&[RpoDigest::from(message), RpoDigest::from(public_key)]).as_bytes()
+}
+
+/// Commitment to the public key of the oracle, encoded as `oracle::read` expects it.
+pub fn key_commitment(key_pair: &KeyPair) -> Vec<u8> {
+    let public_key: Word = key_pair.public_key().into();
+    bytes::word_to_le_bytes(&public_key).to_vec()
+}
@@ -0,0 +1,102 @@
+//! Module recording the code of a program after each compiler pass.
+//!
+//! When a performance or correctness regression comes from an optimization,
+//! the final program alone doesn't tell which pass is responsible. With
+//! `CodegenOptions::emit_pass_ir`, the compiler records the Miden assembly of
+//! the whole program after every pass, so the effect of each pass can be read
+//! as a diff against the previous one (see `report`) or saved to one file per
+//! pass (see `write_files`).
+
+use {
+    crate::codegen::{CodegenBackend, MasmWriter},
+    anyhow::Context,
+    miden_assembly::ast::{CodeBody, ProcedureAst},
+    serde::{Deserialize, Serialize},
+    similar::TextDiff,
+    std::{fmt, fmt::Write, path::Path},
+};
+
+/// Compiler passes, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pass {
+    // Every Move function lowered on its own (see `compiler` module).
+    Lower,
+    // Redundant stack manipulation removed (see `schedule` module).
+    Schedule,
+    // Calls to small procedures inlined (see `inline` module).
+    Inline,
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Pass::Lower => "lower",
+            Pass::Schedule => "schedule",
+            Pass::Inline => "inline",
+        })
+    }
+}
+
+/// Miden assembly of the program after a pass.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PassIr {
+    pub pass: Pass,
+    pub masm: String,
+}
+
+/// Formats the program made of the given procedures, in the order of their
+/// indices, and body, importing the library procedures in `imports`.
+pub(crate) fn snapshot<'a>(
+    pass: Pass,
+    imports: impl IntoIterator<Item = &'a str>,
+    procedures: impl IntoIterator<Item = &'a ProcedureAst>,
+    body: &CodeBody,
+) -> anyhow::Result<PassIr> {
+    let mut writer = MasmWriter::new(Vec::new());
+    for path in imports {
+        writer.import(path)?;
+    }
+    for procedure in procedures {
+        writer.procedure(procedure.clone())?;
+    }
+    writer.entry(body.clone())?;
+    let masm = String::from_utf8(writer.finish()?)?;
+    Ok(PassIr { pass, masm })
+}
+
+/// Combined report listing the program after the first pass, followed by
+/// what every later pass changed as a unified diff.
+pub fn report(passes: &[PassIr]) -> String {
+    let mut report = String::new();
+    let mut previous: Option<&PassIr> = None;
+    for ir in passes {
+        match previous {
+            None => {
+                writeln!(report, "# After {}\n\n{}", ir.pass, ir.masm).ok();
+            }
+            Some(old) if old.masm == ir.masm => {
+                writeln!(report, "# After {}: no changes\n", ir.pass).ok();
+            }
+            Some(old) => {
+                let (from, to) = (old.pass.to_string(), ir.pass.to_string());
+                let diff = TextDiff::from_lines(&old.masm, &ir.masm);
+                let diff = diff.unified_diff().header(&from, &to).to_string();
+                writeln!(report, "# After {}\n\n{diff}", ir.pass).ok();
+            }
+        }
+        previous = Some(ir);
+    }
+    report
+}
+
+/// Writes the program after each pass to its own file in `dir`, named after
+/// the position and name of the pass, such as `2-schedule.masm`.
+pub fn write_files(passes: &[PassIr], dir: &Path) -> anyhow::Result<()> {
+    for (index, ir) in passes.iter().enumerate() {
+        let path = dir.join(format!("{}-{}.masm", index + 1, ir.pass));
+        std::fs::write(&path, &ir.masm)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
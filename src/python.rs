@@ -0,0 +1,73 @@
+//! Python bindings, enabled by the `python` feature.
+//!
+//! The crate builds as a native extension module named `move2miden`, e.g. with maturin.
+//! Compilation errors are raised as `RuntimeError` with the rendered diagnostics.
+
+use {
+    crate::{compiler, frontend, move_utils, runner},
+    pyo3::{exceptions::PyRuntimeError, prelude::*},
+    std::collections::BTreeMap,
+};
+
+/// Compiles serialized module bytecode, returning the Miden assembly of the program.
+#[pyfunction]
+fn compile(module: &[u8]) -> PyResult<String> {
+    let module = move_utils::parse_module(module).map_err(to_py_err)?;
+    let artifact = compiler::compile(&module).map_err(to_py_err)?;
+    Ok(artifact.masm())
+}
+
+/// Compiles Move source files, returning the Miden assembly of the program.
+#[pyfunction]
+#[pyo3(signature = (sources, named_addresses = None))]
+fn build(
+    sources: Vec<String>,
+    named_addresses: Option<BTreeMap<String, String>>,
+) -> PyResult<String> {
+    let named_addresses =
+        frontend::parse_named_addresses(named_addresses.unwrap_or_default()).map_err(to_py_err)?;
+    let artifact = frontend::compile_to_miden(&sources, named_addresses)
+        .map_err(|diagnostics| PyRuntimeError::new_err(diagnostics.render()))?;
+    Ok(artifact.masm())
+}
+
+/// Checks Move source files, returning `(module, function, compiled)` for every
+/// function along with the rendered diagnostics.
+#[pyfunction]
+#[pyo3(signature = (sources, named_addresses = None))]
+fn check(
+    sources: Vec<String>,
+    named_addresses: Option<BTreeMap<String, String>>,
+) -> PyResult<(Vec<(String, String, bool)>, String)> {
+    let named_addresses =
+        frontend::parse_named_addresses(named_addresses.unwrap_or_default()).map_err(to_py_err)?;
+    let result = frontend::check_sources(&sources, named_addresses);
+    let functions = result
+        .functions
+        .into_iter()
+        .map(|f| (f.module, f.function, f.procedure.is_some()))
+        .collect();
+    Ok((functions, result.diagnostics.render()))
+}
+
+/// Compiles and runs serialized module bytecode with the given entry function
+/// parameters, returning its return values.
+#[pyfunction]
+fn run(module: &[u8], inputs: Vec<u64>) -> PyResult<Vec<u64>> {
+    let module = move_utils::parse_module(module).map_err(to_py_err)?;
+    let artifact = compiler::compile(&module).map_err(to_py_err)?;
+    runner::run(&artifact, &inputs).map_err(to_py_err)
+}
+
+fn to_py_err(error: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{error:#}"))
+}
+
+#[pymodule]
+fn move2miden(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(build, m)?)?;
+    m.add_function(wrap_pyfunction!(check, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    Ok(())
+}
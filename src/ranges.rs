@@ -0,0 +1,427 @@
+//! Module computing the ranges of the integer values of a function, to find the
+//! additions, subtractions and multiplications which can't overflow.
+//!
+//! Move aborts on arithmetic overflow, so the compiler lowers arithmetic to
+//! checked Miden instructions, which cost more cycles than field arithmetic.
+//! When the ranges of the operands show the result always fits in its type
+//! (e.g. `(x as u32) + 1` for a `u8` value `x`), the check can be dropped.
+//!
+//! The analysis is deliberately simple: the operand stack is tracked instruction
+//! by instruction, and every local holds the union of all values stored into it
+//! anywhere in the function, ignoring branch conditions. Locals whose range keeps
+//! growing, like loop counters, are widened to the whole range of their type.
+
+use {
+    move_binary_format::{
+        access::ModuleAccess,
+        file_format::{
+            Bytecode, CodeOffset, SignatureIndex, SignatureToken, StructFieldInformation,
+        },
+        CompiledModule,
+    },
+    move_core_types::value::MoveValue,
+    std::collections::BTreeSet,
+};
+
+/// Passes over the code after which locals still growing are widened.
+const WIDENING_PASSES: usize = 3;
+
+/// Inclusive range of the values of an integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Range {
+    low: u128,
+    high: u128,
+    // Largest value of the type.
+    max: u128,
+}
+
+impl Range {
+    fn exact(value: u128, max: u128) -> Self {
+        Self {
+            low: value,
+            high: value,
+            max,
+        }
+    }
+
+    fn full(max: u128) -> Self {
+        Self {
+            low: 0,
+            high: max,
+            max,
+        }
+    }
+
+    fn of_type(token: &SignatureToken) -> Option<Self> {
+        type_max(token).map(Self::full)
+    }
+
+    fn join(self, other: Self) -> Self {
+        Self {
+            low: self.low.min(other.low),
+            high: self.high.max(other.high),
+            max: self.max,
+        }
+    }
+
+    /// Range of a result which aborts if it isn't in `low..=high`.
+    fn clamped(low: u128, high: Option<u128>, max: u128) -> Self {
+        let high = high.map_or(max, |high| high.min(max));
+        Self {
+            low: low.min(high),
+            high,
+            max,
+        }
+    }
+}
+
+/// Abstract value on the operand stack: an integer in a range, or anything else.
+type Value = Option<Range>;
+
+fn type_max(token: &SignatureToken) -> Option<u128> {
+    match token {
+        SignatureToken::U8 => Some(u8::MAX as u128),
+        SignatureToken::U16 => Some(u16::MAX as u128),
+        SignatureToken::U32 => Some(u32::MAX as u128),
+        SignatureToken::U64 => Some(u64::MAX as u128),
+        SignatureToken::U128 => Some(u128::MAX),
+        _ => None,
+    }
+}
+
+/// Offsets of the `Add`, `Sub` and `Mul` instructions of `code` whose result may
+/// not fit in its type, and which therefore need a range check.
+pub fn overflow_checks(
+    module: &CompiledModule,
+    code: &[Bytecode],
+    params: &[SignatureToken],
+    locals: &[SignatureToken],
+) -> BTreeSet<CodeOffset> {
+    let all_sites = || {
+        code.iter()
+            .enumerate()
+            .filter(|(_, c)| matches!(c, Bytecode::Add | Bytecode::Sub | Bytecode::Mul))
+            .map(|(offset, _)| offset as CodeOffset)
+            .collect()
+    };
+    let types: Vec<&SignatureToken> = params.iter().chain(locals).collect();
+    // Parameters may hold any value of their type; other locals are unset until stored
+    let mut ranges: Vec<Value> = types
+        .iter()
+        .enumerate()
+        .map(|(index, token)| {
+            if index < params.len() {
+                Range::of_type(token)
+            } else {
+                None
+            }
+        })
+        .collect();
+    let mut pass = 0;
+    loop {
+        let mut analysis = Pass {
+            module,
+            types: &types,
+            locals: ranges.clone(),
+            stack: Vec::new(),
+            checks: BTreeSet::new(),
+        };
+        for (offset, bytecode) in code.iter().enumerate() {
+            if analysis.step(offset as CodeOffset, bytecode).is_none() {
+                // Unknown stack effect, so nothing can be proven
+                return all_sites();
+            }
+        }
+        if analysis.locals == ranges {
+            return analysis.checks;
+        }
+        for (index, (old, new)) in ranges.iter_mut().zip(analysis.locals).enumerate() {
+            if pass >= WIDENING_PASSES && *old != new {
+                *old = Range::of_type(types[index]);
+            } else {
+                *old = new;
+            }
+        }
+        pass += 1;
+    }
+}
+
+/// State of a pass over the code of a function.
+struct Pass<'a> {
+    module: &'a CompiledModule,
+    // Types of the parameters then the locals.
+    types: &'a [&'a SignatureToken],
+    // Union of the values stored into every local; `None` for unset integer locals.
+    locals: Vec<Value>,
+    stack: Vec<Value>,
+    checks: BTreeSet<CodeOffset>,
+}
+
+impl Pass<'_> {
+    /// Updates the state after `bytecode`, or returns `None` if its stack effect is unknown.
+    fn step(&mut self, offset: CodeOffset, bytecode: &Bytecode) -> Option<()> {
+        let module = self.module;
+        match bytecode {
+            Bytecode::LdU8(x) => self.push(Some(Range::exact(*x as u128, u8::MAX as u128))),
+            Bytecode::LdU16(x) => self.push(Some(Range::exact(*x as u128, u16::MAX as u128))),
+            Bytecode::LdU32(x) => self.push(Some(Range::exact(*x as u128, u32::MAX as u128))),
+            Bytecode::LdU64(x) => self.push(Some(Range::exact(*x as u128, u64::MAX as u128))),
+            Bytecode::LdU128(x) => self.push(Some(Range::exact(*x, u128::MAX))),
+            Bytecode::LdConst(index) => {
+                let constant = module.constant_at(*index);
+                let value = type_max(&constant.type_).and_then(|max| {
+                    let value = match constant.deserialize_constant()? {
+                        MoveValue::U8(x) => x as u128,
+                        MoveValue::U16(x) => x as u128,
+                        MoveValue::U32(x) => x as u128,
+                        MoveValue::U64(x) => x as u128,
+                        MoveValue::U128(x) => x,
+                        _ => return None,
+                    };
+                    Some(Range::exact(value, max))
+                });
+                self.push(value);
+            }
+            Bytecode::CopyLoc(index) | Bytecode::MoveLoc(index) => {
+                let index = *index as usize;
+                let token = self.types.get(index)?;
+                // Unset locals can't be read, but stay sound if they are
+                let value = self.locals[index].or_else(|| Range::of_type(token));
+                self.push(value);
+            }
+            Bytecode::StLoc(index) => {
+                let value = self.pop()?;
+                let local = self.locals.get_mut(*index as usize)?;
+                *local = match (*local, value) {
+                    (Some(old), Some(new)) => Some(old.join(new)),
+                    (None, new) => new,
+                    // Integer locals only hold integers
+                    (Some(old), None) => Some(Range::full(old.max)),
+                };
+            }
+            Bytecode::MutBorrowLoc(index) => {
+                // The local may be written through the reference
+                let index = *index as usize;
+                self.locals[index] = Range::of_type(self.types.get(index)?);
+                self.push(None);
+            }
+            Bytecode::Add | Bytecode::Sub | Bytecode::Mul => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                let result = match (left, right) {
+                    (Some(a), Some(b)) => arithmetic(bytecode, a, b),
+                    _ => None,
+                };
+                match result {
+                    Some((range, true)) => self.push(Some(range)),
+                    Some((range, false)) => {
+                        self.checks.insert(offset);
+                        self.push(Some(range));
+                    }
+                    None => {
+                        self.checks.insert(offset);
+                        self.push(left.map(|a| Range::full(a.max)));
+                    }
+                }
+            }
+            Bytecode::Div
+            | Bytecode::Mod
+            | Bytecode::BitAnd
+            | Bytecode::BitOr
+            | Bytecode::Xor
+            | Bytecode::Shl
+            | Bytecode::Shr => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                let result = match (left, right) {
+                    (Some(a), Some(b)) => Some(bitwise(bytecode, a, b)),
+                    (Some(a), None) => Some(Range::full(a.max)),
+                    _ => None,
+                };
+                self.push(result);
+            }
+            Bytecode::CastU8 => self.cast(u8::MAX as u128)?,
+            Bytecode::CastU16 => self.cast(u16::MAX as u128)?,
+            Bytecode::CastU32 => self.cast(u32::MAX as u128)?,
+            Bytecode::CastU64 => self.cast(u64::MAX as u128)?,
+            Bytecode::CastU128 => self.cast(u128::MAX)?,
+            Bytecode::VecLen(_) => {
+                self.pop()?;
+                self.push(Some(Range::full(u64::MAX as u128)));
+            }
+            Bytecode::Call(index) => {
+                let handle = module.function_handle_at(*index);
+                self.call(handle.parameters, handle.return_)?;
+            }
+            Bytecode::CallGeneric(index) => {
+                let instantiation = module.function_instantiation_at(*index);
+                let handle = module.function_handle_at(instantiation.handle);
+                self.call(handle.parameters, handle.return_)?;
+            }
+            Bytecode::Unpack(index) => {
+                self.pop()?;
+                let struct_def = module.struct_def_at(*index);
+                match &struct_def.field_information {
+                    StructFieldInformation::Declared(fields) => {
+                        for field in fields {
+                            self.push(Range::of_type(&field.signature.0));
+                        }
+                    }
+                    StructFieldInformation::Native => return None,
+                }
+            }
+            _ => {
+                let (pops, pushes) = stack_effect(module, bytecode)?;
+                for _ in 0..pops {
+                    self.pop()?;
+                }
+                for _ in 0..pushes {
+                    self.push(None);
+                }
+            }
+        }
+        // The operand stack is empty at the start of every basic block
+        if bytecode.is_branch() || bytecode.is_unconditional_branch() {
+            self.stack.clear();
+        }
+        Some(())
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Option<Value> {
+        self.stack.pop()
+    }
+
+    fn cast(&mut self, max: u128) -> Option<()> {
+        // Casts abort if the value doesn't fit
+        let value = match self.pop()? {
+            Some(range) => Range::clamped(range.low, Some(range.high), max),
+            None => Range::full(max),
+        };
+        self.push(Some(value));
+        Some(())
+    }
+
+    fn call(&mut self, params: SignatureIndex, returns: SignatureIndex) -> Option<()> {
+        for _ in 0..self.module.signature_at(params).len() {
+            self.pop()?;
+        }
+        for token in &self.module.signature_at(returns).0 {
+            self.push(Range::of_type(token));
+        }
+        Some(())
+    }
+}
+
+/// Range of the result of an `Add`, `Sub` or `Mul`, and whether it always fits
+/// in its type. Results which don't fit abort, so the range is clamped.
+fn arithmetic(bytecode: &Bytecode, a: Range, b: Range) -> Option<(Range, bool)> {
+    let max = a.max;
+    let result = match bytecode {
+        Bytecode::Add => {
+            let high = a.high.checked_add(b.high);
+            let safe = high.is_some_and(|high| high <= max);
+            (Range::clamped(a.low.saturating_add(b.low), high, max), safe)
+        }
+        Bytecode::Sub => {
+            let safe = a.low >= b.high;
+            let low = a.low.saturating_sub(b.high);
+            (
+                Range::clamped(low, Some(a.high.saturating_sub(b.low)), max),
+                safe,
+            )
+        }
+        Bytecode::Mul => {
+            let high = a.high.checked_mul(b.high);
+            let safe = high.is_some_and(|high| high <= max);
+            (Range::clamped(a.low.saturating_mul(b.low), high, max), safe)
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Range of the result of a division, remainder, bitwise operation or shift.
+fn bitwise(bytecode: &Bytecode, a: Range, b: Range) -> Range {
+    let max = a.max;
+    // Smallest value with all bits set at least as large as both operands
+    let mask = |x: u128| match x.checked_next_power_of_two() {
+        Some(power) if power > x => power - 1,
+        Some(power) => power.saturating_mul(2) - 1,
+        None => u128::MAX,
+    };
+    match bytecode {
+        Bytecode::Div => Range {
+            low: a.low / b.high.max(1),
+            high: a.high / b.low.max(1),
+            max,
+        },
+        Bytecode::Mod => Range::clamped(0, Some(a.high.min(b.high.saturating_sub(1))), max),
+        Bytecode::BitAnd => Range::clamped(0, Some(a.high.min(b.high)), max),
+        Bytecode::BitOr | Bytecode::Xor => Range::clamped(0, Some(mask(a.high.max(b.high))), max),
+        Bytecode::Shr => Range::clamped(0, Some(a.high), max),
+        // Bits shifted out are dropped
+        _ => Range::full(max),
+    }
+}
+
+/// Number of values popped and pushed by instructions producing no integers.
+fn stack_effect(module: &CompiledModule, bytecode: &Bytecode) -> Option<(usize, usize)> {
+    let field_count = |index| match &module.struct_def_at(index).field_information {
+        StructFieldInformation::Declared(fields) => Some(fields.len()),
+        StructFieldInformation::Native => None,
+    };
+    let effect = match bytecode {
+        Bytecode::Nop | Bytecode::Branch(_) | Bytecode::Ret => (0, 0),
+        Bytecode::LdTrue | Bytecode::LdFalse | Bytecode::LdU256(_) | Bytecode::ImmBorrowLoc(_) => {
+            (0, 1)
+        }
+        Bytecode::Pop | Bytecode::BrTrue(_) | Bytecode::BrFalse(_) | Bytecode::Abort => (1, 0),
+        Bytecode::Not
+        | Bytecode::CastU256
+        | Bytecode::ReadRef
+        | Bytecode::FreezeRef
+        | Bytecode::MutBorrowField(_)
+        | Bytecode::MutBorrowFieldGeneric(_)
+        | Bytecode::ImmBorrowField(_)
+        | Bytecode::ImmBorrowFieldGeneric(_)
+        | Bytecode::MutBorrowGlobal(_)
+        | Bytecode::MutBorrowGlobalGeneric(_)
+        | Bytecode::ImmBorrowGlobal(_)
+        | Bytecode::ImmBorrowGlobalGeneric(_)
+        | Bytecode::Exists(_)
+        | Bytecode::ExistsGeneric(_)
+        | Bytecode::MoveFrom(_)
+        | Bytecode::MoveFromGeneric(_)
+        | Bytecode::VecPopBack(_) => (1, 1),
+        Bytecode::Eq
+        | Bytecode::Neq
+        | Bytecode::Lt
+        | Bytecode::Gt
+        | Bytecode::Le
+        | Bytecode::Ge
+        | Bytecode::Or
+        | Bytecode::And
+        | Bytecode::VecImmBorrow(_)
+        | Bytecode::VecMutBorrow(_) => (2, 1),
+        Bytecode::WriteRef
+        | Bytecode::MoveTo(_)
+        | Bytecode::MoveToGeneric(_)
+        | Bytecode::VecPushBack(_) => (2, 0),
+        Bytecode::VecSwap(_) => (3, 0),
+        Bytecode::Pack(index) => (field_count(*index)?, 1),
+        Bytecode::PackGeneric(index) => {
+            (field_count(module.struct_instantiation_at(*index).def)?, 1)
+        }
+        Bytecode::UnpackGeneric(index) => {
+            (1, field_count(module.struct_instantiation_at(*index).def)?)
+        }
+        Bytecode::VecPack(_, count) => (*count as usize, 1),
+        Bytecode::VecUnpack(_, count) => (1, *count as usize),
+        _ => return None,
+    };
+    Some(effect)
+}
@@ -0,0 +1,161 @@
+//! Module for verifying proofs of other Miden programs inside a program, so
+//! aggregation logic (e.g. summing the results of many executions) can be
+//! written in Move.
+//!
+//! Move code verifies a proof with `recursion::verify(num_queries, grinding)`,
+//! declared by the native module in `move/recursion.move`, then reads the stack
+//! inputs and outputs of the proven execution with `recursion::input(index)`
+//! and `recursion::output(index)`. The proof is provided by the advice
+//! provider and checked by the STARK verifier of the standard library.
+//!
+//! The public inputs of the proof, its 16 stack inputs followed by its 16 stack
+//! outputs, are too many for the operand stack, so they go through memory: the
+//! program reads them from the advice stack into the scratch region of its
+//! memory map (see `memory::MemoryMap`), then pushes them back onto the advice
+//! stack from memory, through the advice map, for the verifier, which reads
+//! them first. Move code thus reads the very values the proof was verified
+//! against, whatever the host provides. `advice_stack` builds the advice stack
+//! of a proof for the host.
+
+use {
+    crate::{
+        error_map::{Check, ErrorCodes},
+        intrinsics,
+        memory::{self, MemoryMap},
+    },
+    miden::math::{Felt, StarkField},
+    miden_assembly::ast::{AdviceInjectorNode, Instruction, Node},
+};
+
+/// Standard library procedure verifying a proof provided by the advice provider,
+/// given `[log(trace_length), num_queries, log(blowup), grinding]`.
+const VERIFY: (&str, &str) = ("std::crypto::stark::verifier", "verify");
+
+/// Blowup factor of the proofs the verifier accepts, as its logarithm.
+const LOG_BLOWUP: u32 = 3;
+
+/// Number of stack inputs, and of stack outputs, of a proven execution.
+pub const STACK_WIDTH: usize = 16;
+
+/// Offset in the scratch region of the public inputs of the last verified
+/// proof, one element per address.
+const PUBLIC_INPUTS_OFFSET: u32 = 0;
+
+/// Offset in the scratch region of the same public inputs packed into words,
+/// as the advice map stores them.
+const PUBLIC_INPUTS_WORDS_OFFSET: u32 = PUBLIC_INPUTS_OFFSET + 2 * STACK_WIDTH as u32;
+
+/// Number of scratch addresses used, which must fit in `memory::SCRATCH_SIZE`.
+const SCRATCH_USED: u32 = PUBLIC_INPUTS_WORDS_OFFSET + 2 * STACK_WIDTH as u32 / 4;
+const _: () = assert!(SCRATCH_USED <= memory::SCRATCH_SIZE);
+
+/// Standard library procedures which may be called by generated code, as
+/// library path and procedure name.
+pub fn imports() -> impl Iterator<Item = (&'static str, &'static str)> {
+    [VERIFY].into_iter()
+}
+
+/// Lowering of `recursion::verify`, with `grinding` on top of `num_queries`
+/// on the stack. The host provides the logarithm of the trace length of the
+/// proof, then the public inputs, then the data of the verifier.
+pub fn verify(map: &MemoryMap, _codes: &ErrorCodes) -> anyhow::Result<Vec<Node>> {
+    let inputs_addr = map.scratch + PUBLIC_INPUTS_OFFSET;
+    let words_addr = map.scratch + PUBLIC_INPUTS_WORDS_OFFSET;
+    // [grinding, num_queries] -> [log(trace_length), num_queries, log(blowup), grinding]
+    let mut result = vec![
+        Instruction::Swap1,
+        Instruction::PushU32(LOG_BLOWUP),
+        Instruction::Swap1,
+        Instruction::AdvPush(1),
+        Instruction::U32Assert,
+    ];
+    // Every word of public inputs is kept both as 4 elements and as a word
+    for word in 0..(2 * STACK_WIDTH as u32 / 4) {
+        for element in 0..4 {
+            result.extend([
+                Instruction::AdvPush(1),
+                Instruction::Dup0,
+                Instruction::MemStoreImm(inputs_addr + 4 * word + element),
+            ]);
+        }
+        result.extend([
+            Instruction::MemStoreWImm(words_addr + word),
+            Instruction::DropW,
+        ]);
+    }
+    // The advice map entry is only read right after it's written, so its key
+    // doesn't matter
+    result.extend([
+        Instruction::PushU32(map.scratch + SCRATCH_USED),
+        Instruction::PushU32(words_addr),
+        Instruction::PadW,
+        Instruction::AdvInject(AdviceInjectorNode::InsertMem),
+        Instruction::AdvInject(AdviceInjectorNode::PushMapVal),
+        Instruction::DropW,
+        Instruction::Drop,
+        Instruction::Drop,
+    ]);
+    let mut nodes: Vec<Node> = result.into_iter().map(Node::Instruction).collect();
+    let (path, procedure) = VERIFY;
+    nodes.push(intrinsics::exec_imported(path, procedure)?);
+    Ok(nodes)
+}
+
+/// Code reading the element of the public inputs at the index on top of the
+/// stack, `offset` being the position of the first stack input or output, and
+/// leaving it split into limbs like any `u64`. Aborts with the code of
+/// `Check::IndexOutOfBounds` unless the index is below 16.
+fn read(map: &MemoryMap, offset: u32, codes: &ErrorCodes) -> Vec<Node> {
+    [
+        Instruction::Dup0,
+        Instruction::PushU32(STACK_WIDTH as u32),
+        Instruction::U32Lt,
+        Instruction::AssertWithError(codes.code(Check::IndexOutOfBounds)),
+        Instruction::PushU32(map.scratch + PUBLIC_INPUTS_OFFSET + offset),
+        Instruction::Add,
+        Instruction::MemLoad,
+        Instruction::U32Split,
+    ]
+    .into_iter()
+    .map(Node::Instruction)
+    .collect()
+}
+
+/// Lowering of `recursion::input`, reading a stack input of the last verified proof.
+pub fn read_input(memory: &MemoryMap, codes: &ErrorCodes) -> anyhow::Result<Vec<Node>> {
+    Ok(read(memory, 0, codes))
+}
+
+/// Lowering of `recursion::output`, reading a stack output of the last verified proof.
+pub fn read_output(memory: &MemoryMap, codes: &ErrorCodes) -> anyhow::Result<Vec<Node>> {
+    Ok(read(memory, STACK_WIDTH as u32, codes))
+}
+
+/// Advice stack of a program verifying a proof, given the logarithm of the
+/// trace length of the proof, the stack inputs and outputs of the proven
+/// execution, in the order of `StackInputs` (the first element on top of the
+/// stack), and the advice stack read by the verifier for the proof itself. The
+/// Merkle paths of the proof must also be added to the advice inputs. Missing inputs and outputs are zero.
+pub fn advice_stack(
+    log_trace_length: u32,
+    inputs: &[u64],
+    outputs: &[u64],
+    verifier_data: &[u64],
+) -> anyhow::Result<Vec<u64>> {
+    let mut stack = Vec::with_capacity(1 + 2 * STACK_WIDTH + verifier_data.len());
+    stack.push(log_trace_length as u64);
+    for (name, values) in [("inputs", inputs), ("outputs", outputs)] {
+        anyhow::ensure!(
+            values.len() <= STACK_WIDTH,
+            "A proven execution has at most {STACK_WIDTH} stack {name}, but {} were given",
+            values.len()
+        );
+        if let Some(value) = values.iter().find(|v| **v >= Felt::MODULUS) {
+            anyhow::bail!("Stack {name} value {value} is not a field element");
+        }
+        stack.extend_from_slice(values);
+        stack.resize(stack.len() + STACK_WIDTH - values.len(), 0);
+    }
+    stack.extend_from_slice(verifier_data);
+    Ok(stack)
+}
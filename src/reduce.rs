@@ -0,0 +1,184 @@
+//! Module minimizing the bytecode of a function on which the compiler fails.
+//!
+//! Contiguous runs of instructions are removed from the function, starting with
+//! halves of its code and down to single instructions, as long as the module
+//! still passes the bytecode verifier and the failure is preserved. Branches
+//! into a removed run are redirected to the first instruction after it. The
+//! result is written as a `.mv` file, to be used as a test fixture.
+
+use {
+    move_binary_format::{
+        file_format::{Bytecode, FunctionDefinitionIndex},
+        CompiledModule,
+    },
+    std::{
+        ops::Range,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Reduces the code of `function` in `module` while `interesting` holds for the
+/// reduced module, which it must for `module` itself. Returns `module`
+/// unchanged if the function is native.
+pub fn reduce_function(
+    module: &CompiledModule,
+    function: FunctionDefinitionIndex,
+    mut interesting: impl FnMut(&CompiledModule) -> bool,
+) -> CompiledModule {
+    let mut best = module.clone();
+    let Some(mut chunk) = code_len(&best, function).map(|len| (len / 2).max(1)) else {
+        return best;
+    };
+    loop {
+        let mut removed = false;
+        let mut start = 0;
+        while start < code_len(&best, function).unwrap_or_default() {
+            let end = (start + chunk).min(code_len(&best, function).unwrap_or_default());
+            match without(&best, function, start..end) {
+                Some(candidate)
+                    if move_bytecode_verifier::verify_module(&candidate).is_ok()
+                        && interesting(&candidate) =>
+                {
+                    best = candidate;
+                    removed = true;
+                }
+                _ => start += chunk,
+            }
+        }
+        if !removed {
+            if chunk == 1 {
+                return best;
+            }
+            chunk /= 2;
+        }
+    }
+}
+
+/// Reduces the code of every function of `module` in turn, see `reduce_function`.
+pub fn reduce_module(
+    module: &CompiledModule,
+    mut interesting: impl FnMut(&CompiledModule) -> bool,
+) -> CompiledModule {
+    (0..module.function_defs.len()).fold(module.clone(), |module, index| {
+        let function = FunctionDefinitionIndex(index as u16);
+        reduce_function(&module, function, &mut interesting)
+    })
+}
+
+/// Writes the serialized module to `<dir>/<name>.mv`, returning the path.
+pub fn write_reproducer(
+    module: &CompiledModule,
+    dir: &Path,
+    name: &str,
+) -> anyhow::Result<PathBuf> {
+    let mut bytes = Vec::new();
+    module.serialize(&mut bytes)?;
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{name}.mv"));
+    std::fs::write(&path, bytes)?;
+    Ok(path)
+}
+
+fn code_len(module: &CompiledModule, function: FunctionDefinitionIndex) -> Option<usize> {
+    let code = module.function_defs[function.0 as usize].code.as_ref()?;
+    Some(code.code.len())
+}
+
+/// The module with `range` removed from the code of `function`, or `None` if
+/// nothing would be left.
+fn without(
+    module: &CompiledModule,
+    function: FunctionDefinitionIndex,
+    range: Range<usize>,
+) -> Option<CompiledModule> {
+    let mut module = module.clone();
+    let code = &mut module.function_defs[function.0 as usize]
+        .code
+        .as_mut()?
+        .code;
+    if range.is_empty() || range.len() == code.len() {
+        return None;
+    }
+    code.drain(range.clone());
+    let removed = range.len() as u16;
+    let remap = |target: &mut u16| {
+        if *target as usize >= range.end {
+            *target -= removed;
+        } else if *target as usize >= range.start {
+            *target = range.start as u16;
+        }
+    };
+    for instruction in code.iter_mut() {
+        match instruction {
+            Bytecode::Branch(target) | Bytecode::BrTrue(target) | Bytecode::BrFalse(target) => {
+                remap(target)
+            }
+            _ => (),
+        }
+    }
+    Some(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, move_binary_format::file_format::basic_test_module};
+
+    fn module_with_code(code: Vec<Bytecode>) -> CompiledModule {
+        let mut module = basic_test_module();
+        module.function_defs[0].code.as_mut().unwrap().code = code;
+        module
+    }
+
+    #[test]
+    fn test_without_remaps_branches() {
+        let module = module_with_code(vec![
+            Bytecode::Nop,
+            Bytecode::Nop,
+            Bytecode::Branch(4),
+            Bytecode::Nop,
+            Bytecode::Ret,
+            Bytecode::Branch(3),
+        ]);
+        let function = FunctionDefinitionIndex(0);
+        let reduced = without(&module, function, 0..1).unwrap();
+        assert_eq!(
+            reduced.function_defs[0].code.as_ref().unwrap().code,
+            [
+                Bytecode::Nop,
+                Bytecode::Branch(3),
+                Bytecode::Nop,
+                Bytecode::Ret,
+                Bytecode::Branch(2)
+            ]
+        );
+        // Branches into the removed run continue after it
+        let reduced = without(&module, function, 3..4).unwrap();
+        assert_eq!(
+            reduced.function_defs[0].code.as_ref().unwrap().code[4],
+            Bytecode::Branch(3)
+        );
+        assert!(without(&module, function, 0..6).is_none());
+    }
+
+    #[test]
+    fn test_reduce_keeps_interesting_instructions() {
+        let module = module_with_code(vec![
+            Bytecode::Nop,
+            Bytecode::Nop,
+            Bytecode::Nop,
+            Bytecode::Nop,
+            Bytecode::Nop,
+            Bytecode::Ret,
+        ]);
+        let count = |module: &CompiledModule| {
+            let code = &module.function_defs[0].code.as_ref().unwrap().code;
+            code.iter().filter(|i| **i == Bytecode::Nop).count()
+        };
+        // A failure needing two of the instructions
+        let reduced = reduce_function(&module, FunctionDefinitionIndex(0), |m| count(m) >= 2);
+        assert_eq!(
+            reduced.function_defs[0].code.as_ref().unwrap().code,
+            [Bytecode::Nop, Bytecode::Nop, Bytecode::Ret]
+        );
+    }
+}
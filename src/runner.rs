@@ -0,0 +1,219 @@
+//! Module for executing compiled programs on the Miden VM.
+
+use {
+    crate::{
+        abi::{self, EncodedInputs, EntryAbi},
+        artifact::{Artifact, CompiledArtifact},
+        backend::{AstBackend, MidenBackend},
+        error_map::ErrorMap,
+        limits::Limits,
+        state::State,
+        witness,
+    },
+    miden::{
+        AdviceInputs, DefaultHost, ExecutionError, ExecutionOptions, ExecutionProof,
+        ExecutionTrace, MemAdviceProvider, Program, ProvingOptions, StackInputs,
+    },
+};
+
+/// Executes the program with the given flattened entry function parameters
+/// (see `abi::encode_inputs`) and returns the elements of the output stack
+/// holding the return values.
+/// Failures caused by a Move abort are described using the error map.
+pub fn run(artifact: &CompiledArtifact, values: &[u64]) -> anyhow::Result<Vec<u64>> {
+    let program = AstBackend.assemble(&artifact.program)?;
+    execute(&program, &artifact.abi, &artifact.error_map, values)
+}
+
+/// Executes the program as `run` does, failing once the execution takes more
+/// than `limits.max_cycles` cycles, e.g. for programs of untrusted modules.
+pub fn run_with_limits(
+    artifact: &CompiledArtifact,
+    values: &[u64],
+    limits: &Limits,
+) -> anyhow::Result<Vec<u64>> {
+    let program = AstBackend.assemble(&artifact.program)?;
+    let options = ExecutionOptions::new(Some(limits.max_cycles), 64)
+        .map_err(|e| anyhow::anyhow!("Invalid execution options: {e:?}"))?;
+    let inputs = encode(&artifact.abi, values)?;
+    let trace = execute_inputs(
+        &program,
+        &artifact.error_map,
+        inputs,
+        AdviceInputs::default(),
+        options,
+    )?;
+    Ok(trace.stack_outputs().stack()[..artifact.abi.output_width].to_vec())
+}
+
+/// Executes a program checking a batch of instances of its entry function
+/// (see `compiler::EntryMode::Batch`) with the flattened parameter values of
+/// every instance. Fails unless the entry function returns true for all of them.
+pub fn run_batch(artifact: &CompiledArtifact, instances: &[Vec<u64>]) -> anyhow::Result<()> {
+    anyhow::ensure!(artifact.abi.batch, "The program doesn't check batches");
+    artifact.abi.check_version()?;
+    let program = AstBackend.assemble(&artifact.program)?;
+    for values in instances {
+        abi::check_inputs(&artifact.abi.params, values)?;
+    }
+    let inputs = abi::encode_batch(&artifact.abi.inputs, instances)?;
+    execute_inputs(
+        &program,
+        &artifact.error_map,
+        inputs,
+        AdviceInputs::default(),
+        ExecutionOptions::default(),
+    )?;
+    Ok(())
+}
+
+/// Executes the program as `run` does, also returning the number of VM cycles
+/// the execution took.
+pub fn run_with_cycles(
+    artifact: &CompiledArtifact,
+    values: &[u64],
+) -> anyhow::Result<(Vec<u64>, usize)> {
+    let program = AstBackend.assemble(&artifact.program)?;
+    let trace = trace(
+        &program,
+        &artifact.abi,
+        &artifact.error_map,
+        values,
+        AdviceInputs::default(),
+    )?;
+    let outputs = trace.stack_outputs().stack();
+    Ok((
+        outputs[..artifact.abi.output_width].to_vec(),
+        trace.trace_len_summary().main_trace_len(),
+    ))
+}
+
+/// Executes the program as `run` does, providing the Merkle paths of the
+/// resources of the state the entry function acquires (see `witness` module).
+pub fn run_with_storage(
+    artifact: &CompiledArtifact,
+    values: &[u64],
+    state: &State,
+) -> anyhow::Result<Vec<u64>> {
+    let program = AstBackend.assemble(&artifact.program)?;
+    let advice = witness::storage_advice(artifact, &state.snapshot())?;
+    execute_with_advice(&program, &artifact.abi, &artifact.error_map, values, advice)
+}
+
+/// Executes a program loaded from a bundle as `execute_with_advice` does, so
+/// programs compiled earlier run without recompiling them.
+pub fn execute_artifact(
+    artifact: &Artifact,
+    values: &[u64],
+    advice: AdviceInputs,
+) -> anyhow::Result<Vec<u64>> {
+    execute_with_advice(
+        &artifact.program,
+        &artifact.abi,
+        &artifact.error_map,
+        values,
+        advice,
+    )
+}
+
+/// Executes an assembled program, e.g. one rebuilt from a bundle, as `run` does.
+pub fn execute(
+    program: &Program,
+    abi: &EntryAbi,
+    error_map: &ErrorMap,
+    values: &[u64],
+) -> anyhow::Result<Vec<u64>> {
+    execute_with_advice(program, abi, error_map, values, AdviceInputs::default())
+}
+
+/// Executes an assembled program as `execute` does, with additional advice
+/// inputs, e.g. the Merkle paths of storage proofs.
+pub fn execute_with_advice(
+    program: &Program,
+    abi: &EntryAbi,
+    error_map: &ErrorMap,
+    values: &[u64],
+    advice: AdviceInputs,
+) -> anyhow::Result<Vec<u64>> {
+    let trace = trace(program, abi, error_map, values, advice)?;
+    let outputs = trace.stack_outputs().stack();
+    Ok(outputs[..abi.output_width].to_vec())
+}
+
+fn trace(
+    program: &Program,
+    abi: &EntryAbi,
+    error_map: &ErrorMap,
+    values: &[u64],
+    advice: AdviceInputs,
+) -> anyhow::Result<ExecutionTrace> {
+    execute_inputs(
+        program,
+        error_map,
+        encode(abi, values)?,
+        advice,
+        ExecutionOptions::default(),
+    )
+}
+
+fn execute_inputs(
+    program: &Program,
+    error_map: &ErrorMap,
+    inputs: EncodedInputs,
+    advice: AdviceInputs,
+    options: ExecutionOptions,
+) -> anyhow::Result<ExecutionTrace> {
+    let advice = advice.with_stack_values(inputs.advice)?;
+    miden::execute(
+        program,
+        StackInputs::try_from_values(inputs.stack)?,
+        DefaultHost::new(MemAdviceProvider::from(advice)),
+        options,
+    )
+    .map_err(|e| describe_failure(e, error_map))
+}
+
+/// Executes an assembled program as `execute_with_advice` does, and proves
+/// the execution. Returns the return values with the proof.
+pub fn prove_with_advice(
+    program: &Program,
+    abi: &EntryAbi,
+    error_map: &ErrorMap,
+    values: &[u64],
+    advice: AdviceInputs,
+) -> anyhow::Result<(Vec<u64>, ExecutionProof)> {
+    let inputs = encode(abi, values)?;
+    let advice = advice.with_stack_values(inputs.advice)?;
+    let (outputs, proof) = miden::prove(
+        program,
+        StackInputs::try_from_values(inputs.stack)?,
+        DefaultHost::new(MemAdviceProvider::from(advice)),
+        ProvingOptions::default(),
+    )
+    .map_err(|e| describe_failure(e, error_map))?;
+    Ok((outputs.stack()[..abi.output_width].to_vec(), proof))
+}
+
+/// Encodes the inputs of a call of the entry function, a batch of one instance
+/// for programs checking batches. Fails if the program was compiled with other
+/// encoding conventions, or if an address or signer input isn't an account ID.
+fn encode(abi: &EntryAbi, values: &[u64]) -> anyhow::Result<EncodedInputs> {
+    abi.check_version()?;
+    abi::check_inputs(&abi.params, values)?;
+    if abi.batch {
+        abi::encode_batch(&abi.inputs, &[values.to_vec()])
+    } else {
+        abi::encode_inputs(&abi.inputs, values)
+    }
+}
+
+fn describe_failure(e: ExecutionError, error_map: &ErrorMap) -> anyhow::Error {
+    match e {
+        // Move aborts fail an assertion with the abort code as error code
+        ExecutionError::FailedAssertion { err_code, .. } if err_code != 0 => {
+            let message = error_map.describe(err_code as u64);
+            anyhow::Error::new(e).context(message)
+        }
+        e => e.into(),
+    }
+}
@@ -0,0 +1,435 @@
+//! Module removing redundant stack manipulation from generated code.
+//!
+//! Lowering each Move instruction on its own produces sequences which shuffle the
+//! stack back and forth, such as a `swap` right before a commutative operation.
+//! Within every straight-line run of instructions, such patterns are rewritten
+//! until none remain. Rewrites replacing instructions with cheaper equivalents
+//! are only applied when the cost model agrees. Control flow is never changed;
+//! the bodies of `if`, `while` and `repeat` blocks are optimized separately.
+//!
+//! Instructions are also reordered where their dependencies allow it: values
+//! pushed by independent instructions, e.g. loads of two locals, and permuted
+//! right after are pushed in their final order instead. An instruction depends
+//! on another if it reads the value the other pushed, so `dup`s of values pushed
+//! earlier in the run keep their order. Reordering is limited to these pushes;
+//! instructions consuming values are never moved.
+
+use {
+    crate::{abi, cost::CostModel},
+    miden_assembly::ast::{CodeBody, Instruction, Node},
+};
+
+/// Optimizes a code body and all the blocks nested in it.
+pub fn optimize(body: &CodeBody, model: &dyn CostModel) -> CodeBody {
+    CodeBody::new(optimize_nodes(body.nodes(), model))
+}
+
+fn optimize_nodes(nodes: &[Node], model: &dyn CostModel) -> Vec<Node> {
+    let mut result: Vec<Node> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match node {
+            Node::Instruction(_) => {
+                result.push(node.clone());
+                // Rewrites only involve the instructions at the end of `result`,
+                // so applying them after every push reaches a fixpoint.
+                while rewrite(&mut result, model) {}
+            }
+            Node::IfElse {
+                true_case,
+                false_case,
+            } => result.push(Node::IfElse {
+                true_case: optimize(true_case, model),
+                false_case: optimize(false_case, model),
+            }),
+            Node::Repeat { times, body } => result.push(Node::Repeat {
+                times: *times,
+                body: optimize(body, model),
+            }),
+            Node::While { body } => result.push(Node::While {
+                body: optimize(body, model),
+            }),
+        }
+    }
+    result
+}
+
+/// Rewrites the last instructions of `nodes` if they match a known pattern.
+/// Returns whether anything changed.
+fn rewrite(nodes: &mut Vec<Node>, model: &dyn CostModel) -> bool {
+    if reorder(nodes, model) {
+        return true;
+    }
+    let len = nodes.len();
+    if len < 2 {
+        return false;
+    }
+    let (Node::Instruction(first), Node::Instruction(second)) = (&nodes[len - 2], &nodes[len - 1])
+    else {
+        return false;
+    };
+    let cancels = match (first, second) {
+        // Stack manipulation undone by the next instruction
+        (Instruction::Swap1, Instruction::Swap1) => true,
+        (Instruction::Dup0, Instruction::Drop) => true,
+        (Instruction::PushU32(_), Instruction::Drop) => true,
+        // Only applied to bools, e.g. a negated condition of a `while` loop
+        // exiting on true
+        (Instruction::Not, Instruction::Not) => true,
+        (up, down) if movup_depth(up).is_some() && movup_depth(up) == movdn_depth(down) => true,
+        (down, up) if movdn_depth(down).is_some() && movdn_depth(down) == movup_depth(up) => true,
+        _ => false,
+    };
+    if cancels {
+        nodes.truncate(len - 2);
+        return true;
+    }
+    // The order of the operands of a commutative operation doesn't matter
+    if *first == Instruction::Swap1 && is_commutative(second) {
+        nodes.remove(len - 2);
+        return true;
+    }
+    // Rotating the top 3 elements twice in one direction is the same as once in the other
+    let rotation = match (first, second) {
+        (Instruction::MovUp2, Instruction::MovUp2) => Some(Instruction::MovDn2),
+        (Instruction::MovDn2, Instruction::MovDn2) => Some(Instruction::MovUp2),
+        _ => None,
+    };
+    if let Some(rotation) = rotation {
+        if model.instruction(&rotation) < model.instruction(first) + model.instruction(second) {
+            nodes.truncate(len - 2);
+            nodes.push(Node::Instruction(rotation));
+            return true;
+        }
+    }
+    // Asserting the result of a comparison takes a single instruction, which is
+    // worth it even at the same cost
+    if (first, second) == (&Instruction::Eq, &Instruction::Assert)
+        && model.instruction(&Instruction::AssertEq)
+            <= model.instruction(first) + model.instruction(second)
+    {
+        nodes.truncate(len - 2);
+        nodes.push(Node::Instruction(Instruction::AssertEq));
+        return true;
+    }
+    false
+}
+
+/// Replaces independent pushes followed by a permutation of the values they
+/// pushed with the pushes in the permuted order. Returns whether anything changed.
+fn reorder(nodes: &mut Vec<Node>, model: &dyn CostModel) -> bool {
+    let Some(Node::Instruction(last)) = nodes.last() else {
+        return false;
+    };
+    let Some(permutation) = permutation(last) else {
+        return false;
+    };
+    let width = permutation.len();
+    if nodes.len() <= width {
+        return false;
+    }
+    let start = nodes.len() - 1 - width;
+    let mut pushes = Vec::with_capacity(width);
+    for node in &nodes[start..nodes.len() - 1] {
+        let Node::Instruction(instruction) = node else {
+            return false;
+        };
+        pushes.push(instruction);
+    }
+    // Depth each push reads below the pushed values, for `dup`s
+    let mut reads = Vec::with_capacity(width);
+    for (pushed, instruction) in pushes.iter().enumerate() {
+        match dup_depth(instruction) {
+            // Reads a value pushed before it, so must stay after it
+            Some(depth) if depth < pushed => return false,
+            Some(depth) => reads.push(Some(depth - pushed)),
+            None if pushes_independent_value(instruction) => reads.push(None),
+            None => return false,
+        }
+    }
+    // Position `p` from the top ends up holding the value at `permutation[p]`,
+    // pushed by the push at `width - 1 - permutation[p]`
+    let mut reordered = Vec::with_capacity(width);
+    for pushed in 0..width {
+        let push = width - 1 - permutation[width - 1 - pushed];
+        reordered.push(match reads[push] {
+            Some(depth) if depth + pushed > 15 => return false,
+            Some(depth) => abi::dup(depth + pushed),
+            None => pushes[push].clone(),
+        });
+    }
+    let cost: u64 = pushes.iter().map(|i| model.instruction(i)).sum();
+    if reordered.iter().map(|i| model.instruction(i)).sum::<u64>() >= cost + model.instruction(last)
+    {
+        return false;
+    }
+    nodes.truncate(start);
+    nodes.extend(reordered.into_iter().map(Node::Instruction));
+    true
+}
+
+/// Whether the instruction only pushes a value, without reading the stack.
+/// Memory is only read, so such instructions can be reordered among themselves.
+fn pushes_independent_value(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::PushU8(_)
+            | Instruction::PushU16(_)
+            | Instruction::PushU32(_)
+            | Instruction::PushFelt(_)
+            | Instruction::LocLoad(_)
+            | Instruction::MemLoadImm(_)
+    )
+}
+
+/// Permutation of the top of the stack applied by a stack manipulation
+/// instruction: position `p` from the top ends up holding the element which was
+/// at `permutation[p]`.
+fn permutation(instruction: &Instruction) -> Option<Vec<usize>> {
+    if *instruction == Instruction::Swap1 {
+        return Some(vec![1, 0]);
+    }
+    if let Some(depth) = movup_depth(instruction) {
+        let depth = depth as usize;
+        return Some([depth].into_iter().chain(0..depth).collect());
+    }
+    let depth = movdn_depth(instruction)? as usize;
+    Some((1..=depth).chain([0]).collect())
+}
+
+fn is_commutative(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Add
+            | Instruction::Mul
+            | Instruction::Eq
+            | Instruction::Neq
+            | Instruction::And
+            | Instruction::Or
+            | Instruction::Xor
+            | Instruction::U32And
+            | Instruction::U32Or
+            | Instruction::U32Xor
+            | Instruction::U32WrappingAdd
+            | Instruction::U32WrappingMul
+            | Instruction::U32CheckedAdd
+            | Instruction::U32CheckedMul
+            | Instruction::U32OverflowingAdd
+            | Instruction::U32OverflowingMul
+    )
+}
+
+fn dup_depth(instruction: &Instruction) -> Option<usize> {
+    Some(match instruction {
+        Instruction::Dup0 => 0,
+        Instruction::Dup1 => 1,
+        Instruction::Dup2 => 2,
+        Instruction::Dup3 => 3,
+        Instruction::Dup4 => 4,
+        Instruction::Dup5 => 5,
+        Instruction::Dup6 => 6,
+        Instruction::Dup7 => 7,
+        Instruction::Dup8 => 8,
+        Instruction::Dup9 => 9,
+        Instruction::Dup10 => 10,
+        Instruction::Dup11 => 11,
+        Instruction::Dup12 => 12,
+        Instruction::Dup13 => 13,
+        Instruction::Dup14 => 14,
+        Instruction::Dup15 => 15,
+        _ => return None,
+    })
+}
+
+fn movup_depth(instruction: &Instruction) -> Option<u8> {
+    Some(match instruction {
+        Instruction::MovUp2 => 2,
+        Instruction::MovUp3 => 3,
+        Instruction::MovUp4 => 4,
+        Instruction::MovUp5 => 5,
+        Instruction::MovUp6 => 6,
+        Instruction::MovUp7 => 7,
+        Instruction::MovUp8 => 8,
+        Instruction::MovUp9 => 9,
+        Instruction::MovUp10 => 10,
+        Instruction::MovUp11 => 11,
+        Instruction::MovUp12 => 12,
+        Instruction::MovUp13 => 13,
+        Instruction::MovUp14 => 14,
+        Instruction::MovUp15 => 15,
+        _ => return None,
+    })
+}
+
+fn movdn_depth(instruction: &Instruction) -> Option<u8> {
+    Some(match instruction {
+        Instruction::MovDn2 => 2,
+        Instruction::MovDn3 => 3,
+        Instruction::MovDn4 => 4,
+        Instruction::MovDn5 => 5,
+        Instruction::MovDn6 => 6,
+        Instruction::MovDn7 => 7,
+        Instruction::MovDn8 => 8,
+        Instruction::MovDn9 => 9,
+        Instruction::MovDn10 => 10,
+        Instruction::MovDn11 => 11,
+        Instruction::MovDn12 => 12,
+        Instruction::MovDn13 => 13,
+        Instruction::MovDn14 => 14,
+        Instruction::MovDn15 => 15,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::cost::{DefaultCostModel, MeasuredCostModel},
+    };
+
+    fn instructions(instructions: &[Instruction]) -> Vec<Node> {
+        instructions
+            .iter()
+            .cloned()
+            .map(Node::Instruction)
+            .collect()
+    }
+
+    #[test]
+    fn test_redundant_stack_manipulation_is_removed() {
+        let body = CodeBody::new(instructions(&[
+            Instruction::PushU32(1),
+            Instruction::MovUp2,
+            Instruction::Dup0,
+            Instruction::Drop,
+            Instruction::MovDn2,
+            Instruction::Swap1,
+            Instruction::Add,
+        ]));
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            instructions(&[Instruction::PushU32(1), Instruction::Add])
+        );
+    }
+
+    #[test]
+    fn test_double_rotation_is_replaced() {
+        let body = CodeBody::new(instructions(&[Instruction::MovUp2, Instruction::MovUp2]));
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            instructions(&[Instruction::MovDn2])
+        );
+
+        // Kept when the replacement is not cheaper
+        let model = MeasuredCostModel::from_json(r#"{ "movdn": 2 }"#).unwrap();
+        assert_eq!(optimize(&body, &model).nodes(), body.nodes());
+    }
+
+    #[test]
+    fn test_asserted_comparison_is_fused() {
+        let body = CodeBody::new(instructions(&[Instruction::Eq, Instruction::Assert]));
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            instructions(&[Instruction::AssertEq])
+        );
+    }
+
+    #[test]
+    fn test_double_negation_is_removed() {
+        let body = CodeBody::new(instructions(&[
+            Instruction::Eq,
+            Instruction::Not,
+            Instruction::Not,
+        ]));
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            instructions(&[Instruction::Eq])
+        );
+    }
+
+    #[test]
+    fn test_independent_pushes_are_reordered() {
+        let body = CodeBody::new(instructions(&[
+            Instruction::LocLoad(0),
+            Instruction::PushU32(3),
+            Instruction::Swap1,
+            Instruction::Sub,
+        ]));
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            instructions(&[
+                Instruction::PushU32(3),
+                Instruction::LocLoad(0),
+                Instruction::Sub
+            ])
+        );
+
+        let body = CodeBody::new(instructions(&[
+            Instruction::PushU32(1),
+            Instruction::PushU32(2),
+            Instruction::PushU32(3),
+            Instruction::MovUp2,
+        ]));
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            instructions(&[
+                Instruction::PushU32(2),
+                Instruction::PushU32(3),
+                Instruction::PushU32(1)
+            ])
+        );
+
+        // Copies of values below the pushes read them one element deeper once moved
+        let body = CodeBody::new(instructions(&[
+            Instruction::Dup1,
+            Instruction::PushU32(3),
+            Instruction::Swap1,
+        ]));
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            instructions(&[Instruction::PushU32(3), Instruction::Dup2])
+        );
+    }
+
+    #[test]
+    fn test_dependent_pushes_are_kept() {
+        // The copy reads the pushed value
+        let nodes = instructions(&[
+            Instruction::PushU32(3),
+            Instruction::Dup0,
+            Instruction::Swap1,
+        ]);
+        let body = CodeBody::new(nodes.clone());
+        assert_eq!(optimize(&body, &DefaultCostModel).nodes(), nodes);
+        // The load consumes the address below it
+        let nodes = instructions(&[
+            Instruction::MemLoad,
+            Instruction::PushU32(3),
+            Instruction::Swap1,
+        ]);
+        let body = CodeBody::new(nodes.clone());
+        assert_eq!(optimize(&body, &DefaultCostModel).nodes(), nodes);
+        // Copies can't read deeper than 15 elements
+        let nodes = instructions(&[
+            Instruction::Dup15,
+            Instruction::PushU32(3),
+            Instruction::Swap1,
+        ]);
+        let body = CodeBody::new(nodes.clone());
+        assert_eq!(optimize(&body, &DefaultCostModel).nodes(), nodes);
+    }
+
+    #[test]
+    fn test_order_sensitive_operations_are_kept() {
+        let nodes = instructions(&[Instruction::Swap1, Instruction::Sub]);
+        let body = CodeBody::new(vec![Node::While {
+            body: CodeBody::new(nodes.clone()),
+        }]);
+        assert_eq!(
+            optimize(&body, &DefaultCostModel).nodes(),
+            [Node::While {
+                body: CodeBody::new(nodes)
+            }]
+        );
+    }
+}
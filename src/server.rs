@@ -0,0 +1,272 @@
+//! JSON-RPC 2.0 server exposing the compiler to other processes.
+//!
+//! Requests and responses are JSON objects, one per line, over TCP.
+//! All connections share a single `Compiler`, so modules loaded once can be
+//! compiled and run repeatedly. At most `MAX_CONNECTIONS` connections are
+//! served at a time, and requests are at most `MAX_REQUEST_BYTES` long.
+//! Supported methods:
+//! - `load { module }`: loads hex encoded module bytecode, returns `{ id }`.
+//!   Modules come from untrusted clients, so they are only loaded if they pass
+//!   `compiler::compile_untrusted` with the default `Limits`.
+//! - `compile { id }`: returns `{ masm, program_hash, abi, entry, metadata, build_info }` for a loaded module.
+//! - `run { id, inputs }`: runs a loaded module with the given entry parameters,
+//!   within the cycles allowed by the default `Limits`, returns `{ outputs }`.
+//! - `check { sources, named_addresses }`: compiles sources given as a map from file
+//!   names to their text in recovery mode, returns `{ functions, diagnostics }`. The
+//!   server never reads files named by clients: sources are written to a scratch
+//!   directory of the request, and locations in diagnostics are relative to it.
+
+use {
+    crate::{
+        bundle::{from_hex, Bundle},
+        compiler::{self, Compiler},
+        diagnostics::Diagnostics,
+        frontend,
+        limits::Limits,
+        runner,
+    },
+    anyhow::Context,
+    move_core_types::{
+        account_address::AccountAddress, identifier::Identifier, language_storage::ModuleId,
+    },
+    serde_json::{json, Value},
+    std::{
+        collections::BTreeMap,
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+            Arc,
+        },
+    },
+};
+
+// Standard JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+const SERVER_ERROR: i64 = -32000;
+
+/// Connections served at the same time, each on its own thread.
+pub const MAX_CONNECTIONS: usize = 64;
+/// Length of a request line, enough for the hex encoding of the largest module
+/// allowed by the default `Limits`.
+pub const MAX_REQUEST_BYTES: usize = 1 << 20;
+
+/// Accepts connections until the listener fails, serving each one on its own thread.
+/// Connections beyond `MAX_CONNECTIONS` get an error response and are closed.
+pub fn serve(listener: TcpListener, compiler: Arc<Compiler>) -> anyhow::Result<()> {
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            let response = error_response(Value::Null, SERVER_ERROR, "Too many connections");
+            let _ = writeln!(stream, "{response}");
+            continue;
+        }
+        let compiler = compiler.clone();
+        let connections = connections.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &compiler) {
+                eprintln!("Connection failed: {e:#}");
+            }
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, compiler: &Compiler) -> anyhow::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .by_ref()
+            .take(MAX_REQUEST_BYTES as u64 + 1)
+            .read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if line.len() > MAX_REQUEST_BYTES {
+            // The end of the request can't be found without reading it, so the
+            // connection is closed
+            let message = format!("Request exceeds {MAX_REQUEST_BYTES} bytes");
+            writeln!(
+                writer,
+                "{}",
+                error_response(Value::Null, INVALID_REQUEST, &message)
+            )?;
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&line);
+        if text.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&text) {
+            Ok(request) => handle_request(compiler, &request),
+            Err(e) => error_response(Value::Null, PARSE_ERROR, &e.to_string()),
+        };
+        writeln!(writer, "{response}")?;
+    }
+}
+
+/// Handles a single JSON-RPC request, returning the response.
+pub fn handle_request(compiler: &Compiler, request: &Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let params = &request["params"];
+    let result = match request["method"].as_str().unwrap_or_default() {
+        "load" => load(compiler, params),
+        "compile" => compile(compiler, params),
+        "run" => run(compiler, params),
+        "check" => check(params),
+        method => return error_response(id, METHOD_NOT_FOUND, &format!("Unknown method {method}")),
+    };
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error_response(id, SERVER_ERROR, &format!("{e:#}")),
+    }
+}
+
+fn load(compiler: &Compiler, params: &Value) -> anyhow::Result<Value> {
+    let module = string_param(params, "module")?;
+    let bytes = from_hex(module)?;
+    // Verifies the bytecode and checks it compiles within limits, so loaded modules
+    // are safe to compile and run later
+    compiler::compile_untrusted(&bytes, &Limits::default())?;
+    let id = compiler.load_bytes(&bytes)?;
+    Ok(json!({ "id": id.to_string() }))
+}
+
+fn compile(compiler: &Compiler, params: &Value) -> anyhow::Result<Value> {
+    let id = module_id(params)?;
+    let artifact = compiler.compile(&id)?;
+    let bundle = Bundle::from_artifact(&artifact)?;
+    Ok(json!({
+        "masm": bundle.masm,
+        "program_hash": bundle.program_hash,
+        "abi": serde_json::to_value(&artifact.abi)?,
+        "entry": artifact.entry().to_string(),
+        "metadata": serde_json::to_value(&bundle.metadata)?,
+        "build_info": serde_json::to_value(&artifact.build_info)?,
+    }))
+}
+
+fn run(compiler: &Compiler, params: &Value) -> anyhow::Result<Value> {
+    let id = module_id(params)?;
+    let inputs: Vec<u64> = serde_json::from_value(params["inputs"].clone())
+        .context("Expected `inputs` to be a list of integers")?;
+    let artifact = compiler.compile(&id)?;
+    // Modules come from clients, so executions are bounded like their compilation
+    let outputs = runner::run_with_limits(&artifact, &inputs, &Limits::default())?;
+    Ok(json!({ "outputs": outputs }))
+}
+
+fn check(params: &Value) -> anyhow::Result<Value> {
+    let sources: BTreeMap<String, String> = serde_json::from_value(params["sources"].clone())
+        .context("Expected `sources` to map file names to Move sources")?;
+    let named_addresses: BTreeMap<String, String> =
+        serde_json::from_value(params["named_addresses"].clone()).unwrap_or_default();
+    let named_addresses = frontend::parse_named_addresses(named_addresses)?;
+    let dir = scratch_dir();
+    let result =
+        write_sources(&dir, &sources).map(|paths| frontend::check_sources(&paths, named_addresses));
+    let _ = std::fs::remove_dir_all(&dir);
+    let result = result?;
+    let functions: Vec<Value> = result
+        .functions
+        .iter()
+        .map(|f| {
+            json!({
+                "module": f.module,
+                "function": f.function,
+                "compiled": f.procedure.is_some(),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "functions": functions,
+        "diagnostics": diagnostics_to_json(&result.diagnostics, &dir),
+    }))
+}
+
+/// Scratch directory for the sources of a `check` request, unique to the request
+/// and to this process.
+fn scratch_dir() -> PathBuf {
+    static REQUESTS: AtomicU64 = AtomicU64::new(0);
+    let request = REQUESTS.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir()
+        .join("move2miden-server")
+        .join(format!("{}-{request}", std::process::id()))
+}
+
+/// Writes the sources of a `check` request to `dir`, returning their paths. File
+/// names must be plain `.move` file names, so nothing is written outside `dir`.
+fn write_sources(dir: &Path, sources: &BTreeMap<String, String>) -> anyhow::Result<Vec<String>> {
+    std::fs::create_dir_all(dir).context("Failed to create scratch directory")?;
+    let mut paths = Vec::new();
+    for (name, text) in sources {
+        let plain = Path::new(name)
+            .file_name()
+            .is_some_and(|file| file == name.as_str());
+        anyhow::ensure!(
+            plain && name.ends_with(".move"),
+            "Invalid source file name {name}"
+        );
+        let path = dir.join(name);
+        std::fs::write(&path, text).context("Failed to write scratch file")?;
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    Ok(paths)
+}
+
+fn diagnostics_to_json(diagnostics: &Diagnostics, dir: &Path) -> Value {
+    // Clients only know the names of the files they sent
+    let prefix = format!("{}{}", dir.display(), std::path::MAIN_SEPARATOR);
+    let list: Vec<Value> = diagnostics
+        .diagnostics
+        .iter()
+        .map(|d| {
+            let location = d.primary.as_ref().map(|(span, _)| {
+                let file = span.file.strip_prefix(&prefix).unwrap_or(&span.file);
+                json!({ "file": file, "line": span.line, "column": span.column })
+            });
+            json!({
+                "severity": format!("{:?}", d.severity).to_lowercase(),
+                "message": d.message,
+                "location": location,
+            })
+        })
+        .collect();
+    json!({ "items": list, "rendered": diagnostics.render().replace(&prefix, "") })
+}
+
+/// Parses a module id of the form `address::name`, as returned by `load`.
+fn module_id(params: &Value) -> anyhow::Result<ModuleId> {
+    let id = string_param(params, "id")?;
+    let (address, name) = id
+        .rsplit_once("::")
+        .ok_or_else(|| anyhow::anyhow!("Invalid module id {id}"))?;
+    // `ModuleId` displays addresses without the `0x` prefix
+    let literal = format!("0x{}", address.trim_start_matches("0x"));
+    let address = AccountAddress::from_hex_literal(&literal)
+        .map_err(|e| anyhow::anyhow!("Invalid module address {address}: {e}"))?;
+    Ok(ModuleId::new(address, Identifier::new(name)?))
+}
+
+fn string_param<'a>(params: &'a Value, name: &str) -> anyhow::Result<&'a str> {
+    params[name]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("Missing string parameter `{name}`"))
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
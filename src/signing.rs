@@ -0,0 +1,152 @@
+//! Module for signing bundles, so deployments can be limited to programs built
+//! by an approved compiler build.
+//!
+//! The signature covers every field of the bundle but the signature itself, so
+//! neither the program nor what integrators read about it, such as its storage
+//! layout, error map or build information, can be changed without invalidating
+//! it. Keys are 32 byte Ed25519 secret keys, hex encoded.
+
+use {
+    crate::bundle::{from_hex, to_hex, Bundle, BundleSignature},
+    anyhow::Context,
+    ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey},
+    std::path::Path,
+};
+
+/// Prefix of signed messages, so signatures can't be reused for other purposes.
+const DOMAIN: &[u8] = b"move2miden bundle signature v2";
+
+/// Reads a hex encoded secret key from a file.
+pub fn load_signing_key(path: &Path) -> anyhow::Result<SigningKey> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read key {}", path.display()))?;
+    let bytes = from_hex(contents.trim()).context("Invalid signing key")?;
+    let secret = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signing keys must be 32 bytes long"))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Parses a hex encoded public key.
+pub fn parse_verifying_key(hex: &str) -> anyhow::Result<VerifyingKey> {
+    let bytes: [u8; 32] = from_hex(hex)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Public keys must be 32 bytes long"))?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid public key")
+}
+
+/// Signs the bundle, replacing any previous signature.
+pub fn sign(bundle: &mut Bundle, key: &SigningKey) -> anyhow::Result<()> {
+    let signature = key.sign(&signed_message(bundle)?);
+    bundle.signature = Some(BundleSignature {
+        public_key: to_hex(key.verifying_key().as_bytes()),
+        signature: to_hex(&signature.to_bytes()),
+    });
+    Ok(())
+}
+
+/// Checks that the bundle is signed by one of the trusted keys.
+pub fn verify_signature(bundle: &Bundle, trusted_keys: &[VerifyingKey]) -> anyhow::Result<()> {
+    let Some(signature) = &bundle.signature else {
+        anyhow::bail!("The bundle isn't signed");
+    };
+    let key = parse_verifying_key(&signature.public_key)?;
+    if !trusted_keys.contains(&key) {
+        anyhow::bail!(
+            "The bundle is signed by untrusted key {}",
+            signature.public_key
+        );
+    }
+    let bytes: [u8; 64] = from_hex(&signature.signature)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signatures must be 64 bytes long"))?;
+    // Strict verification rejects weak keys and non-canonical signatures, so a
+    // signature can't be altered into another valid one
+    key.verify_strict(&signed_message(bundle)?, &Signature::from_bytes(&bytes))
+        .context("Invalid bundle signature")
+}
+
+/// Message signed for a bundle: the domain, then the JSON encoding of the
+/// bundle without its signature. Fields are encoded in declaration order and
+/// maps are ordered, so the encoding is the same wherever it is computed.
+fn signed_message(bundle: &Bundle) -> anyhow::Result<Vec<u8>> {
+    let unsigned = Bundle {
+        signature: None,
+        ..bundle.clone()
+    };
+    let mut message = DOMAIN.to_vec();
+    serde_json::to_writer(&mut message, &unsigned)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            abi::StorageLayout,
+            artifact::{BuildInfo, CompilerOptions, MetadataEntry, ModuleMetadata},
+            bundle::COMPILER_VERSION,
+            error_map::{ErrorCodes, ErrorMap},
+        },
+    };
+
+    fn bundle() -> Bundle {
+        Bundle {
+            program_hash: "aa".into(),
+            masm: String::new(),
+            procedures: Vec::new(),
+            metadata: ModuleMetadata::default(),
+            error_map: ErrorMap::default(),
+            abi: None,
+            layout: None,
+            test_vectors: Vec::new(),
+            compiler_version: COMPILER_VERSION.into(),
+            signature: None,
+            build_info: None,
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let trusted = [key.verifying_key()];
+        let mut bundle = bundle();
+        assert!(verify_signature(&bundle, &trusted).is_err());
+        sign(&mut bundle, &key).unwrap();
+        verify_signature(&bundle, &trusted).unwrap();
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(verify_signature(&bundle, &[other]).is_err());
+
+        let mut tampered = bundle.clone();
+        tampered.compiler_version = "0.0.0".into();
+        assert!(verify_signature(&tampered, &trusted).is_err());
+        tampered = bundle.clone();
+        tampered.program_hash = "bb".into();
+        assert!(verify_signature(&tampered, &trusted).is_err());
+
+        // Fields integrators read about the program are covered too
+        tampered = bundle.clone();
+        tampered.layout = Some(StorageLayout {
+            heap_pointer_address: 0,
+            output_buffer_address: 1,
+            heap_start: 17,
+            data_section_size: 0,
+            structs: Vec::new(),
+        });
+        assert!(verify_signature(&tampered, &trusted).is_err());
+        tampered = bundle.clone();
+        tampered.error_map.checks = ErrorCodes::new(0x1000).unwrap();
+        assert!(verify_signature(&tampered, &trusted).is_err());
+        tampered = bundle.clone();
+        tampered.metadata.entries.push(MetadataEntry {
+            key: b"key".to_vec(),
+            value: Vec::new(),
+        });
+        assert!(verify_signature(&tampered, &trusted).is_err());
+        tampered = bundle;
+        tampered.build_info = Some(BuildInfo::new(CompilerOptions::default()));
+        assert!(verify_signature(&tampered, &trusted).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Module for running a sequence of compiled entry calls against a global
+//! storage state, so multi-step flows can be tested without a rollup.
+//!
+//! Every call is given the Merkle paths of the resources it acquires from the
+//! current state (see `witness` module) and records the root it was run
+//! against, so runs of the same flow can be compared step by step. Programs
+//! can't write global storage yet, so the host applies the effects of a call
+//! to the state between steps with `Simulator::update`, which threads the root
+//! of the updated state to the next call.
+//!
+//! Entry functions taking the root as their first parameters are given the
+//! threaded root, with the paths of every resource of the state, and check the
+//! resources they read against it with `merkle::verify`. A call run against a
+//! stale root, e.g. after changing the state with `Simulator::state_mut`, then
+//! fails.
+
+use {
+    crate::{
+        abi::StorageLayout,
+        artifact::CompiledArtifact,
+        backend::{AstBackend, MidenBackend},
+        runner,
+        state::State,
+        witness,
+    },
+    anyhow::Context,
+    miden::{math::StarkField, ExecutionProof, Word},
+};
+
+/// An entry call, with the flattened parameter values of the entry function.
+#[derive(Debug)]
+pub struct Call<'a> {
+    pub artifact: &'a CompiledArtifact,
+    pub values: Vec<u64>,
+    // Whether the entry function takes the root of the state as its first 4
+    // `u64` parameters, before `values`.
+    pub takes_root: bool,
+}
+
+/// Result of a call run by the simulator.
+#[derive(Debug)]
+pub struct Step {
+    pub outputs: Vec<u64>,
+    // Root of the state the call was run against.
+    pub root: Word,
+    // Only when the simulator proves its steps.
+    pub proof: Option<ExecutionProof>,
+}
+
+#[derive(Debug, Default)]
+pub struct Simulator {
+    state: State,
+    prove: bool,
+    // Root the next call runs against, once the first call has run.
+    root: Option<Word>,
+}
+
+impl Simulator {
+    pub fn new(state: State) -> Self {
+        Self {
+            state,
+            prove: false,
+            root: None,
+        }
+    }
+
+    /// Proves the execution of every call, which is much slower than running it.
+    pub fn with_proofs(mut self, prove: bool) -> Self {
+        self.prove = prove;
+        self
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Changes made to the state here aren't threaded to the next call, which
+    /// still runs against the previous root.
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    /// Applies the effects of a call to the state, returning the root of the
+    /// updated state, which the next call runs against.
+    pub fn update(
+        &mut self,
+        layout: &StorageLayout,
+        effects: impl FnOnce(&mut State),
+    ) -> anyhow::Result<Word> {
+        effects(&mut self.state);
+        let root = self.state.root(layout)?;
+        self.root = Some(root);
+        Ok(root)
+    }
+
+    /// Runs a single call against the root threaded from the previous call, or
+    /// the root of the current state for the first call.
+    pub fn apply(&mut self, call: &Call<'_>) -> anyhow::Result<Step> {
+        let artifact = call.artifact;
+        let root = match self.root {
+            Some(root) => root,
+            None => self.state.root(&artifact.layout)?,
+        };
+        let program = AstBackend.assemble(&artifact.program)?;
+        let snapshot = self.state.snapshot();
+        let mut values = Vec::with_capacity(8 + call.values.len());
+        let advice = if call.takes_root {
+            // Elements are `u64`s, given as their limbs from the low one
+            values.extend(
+                root.iter()
+                    .flat_map(|e| [e.as_int() & 0xffff_ffff, e.as_int() >> 32]),
+            );
+            // The program may check any resource against the root
+            let resources: Vec<String> = artifact
+                .layout
+                .structs
+                .iter()
+                .map(|s| s.name.clone())
+                .collect();
+            witness::storage_advice_for(&artifact.layout, &resources, &snapshot)?
+        } else {
+            witness::storage_advice(artifact, &snapshot)?
+        };
+        values.extend(&call.values);
+        let (outputs, proof) = if self.prove {
+            let (outputs, proof) = runner::prove_with_advice(
+                &program,
+                &artifact.abi,
+                &artifact.error_map,
+                &values,
+                advice,
+            )?;
+            (outputs, Some(proof))
+        } else {
+            let outputs = runner::execute_with_advice(
+                &program,
+                &artifact.abi,
+                &artifact.error_map,
+                &values,
+                advice,
+            )?;
+            (outputs, None)
+        };
+        // Programs can't write global storage, so the state is the same after the call
+        self.root = Some(root);
+        Ok(Step {
+            outputs,
+            root,
+            proof,
+        })
+    }
+
+    /// Runs the calls in order, stopping at the first failure.
+    pub fn run(&mut self, calls: &[Call<'_>]) -> anyhow::Result<Vec<Step>> {
+        calls
+            .iter()
+            .enumerate()
+            .map(|(i, call)| {
+                self.apply(call)
+                    .with_context(|| format!("Call {i} to {} failed", call.artifact.entry()))
+            })
+            .collect()
+    }
+}
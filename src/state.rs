@@ -0,0 +1,83 @@
+//! Module defining a serializable state of global storage, so a sequence of
+//! calls can be run against the same state across runs and the roots of the
+//! resulting states compared.
+//!
+//! States are saved as JSON. Every account, identified by its Miden account ID
+//! (see `accounts` module), holds its resources by struct name,
+//! with one value per field in the order of the storage layout, and the state
+//! is committed to by the tree described in the `witness` module.
+
+use {
+    crate::{
+        abi::StorageLayout,
+        accounts::AccountId,
+        witness::{self, ResourceKey, Snapshot},
+    },
+    anyhow::Context,
+    miden::Word,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, path::Path},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    // Resources by owner account, then by struct name.
+    pub accounts: BTreeMap<AccountId, BTreeMap<String, Vec<u64>>>,
+}
+
+impl State {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read state {}", path.display()))?;
+        let state = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse state {}", path.display()))?;
+        Ok(state)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, contents)
+            .with_context(|| format!("Failed to write state {}", path.display()))
+    }
+
+    /// Stores the resource under the account, returning the previous value if any.
+    pub fn insert(
+        &mut self,
+        account: AccountId,
+        resource: &str,
+        fields: Vec<u64>,
+    ) -> Option<Vec<u64>> {
+        self.accounts
+            .entry(account)
+            .or_default()
+            .insert(resource.to_string(), fields)
+    }
+
+    pub fn get(&self, account: AccountId, resource: &str) -> Option<&[u64]> {
+        self.accounts
+            .get(&account)?
+            .get(resource)
+            .map(Vec::as_slice)
+    }
+
+    /// Resources of every account, as expected by the `witness` module.
+    pub fn snapshot(&self) -> Snapshot {
+        self.accounts
+            .iter()
+            .flat_map(|(account, resources)| {
+                resources.iter().map(|(resource, fields)| {
+                    let key = ResourceKey {
+                        account: *account,
+                        resource: resource.clone(),
+                    };
+                    (key, fields.clone())
+                })
+            })
+            .collect()
+    }
+
+    /// Root of the tree committing to the state (see `witness::storage_root`).
+    pub fn root(&self, layout: &StorageLayout) -> anyhow::Result<Word> {
+        witness::storage_root(layout, &self.snapshot())
+    }
+}
@@ -0,0 +1,169 @@
+//! Conformance corpus checking the results of running compiled Move programs.
+//!
+//! Every entry of the corpus is compiled and run with this crate and its outputs
+//! compared with the expected ones. When `MOVE2MIDEN_REFERENCE_COMPILER` is set,
+//! the same sources are also compiled with that toolchain and both programs are
+//! run with the same inputs, reporting every divergence. The variable holds a
+//! command line in which `{input}` is replaced by the Move source file and
+//! `{output}` by the Miden assembly file the command must produce, e.g.
+//! `midenc compile {input} --emit masm -o {output}`.
+//!
+//! When a case fails to compile, its bytecode is reduced while compilation keeps
+//! failing with the same error, and the result is written to the temporary
+//! directory as a reproducer fixture (see `reduce` module).
+
+use {
+    super::move_compile,
+    crate::{abi, compiler, diagnostics::ErrorCode, move_utils, reduce, runner},
+    anyhow::Context,
+    miden::{AdviceInputs, DefaultHost, MemAdviceProvider, StackInputs},
+    miden_assembly::Assembler,
+    miden_stdlib::StdLibrary,
+    move_binary_format::CompiledModule,
+    std::{fmt::Write, process::Command},
+};
+
+const REFERENCE_COMPILER_VAR: &str = "MOVE2MIDEN_REFERENCE_COMPILER";
+
+struct Case {
+    package: &'static str,
+    inputs: &'static [u64],
+    outputs: &'static [u64],
+}
+
+const CORPUS: &[Case] = &[
+    Case {
+        package: "arithmetic",
+        inputs: &[],
+        outputs: &[],
+    },
+    Case {
+        package: "outputs",
+        inputs: &[],
+        outputs: &[5, 7],
+    },
+    Case {
+        package: "wide_inputs",
+        inputs: &[
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
+        ],
+        outputs: &[171],
+    },
+    Case {
+        package: "u64s",
+        // 0x1_0000_0001 + 0xffff_ffff, as low and high limbs
+        inputs: &[1, 1, 0xffff_ffff, 0],
+        outputs: &[0, 2],
+    },
+    Case {
+        package: "u128s",
+        // 0xffff_ffff + 1, as limbs from the lowest
+        inputs: &[0xffff_ffff, 0, 0, 0, 1, 0, 0, 0],
+        outputs: &[0, 1, 0, 0],
+    },
+    Case {
+        package: "casts",
+        // 200 cast through every integer type
+        inputs: &[200, 0],
+        outputs: &[200, 0, 0, 0],
+    },
+];
+
+#[test]
+fn test_conformance_corpus() {
+    let reference = std::env::var(REFERENCE_COMPILER_VAR).ok();
+    let mut report = String::new();
+    for case in CORPUS {
+        if let Err(e) = check_case(case, reference.as_deref(), &mut report) {
+            writeln!(report, "{}: {e:#}", case.package).ok();
+        }
+    }
+    assert!(report.is_empty(), "Conformance divergences:\n{report}");
+}
+
+fn check_case(case: &Case, reference: Option<&str>, report: &mut String) -> anyhow::Result<()> {
+    let bytes = move_compile(case.package)?;
+    let module = move_utils::parse_module(&bytes)?;
+    let artifact = match compiler::compile(&module) {
+        Ok(artifact) => artifact,
+        Err(e) => {
+            let reproducer = write_reproducer(case, &module, &e)?;
+            return Err(e.context(format!("Reproducer written to {}", reproducer.display())));
+        }
+    };
+    let outputs = runner::run(&artifact, case.inputs)?;
+    if outputs != case.outputs {
+        writeln!(
+            report,
+            "{}: expected {:?}, move2miden produced {:?}",
+            case.package, case.outputs, outputs
+        )?;
+    }
+    if let Some(command) = reference {
+        let reference_outputs = run_reference(command, case, &artifact.abi.inputs)?;
+        let width = outputs.len().min(reference_outputs.len());
+        if reference_outputs[..width] != outputs[..width] {
+            writeln!(
+                report,
+                "{}: move2miden produced {:?}, reference compiler produced {:?}",
+                case.package,
+                outputs,
+                &reference_outputs[..width]
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Reduces a module failing to compile, keeping the error code of `error`, or
+/// its message if it has none, and writes the result to the temporary directory.
+fn write_reproducer(
+    case: &Case,
+    module: &CompiledModule,
+    error: &anyhow::Error,
+) -> anyhow::Result<std::path::PathBuf> {
+    let failure = |error: &anyhow::Error| match error.downcast_ref::<ErrorCode>() {
+        Some(code) => code.to_string(),
+        None => error.root_cause().to_string(),
+    };
+    let expected = failure(error);
+    let reduced = reduce::reduce_module(module, |module| {
+        compiler::compile(module).is_err_and(|e| failure(&e) == expected)
+    });
+    let dir = std::env::temp_dir().join("move2miden-reproducers");
+    reduce::write_reproducer(&reduced, &dir, case.package)
+}
+
+/// Compiles the case with the reference compiler and runs the result with the
+/// inputs laid out as for this crate's program, returning the whole output stack.
+fn run_reference(
+    command: &str,
+    case: &Case,
+    layout: &abi::InputLayout,
+) -> anyhow::Result<Vec<u64>> {
+    let input = format!("src/tests/res/move_sources/{}.move", case.package);
+    let output = std::env::temp_dir().join(format!("move2miden-reference-{}.masm", case.package));
+    let output = output.to_string_lossy().into_owned();
+    let mut args = command
+        .split_whitespace()
+        .map(|arg| arg.replace("{input}", &input).replace("{output}", &output));
+    let program = args.next().context("Empty reference compiler command")?;
+    let status = Command::new(&program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to run reference compiler {program}"))?;
+    anyhow::ensure!(status.success(), "Reference compiler failed with {status}");
+
+    let masm = std::fs::read_to_string(&output).context("Missing reference compiler output")?;
+    let assembler = Assembler::default().with_library(&StdLibrary::default())?;
+    let program = assembler.compile(masm)?;
+    let inputs = abi::encode_inputs(layout, case.inputs)?;
+    let advice = AdviceInputs::default().with_stack_values(inputs.advice)?;
+    let trace = miden::execute(
+        &program,
+        StackInputs::try_from_values(inputs.stack)?,
+        DefaultHost::new(MemAdviceProvider::from(advice)),
+        Default::default(),
+    )?;
+    Ok(trace.stack_outputs().stack().to_vec())
+}
@@ -1,20 +1,55 @@
+mod conformance;
+
 use {
-    crate::{compiler, move_utils},
+    crate::{
+        abi, abi_doc, analysis, audit,
+        backend::{AstBackend, MidenBackend},
+        bundle,
+        codegen::{MasmSink, MasmWriter, ValidationSink},
+        compiler::{self, TargetContext},
+        cost::MeasuredCostModel,
+        diagnostics::{self, ErrorCode, Severity},
+        error_map::{Check, ErrorCodes},
+        frontend,
+        limits::Limits,
+        memory, move_utils,
+        passes::{self, Pass},
+        reduce, server, verify,
+    },
     anyhow::Context,
-    miden::DefaultHost,
-    miden_assembly::Assembler,
+    miden::{
+        crypto::dsa::rpo_falcon512::KeyPair, AdviceInputs, DefaultHost, MemAdviceProvider,
+        StackInputs,
+    },
+    miden_assembly::{
+        ast::{AdviceInjectorNode, Instruction, Node, ProgramAst, SourceLocation},
+        Assembler,
+    },
+    miden_stdlib::StdLibrary,
+    move_binary_format::{
+        access::ModuleAccess,
+        file_format::{
+            Bytecode, ConstantPoolIndex, FunctionDefinitionIndex, FunctionHandleIndex,
+            IdentifierIndex, ModuleHandle, Signature, SignatureIndex, SignatureToken, Visibility,
+        },
+        CompiledModule,
+    },
     move_compiler::{
         shared::{NumberFormat, NumericalAddress},
         Compiler, Flags,
     },
-    std::collections::BTreeSet,
+    move_core_types::{identifier::Identifier, metadata::Metadata},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        time::Duration,
+    },
 };
 
 #[test]
 fn test_compile_arithmetic() {
     let bytes = move_compile("arithmetic").unwrap();
     let move_module = move_utils::parse_module(&bytes).unwrap();
-    let miden_ast = compiler::compile(&move_module).unwrap();
+    let miden_ast = compiler::compile(&move_module).unwrap().program;
     let assembler = Assembler::default();
     let program = assembler.compile_ast(&miden_ast).unwrap();
     let result = miden::execute(
@@ -28,6 +63,16 @@ fn test_compile_arithmetic() {
     assert_eq!(outputs, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
 }
 
+#[test]
+fn test_procedure_names_demangle() {
+    let bytes = move_compile("arithmetic").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let program = compiler::compile_program(&move_module).unwrap();
+    let demangled = crate::mangle::demangle(&program.procedures()[0].name.to_string()).unwrap();
+    assert_eq!(demangled.module, move_module.self_id());
+    assert_eq!(demangled.function.as_str(), "add");
+}
+
 #[test]
 fn test_compile_loop() {
     let bytes = move_compile("repeat").unwrap();
@@ -35,17 +80,2743 @@ fn test_compile_loop() {
     println!("{move_module:?}");
 }
 
-fn move_compile(package_name: &str) -> anyhow::Result<Vec<u8>> {
-    let known_attributes = BTreeSet::new();
-    let named_address_mapping = [(
-        package_name,
-        NumericalAddress::new([0; 32], NumberFormat::Hex),
-    )]
+#[test]
+fn test_loop_condition_blocks() {
+    // The condition of the loop in `main` calls a function and short-circuits
+    let bytes = move_compile("conditions").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let result = analysis::analyze(&move_module).unwrap();
+    let bounded: Vec<(&str, bool)> = result
+        .functions
+        .iter()
+        .map(|f| (f.name.as_str(), f.bytecode_cost.bounded))
+        .collect();
+    assert_eq!(bounded, [("is_small", true), ("main", false)]);
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(crate::runner::run(&artifact, &[0]).unwrap(), vec![0]);
+    assert_eq!(crate::runner::run(&artifact, &[5]).unwrap(), vec![5]);
+    assert_eq!(crate::runner::run(&artifact, &[20]).unwrap(), vec![10]);
+
+    // A short-circuiting `||` condition, evaluated again at the end of the body
+    let bytes = move_compile("disjunction").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    for (n, m) in [(0, 0), (3, 7), (7, 3), (4, 4)] {
+        assert_eq!(
+            crate::runner::run(&artifact, &[n, m]).unwrap(),
+            vec![n.max(m)],
+            "{n}, {m}"
+        );
+    }
+}
+
+#[test]
+fn test_rotated_loops() {
+    // do { i = i + 1 } while (i < n); i
+    // The loop is a single block, ending with the branch back to itself
+    let module = with_entry_code(
+        "rotated",
+        vec![
+            Bytecode::LdU32(0),
+            Bytecode::StLoc(1),
+            Bytecode::CopyLoc(1), // Label::Point(2)
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(1),
+            Bytecode::CopyLoc(1),
+            Bytecode::CopyLoc(0),
+            Bytecode::Lt,
+            Bytecode::BrTrue(2),
+            Bytecode::MoveLoc(1),
+            Bytecode::Ret,
+        ],
+    );
+    let artifact = compiler::compile(&module).unwrap();
+    for (n, expected) in [(0, 1), (1, 1), (5, 5)] {
+        assert_eq!(crate::runner::run(&artifact, &[n]).unwrap(), vec![expected]);
+    }
+
+    // do { i = i + 1; if (i % 2 == 0) j = j + 1 } until (i >= n); i + j
+    // The condition is checked by a later block than the header
+    let module = with_entry_code(
+        "rotated",
+        vec![
+            Bytecode::LdU32(0),
+            Bytecode::StLoc(1),
+            Bytecode::LdU32(0),
+            Bytecode::StLoc(2),
+            Bytecode::CopyLoc(1), // Label::Point(4)
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(1),
+            Bytecode::CopyLoc(1),
+            Bytecode::LdU32(2),
+            Bytecode::Mod,
+            Bytecode::LdU32(0),
+            Bytecode::Eq,
+            Bytecode::BrFalse(18),
+            Bytecode::CopyLoc(2),
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(2),
+            Bytecode::CopyLoc(1), // Label::Point(18)
+            Bytecode::CopyLoc(0),
+            Bytecode::Ge,
+            Bytecode::BrFalse(4),
+            Bytecode::MoveLoc(1),
+            Bytecode::MoveLoc(2),
+            Bytecode::Add,
+            Bytecode::Ret,
+        ],
+    );
+    let artifact = compiler::compile(&module).unwrap();
+    for (n, expected) in [(0, 1), (1, 1), (6, 9), (7, 10)] {
+        assert_eq!(crate::runner::run(&artifact, &[n]).unwrap(), vec![expected]);
+    }
+
+    // while (i < n) { do { j = j + 1 } while (j < i + i); i = i + 1 }; j
+    // A rotated loop in the body of a loop checking its condition first
+    let module = with_entry_code(
+        "rotated",
+        vec![
+            Bytecode::LdU32(0),
+            Bytecode::StLoc(1),
+            Bytecode::LdU32(0),
+            Bytecode::StLoc(2),
+            Bytecode::CopyLoc(1), // Label::Point(4)
+            Bytecode::CopyLoc(0),
+            Bytecode::Lt,
+            Bytecode::BrFalse(23),
+            Bytecode::CopyLoc(2), // Label::Point(8)
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(2),
+            Bytecode::CopyLoc(2),
+            Bytecode::CopyLoc(1),
+            Bytecode::CopyLoc(1),
+            Bytecode::Add,
+            Bytecode::Lt,
+            Bytecode::BrTrue(8),
+            Bytecode::CopyLoc(1), // Label::Point(18)
+            Bytecode::LdU32(1),
+            Bytecode::Add,
+            Bytecode::StLoc(1),
+            Bytecode::Branch(4),
+            Bytecode::MoveLoc(2), // Label::Point(23)
+            Bytecode::Ret,
+        ],
+    );
+    let artifact = compiler::compile(&module).unwrap();
+    for (n, expected) in [(0, 0), (1, 1), (2, 2), (4, 6)] {
+        assert_eq!(crate::runner::run(&artifact, &[n]).unwrap(), vec![expected]);
+    }
+}
+
+#[test]
+fn test_counted_loops() {
+    let bytes = move_compile("counted").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    // Loops with constant bounds run as `repeat` blocks, without one if they never run
+    let repeats: Vec<Option<u32>> = artifact.program.procedures()[..3]
+        .iter()
+        .map(|procedure| {
+            assert!(!procedure
+                .body
+                .nodes()
+                .iter()
+                .any(|node| matches!(node, Node::While { .. })));
+            procedure.body.nodes().iter().find_map(|node| match node {
+                Node::Repeat { times, .. } => Some(*times),
+                _ => None,
+            })
+        })
+        .collect();
+    assert_eq!(repeats, [None, Some(1), Some(4)]);
+    // Totals and counters after 0, 1 and 4 trips
+    assert_eq!(
+        crate::runner::run(&artifact, &[100]).unwrap(),
+        vec![100, 3, 107, 8, 118, 12]
+    );
+}
+
+#[test]
+fn test_analysis_and_codegen() {
+    let bytes = move_compile("repeat").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let result = analysis::analyze(&move_module).unwrap();
+    let call_graph = result.call_graph();
+    assert_eq!(
+        call_graph["main"].iter().copied().collect::<Vec<_>>(),
+        ["collatz", "fib", "sum"]
+    );
+    assert!(call_graph["sum"].is_empty());
+    let bounded: Vec<(&str, bool)> = result
+        .functions
+        .iter()
+        .map(|f| (f.name.as_str(), f.bytecode_cost.bounded))
+        .collect();
+    assert_eq!(
+        bounded,
+        [
+            ("sum", false),
+            ("fib", false),
+            ("collatz", false),
+            ("main", true)
+        ]
+    );
+
+    // Code generation can run several times from the same analysis
+    let expected = compiler::compile(&move_module).unwrap().program;
+    for _ in 0..2 {
+        let artifact = compiler::codegen(&result, Default::default()).unwrap();
+        assert_eq!(artifact.program, expected);
+    }
+}
+
+#[test]
+fn test_compile_hash() {
+    // The entry module must be the last one in the file
+    let bytes = move_compile("hashing").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let miden_ast = compiler::compile(&move_module).unwrap().program;
+    let assembler = Assembler::default()
+        .with_library(&StdLibrary::default())
+        .unwrap();
+    let program = assembler.compile_ast(&miden_ast).unwrap();
+    let result = miden::execute(
+        &program,
+        Default::default(),
+        DefaultHost::default(),
+        Default::default(),
+    )
+    .unwrap();
+    // keccak256 of 32 zero bytes is 290decd9548b62a8d60345a988386fc8
+    // 4ba6bc95484008f6362f93160ef3e563. The procedure leaves the little-endian
+    // limbs of the digest with the first on top, which is the last return
+    // value of `main`, so outputs hold them from the last limb.
+    let digest: [u64; 8] = [
+        0xd9ec0d29, 0xa8628b54, 0xa94503d6, 0xc86f3888, 0x95bca64b, 0xf6084048, 0x16932f36,
+        0x63e5f30e,
+    ];
+    let outputs = result.stack_outputs().stack();
+    let expected: Vec<u64> = digest.iter().rev().copied().collect();
+    assert_eq!(&outputs[..8], expected.as_slice());
+    assert_eq!(&outputs[8..], &[0; 8]);
+}
+
+#[test]
+fn test_compile_struct_return() {
+    let bytes = move_compile("structs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let miden_ast = compiler::compile(&move_module).unwrap().program;
+    let assembler = Assembler::default();
+    let program = assembler.compile_ast(&miden_ast).unwrap();
+    let result = miden::execute(
+        &program,
+        Default::default(),
+        DefaultHost::default(),
+        Default::default(),
+    )
+    .unwrap();
+    let outputs = result.stack_outputs().stack();
+    assert_eq!(outputs, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_canaries() {
+    let bytes = move_compile("structs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&move_module).unwrap();
+    let options = compiler::CodegenOptions {
+        canaries: true,
+        ..compiler::CodegenOptions::default()
+    };
+    let artifact = compiler::codegen(&analysis, options).unwrap();
+    assert!(artifact.build_info.options.canaries);
+    assert_eq!(
+        crate::runner::run(&artifact, &[]).unwrap(),
+        Vec::<u64>::new()
+    );
+
+    // Writing right after an allocation overwrites its trailing canary
+    let run = |offset: u32| {
+        let mut nodes = memory::alloc(1, &memory::MemoryMap::default(), true);
+        nodes.extend([
+            Node::Instruction(Instruction::PushU32(7)),
+            Node::Instruction(Instruction::Swap1),
+            Node::Instruction(Instruction::PushU32(offset)),
+            Node::Instruction(Instruction::Add),
+            Node::Instruction(Instruction::MemStore),
+        ]);
+        nodes.extend(memory::check_canaries(0, &memory::MemoryMap::default()));
+        let program = ProgramAst::new(nodes, Vec::new()).unwrap();
+        let program = Assembler::default().compile_ast(&program).unwrap();
+        miden::execute(
+            &program,
+            Default::default(),
+            DefaultHost::default(),
+            Default::default(),
+        )
+    };
+    assert!(run(0).is_ok());
+    assert!(run(1).is_err());
+}
+
+#[test]
+fn test_memory_map() {
+    let bytes = move_compile("structs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&move_module).unwrap();
+    let codegen = |map| {
+        let options = compiler::CodegenOptions {
+            canaries: true,
+            memory: map,
+            ..compiler::CodegenOptions::default()
+        };
+        compiler::codegen(&analysis, options)
+    };
+
+    // Regions moved above addresses reserved by other code
+    let map = memory::MemoryMap {
+        heap_pointer: 100,
+        output_buffer: 200,
+        heap_start: 300,
+        scratch: 1 << 20,
+    };
+    let artifact = codegen(map).unwrap();
+    assert_eq!(artifact.build_info.options.memory, map);
+    assert_eq!(artifact.layout.heap_start, 300);
+    let nodes = artifact.program.body().nodes();
+    assert!(nodes.contains(&Node::Instruction(Instruction::MemLoadImm(100))));
+    assert!(!nodes.contains(&Node::Instruction(Instruction::MemLoadImm(
+        memory::HEAP_POINTER_ADDR
+    ))));
+    assert_eq!(
+        crate::runner::run(&artifact, &[]).unwrap(),
+        Vec::<u64>::new()
+    );
+
+    // The heap can't start inside the output buffer
+    let overlapping = memory::MemoryMap {
+        heap_start: memory::OUTPUT_BUFFER_ADDR + 1,
+        ..memory::MemoryMap::default()
+    };
+    let error = codegen(overlapping).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::MEMORY_LAYOUT)
+    );
+    // Nor can a region contain the start of the procedure locals
+    let overlapping = memory::MemoryMap {
+        scratch: memory::LOCALS_START - 1,
+        ..memory::MemoryMap::default()
+    };
+    let error = codegen(overlapping).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::MEMORY_LAYOUT)
+    );
+    assert!(
+        format!("{error:#}").contains("procedure locals"),
+        "{error:#}"
+    );
+    assert_eq!(
+        memory::MemoryMap::default().heap_end(),
+        memory::LOCALS_START
+    );
+    assert_eq!(map.heap_end(), 1 << 20);
+}
+
+#[test]
+fn test_memory_footprint() {
+    let bytes = move_compile("structs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&move_module).unwrap();
+    let codegen = |map, canaries| {
+        let options = compiler::CodegenOptions {
+            canaries,
+            memory: map,
+            ..compiler::CodegenOptions::default()
+        };
+        compiler::codegen(&analysis, options).unwrap()
+    };
+
+    // `make` allocates a pair, and `main` calls it once
+    let artifact = codegen(memory::MemoryMap::default(), false);
+    let footprints: Vec<_> = artifact
+        .stats
+        .memory
+        .iter()
+        .map(|f| (f.function.as_str(), f.locals, f.heap))
+        .collect();
+    assert_eq!(
+        footprints,
+        [("make", Some(0), Some(2)), ("main", Some(0), Some(2))]
+    );
+    assert_eq!(artifact.stats.memory_collisions().count(), 0);
+    // Canaries take heap space too
+    let artifact = codegen(memory::MemoryMap::default(), true);
+    assert_eq!(artifact.stats.memory[1].heap, Some(5));
+
+    // The pair doesn't fit below a scratch region right after the data section
+    let map = memory::MemoryMap {
+        heap_start: 20,
+        scratch: 21,
+        ..memory::MemoryMap::default()
+    };
+    let artifact = codegen(map, false);
+    assert_eq!(
+        artifact.stats.memory_collisions().collect::<Vec<_>>(),
+        [
+            ("make", ["scratch region"].as_slice()),
+            ("main", ["scratch region"].as_slice())
+        ]
+    );
+    // so allocating it aborts instead of overwriting the scratch region
+    let error = crate::runner::run(&artifact, &[]).unwrap_err();
+    let message = format!("{error:#}");
+    assert!(
+        message.contains(&memory::EOUT_OF_MEMORY.to_string()),
+        "{message}"
+    );
+}
+
+#[test]
+fn test_reduce_failing_function() {
+    let bytes = move_compile("reduce").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let error_code = |module: &CompiledModule| -> Option<ErrorCode> {
+        let error = compiler::compile(module).err()?;
+        error.downcast_ref::<ErrorCode>().copied()
+    };
+    let expected = error_code(&module).unwrap();
+    let reduced = reduce::reduce_module(&module, |m| error_code(m) == Some(expected));
+    assert_eq!(error_code(&reduced), Some(expected));
+    let main = module.function_defs.len() - 1;
+    let code_len =
+        |module: &CompiledModule| module.function_defs[main].code.as_ref().unwrap().code.len();
+    assert!(code_len(&reduced) < code_len(&module));
+
+    let dir = std::env::temp_dir().join("move2miden-reduce-test");
+    let path = reduce::write_reproducer(&reduced, &dir, "main").unwrap();
+    let written = move_utils::parse_module(&std::fs::read(path).unwrap()).unwrap();
+    assert_eq!(written, reduced);
+}
+
+#[test]
+fn test_entry_outputs() {
+    let bytes = move_compile("outputs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(artifact.abi.function, "main");
+    assert_eq!(artifact.entry().module, move_module.self_id());
+    assert_eq!(artifact.entry().function, "main");
+    assert_eq!(artifact.abi.output_width, 2);
+    let assembler = Assembler::default();
+    let program = assembler.compile_ast(&artifact.program).unwrap();
+    let result = miden::execute(
+        &program,
+        Default::default(),
+        DefaultHost::default(),
+        Default::default(),
+    )
+    .unwrap();
+    let outputs = result.stack_outputs().stack();
+    assert_eq!(outputs, &[5, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_constants() {
+    let bytes = move_compile("constants").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), [42]);
+}
+
+#[test]
+fn test_address_constants() {
+    let bytes = move_compile("addresses").unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), [0x1234, 0]);
+
+    // Addresses without an account ID can't be loaded
+    for constant in &mut module.constant_pool {
+        if constant.type_ == SignatureToken::Address {
+            constant.data = vec![0xff; 32];
+        }
+    }
+    let error = compiler::compile(&module).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::UNSUPPORTED_TYPE)
+    );
+}
+
+#[test]
+fn test_single_use_locals_stay_on_stack() {
+    let bytes = move_compile("locals").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert!(!artifact.program.to_string().contains("loc_"));
+    assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), [42]);
+}
+
+#[test]
+fn test_locals_in_slots() {
+    // Loop variables are kept in procedure locals across iterations
+    let bytes = move_compile("repeat").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    let sum = &artifact.program.procedures()[0];
+    assert!(sum.num_locals >= 3);
+    assert!(artifact.program.to_string().contains("loc_store"));
+    crate::runner::run(&artifact, &[]).unwrap();
+
+    // Parameters used out of order are stored on entry, a u64 taking two slots.
+    // The entry function keeps them in the data section.
+    let bytes = move_compile("u64s").unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    let sub = module.function_defs[1].function;
+    module.function_defs[1].code.as_mut().unwrap().code = vec![
+        Bytecode::MoveLoc(1),
+        Bytecode::MoveLoc(0),
+        Bytecode::Sub,
+        Bytecode::Ret,
+    ];
+    let main = module.function_defs.last_mut().unwrap();
+    main.code.as_mut().unwrap().code = vec![
+        Bytecode::LdU64(1),
+        Bytecode::LdU64(3),
+        Bytecode::Call(sub),
+        Bytecode::MoveLoc(1),
+        Bytecode::MoveLoc(0),
+        Bytecode::Sub,
+        Bytecode::Add,
+        Bytecode::Ret,
+    ];
+    let artifact = compiler::compile(&module).unwrap();
+    assert_eq!(artifact.program.procedures()[1].num_locals, 4);
+    assert!(artifact.layout.data_section_size >= 4);
+    // (3 - 1) + (0x1_0000_0000 - 1)
+    assert_eq!(
+        crate::runner::run(&artifact, &[1, 0, 0, 1]).unwrap(),
+        vec![1, 1]
+    );
+    assert!(crate::runner::run(&artifact, &[2, 0, 1, 0]).is_err());
+
+    // Only locals read or written apart from the stack get slots, here a u128
+    // and a u64 but not the parameters, moved in order
+    module
+        .signatures
+        .push(Signature(vec![SignatureToken::U128, SignatureToken::U64]));
+    let locals = SignatureIndex((module.signatures.len() - 1) as u16);
+    let add = module.function_defs[0].code.as_mut().unwrap();
+    add.locals = locals;
+    add.code = vec![
+        Bytecode::MoveLoc(0),
+        Bytecode::MoveLoc(1),
+        Bytecode::Add,
+        Bytecode::StLoc(3),
+        Bytecode::LdU128(7),
+        Bytecode::StLoc(2),
+        Bytecode::MoveLoc(3),
+        Bytecode::Ret,
+    ];
+    let artifact = compiler::compile(&module).unwrap();
+    assert_eq!(artifact.program.procedures()[0].num_locals, 6);
+    assert_eq!(
+        crate::runner::run(&artifact, &[1, 0, 0, 1]).unwrap(),
+        vec![1, 1]
+    );
+}
+
+#[test]
+fn test_local_references() {
+    let bytes = move_compile("references").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    // Locals of other functions are referenced by their procedure local address,
+    // while the entry function references the data section
+    assert!(artifact.program.to_string().contains("locaddr"));
+    // (5 + 1) + 7 * 2 + (5 + 1)
+    assert_eq!(
+        crate::runner::run(&artifact, &[5, 0, 7]).unwrap(),
+        vec![26, 0]
+    );
+    // Values written through references are still checked for overflows
+    assert!(crate::runner::run(&artifact, &[0xffff_ffff, 0xffff_ffff, 0]).is_err());
+    assert!(crate::runner::run(&artifact, &[0, 0, 0x8000_0000]).is_err());
+}
+
+#[test]
+fn test_frozen_references() {
+    let bytes = move_compile("frozen").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let main = &module
+        .function_defs
+        .last()
+        .unwrap()
+        .code
+        .as_ref()
+        .unwrap()
+        .code;
+    assert!(main.contains(&Bytecode::FreezeRef));
+    let artifact = compiler::compile(&module).unwrap();
+    // Reads through the frozen reference see the writes made through the
+    // mutable one, as both refer to the same local: (5 + 1) + (5 + 1)
+    assert_eq!(crate::runner::run(&artifact, &[5, 0]).unwrap(), vec![12, 0]);
+    assert_eq!(
+        crate::runner::run(&artifact, &[0x7fff_ffff, 1]).unwrap(),
+        vec![0, 3]
+    );
+}
+
+#[test]
+fn test_pop_wide_values() {
+    let bytes = move_compile("pops").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let main = &module
+        .function_defs
+        .last()
+        .unwrap()
+        .code
+        .as_ref()
+        .unwrap()
+        .code;
+    assert_eq!(main.iter().filter(|b| **b == Bytecode::Pop).count(), 3);
+    let artifact = compiler::compile(&module).unwrap();
+    // A u64, a u128 and a struct pointer are popped, each as its own number of
+    // elements, leaving the parameters below them untouched
+    assert_eq!(
+        crate::runner::run(&artifact, &[5, 0, 9]).unwrap(),
+        vec![6, 0, 9]
+    );
+    assert_eq!(
+        crate::runner::run(&artifact, &[u32::MAX as u64, 2, 1]).unwrap(),
+        vec![0, 3, 1]
+    );
+}
+
+#[test]
+fn test_discarded_values() {
+    let bytes = move_compile("discards").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let main = &module
+        .function_defs
+        .last()
+        .unwrap()
+        .code
+        .as_ref()
+        .unwrap()
+        .code;
+    assert_eq!(main.iter().filter(|b| **b == Bytecode::Pop).count(), 2);
+    let artifact = compiler::compile(&module).unwrap();
+    // The u128 is dropped as a word and the u64 as its 2 limbs, leaving the
+    // stack as it was before the calls
+    assert!(artifact.program.to_string().contains("dropw"));
+    assert_eq!(crate::runner::run(&artifact, &[5, 0]).unwrap(), vec![6, 0]);
+    assert_eq!(
+        crate::runner::run(&artifact, &[u32::MAX as u64, 1]).unwrap(),
+        vec![0, 2]
+    );
+}
+
+#[test]
+fn test_emit_pass_ir() {
+    let bytes = move_compile("u128s").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&module).unwrap();
+    let options = compiler::CodegenOptions {
+        emit_pass_ir: true,
+        ..compiler::CodegenOptions::default()
+    };
+    let artifact = compiler::codegen(&analysis, options).unwrap();
+    let passes: Vec<_> = artifact.pass_ir.iter().map(|ir| ir.pass).collect();
+    assert_eq!(passes, [Pass::Lower, Pass::Schedule, Pass::Inline]);
+    // The last pass records the final program
+    let written = compiler::compile_with(&module, MasmWriter::new(Vec::new())).unwrap();
+    assert_eq!(
+        artifact.pass_ir[2].masm,
+        String::from_utf8(written).unwrap()
+    );
+    let report = passes::report(&artifact.pass_ir);
+    assert!(report.starts_with("# After lower\n"));
+    assert!(report.contains("# After schedule"));
+    // Passes aren't recorded unless requested
+    assert!(compiler::compile(&module).unwrap().pass_ir.is_empty());
+}
+
+#[test]
+fn test_constant_hash_folding() {
+    use miden::{crypto::Rpo256, math::StarkField};
+
+    let bytes = move_compile("domain").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    // Every digest element is returned as the low and high limbs of a u64
+    // The bytes are hashed packed 4 per element
+    let packed = crate::bytes::pack_bytes(b"move2miden").unwrap();
+    let expected: Vec<u64> = Rpo256::hash_elements(&packed)
+        .as_elements()
+        .iter()
+        .flat_map(|felt| [felt.as_int() & 0xffff_ffff, felt.as_int() >> 32])
+        .collect();
+    assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), expected);
+}
+
+#[test]
+fn test_runtime_byte_packing() {
+    use miden::math::StarkField;
+
+    let bytes = move_compile("packing").unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    // main(i) = *vector::borrow(&hash::pack(DATA), i)
+    let pack = module
+        .function_handles
+        .iter()
+        .position(|handle| module.identifier_at(handle.name).as_str() == "pack")
+        .unwrap();
+    module.signatures.push(Signature(vec![SignatureToken::U32]));
+    let element = SignatureIndex((module.signatures.len() - 1) as u16);
+    let main = module.function_defs.last_mut().unwrap();
+    main.code.as_mut().unwrap().code = vec![
+        Bytecode::LdConst(ConstantPoolIndex(0)),
+        Bytecode::Call(FunctionHandleIndex(pack as u16)),
+        Bytecode::MoveLoc(0),
+        Bytecode::VecImmBorrow(element),
+        Bytecode::ReadRef,
+        Bytecode::Ret,
+    ];
+    let analysis = analysis::analyze(&module).unwrap();
+    // Programs pack bytes as hosts do
+    let expected = crate::bytes::pack_bytes(&[1, 2, 3, 4, 5]).unwrap();
+    for canaries in [false, true] {
+        let options = compiler::CodegenOptions {
+            canaries,
+            ..compiler::CodegenOptions::default()
+        };
+        let artifact = compiler::codegen(&analysis, options).unwrap();
+        for (i, element) in expected.iter().enumerate() {
+            assert_eq!(
+                crate::runner::run(&artifact, &[i as u64, 0]).unwrap(),
+                vec![element.as_int()]
+            );
+        }
+        assert!(crate::runner::run(&artifact, &[expected.len() as u64, 0]).is_err());
+        // The packed vector is as long as the bytes
+        let main = artifact.stats.memory.last().unwrap();
+        assert_eq!(main.heap, None);
+    }
+}
+
+#[test]
+fn test_target_contexts() {
+    let bytes = move_compile("kernel").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&module).unwrap();
+    let compile = |target| {
+        let options = compiler::CodegenOptions {
+            target,
+            ..compiler::CodegenOptions::default()
+        };
+        compiler::codegen(&analysis, options)
+    };
+
+    let artifact = compile(TargetContext::Account).unwrap();
+    assert_eq!(artifact.build_info.options.target, TargetContext::Account);
+    let get_id = crate::intrinsics::exec_imported("miden::account", "get_id").unwrap();
+    assert!(artifact.program.body().nodes().contains(&get_id));
+
+    // Incrementing the nonce is reserved to account code
+    let error = compile(TargetContext::NoteScript).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::TARGET_CONTEXT)
+    );
+    assert!(format!("{error:#}").contains("account::incr_nonce requires account context"));
+    let error = compile(TargetContext::Standalone).unwrap_err();
+    assert!(format!("{error:#}").contains("but the program is compiled for standalone context"));
+    // The call is located, before any code is generated
+    let location = error.downcast_ref::<compiler::CodeLocation>().unwrap();
+    assert_eq!(location.offset, Some(1));
+
+    // Global storage is only reported as unsupported where the kernel provides it
+    let bytes = move_compile("storage").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let error = compiler::compile(&module).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::TARGET_CONTEXT)
+    );
+    let analysis = analysis::analyze(&module).unwrap();
+    let options = compiler::CodegenOptions {
+        target: TargetContext::Account,
+        ..compiler::CodegenOptions::default()
+    };
+    let error = compiler::codegen(&analysis, options).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::UNSUPPORTED_OPCODE)
+    );
+    assert_eq!(
+        "note-script".parse::<TargetContext>(),
+        Ok(TargetContext::NoteScript)
+    );
+}
+
+#[test]
+fn test_create_note() {
+    let bytes = move_compile("notes").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let compile = |module: &CompiledModule| {
+        let analysis = analysis::analyze(module)?;
+        let options = compiler::CodegenOptions {
+            target: TargetContext::Account,
+            ..compiler::CodegenOptions::default()
+        };
+        compiler::codegen(&analysis, options)
+    };
+
+    let artifact = compile(&module).unwrap();
+    let create_note = crate::intrinsics::exec_imported("miden::tx", "create_note").unwrap();
+    let nodes = artifact.program.body().nodes();
+    assert!(nodes.contains(&create_note));
+    assert!(nodes.contains(&Node::Instruction(Instruction::PushU32(0xc0000000))));
+    assert!(
+        nodes.contains(&Node::Instruction(Instruction::AssertWithError(
+            ErrorCodes::default().code(Check::InvalidNoteAmount)
+        )))
+    );
+    let error = compiler::compile(&module).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::TARGET_CONTEXT)
+    );
+
+    // Network execution requires public notes
+    let mut network = module.clone();
+    for code in &mut network.function_defs[0].code.as_mut().unwrap().code {
+        if let Bytecode::LdU32(tag) = code {
+            *tag = 0x40000000;
+        }
+    }
+    let error = compile(&network).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::INVALID_NOTE)
+    );
+    assert!(format!("{error:#}").contains("requests network execution"));
+
+    // Recipients are 32 byte digests, the length prefix being 31 here
+    let mut short = module.clone();
+    short.constant_pool[0].data.truncate(32);
+    short.constant_pool[0].data[0] = 31;
+    let error = compile(&short).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::INVALID_NOTE)
+    );
+    assert!(format!("{error:#}").contains("got 31 bytes"));
+}
+
+#[test]
+fn test_entry_inputs_spill_to_advice() {
+    let bytes = move_compile("wide_inputs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let miden_ast = compiler::compile(&move_module).unwrap().program;
+    let assembler = Assembler::default();
+    let program = assembler.compile_ast(&miden_ast).unwrap();
+    let layout = abi::InputLayout {
+        stack_width: 16,
+        advice_width: 2,
+    };
+    let values: Vec<u64> = (1..=18).collect();
+    let inputs = abi::encode_inputs(&layout, &values).unwrap();
+    let advice = AdviceInputs::default()
+        .with_stack_values(inputs.advice)
+        .unwrap();
+    let result = miden::execute(
+        &program,
+        StackInputs::try_from_values(inputs.stack).unwrap(),
+        DefaultHost::new(MemAdviceProvider::from(advice)),
+        Default::default(),
+    )
+    .unwrap();
+    let outputs = result.stack_outputs().stack();
+    assert_eq!(outputs, &[171, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_entry_inputs_validated() {
+    let bytes = move_compile("inputs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(crate::runner::run(&artifact, &[1, 255]).unwrap(), vec![255]);
+    assert_eq!(crate::runner::run(&artifact, &[0, 7]).unwrap(), vec![7]);
+    // Out of the domain of `bool` and `u8`
+    assert!(crate::runner::run(&artifact, &[2, 7]).is_err());
+    assert!(crate::runner::run(&artifact, &[1, 256]).is_err());
+
+    // Addresses must be account IDs
+    let bytes = move_compile("owners").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(
+        crate::runner::run(&artifact, &[0x1234, 5, 0]).unwrap(),
+        vec![0x1234]
+    );
+    let error = crate::runner::run(&artifact, &[u64::MAX, 5, 0]).unwrap_err();
+    assert!(
+        format!("{error:#}").contains("Invalid address input"),
+        "{error:#}"
+    );
+}
+
+#[test]
+fn test_u64_arithmetic() {
+    let bytes = move_compile("u64s").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(artifact.abi.inputs.width(), 4);
+    assert_eq!(artifact.abi.output_width, 2);
+    // u64::MAX + 0x1_0000_0000
+    assert_eq!(
+        crate::runner::run(&artifact, &[0xffff_ffff, 0xffff_fffe, 0, 1]).unwrap(),
+        vec![0xffff_ffff, 0xffff_ffff]
+    );
+    // Move aborts on overflow
+    assert!(crate::runner::run(&artifact, &[0xffff_ffff, 0xffff_ffff, 1, 0]).is_err());
+    // Limbs must be u32s
+    assert!(crate::runner::run(&artifact, &[1 << 32, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn test_ordered_comparisons() {
+    let bytes = move_compile("comparisons").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    // u32 values are ordered natively
+    let nodes = artifact.program.body().nodes();
+    for instruction in [
+        Instruction::U32Lt,
+        Instruction::U32Gt,
+        Instruction::U32Lte,
+        Instruction::U32Gte,
+    ] {
+        assert!(nodes.contains(&Node::Instruction(instruction)));
+    }
+    let run = |x: u64, y: u64, a: u64, b: u64| {
+        let values = [x, y, a & 0xffff_ffff, a >> 32, b & 0xffff_ffff, b >> 32];
+        crate::runner::run(&artifact, &values).unwrap()
+    };
+    // x < y, x > y, x <= y, x >= y, then the same for a and b
+    assert_eq!(
+        run(1, 2, 0xffff_ffff, 0x1_0000_0000),
+        vec![1, 0, 1, 0, 1, 0, 1, 0]
+    );
+    assert_eq!(
+        run(2, 1, 0x1_0000_0000, 0xffff_ffff),
+        vec![0, 1, 0, 1, 0, 1, 0, 1]
+    );
+    assert_eq!(
+        run(u32::MAX as u64, u32::MAX as u64, u64::MAX, u64::MAX),
+        vec![0, 0, 1, 1, 0, 0, 1, 1]
+    );
+}
+
+#[test]
+fn test_boolean_operations() {
+    let bytes = move_compile("booleans").unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    // (!a, a != b)
+    for (a, b) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+        assert_eq!(
+            crate::runner::run(&artifact, &[a, b]).unwrap(),
+            vec![1 - a, (a != b) as u64]
+        );
+    }
+
+    // The Move compiler lowers `&&` and `||` to branches, but other toolchains
+    // may emit `And` and `Or`
+    module
+        .signatures
+        .push(Signature(vec![SignatureToken::Bool]));
+    let returns = SignatureIndex((module.signatures.len() - 1) as u16);
+    let main = module.function_defs.last().unwrap().function;
+    module.function_handles[main.0 as usize].return_ = returns;
+    for (opcode, expected) in [(Bytecode::And, [0, 0, 0, 1]), (Bytecode::Or, [0, 1, 1, 1])] {
+        let main = module.function_defs.last_mut().unwrap();
+        main.code.as_mut().unwrap().code = vec![
+            Bytecode::MoveLoc(0),
+            Bytecode::MoveLoc(1),
+            opcode,
+            Bytecode::Ret,
+        ];
+        let artifact = compiler::compile(&module).unwrap();
+        for ((a, b), expected) in [(0, 0), (0, 1), (1, 0), (1, 1)].into_iter().zip(expected) {
+            assert_eq!(
+                crate::runner::run(&artifact, &[a, b]).unwrap(),
+                vec![expected]
+            );
+        }
+    }
+}
+
+#[test]
+fn test_boolean_literals() {
+    let bytes = move_compile("literals").unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), vec![1, 0]);
+
+    // Literals are valid branch conditions: main() = if (literal) 7 else 9
+    module.signatures.push(Signature(vec![SignatureToken::U32]));
+    let returns = SignatureIndex((module.signatures.len() - 1) as u16);
+    let main = module.function_defs.last().unwrap().function;
+    module.function_handles[main.0 as usize].return_ = returns;
+    for (literal, expected) in [(Bytecode::LdTrue, 7), (Bytecode::LdFalse, 9)] {
+        let main = module.function_defs.last_mut().unwrap();
+        main.code.as_mut().unwrap().code = vec![
+            literal,
+            Bytecode::BrFalse(4),
+            Bytecode::LdU32(7),
+            Bytecode::Branch(5),
+            Bytecode::LdU32(9),
+            Bytecode::Ret,
+        ];
+        let artifact = compiler::compile(&module).unwrap();
+        assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), vec![expected]);
+    }
+}
+
+#[test]
+fn test_division_by_zero() {
+    let bytes = move_compile("division").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    // (7 + 2^32) % 2^32
+    assert_eq!(
+        crate::runner::run(&artifact, &[14, 2, 7, 1, 0, 1]).unwrap(),
+        vec![3, 7, 0]
+    );
+    // Move aborts on division by zero, whatever the width of the divisor
+    assert!(crate::runner::run(&artifact, &[14, 0, 7, 1, 0, 1]).is_err());
+    assert!(crate::runner::run(&artifact, &[14, 2, 7, 1, 0, 0]).is_err());
+    // Nonzero constant divisors aren't checked
+    let check = Node::Instruction(Instruction::AssertWithError(
+        ErrorCodes::default().code(Check::DivisionByZero),
+    ));
+    let halve = &artifact.program.procedures()[0];
+    assert!(!halve.body.nodes().contains(&check));
+    assert!(artifact.program.body().nodes().contains(&check));
+}
+
+#[test]
+fn test_shifts() {
+    let bytes = move_compile("shifts").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    // Bits shifted out to the left are dropped
+    assert_eq!(
+        crate::runner::run(&artifact, &[0x81, 1, 4, 256, 2, 0x8000_0001, 1]).unwrap(),
+        vec![2, 1, 64, 2]
+    );
+    // Move aborts when shifting by the number of bits of the type or more
+    assert!(crate::runner::run(&artifact, &[0x81, 8, 4, 256, 2, 1, 1]).is_err());
+    assert!(crate::runner::run(&artifact, &[0x81, 1, 4, 256, 64, 1, 1]).is_err());
+    assert!(crate::runner::run(&artifact, &[0x81, 1, 4, 256, 2, 1, 32]).is_err());
+}
+
+#[test]
+fn test_casts() {
+    let bytes = move_compile("casts").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(
+        crate::runner::run(&artifact, &[255, 0]).unwrap(),
+        vec![255, 0, 0, 0]
+    );
+    // Narrowing casts abort when the value doesn't fit, as in Move
+    for inputs in [[256, 0], [0x1_0000, 0], [0, 1]] {
+        let error = crate::runner::run(&artifact, &inputs).unwrap_err();
+        assert!(
+            format!("{error:#}").contains("ECAST_OUT_OF_RANGE"),
+            "{error:#}"
+        );
+    }
+}
+
+#[test]
+fn test_narrow_arithmetic() {
+    let bytes = move_compile("narrow").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(
+        crate::runner::run(&artifact, &[100, 155, 218]).unwrap(),
+        vec![255, 65400]
+    );
+    // Results must fit in the type, though they fit in a u32
+    for inputs in [[100, 156, 0], [0, 0, 219]] {
+        let error = crate::runner::run(&artifact, &inputs).unwrap_err();
+        assert!(format!("{error:#}").contains("EOVERFLOW"), "{error:#}");
+    }
+}
+
+#[test]
+fn test_narrow_literals() {
+    let bytes = move_compile("narrow").unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    let mut run = |code: Vec<Bytecode>| {
+        let main = module.function_defs.last_mut().unwrap();
+        main.code.as_mut().unwrap().code = code;
+        let artifact = compiler::compile(&module).unwrap();
+        crate::runner::run(&artifact, &[0, 0, 0])
+    };
+    // main() = (255, 65535)
+    let largest = vec![Bytecode::LdU8(255), Bytecode::LdU16(65535), Bytecode::Ret];
+    assert_eq!(run(largest).unwrap(), vec![255, 65535]);
+    // Sums of literals are checked against the width of their type
+    let sums = |x, y| {
+        vec![
+            Bytecode::LdU8(x),
+            Bytecode::LdU8(y),
+            Bytecode::Add,
+            Bytecode::LdU16(1000),
+            Bytecode::LdU16(1),
+            Bytecode::Add,
+            Bytecode::Ret,
+        ]
+    };
+    assert_eq!(run(sums(200, 55)).unwrap(), vec![255, 1001]);
+    assert!(run(sums(200, 56)).is_err());
+}
+
+#[test]
+fn test_bitwise_operations() {
+    let bytes = move_compile("bitwise").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    let inputs = [
+        // 0xf0f00000_ffff0000 & 0xff00ff00_0f0f0f0f
+        0xffff_0000,
+        0xf0f0_0000,
+        0x0f0f_0f0f,
+        0xff00_ff00,
+        // Limbs of u128s, the low limb first
+        1,
+        2,
+        3,
+        4,
+        3,
+        3,
+        3,
+        3,
+        0b1010,
+        0b0101,
+    ];
+    assert_eq!(
+        crate::runner::run(&artifact, &inputs).unwrap(),
+        vec![0x0f0f_0000, 0xf000_0000, 2, 1, 0, 7, 0b1111]
+    );
+}
+
+#[test]
+fn test_u128_arithmetic() {
+    let bytes = move_compile("u128s").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(artifact.abi.inputs.width(), 8);
+    assert_eq!(artifact.abi.output_width, 4);
+    // Helpers are appended once, after the procedures of the module
+    let functions = move_module.function_defs().len();
+    let helpers = crate::integers::helpers(&ErrorCodes::default())
+        .unwrap()
+        .len();
+    assert_eq!(artifact.program.procedures().len(), functions + helpers);
+    // 2^96 - 1 + 1
+    let max_u96 = [0xffff_ffff, 0xffff_ffff, 0xffff_ffff, 0];
+    let inputs: Vec<u64> = max_u96.into_iter().chain([1, 0, 0, 0]).collect();
+    assert_eq!(
+        crate::runner::run(&artifact, &inputs).unwrap(),
+        vec![0, 0, 0, 1]
+    );
+    // Move aborts on overflow
+    let inputs = [0xffff_ffff; 4].into_iter().chain([1, 0, 0, 0]);
+    let error = crate::runner::run(&artifact, &inputs.collect::<Vec<u64>>()).unwrap_err();
+    assert!(format!("{error:#}").contains("EOVERFLOW"), "{error:#}");
+}
+
+#[test]
+fn test_u256_arithmetic() {
+    let bytes = move_compile("u256s").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(artifact.abi.inputs.width(), 16);
+    assert_eq!(artifact.abi.output_width, 8);
+    // 2^224 - 1 + 1
+    let mut inputs = vec![0xffff_ffff; 7];
+    inputs.extend([0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(
+        crate::runner::run(&artifact, &inputs).unwrap(),
+        vec![0, 0, 0, 0, 0, 0, 0, 1]
+    );
+    // Move aborts on overflow
+    let mut inputs = vec![0xffff_ffff; 8];
+    inputs.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+    assert!(crate::runner::run(&artifact, &inputs).is_err());
+}
+
+#[test]
+fn test_backend_error_location() {
+    let package_name = "unsupported";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let diagnostics = frontend::compile_to_miden(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    )
+    .unwrap_err();
+    let (span, _) = diagnostics.diagnostics[0].primary.as_ref().unwrap();
+    assert_eq!((span.line, span.column), (5, 17));
+    assert!(diagnostics.render().contains("let _ = mystery();"));
+    assert_eq!(
+        diagnostics.diagnostics[0].code,
+        Some(crate::diagnostics::UNSUPPORTED_NATIVE.code)
+    );
+}
+
+#[test]
+fn test_cycle_budget() {
+    let package_name = "budget";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let diagnostics = frontend::compile_to_miden(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.diagnostics.len(), 1);
+    let diagnostic = &diagnostics.diagnostics[0];
+    assert_eq!(diagnostic.code, Some(crate::diagnostics::CYCLE_BUDGET.code));
+    assert!(
+        diagnostic.message.contains("main"),
+        "{}",
+        diagnostic.message
+    );
+}
+
+#[test]
+fn test_inline_hints() {
+    let package_name = "inlining";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let artifact = frontend::compile_to_miden(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    )
+    .unwrap();
+    // `add` is kept as a call, while the small `mul` is inlined
+    let calls: Vec<&Node> = artifact
+        .program
+        .body()
+        .nodes()
+        .iter()
+        .filter(|node| matches!(node, Node::Instruction(Instruction::ExecLocal(_))))
+        .collect();
+    assert_eq!(calls, [&Node::Instruction(Instruction::ExecLocal(0))]);
+    assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), [9]);
+}
+
+#[test]
+fn test_export_attribute() {
+    let package_name = "export";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let result = frontend::check_sources(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    );
+    let exports: Vec<(&str, bool)> = result
+        .functions
+        .iter()
+        .map(|f| (f.function.as_str(), f.procedure.as_ref().unwrap().is_export))
+        .collect();
+    assert_eq!(
+        exports,
+        [("on_transfer", true), ("helper", false), ("main", false)]
+    );
+}
+
+#[test]
+fn test_acquires() {
+    let bytes = move_compile("storage").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&module).unwrap();
+    let acquires: Vec<(&str, Vec<&str>)> = analysis
+        .functions
+        .iter()
+        .map(|f| {
+            let acquires = f.acquires.iter().map(String::as_str).collect();
+            (f.name.as_str(), acquires)
+        })
+        .collect();
+    assert_eq!(
+        acquires,
+        [
+            ("balance", vec!["Balance"]),
+            ("total", vec!["Balance"]),
+            ("zero", vec![])
+        ]
+    );
+}
+
+#[test]
+fn test_storage_witness() {
+    use crate::{
+        accounts::AccountId,
+        witness::{self, ResourceKey, Snapshot, STORAGE_DEPTH},
+    };
+    use miden::{crypto::NodeIndex, Word};
+
+    let bytes = move_compile("storage").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let layout = abi::StorageLayout::new(&module, 0);
+    let key = |id| ResourceKey {
+        account: AccountId::new(id).unwrap(),
+        resource: "Balance".to_string(),
+    };
+    let snapshot: Snapshot = [(key(1), vec![100]), (key(2), vec![200])]
+        .into_iter()
+        .collect();
+    let root = witness::storage_root(&layout, &snapshot).unwrap();
+    let advice = witness::storage_advice_for(&layout, &["Balance".to_string()], &snapshot).unwrap();
+    for (key, fields) in &snapshot {
+        let index = NodeIndex::new(STORAGE_DEPTH, witness::leaf_index(key)).unwrap();
+        let path = advice.merkle_store().get_path(root.into(), index).unwrap();
+        // The balance is a u64, hashed as its limbs
+        assert_eq!(Word::from(path.value), witness::leaf_value(&[fields[0], 0]));
+    }
+
+    // Resources must match the storage layout
+    let wrong_fields: Snapshot = [(key(1), vec![100, 0])].into_iter().collect();
+    assert!(witness::storage_root(&layout, &wrong_fields).is_err());
+    let unknown: Snapshot = [(
+        ResourceKey {
+            account: AccountId::new(1).unwrap(),
+            resource: "Coin".to_string(),
+        },
+        vec![1],
+    )]
+    .into_iter()
+    .collect();
+    assert!(witness::storage_root(&layout, &unknown).is_err());
+}
+
+#[test]
+fn test_state_round_trip() {
+    use crate::{accounts::AccountId, state::State};
+
+    let bytes = move_compile("storage").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let layout = abi::StorageLayout::new(&module, 0);
+    let (alice, bob) = (AccountId::new(1).unwrap(), AccountId::new(2).unwrap());
+    let mut state = State::default();
+    state.insert(alice, "Balance", vec![100]);
+    state.insert(bob, "Balance", vec![200]);
+    let root = state.root(&layout).unwrap();
+
+    let path = std::env::temp_dir().join("move2miden-state-test.json");
+    state.save(&path).unwrap();
+    let loaded = State::load(&path).unwrap();
+    assert_eq!(loaded, state);
+    assert_eq!(loaded.root(&layout).unwrap(), root);
+
+    // Any change to a resource changes the root
+    let mut updated = loaded;
+    assert_eq!(updated.insert(bob, "Balance", vec![150]), Some(vec![200]));
+    assert_eq!(updated.get(bob, "Balance"), Some(&[150][..]));
+    assert_ne!(updated.root(&layout).unwrap(), root);
+}
+
+#[test]
+fn test_load_artifact() {
+    use crate::artifact::Artifact;
+
+    let bytes = move_compile("u64s").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let compiled = compiler::compile(&move_module).unwrap();
+    let bundle = bundle::Bundle::from_artifact(&compiled).unwrap();
+    let path = std::env::temp_dir().join("move2miden-artifact-test.json");
+    bundle.save(&path).unwrap();
+
+    // Programs run again from their bundle as when they were compiled
+    let artifact = Artifact::load(&path).unwrap();
+    assert_eq!(artifact.abi, compiled.abi);
+    let inputs = [0xffff_ffff, 0xffff_fffe, 0, 1];
+    assert_eq!(
+        crate::runner::execute_artifact(&artifact, &inputs, AdviceInputs::default()).unwrap(),
+        crate::runner::run(&compiled, &inputs).unwrap()
+    );
+    // Overflows still abort the loaded program
+    assert!(crate::runner::execute_artifact(
+        &artifact,
+        &[0xffff_ffff, 0xffff_ffff, 1, 0],
+        AdviceInputs::default()
+    )
+    .is_err());
+
+    // Bundles edited after the build, or without an interface, aren't loaded
+    let mut tampered = bundle.clone();
+    tampered.program_hash = "00".into();
+    assert!(Artifact::from_bundle(tampered).is_err());
+    let mut without_abi = bundle;
+    without_abi.abi = None;
+    assert!(Artifact::from_bundle(without_abi).is_err());
+}
+
+#[cfg(feature = "mast")]
+#[test]
+fn test_mast_backend() {
+    use crate::backend::MastBackend;
+
+    // Programs in the syntax common to both assemblers assemble with the MAST backend
+    let program = ProgramAst::parse("begin push.1 push.2 add drop end").unwrap();
+    let assembled = MastBackend.assemble(&program).unwrap();
+    assert_ne!(MastBackend.program_hash(&assembled), [0; 32]);
+
+    // Bundles still record the hash of the program executed by the AST backend
+    let bytes = move_compile("u64s").unwrap();
+    let artifact = compiler::compile(&move_utils::parse_module(&bytes).unwrap()).unwrap();
+    let bundle = bundle::Bundle::from_artifact(&artifact).unwrap();
+    let executed = AstBackend.assemble(&artifact.program).unwrap();
+    assert_eq!(
+        bundle.program_hash,
+        bundle::to_hex(&AstBackend.program_hash(&executed))
+    );
+}
+
+#[test]
+fn test_account_ids() {
+    use crate::accounts::AccountId;
+    use move_core_types::account_address::AccountAddress;
+
+    let address = AccountAddress::from_hex_literal("0xcafe").unwrap();
+    let id = AccountId::from_address(&address).unwrap();
+    assert_eq!(id.as_u64(), 0xcafe);
+    assert_eq!(id.to_string(), "0xcafe");
+    assert_eq!(id.to_address(), address);
+    // Addresses which aren't field elements have no account ID
+    let wide = AccountAddress::from_hex_literal("0x1000000000000000000").unwrap();
+    assert!(AccountId::from_address(&wide).is_err());
+    assert!(AccountId::from_address(
+        &AccountAddress::from_hex_literal("0xffffffffffffffff").unwrap()
+    )
+    .is_err());
+    // Account IDs are saved as numbers
+    assert_eq!(serde_json::to_string(&id).unwrap(), "51966");
+    assert!(serde_json::from_str::<AccountId>("18446744073709551615").is_err());
+}
+
+#[test]
+fn test_simulator() {
+    use {
+        crate::{
+            accounts::AccountId,
+            simulator::{Call, Simulator},
+            state::State,
+            witness::{self, ResourceKey},
+        },
+        miden::math::StarkField,
+    };
+
+    let bytes = move_compile("inputs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    let call = |values: &[u64]| Call {
+        artifact: &artifact,
+        values: values.to_vec(),
+        takes_root: false,
+    };
+    let mut simulator = Simulator::new(State::default());
+    let steps = simulator.run(&[call(&[1, 3]), call(&[0, 4])]).unwrap();
+    let outputs: Vec<&[u64]> = steps.iter().map(|s| s.outputs.as_slice()).collect();
+    assert_eq!(outputs, [&[3][..], &[4][..]]);
+    assert_eq!(steps[0].root, steps[1].root);
+    assert!(steps.iter().all(|s| s.proof.is_none()));
+
+    // The failing call is reported
+    let error = simulator.run(&[call(&[1, 3]), call(&[2, 0])]).unwrap_err();
+    assert!(error.to_string().starts_with("Call 1 to"), "{error}");
+
+    let mut simulator = simulator.with_proofs(true);
+    let step = simulator.apply(&call(&[1, 5])).unwrap();
+    assert_eq!(step.outputs, [5]);
+    assert!(step.proof.is_some());
+
+    // Programs taking the root check resources against the root threaded between steps
+    let mut units = move_compile_with_natives("flow").unwrap();
+    let module = move_utils::parse_module(&units.pop().unwrap()).unwrap();
+    let flow = compiler::compile(&module).unwrap();
+    let alice = AccountId::new(0xa11ce).unwrap();
+    let key = ResourceKey {
+        account: alice,
+        resource: "Balance".to_string(),
+    };
+    let check = |balance: u64| {
+        let limbs = |value: u64| [value & 0xffff_ffff, value >> 32];
+        let mut values = limbs(witness::leaf_index(&key)).to_vec();
+        for element in witness::leaf_value(&limbs(balance)) {
+            values.extend(limbs(element.as_int()));
+        }
+        Call {
+            artifact: &flow,
+            values,
+            takes_root: true,
+        }
+    };
+    let mut state = State::default();
+    state.insert(alice, "Balance", vec![100]);
+    let mut simulator = Simulator::new(state);
+    let first = simulator.apply(&check(100)).unwrap();
+    // Resources must match the root
+    assert!(simulator.apply(&check(150)).is_err());
+    let root = simulator
+        .update(&flow.layout, |state| {
+            state.insert(alice, "Balance", vec![150]);
+        })
+        .unwrap();
+    let second = simulator.apply(&check(150)).unwrap();
+    assert_eq!(second.root, root);
+    assert_ne!(second.root, first.root);
+    // Changes not applied with `update` leave the next call with a stale root
+    simulator.state_mut().insert(alice, "Balance", vec![200]);
+    assert!(simulator.apply(&check(200)).is_err());
+}
+
+#[test]
+fn test_audit() {
+    let bytes = move_compile("audit").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&module).unwrap();
+    let findings = audit::audit(&analysis);
+    let summary: Vec<(&str, Severity)> = findings
+        .iter()
+        .map(|f| (f.function.as_str(), f.severity))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("bump", Severity::Warning),
+            ("bump_and_read", Severity::Note),
+            // `counter.value + 1` and `times - 1`
+            ("bump", Severity::Note),
+            ("bump", Severity::Note)
+        ]
+    );
+}
+
+#[test]
+fn test_overflow_checks() {
+    let bytes = move_compile("overflow").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&module).unwrap();
+    let checked: Vec<(&str, usize)> = analysis
+        .functions
+        .iter()
+        .map(|f| (f.name.as_str(), f.overflow_checks.len()))
+        .collect();
+    assert_eq!(
+        checked,
+        [
+            ("widened", 0),
+            ("add", 1),
+            ("halves", 0),
+            ("decrement", 1),
+            ("masked", 0),
+            ("counter", 1),
+        ]
+    );
+
+    // Additions which may overflow are checked, others use field arithmetic
+    let bytes = move_compile("arithmetic").unwrap();
+    let artifact = compiler::compile(&move_utils::parse_module(&bytes).unwrap()).unwrap();
+    let checked_add = Node::Instruction(Instruction::U32OverflowingAdd);
+    assert!(
+        artifact.program.body().nodes().contains(&checked_add)
+            || artifact
+                .program
+                .procedures()
+                .iter()
+                .any(|p| p.body.nodes().contains(&checked_add))
+    );
+}
+
+#[test]
+fn test_procedure_source_locations() {
+    let package_name = "export";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let artifact = frontend::compile_to_miden(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    )
+    .unwrap();
+    let starts: Vec<SourceLocation> = artifact
+        .program
+        .procedures()
+        .iter()
+        .map(|p| p.start)
+        .collect();
+    assert_eq!(
+        starts,
+        [
+            SourceLocation::new(3, 9),
+            SourceLocation::new(7, 9),
+            SourceLocation::new(11, 22)
+        ]
+    );
+}
+
+#[test]
+fn test_compile_bytecode_with_source_map() {
+    let package_name = "export";
+    let named_address_mapping: BTreeMap<_, _> = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let paths = [format!("src/tests/res/move_sources/{package_name}.move")];
+    let from_sources = frontend::compile_to_miden(&paths, named_address_mapping.clone()).unwrap();
+    let frontend::SourceModule { module, source_map } =
+        frontend::compile_sources(&paths, named_address_mapping.clone())
+            .unwrap()
+            .modules
+            .pop()
+            .unwrap();
+    let artifact = frontend::compile_bytecode(
+        module,
+        source_map,
+        &paths,
+        named_address_mapping,
+        frontend::BuildOptions::default(),
+    )
+    .unwrap();
+    assert!(artifact.source_map.is_some());
+    assert_eq!(artifact.masm(), from_sources.masm());
+    let starts: Vec<SourceLocation> = artifact
+        .program
+        .procedures()
+        .iter()
+        .map(|p| p.start)
+        .collect();
+    assert_eq!(starts[0], SourceLocation::new(3, 9));
+}
+
+#[test]
+fn test_intrinsic_attribute() {
+    let package_name = "bindings";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let artifact = frontend::compile_to_miden(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    )
+    .unwrap();
+    assert_eq!(crate::runner::run(&artifact, &[]).unwrap(), [3]);
+}
+
+#[test]
+fn test_foreign_procedure_invocation() {
+    let package_name = "foreign";
+    let paths = [format!("src/tests/res/move_sources/{package_name}.move")];
+    let compile = |target, foreign_roots| {
+        let named_address_mapping = [(
+            package_name.to_string(),
+            NumericalAddress::new([0; 32], NumberFormat::Hex),
+        )]
+        .into_iter()
+        .collect();
+        let options = frontend::BuildOptions {
+            target,
+            foreign_roots,
+            ..frontend::BuildOptions::default()
+        };
+        frontend::compile_with_options(&paths, named_address_mapping, options)
+    };
+    let (path, procedure) = crate::fpi::KERNEL_PROCEDURE;
+    let execute = crate::intrinsics::exec_imported(path, procedure).unwrap();
+
+    let root = crate::fpi::parse_root(&format!("0x{}", "01".repeat(32))).unwrap();
+    let roots = [("oracle::get_price".to_string(), root)]
+        .into_iter()
+        .collect();
+    let artifact = compile(TargetContext::Account, roots).unwrap();
+    let nodes = artifact.program.body().nodes();
+    assert!(nodes.contains(&execute));
+    assert!(nodes.contains(&Node::Instruction(Instruction::PushFelt(root[0]))));
+    assert!(!nodes.contains(&Node::Instruction(Instruction::AdvLoadW)));
+
+    // Roots aren't taken from the host
+    let errors = compile(TargetContext::NoteScript, BTreeMap::new()).unwrap_err();
+    assert_eq!(
+        errors.diagnostics[0].code,
+        Some(diagnostics::UNSUPPORTED_NATIVE.code)
+    );
+    assert!(
+        errors.diagnostics[0].message.contains("oracle::get_price"),
+        "{}",
+        errors.diagnostics[0].message
+    );
+
+    let errors = compile(TargetContext::Standalone, BTreeMap::new()).unwrap_err();
+    assert_eq!(
+        errors.diagnostics[0].code,
+        Some(diagnostics::TARGET_CONTEXT.code)
+    );
+    assert!(crate::fpi::parse_root("0x01").is_err());
+}
+
+#[test]
+fn test_oracle_read() {
+    let mut units = move_compile_with_natives("prices").unwrap();
+    let mut module = move_utils::parse_module(&units.pop().unwrap()).unwrap();
+    // The key pair is random, so its commitment replaces the constant
+    let key_pair = KeyPair::new().unwrap();
+    let mut key = vec![32];
+    key.extend(crate::oracle::key_commitment(&key_pair));
+    module.constant_pool[0].data = key;
+    let artifact = compiler::compile(&module).unwrap();
+    let program = AstBackend.assemble(&artifact.program).unwrap();
+    let public_key: miden::Word = key_pair.public_key().into();
+    let run = |reports: &[crate::oracle::SignedReport]| {
+        let advice = crate::oracle::advice_inputs(reports, public_key)?;
+        crate::runner::execute_with_advice(
+            &program,
+            &artifact.abi,
+            &artifact.error_map,
+            &[7],
+            advice,
+        )
+    };
+
+    let report = crate::oracle::Report {
+        feed: 7,
+        value: 2_500_000_000 << 8,
+        timestamp: 1_700_000_000,
+    };
+    let signed = report.sign(&key_pair).unwrap();
+    // Value, then timestamp, limbs from the low one
+    assert_eq!(
+        run(&[signed.clone()]).unwrap(),
+        vec![0x02f9_0000, 0x95, 1_700_000_000, 0]
+    );
+    // Reports signed by another oracle are rejected
+    let other = report.sign(&KeyPair::new().unwrap()).unwrap();
+    assert!(run(&[other]).is_err());
+    // So are reports whose signature is for another report
+    let mut forged = signed;
+    forged.report.value += 1;
+    assert!(run(&[forged.clone()]).is_err());
+    forged.signature = forged.report.sign(&key_pair).unwrap().signature;
+    assert!(run(&[forged]).is_ok());
+}
+
+#[test]
+fn test_recursive_verification() {
+    let mut units = move_compile_with_natives("aggregate").unwrap();
+    let module = move_utils::parse_module(&units.pop().unwrap()).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    let verify =
+        crate::intrinsics::exec_imported("std::crypto::stark::verifier", "verify").unwrap();
+    let sum_of_two = artifact
+        .program
+        .procedures()
+        .iter()
+        .find(|p| p.body.nodes().contains(&verify))
+        .unwrap();
+    // The public inputs reach the verifier from memory, through the advice map
+    let insert = Node::Instruction(Instruction::AdvInject(AdviceInjectorNode::InsertMem));
+    assert!(sum_of_two.body.nodes().contains(&insert));
+    // No proof was verified yet
+    assert_eq!(crate::runner::run(&artifact, &[15]).unwrap(), vec![0, 0]);
+    assert!(crate::runner::run(&artifact, &[16]).is_err());
+
+    // The trace length, then the stack inputs and outputs padded to 16 elements
+    let stack = crate::recursion::advice_stack(10, &[1, 2], &[3], &[7, 8]).unwrap();
+    assert_eq!(stack.len(), 1 + 32 + 2);
+    assert_eq!(stack[..4], [10, 1, 2, 0]);
+    assert_eq!(stack[17..19], [3, 0]);
+    assert_eq!(stack[33..], [7, 8]);
+    assert!(crate::recursion::advice_stack(10, &[0; 17], &[], &[]).is_err());
+    assert!(crate::recursion::advice_stack(10, &[u64::MAX], &[], &[]).is_err());
+}
+
+#[test]
+fn test_merkle_verify() {
+    use miden::{
+        crypto::{MerkleStore, MerkleTree},
+        math::{Felt, StarkField},
+        Word,
+    };
+
+    let mut units = move_compile_with_natives("merkle").unwrap();
+    let module = move_utils::parse_module(&units.pop().unwrap()).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    let program = AstBackend.assemble(&artifact.program).unwrap();
+    let leaves: Vec<Word> = (0..4).map(|i| [i, 10, 20, 30].map(Felt::new)).collect();
+    let tree = MerkleTree::new(leaves.clone()).unwrap();
+    let root = Word::from(tree.root());
+    let store = MerkleStore::from(&tree);
+    // Elements and the index are u64s, given as their limbs from the low one
+    let limbs = |value: u64| [value & 0xffff_ffff, value >> 32];
+    let words = |word: &Word| {
+        word.iter()
+            .flat_map(|e| limbs(e.as_int()))
+            .collect::<Vec<_>>()
+    };
+    let run = |root: Vec<u64>, index: u64, leaf: &Word| {
+        let mut values = root;
+        values.extend(limbs(index));
+        values.push(2);
+        values.extend(words(leaf));
+        crate::runner::execute_with_advice(
+            &program,
+            &artifact.abi,
+            &artifact.error_map,
+            &values,
+            AdviceInputs::default().with_merkle_store(store.clone()),
+        )
+    };
+
+    assert_eq!(run(words(&root), 2, &leaves[2]).unwrap(), Vec::<u64>::new());
+    assert_eq!(run(words(&root), 0, &leaves[0]).unwrap(), Vec::<u64>::new());
+    // The leaf must be the node at the index, under the root
+    assert!(run(words(&root), 1, &leaves[2]).is_err());
+    assert!(run(words(&root), 2, &leaves[3]).is_err());
+    let other = MerkleTree::new(leaves[..2].to_vec()).unwrap();
+    assert!(run(words(&Word::from(other.root())), 2, &leaves[2]).is_err());
+    // Elements above the field modulus aren't joined silently
+    let mut wide = words(&root);
+    wide[..2].copy_from_slice(&[1, u32::MAX as u64]);
+    let error = run(wide, 2, &leaves[2]).unwrap_err();
+    assert!(
+        format!("{error:#}").contains("ECAST_OUT_OF_RANGE"),
+        "{error:#}"
+    );
+}
+
+#[test]
+fn test_check_reports_each_function() {
+    let package_name = "unsupported";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let result = frontend::check_sources(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    );
+    let status: Vec<(&str, bool)> = result
+        .functions
+        .iter()
+        .map(|f| (f.function.as_str(), f.procedure.is_some()))
+        .collect();
+    assert_eq!(
+        status,
+        [("mystery", true), ("helper", true), ("main", false)]
+    );
+    assert_eq!(result.diagnostics.diagnostics.len(), 1);
+}
+
+#[test]
+fn test_module_metadata_passthrough() {
+    let bytes = move_compile("arithmetic").unwrap();
+    let mut move_module = move_utils::parse_module(&bytes).unwrap();
+    move_module.metadata.push(Metadata {
+        key: b"aptos::metadata_v1".to_vec(),
+        value: vec![1, 2, 3],
+    });
+    let artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(artifact.metadata.bytecode_version, move_module.version);
+    assert_eq!(
+        artifact.metadata.get(b"aptos::metadata_v1"),
+        Some([1, 2, 3].as_slice())
+    );
+
+    let bundle = bundle::Bundle::from_artifact(&artifact).unwrap();
+    let json = serde_json::to_string(&bundle).unwrap();
+    let loaded: bundle::Bundle = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.metadata, artifact.metadata);
+}
+
+#[test]
+fn test_error_map_from_constants() {
+    let package_name = "errors";
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let artifact = frontend::compile_to_miden(
+        &[format!("src/tests/res/move_sources/{package_name}.move")],
+        named_address_mapping,
+    )
+    .unwrap();
+    assert_eq!(artifact.error_map.errors.len(), 1);
+    assert_eq!(
+        artifact.error_map.describe(1),
+        "Aborted with code 1 (checked::E_TOO_LARGE): The value is larger than the maximum."
+    );
+    let bundle = bundle::Bundle::from_artifact(&artifact).unwrap();
+    assert_eq!(bundle.error_map, artifact.error_map);
+}
+
+#[test]
+fn test_error_codes() {
+    let package_name = "aborts";
+    let path = format!("src/tests/res/move_sources/{package_name}.move");
+    let named_address_mapping: BTreeMap<_, _> = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let failure = |artifact: &crate::artifact::CompiledArtifact, inputs: &[u64]| {
+        format!("{:#}", crate::runner::run(artifact, inputs).unwrap_err())
+    };
+
+    // The abort code of the module is the default code of divisions by zero,
+    // so failures couldn't be told apart
+    let diagnostics =
+        frontend::compile_to_miden(std::slice::from_ref(&path), named_address_mapping.clone())
+            .unwrap_err();
+    assert_eq!(
+        diagnostics.diagnostics[0].code,
+        Some(diagnostics::ABORT_CODE.code)
+    );
+    assert!(diagnostics.render().contains("EDIVISION_BY_ZERO"));
+
+    // Moving the checks elsewhere tells them apart
+    let codes = ErrorCodes::new(0x100).unwrap();
+    let options = frontend::BuildOptions {
+        error_codes: codes,
+        ..frontend::BuildOptions::default()
+    };
+    let artifact = frontend::compile_with_options(&[path], named_address_mapping, options).unwrap();
+    assert_eq!(artifact.build_info.options.error_codes, codes);
+    assert!(artifact
+        .program
+        .body()
+        .nodes()
+        .contains(&Node::Instruction(Instruction::AssertWithError(0x102))));
+    assert!(failure(&artifact, &[11, 0, 1, 0])
+        .contains("Aborted with code 131074 (main::E_TOO_LARGE): The value is larger"));
+    assert!(failure(&artifact, &[5, 0, 0, 0]).contains("Aborted with code 258 (EDIVISION_BY_ZERO)"));
+    assert_eq!(
+        crate::runner::run(&artifact, &[6, 0, 2, 0]).unwrap(),
+        vec![3, 0]
+    );
+    // The namespace is recorded for hosts decoding failures of bundles
+    let bundle = bundle::Bundle::from_artifact(&artifact).unwrap();
+    assert_eq!(bundle.error_map.checks, codes);
+}
+
+#[test]
+fn test_abi_doc() {
+    let bytes = move_compile("structs").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&move_module).unwrap();
+    let bundle = bundle::Bundle::from_artifact(&artifact).unwrap();
+    let report = abi_doc::render(&bundle).unwrap();
+    assert!(report.contains("`main()`"));
+    assert!(report.contains(&bundle.program_hash));
+    assert!(report.contains("### `Pair`"));
+    assert!(report.contains("| 1 | `y` | `u32` |"));
+    assert!(report.contains("- `131074`: `EDIVISION_BY_ZERO` Division by zero"));
+
+    let json = serde_json::to_string(&bundle).unwrap();
+    let loaded: bundle::Bundle = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.layout, Some(artifact.layout));
+}
+
+#[test]
+fn test_abi_version() {
+    let bytes = move_compile("arithmetic").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let mut artifact = compiler::compile(&move_module).unwrap();
+    assert_eq!(artifact.abi.version, abi::ABI_VERSION);
+    let bundle = bundle::Bundle::from_artifact(&artifact).unwrap();
+    assert!(abi_doc::render(&bundle)
+        .unwrap()
+        .contains(&format!("ABI version: {}", abi::ABI_VERSION)));
+
+    // Interfaces recorded before versioning follow the first version
+    let mut json = serde_json::to_value(&artifact.abi).unwrap();
+    json.as_object_mut().unwrap().remove("version");
+    let loaded: abi::EntryAbi = serde_json::from_value(json).unwrap();
+    assert_eq!(loaded.version, 1);
+
+    // Inputs aren't encoded for programs expecting other conventions
+    artifact.abi.version = abi::ABI_VERSION + 1;
+    let inputs = vec![0; artifact.abi.inputs.width()];
+    let error = crate::runner::run(&artifact, &inputs).unwrap_err();
+    assert!(error.to_string().contains("ABI version"));
+}
+
+#[test]
+fn test_assert_result() {
+    let compile = |package_name: &str| {
+        frontend::compile_predicate(
+            &[format!("src/tests/res/move_sources/{package_name}.move")],
+            [(
+                package_name.to_string(),
+                NumericalAddress::new([0; 32], NumberFormat::Hex),
+            )]
+            .into_iter()
+            .collect(),
+        )
+    };
+    let artifact = compile("predicate").unwrap();
+    assert!(artifact.abi.asserts_result);
+    assert_eq!(artifact.abi.output_width, 0);
+    assert!(artifact
+        .program
+        .body()
+        .nodes()
+        .contains(&Node::Instruction(Instruction::AssertEq)));
+    assert_eq!(
+        crate::runner::run(&artifact, &[42]).unwrap(),
+        Vec::<u64>::new()
+    );
+    assert!(crate::runner::run(&artifact, &[41]).is_err());
+
+    // The entry function must return a bool
+    let diagnostics = compile("wide_inputs").unwrap_err();
+    assert_eq!(
+        diagnostics.diagnostics[0].code,
+        Some(diagnostics::ENTRY_POINT.code)
+    );
+}
+
+#[test]
+fn test_memoize() {
+    let package_name = "memo";
+    let path = format!("src/tests/res/move_sources/{package_name}.move");
+    let named_address_mapping: BTreeMap<_, _> = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let bytes = move_compile(package_name).unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let analysis = analysis::analyze(&module).unwrap();
+    assert_eq!(
+        analysis.pure_functions(),
+        ["main", "scale"].into_iter().collect()
+    );
+
+    let options = frontend::BuildOptions {
+        memoize: true,
+        ..frontend::BuildOptions::default()
+    };
+    let artifact = frontend::compile_with_options(&[path], named_address_mapping, options).unwrap();
+    assert!(artifact.build_info.options.memoize);
+    let memoized = |nodes: &[Node]| {
+        nodes.iter().any(|node| {
+            matches!(node, Node::IfElse { true_case, .. }
+                if matches!(true_case.nodes(), [Node::Instruction(Instruction::MemLoadImm(_))]))
+        })
+    };
+    assert!(
+        memoized(artifact.program.body().nodes())
+            || artifact
+                .program
+                .procedures()
+                .iter()
+                .any(|procedure| memoized(procedure.body.nodes()))
+    );
+    assert_eq!(crate::runner::run(&artifact, &[2]).unwrap(), vec![44]);
+}
+
+#[test]
+fn test_opcode_policy() {
+    let bytes = move_compile("predicate").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let expected = compiler::compile(&module).unwrap().program;
+    let with_opcode = |opcode: Bytecode| {
+        let mut module = module.clone();
+        let code = module.function_defs[0].code.as_mut().unwrap();
+        code.code.insert(1, opcode);
+        module
+    };
+
+    // Nop is lowered to nothing
+    let with_nop = with_opcode(Bytecode::Nop);
+    assert_eq!(compiler::compile(&with_nop).unwrap().program, expected);
+
+    // Unimplemented opcodes fail compilation, unless they are skipped
+    let with_unsupported = with_opcode(Bytecode::FreezeRef);
+    let error = compiler::compile(&with_unsupported).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::UNSUPPORTED_OPCODE)
+    );
+    let analysis = analysis::analyze(&with_unsupported).unwrap();
+    let options = compiler::CodegenOptions {
+        opcode_policy: compiler::OpcodePolicy::Skip,
+        ..compiler::CodegenOptions::default()
+    };
+    let artifact = compiler::codegen(&analysis, options).unwrap();
+    assert_eq!(artifact.program, expected);
+    assert_eq!(
+        artifact.skipped_opcodes,
+        [compiler::CodeLocation {
+            function: FunctionDefinitionIndex(0),
+            offset: Some(1),
+        }]
+    );
+    assert!(artifact.build_info.options.skip_unsupported_opcodes);
+}
+
+#[test]
+fn test_vector_bounds() {
+    let bytes = move_compile("vectors").unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    // Inputs can't provide the vector a reference points to
+    let error = compiler::compile(&module).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::UNSUPPORTED_TYPE)
+    );
+
+    // main(i) = *vector::borrow(&VALUES, i)
+    module.signatures.push(Signature(vec![SignatureToken::U32]));
+    let element = SignatureIndex((module.signatures.len() - 1) as u16);
+    module.signatures.push(Signature(vec![SignatureToken::U64]));
+    let params = SignatureIndex((module.signatures.len() - 1) as u16);
+    let main = module.function_defs.last_mut().unwrap();
+    let handle = main.function;
+    main.code.as_mut().unwrap().code = vec![
+        Bytecode::LdConst(ConstantPoolIndex(0)),
+        Bytecode::MoveLoc(0),
+        Bytecode::VecImmBorrow(element),
+        Bytecode::ReadRef,
+        Bytecode::Ret,
+    ];
+    module.function_handles[handle.0 as usize].parameters = params;
+    let artifact = compiler::compile(&module).unwrap();
+    for (i, expected) in [10, 20, 30].into_iter().enumerate() {
+        assert_eq!(
+            crate::runner::run(&artifact, &[i as u64, 0]).unwrap(),
+            vec![expected]
+        );
+    }
+    // Move aborts instead of reading the memory after the vector
+    for (lo, hi) in [(3, 0), (4, 0), (1 << 20, 0), (0, 1)] {
+        assert!(crate::runner::run(&artifact, &[lo, hi]).is_err());
+    }
+}
+
+#[test]
+fn test_verify_artifact() {
+    let package_name = "wide_inputs";
+    let path = format!("src/tests/res/move_sources/{package_name}.move");
+    let named_address_mapping = [(
+        package_name.to_string(),
+        NumericalAddress::new([0; 32], NumberFormat::Hex),
+    )]
+    .into_iter()
+    .collect();
+    let artifact =
+        frontend::compile_to_miden(std::slice::from_ref(&path), named_address_mapping).unwrap();
+    let mut bundle = bundle::Bundle::from_artifact(&artifact).unwrap();
+    verify::record_test_vector(&mut bundle, &artifact, (1..=18).collect()).unwrap();
+    assert_eq!(bundle.test_vectors[0].outputs, [171]);
+    let main = bundle.procedures.last().unwrap();
+    assert!(main.source.is_some());
+
+    let source = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(verify::verify(&bundle, &[source]), Vec::<String>::new());
+
+    let mut tampered = bundle.clone();
+    tampered.test_vectors[0].outputs = vec![170];
+    tampered.program_hash = "00".into();
+    let problems = verify::verify(&tampered, &[]);
+    assert_eq!(problems.len(), 2);
+    assert!(problems[0].starts_with("Program hash mismatch"));
+    assert!(problems[1].contains("expected outputs [170], got [171]"));
+
+    let problems = verify::verify(&bundle, &["module other {}".to_string()]);
+    assert!(problems[0].contains("which wasn't given"));
+}
+
+#[test]
+fn test_build_info() {
+    let bytes = move_compile("arithmetic").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let model = MeasuredCostModel::from_json(r#"{"mem_store": 3, "max_repeat": 16}"#).unwrap();
+    let artifact = compiler::compile_with_cost_model(&move_module, Box::new(model)).unwrap();
+    let options = &artifact.build_info.options;
+    assert_eq!(options.cost_model["mem_store"], 3);
+    assert_eq!(options.cost_model["max_repeat"], 16);
+    assert!(!options.attributes);
+
+    let masm = artifact.masm();
+    assert!(masm.starts_with(&format!(
+        "# Compiled by move2miden {}",
+        env!("CARGO_PKG_VERSION")
+    )));
+    let assembler = Assembler::default()
+        .with_library(&StdLibrary::default())
+        .unwrap();
+    assert_eq!(
+        assembler.compile(masm).unwrap().hash(),
+        assembler.compile_ast(&artifact.program).unwrap().hash()
+    );
+}
+
+#[test]
+fn test_compile_untrusted_limits() {
+    let bytes = move_compile("arithmetic").unwrap();
+    let limits = Limits::default();
+    let artifact = compiler::compile_untrusted(&bytes, &limits).unwrap();
+    assert!(!artifact.build_info.options.attributes);
+
+    let too_small = [
+        Limits {
+            max_module_bytes: 16,
+            ..Limits::default()
+        },
+        Limits {
+            max_functions: 0,
+            ..Limits::default()
+        },
+        Limits {
+            max_function_code: 1,
+            ..Limits::default()
+        },
+        Limits {
+            max_instructions: 1,
+            ..Limits::default()
+        },
+        Limits {
+            timeout: Duration::ZERO,
+            ..Limits::default()
+        },
+    ];
+    for limits in too_small {
+        let error = compiler::compile_untrusted(&bytes, &limits).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ErrorCode>(),
+            Some(&diagnostics::RESOURCE_LIMIT),
+            "{limits:?}"
+        );
+    }
+}
+
+#[test]
+fn test_compile_untrusted_repeat_limit() {
+    // The loops are compiled to nested `repeat` blocks, which the assembler
+    // copies once per iteration
+    let bytes = move_compile("nested").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let artifact = compiler::compile(&module).unwrap();
+    assert!(artifact.stats.instructions > 200usize.pow(4));
+    let error = compiler::compile_untrusted(&bytes, &Limits::default()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::RESOURCE_LIMIT)
+    );
+}
+
+#[test]
+fn test_compile_untrusted_mutated_bytecode() {
+    // Flips bytes of valid modules at pseudo-random positions; every mutation
+    // must be compiled or rejected with an error, without panicking
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    for package in ["arithmetic", "structs", "constants"] {
+        let bytes = move_compile(package).unwrap();
+        for _ in 0..200 {
+            let mut mutated = bytes.clone();
+            for _ in 0..3 {
+                seed = seed
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                let position = (seed >> 33) as usize % mutated.len();
+                mutated[position] ^= (seed >> 8) as u8 | 1;
+            }
+            let _ = compiler::compile_untrusted(&mutated, &Limits::default());
+        }
+    }
+}
+
+#[test]
+fn test_codegen_backends() {
+    let bytes = move_compile("arithmetic").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    compiler::compile_with(&move_module, ValidationSink::default()).unwrap();
+    let masm = compiler::compile_with(&move_module, MasmSink::default()).unwrap();
+    let miden_ast = compiler::compile_program(&move_module).unwrap();
+    assert_eq!(masm, miden_ast.to_string());
+}
+
+#[test]
+fn test_streamed_masm_matches_program() {
+    for package in ["arithmetic", "hashing", "structs"] {
+        let bytes = move_compile(package).unwrap();
+        let module = move_utils::parse_module(&bytes).unwrap();
+        let program = compiler::compile_program(&module).unwrap();
+        let written = compiler::compile_with(&module, MasmWriter::new(Vec::new())).unwrap();
+        let assembler = Assembler::default()
+            .with_library(&StdLibrary::default())
+            .unwrap();
+        let expected = assembler.compile_ast(&program).unwrap();
+        let streamed = assembler
+            .compile(String::from_utf8(written).unwrap())
+            .unwrap();
+        assert_eq!(expected.hash(), streamed.hash(), "{package}");
+    }
+}
+
+#[test]
+fn test_reusable_compiler() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<compiler::Compiler>();
+
+    let compiler = compiler::Compiler::new();
+    let arithmetic = compiler
+        .load_bytes(&move_compile("arithmetic").unwrap())
+        .unwrap();
+    let outputs = compiler
+        .load_bytes(&move_compile("outputs").unwrap())
+        .unwrap();
+    assert_eq!(compiler.modules().unwrap().len(), 2);
+    let first = compiler.compile(&arithmetic).unwrap();
+    assert_eq!(compiler.analyses().hits(), 0);
+    let second = compiler.compile(&arithmetic).unwrap();
+    assert_eq!(first.program, second.program);
+    assert!(compiler.analyses().hits() > 0);
+    assert_eq!(compiler.compile(&outputs).unwrap().abi.output_width, 2);
+    assert!(compiler.unload(&outputs).unwrap());
+    assert!(compiler.compile(&outputs).is_err());
+}
+
+#[test]
+fn test_analysis_cache() {
+    let bytes = move_compile("conditions").unwrap();
+    let module = move_utils::parse_module(&bytes).unwrap();
+    let cache = analysis::AnalysisCache::new();
+    let first = analysis::analyze_cached(&module, &cache).unwrap();
+    assert_eq!(cache.hits(), 0);
+    let second = analysis::analyze_cached(&module, &cache).unwrap();
+    let with_code = module.function_defs.iter().filter(|f| f.code.is_some());
+    assert_eq!(cache.hits(), with_code.count());
+
+    let uncached = analysis::analyze(&module).unwrap();
+    for analyses in [&first, &second] {
+        for (cached, fresh) in analyses.functions.iter().zip(&uncached.functions) {
+            assert_eq!(cached.cfg, fresh.cfg);
+            assert_eq!(cached.counted_loops, fresh.counted_loops);
+            assert_eq!(cached.bytecode_cost, fresh.bytecode_cost);
+        }
+    }
+}
+
+#[test]
+fn test_server_requests() {
+    let compiler = compiler::Compiler::new();
+    let request = |method: &str, params: serde_json::Value| {
+        let request =
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        server::handle_request(&compiler, &request)
+    };
+
+    let module = bundle::to_hex(&move_compile("outputs").unwrap());
+    let response = request("load", serde_json::json!({ "module": module }));
+    let id = response["result"]["id"].as_str().unwrap().to_string();
+    let response = request("compile", serde_json::json!({ "id": id }));
+    assert!(response["result"]["masm"]
+        .as_str()
+        .unwrap()
+        .contains("begin"));
+    let response = request("run", serde_json::json!({ "id": id, "inputs": [] }));
+    assert_eq!(response["result"]["outputs"], serde_json::json!([5, 7]));
+
+    // Modules from clients are compiled as untrusted bytecode
+    let oversized = bundle::to_hex(&vec![0; Limits::default().max_module_bytes + 1]);
+    let response = request("load", serde_json::json!({ "module": oversized }));
+    assert!(response["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains(&diagnostics::RESOURCE_LIMIT.to_string()));
+
+    // Sources are sent as text, and file names can't point outside the scratch directory
+    let text = std::fs::read_to_string("src/tests/res/move_sources/unsupported.move").unwrap();
+    let response = request(
+        "check",
+        serde_json::json!({
+            "sources": { "unsupported.move": text },
+            "named_addresses": { "unsupported": "0x0" },
+        }),
+    );
+    let items = response["result"]["diagnostics"]["items"]
+        .as_array()
+        .unwrap();
+    assert!(!items.is_empty());
+    for item in items {
+        assert!(matches!(
+            item["location"]["file"].as_str(),
+            None | Some("unsupported.move")
+        ));
+    }
+    for name in ["../unsupported.move", "/etc/passwd", "unsupported.txt"] {
+        let response = request("check", serde_json::json!({ "sources": { name: text } }));
+        assert!(response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Invalid source file name"));
+    }
+
+    // Executions are bounded, so looping modules fail instead of running forever
+    let module = bundle::to_hex(&move_compile("spin").unwrap());
+    let response = request("load", serde_json::json!({ "module": module }));
+    let id = response["result"]["id"].as_str().unwrap().to_string();
+    let response = request("run", serde_json::json!({ "id": id, "inputs": [0, 0] }));
+    assert_eq!(response["result"]["outputs"], serde_json::json!([0, 0]));
+    let response = request("run", serde_json::json!({ "id": id, "inputs": [1, 0] }));
+    assert_eq!(response["error"]["code"], -32000);
+
+    let response = request("unknown", serde_json::Value::Null);
+    assert_eq!(response["error"]["code"], -32601);
+}
+
+#[test]
+fn test_lsp_session() {
+    use serde_json::{json, Value};
+
+    let frame = |body: &str| format!("Content-Length: {}\r\n\r\n{body}", body.len());
+    let uri = "file:///project/sources/unsupported.move";
+    let text = std::fs::read_to_string("src/tests/res/move_sources/unsupported.move").unwrap();
+    let text_document = json!({ "textDocument": { "uri": uri } });
+    let messages = [
+        json!({ "id": 1, "method": "initialize", "params": {
+            "initializationOptions": { "namedAddresses": { "unsupported": "0x0" } },
+        } }),
+        json!({ "method": "textDocument/didOpen", "params": {
+            "textDocument": { "uri": uri, "text": text },
+        } }),
+        json!({ "id": 2, "method": "textDocument/inlayHint", "params": text_document }),
+        // Malformed messages and unknown methods don't stop the server
+        json!({ "method": "textDocument/didOpen", "params": {} }),
+        json!({ "id": 3, "method": "textDocument/inlayHint", "params": {} }),
+        json!({ "id": 4, "method": "textDocument/definition", "params": text_document }),
+        json!({ "method": "textDocument/didClose", "params": text_document }),
+        json!({ "id": 5, "method": "shutdown" }),
+        json!({ "method": "exit" }),
+    ];
+    let mut input = frame("{ not json");
+    for mut message in messages {
+        message["jsonrpc"] = json!("2.0");
+        input.push_str(&frame(&message.to_string()));
+    }
+    let mut output = Vec::new();
+    crate::lsp::run(input.as_bytes(), &mut output, BTreeMap::new()).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let mut received = Vec::new();
+    let mut rest = output.as_str();
+    while let Some(header) = rest.strip_prefix("Content-Length: ") {
+        let (length, body) = header.split_once("\r\n\r\n").unwrap();
+        let length: usize = length.parse().unwrap();
+        received.push(serde_json::from_str::<Value>(&body[..length]).unwrap());
+        rest = &body[length..];
+    }
+    let response = |id: u64| received.iter().find(|m| m["id"] == id).unwrap();
+    let notifications = |method: &str| {
+        received
+            .iter()
+            .filter(|m| m["method"] == method)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(received[0]["error"]["code"], -32700);
+    assert!(response(1)["result"]["capabilities"]["inlayHintProvider"]
+        .as_bool()
+        .unwrap());
+    // The call to the native function without an intrinsic is reported on its line
+    let published = notifications("textDocument/publishDiagnostics");
+    assert_eq!(published.len(), 1);
+    let diagnostics = published[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 4);
+    // Compiled functions get their estimated cost
+    let hints = response(2)["result"].as_array().unwrap();
+    assert!(!hints.is_empty());
+    assert!(hints
+        .iter()
+        .all(|hint| hint["label"].as_str().unwrap().ends_with("cycles")));
+    assert_eq!(notifications("window/logMessage").len(), 1);
+    assert_eq!(response(3)["error"]["code"], -32602);
+    assert_eq!(response(4)["error"]["code"], -32601);
+    assert_eq!(response(5)["result"], Value::Null);
+}
+
+#[cfg(feature = "aptos")]
+#[test]
+fn test_aptos_timestamp_from_advice() {
+    let bytes = move_compile("aptos").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let miden_ast = compiler::compile(&move_module).unwrap().program;
+    let program = Assembler::default().compile_ast(&miden_ast).unwrap();
+    let advice = AdviceInputs::default().with_stack_values([42]).unwrap();
+    let result = miden::execute(
+        &program,
+        Default::default(),
+        DefaultHost::new(MemAdviceProvider::from(advice)),
+        Default::default(),
+    )
+    .unwrap();
+    // Emitted events, a u64 and a struct pointer, are dropped
+    assert_eq!(result.stack_outputs().stack()[..3], [42, 0, 0]);
+}
+
+#[cfg(feature = "move-vm")]
+#[test]
+fn test_compare_gas() {
+    let bytes = move_compile("u64s").unwrap();
+    let move_module = move_utils::parse_module(&bytes).unwrap();
+    let measurements = crate::gas::compare(&[move_module], &[vec![1, 0, 2, 0]]).unwrap();
+    assert_eq!(measurements.len(), 1);
+    assert_eq!(measurements[0].function, "main::main");
+    assert!(measurements[0].gas > 0);
+    assert!(measurements[0].cycles > 0);
+    let report = crate::gas::render(&measurements);
+    assert!(report.contains("| `main::main` | 1, 0, 2, 0 |"));
+}
+
+#[test]
+fn test_compile_package() {
+    let modules: Vec<_> = move_compile_all("hashing")
+        .unwrap()
+        .iter()
+        .map(|bytes| move_utils::parse_module(bytes).unwrap())
+        .collect();
+    let artifacts = compiler::compile_package(&modules).unwrap();
+    // Only the `main` module has an entry function
+    assert_eq!(artifacts.len(), 1);
+    let expected = compiler::compile(modules.last().unwrap()).unwrap();
+    assert_eq!(artifacts[0].program, expected.program);
+
+    let modules: Vec<_> = move_compile_all("package")
+        .unwrap()
+        .iter()
+        .map(|bytes| move_utils::parse_module(bytes).unwrap())
+        .collect();
+    let error = compiler::compile_package(&modules).unwrap_err();
+    assert!(format!("{error:#}").contains("other modules"), "{error:#}");
+}
+
+#[test]
+fn test_compile_package_visibility() {
+    let compile = |visibility, friend: bool| {
+        let mut modules: Vec<_> = move_compile_all("package")
+            .unwrap()
+            .iter()
+            .map(|bytes| move_utils::parse_module(bytes).unwrap())
+            .collect();
+        let util = &mut modules[0];
+        util.function_defs[0].visibility = visibility;
+        if friend {
+            util.identifiers.push(Identifier::new("main").unwrap());
+            util.module_handles.push(ModuleHandle {
+                address: util.module_handles[0].address,
+                name: IdentifierIndex::new(util.identifiers.len() as u16 - 1),
+            });
+            let main = util.module_handles.last().unwrap().clone();
+            util.friend_decls.push(main);
+        }
+        compiler::compile_package(&modules).unwrap_err()
+    };
+    for (visibility, friend) in [(Visibility::Private, true), (Visibility::Friend, false)] {
+        let error = compile(visibility, friend);
+        assert_eq!(
+            error.downcast_ref::<ErrorCode>(),
+            Some(&diagnostics::VISIBILITY),
+            "{error:#}"
+        );
+    }
+    // Friends get as far as the cross-module call
+    let error = compile(Visibility::Friend, true);
+    assert_eq!(
+        error.downcast_ref::<ErrorCode>(),
+        Some(&diagnostics::CROSS_MODULE_CALL),
+        "{error:#}"
+    );
+}
+
+#[test]
+fn test_export_friend_function() {
+    let result = frontend::check_sources(
+        &["src/tests/res/move_sources/friends.move".to_string()],
+        [(
+            "friends".to_string(),
+            NumericalAddress::new([0; 32], NumberFormat::Hex),
+        )]
+        .into_iter()
+        .collect(),
+    );
+    let status = result
+        .functions
+        .iter()
+        .find(|f| f.function == "on_deposit")
+        .unwrap();
+    assert!(status.procedure.is_none());
+    assert!(result
+        .diagnostics
+        .diagnostics
+        .iter()
+        .any(|d| d.code == Some(diagnostics::VISIBILITY.code)));
+}
+
+#[test]
+fn test_batch() {
+    let compile = |package_name: &str| {
+        frontend::compile_batch(
+            &[format!("src/tests/res/move_sources/{package_name}.move")],
+            [(
+                package_name.to_string(),
+                NumericalAddress::new([0; 32], NumberFormat::Hex),
+            )]
+            .into_iter()
+            .collect(),
+        )
+    };
+    let artifact = compile("batch").unwrap();
+    assert!(artifact.abi.batch);
+    assert_eq!(artifact.abi.output_width, 0);
+    assert_eq!(artifact.abi.inputs.stack_width, 0);
+    assert_eq!(artifact.abi.inputs.advice_width, 3);
+    let instances = vec![vec![1, 2, 3], vec![20, 22, 42], vec![0, 0, 0]];
+    crate::runner::run_batch(&artifact, &instances).unwrap();
+    crate::runner::run_batch(&artifact, &[]).unwrap();
+    // A single instance is a batch of one
+    assert_eq!(
+        crate::runner::run(&artifact, &[5, 6, 11]).unwrap(),
+        Vec::<u64>::new()
+    );
+
+    // Fails unless every instance holds
+    let mut invalid = instances.clone();
+    invalid[1][2] = 43;
+    assert!(crate::runner::run_batch(&artifact, &invalid).is_err());
+    // Every instance checks its inputs
+    invalid[1] = vec![1 << 32, 0, 1 << 32];
+    assert!(crate::runner::run_batch(&artifact, &invalid).is_err());
+    // The number of instances is on the stack and their values on the advice stack
+    let inputs = abi::encode_batch(&artifact.abi.inputs, &instances).unwrap();
+    assert_eq!(inputs.stack, vec![3]);
+    assert_eq!(inputs.advice.len(), 9);
+    assert!(abi::encode_batch(&artifact.abi.inputs, &[vec![1, 2]]).is_err());
+
+    // The entry function must return a bool
+    let errors = compile("wide_inputs").unwrap_err();
+    assert_eq!(
+        errors.diagnostics[0].code,
+        Some(diagnostics::ENTRY_POINT.code)
+    );
+}
+
+/// The module of the package with the code of its entry function, which takes
+/// a u32, replaced by `code`, with two u32 locals after the parameter.
+fn with_entry_code(package_name: &str, code: Vec<Bytecode>) -> CompiledModule {
+    let bytes = move_compile(package_name).unwrap();
+    let mut module = move_utils::parse_module(&bytes).unwrap();
+    module
+        .signatures
+        .push(Signature(vec![SignatureToken::U32, SignatureToken::U32]));
+    let locals = SignatureIndex((module.signatures.len() - 1) as u16);
+    let entry = module
+        .function_defs
+        .iter_mut()
+        .find(|definition| definition.is_entry)
+        .unwrap();
+    let unit = entry.code.as_mut().unwrap();
+    unit.locals = locals;
+    unit.code = code;
+    module
+}
+
+fn move_compile(package_name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut units = move_compile_all(package_name)?;
+    units
+        .pop()
+        .with_context(|| format!("No modules in {package_name}.move"))
+}
+
+/// Compiles all modules of the package, in declaration order.
+fn move_compile_all(package_name: &str) -> anyhow::Result<Vec<Vec<u8>>> {
+    move_compile_with_deps(package_name, Vec::new())
+}
+
+/// Compiles all modules of the package, which may use the native modules
+/// shipped in `src/move` at address `miden`.
+fn move_compile_with_natives(package_name: &str) -> anyhow::Result<Vec<Vec<u8>>> {
+    let natives = [
+        "src/move/merkle.move",
+        "src/move/oracle.move",
+        "src/move/recursion.move",
+    ];
+    move_compile_with_deps(package_name, natives.map(String::from).to_vec())
+}
+
+fn move_compile_with_deps(package_name: &str, deps: Vec<String>) -> anyhow::Result<Vec<Vec<u8>>> {
+    let known_attributes = BTreeSet::new();
+    let mut miden = [0; 32];
+    miden[31] = 1;
+    let named_address_mapping = [
+        (
+            package_name,
+            NumericalAddress::new([0; 32], NumberFormat::Hex),
+        ),
+        ("miden", NumericalAddress::new(miden, NumberFormat::Hex)),
+    ]
     .into_iter()
     .collect();
     let compiler = Compiler::from_files(
         vec![format!("src/tests/res/move_sources/{package_name}.move")],
-        Vec::new(),
+        deps,
         named_address_mapping,
         Flags::empty(),
         &known_attributes,
@@ -53,7 +2824,9 @@ fn move_compile(package_name: &str) -> anyhow::Result<Vec<u8>> {
     let (_, result) = compiler
         .build()
         .context(format!("Failed to compile {package_name}.move"))?;
-    let compiled_unit = result.unwrap().0.pop().unwrap().into_compiled_unit();
-    let bytes = compiled_unit.serialize(None);
-    Ok(bytes)
+    let units = result.unwrap().0;
+    Ok(units
+        .into_iter()
+        .map(|unit| unit.into_compiled_unit().serialize(None))
+        .collect())
 }
@@ -0,0 +1,9 @@
+module aborts::main {
+    /// The value is larger than the maximum.
+    const E_TOO_LARGE: u64 = 0x20002;
+
+    public entry fun main(x: u64, y: u64): u64 {
+        assert!(x <= 10, E_TOO_LARGE);
+        x / y
+    }
+}
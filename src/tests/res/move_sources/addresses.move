@@ -0,0 +1,8 @@
+module addresses::main {
+    const OWNER: address = @0x1234;
+    const TREASURY: address = @0xface;
+
+    public entry fun main(): (address, bool) {
+        (OWNER, OWNER == TREASURY)
+    }
+}
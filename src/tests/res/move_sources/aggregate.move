@@ -0,0 +1,15 @@
+module aggregate::sum {
+    use miden::recursion;
+
+    // Sum of the first outputs of two proven executions
+    public fun sum_of_two(): u64 {
+        recursion::verify(27, 16);
+        let first = recursion::output(0);
+        recursion::verify(27, 16);
+        first + recursion::output(0)
+    }
+
+    public entry fun main(index: u32): u64 {
+        recursion::input(index)
+    }
+}
@@ -0,0 +1,23 @@
+module aptos::timestamp {
+    native public fun now_seconds(): u64;
+}
+
+module aptos::event {
+    native public fun emit<T: drop + store>(msg: T);
+}
+
+module aptos::main {
+    use aptos::event;
+    use aptos::timestamp;
+
+    struct Tick has drop, store {
+        time: u64,
+    }
+
+    public entry fun main(): u64 {
+        let time = timestamp::now_seconds();
+        event::emit(time);
+        event::emit(Tick { time });
+        time
+    }
+}
@@ -0,0 +1,22 @@
+module audit::counter {
+    struct Counter has key {
+        value: u64,
+    }
+
+    fun bump(owner: address, times: u64) acquires Counter {
+        if (times > 0) {
+            let counter = borrow_global_mut<Counter>(owner);
+            counter.value = counter.value + 1;
+            bump(owner, times - 1);
+        }
+    }
+
+    fun read(owner: address): u64 acquires Counter {
+        borrow_global<Counter>(owner).value
+    }
+
+    public fun bump_and_read(owner: address): u64 acquires Counter {
+        bump(owner, 2);
+        read(owner)
+    }
+}
@@ -0,0 +1,5 @@
+module batch::sums {
+    public entry fun check(a: u32, b: u32, sum: u32): bool {
+        a + b == sum
+    }
+}
@@ -0,0 +1,10 @@
+module bindings::math {
+    // Limbs are u32 values, as expected by the Miden procedure
+    #[miden(intrinsic = b"std::math::u64::wrapping_add")]
+    native fun wrapping_add(a_hi: u32, a_lo: u32, b_hi: u32, b_lo: u32): (u32, u32);
+
+    public entry fun main(): u32 {
+        let (hi, lo) = wrapping_add(0, 1, 0, 2);
+        hi + lo
+    }
+}
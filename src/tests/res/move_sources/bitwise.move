@@ -0,0 +1,5 @@
+module bitwise::main {
+    public entry fun main(a: u64, b: u64, c: u128, d: u128, x: u32, y: u32): (u64, u128, u32) {
+        (a & b, c ^ d, x | y)
+    }
+}
@@ -0,0 +1,16 @@
+module booleans::main {
+    fun count_to(limit: u32): u32 {
+        let i: u32 = 0;
+        let done = false;
+        while (!done) {
+            i = i + 1;
+            done = i >= limit;
+        };
+        i
+    }
+
+    public entry fun main(a: bool, b: bool): (bool, bool) {
+        assert!(count_to(3) == 3, 1);
+        (!a, a != b)
+    }
+}
@@ -0,0 +1,12 @@
+module budget::limited {
+    #[miden(max_cycles = 100_000)]
+    fun double(x: u32): u32 {
+        x + x
+    }
+
+    #[miden(max_cycles = 5)]
+    public entry fun main() {
+        assert!(double(2) == 4, 1);
+        assert!(double(3) == 6, 2);
+    }
+}
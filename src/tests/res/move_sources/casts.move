@@ -0,0 +1,5 @@
+module casts::main {
+    public entry fun main(x: u64): u128 {
+        ((((((x as u256) as u128) as u32) as u16) as u8) as u64 as u128)
+    }
+}
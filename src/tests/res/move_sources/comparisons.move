@@ -0,0 +1,16 @@
+module comparisons::main {
+    fun count_up_to(n: u64): u64 {
+        let i: u64 = 0;
+        let count: u64 = 0;
+        while (i <= n) {
+            count = count + 1;
+            i = i + 1;
+        };
+        count
+    }
+
+    public entry fun main(x: u32, y: u32, a: u64, b: u64): (bool, bool, bool, bool, bool, bool, bool, bool) {
+        assert!(count_up_to(3) == 4, 1);
+        (x < y, x > y, x <= y, x >= y, a < b, a > b, a <= b, a >= b)
+    }
+}
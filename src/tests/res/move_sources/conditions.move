@@ -0,0 +1,13 @@
+module conditions::steps {
+    fun is_small(x: u32): bool {
+        x < 10
+    }
+
+    public entry fun main(n: u32): u32 {
+        let i: u32 = 0;
+        while (is_small(i) && i < n) {
+            i = i + 1;
+        };
+        i
+    }
+}
@@ -0,0 +1,9 @@
+module constants::main {
+    const BASE: u32 = 40;
+    const BYTES: vector<u8> = b"abc";
+
+    public entry fun main(): u32 {
+        let _ = BYTES;
+        BASE + 2
+    }
+}
@@ -0,0 +1,35 @@
+module counted::loops {
+    fun none(x: u32): (u32, u32) {
+        let i: u32 = 3;
+        while (i < 3) {
+            x = x + i;
+            i = i + 1;
+        };
+        (x, i)
+    }
+
+    fun once(x: u32): (u32, u32) {
+        let i: u32 = 7;
+        while (i <= 7) {
+            x = x + i;
+            i = i + 1;
+        };
+        (x, i)
+    }
+
+    fun many(x: u32): (u32, u32) {
+        let i: u32 = 0;
+        while (i < 10) {
+            x = x + i;
+            i = i + 3;
+        };
+        (x, i)
+    }
+
+    public entry fun main(x: u32): (u32, u32, u32, u32, u32, u32) {
+        let (none_total, none_i) = none(x);
+        let (once_total, once_i) = once(x);
+        let (many_total, many_i) = many(x);
+        (none_total, none_i, once_total, once_i, many_total, many_i)
+    }
+}
@@ -0,0 +1,15 @@
+module discards::main {
+    fun widen(x: u64): u128 {
+        (x as u128) * 3
+    }
+
+    fun twice(x: u64): u64 {
+        x + x
+    }
+
+    public entry fun main(a: u64): u64 {
+        let _ = widen(a);
+        let _ = twice(a);
+        a + 1
+    }
+}
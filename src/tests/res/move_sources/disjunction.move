@@ -0,0 +1,9 @@
+module disjunction::steps {
+    public entry fun main(n: u32, m: u32): u32 {
+        let i: u32 = 0;
+        while (i < n || i < m) {
+            i = i + 1;
+        };
+        i
+    }
+}
@@ -0,0 +1,9 @@
+module division::main {
+    fun halve(x: u32): u32 {
+        x / 2
+    }
+
+    public entry fun main(x: u32, y: u32, z: u64, w: u64): (u32, u64) {
+        (halve(x / y), z % w)
+    }
+}
@@ -0,0 +1,13 @@
+module domain::hash {
+    native public fun rpo256(data: vector<u8>): (u64, u64, u64, u64);
+}
+
+module domain::main {
+    use domain::hash;
+
+    const DOMAIN: vector<u8> = b"move2miden";
+
+    public entry fun main(): (u64, u64, u64, u64) {
+        hash::rpo256(DOMAIN)
+    }
+}
@@ -0,0 +1,9 @@
+module errors::checked {
+    /// The value is larger than the maximum.
+    const E_TOO_LARGE: u64 = 1;
+    const MAX_VALUE: u64 = 10;
+
+    public entry fun main() {
+        assert!(MAX_VALUE > 0, E_TOO_LARGE);
+    }
+}
@@ -0,0 +1,14 @@
+module export::hooks {
+    #[miden(export)]
+    fun on_transfer(amount: u32): u32 {
+        helper(amount)
+    }
+
+    fun helper(amount: u32): u32 {
+        amount + 1
+    }
+
+    public entry fun main() {
+        assert!(on_transfer(1) == 2, 1);
+    }
+}
@@ -0,0 +1,16 @@
+module flow::bank {
+    use miden::merkle;
+
+    struct Balance has key {
+        value: u64,
+    }
+
+    // Checks a leaf of global storage against the root of the state
+    public entry fun main(
+        r0: u64, r1: u64, r2: u64, r3: u64,
+        index: u64,
+        l0: u64, l1: u64, l2: u64, l3: u64,
+    ) {
+        merkle::verify(r0, r1, r2, r3, index, 32, l0, l1, l2, l3);
+    }
+}
@@ -0,0 +1,12 @@
+module foreign::oracle {
+    #[miden(foreign)]
+    native public fun get_price(oracle: address, pair: u32): u64;
+}
+
+module foreign::main {
+    use foreign::oracle;
+
+    public entry fun main(oracle: address, pair: u32): u64 {
+        oracle::get_price(oracle, pair)
+    }
+}
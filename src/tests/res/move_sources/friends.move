@@ -0,0 +1,20 @@
+module friends::vault {
+    friend friends::bank;
+
+    #[miden(export)]
+    public(friend) fun on_deposit(amount: u32): u32 {
+        amount
+    }
+
+    public entry fun main() {
+        assert!(on_deposit(1) == 1, 1);
+    }
+}
+
+module friends::bank {
+    use friends::vault;
+
+    public fun deposit(amount: u32): u32 {
+        vault::on_deposit(amount)
+    }
+}
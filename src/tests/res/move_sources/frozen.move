@@ -0,0 +1,17 @@
+module frozen::main {
+    fun read(x: &u64): u64 {
+        *x
+    }
+
+    fun set(x: &mut u64, value: u64) {
+        *x = value;
+    }
+
+    public entry fun main(a: u64): u64 {
+        let value = 1;
+        set(&mut value, a);
+        let r = &mut value;
+        *r = *r + 1;
+        read(r) + value
+    }
+}
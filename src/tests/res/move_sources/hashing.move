@@ -0,0 +1,13 @@
+module hashing::hash {
+    native public fun keccak256(
+        a0: u32, a1: u32, a2: u32, a3: u32, a4: u32, a5: u32, a6: u32, a7: u32
+    ): (u32, u32, u32, u32, u32, u32, u32, u32);
+}
+
+module hashing::main {
+    use hashing::hash;
+
+    public entry fun main(): (u32, u32, u32, u32, u32, u32, u32, u32) {
+        hash::keccak256(0, 0, 0, 0, 0, 0, 0, 0)
+    }
+}
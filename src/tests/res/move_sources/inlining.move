@@ -0,0 +1,14 @@
+module inlining::hints {
+    #[miden(inline(never))]
+    fun add(x: u32, y: u32): u32 {
+        x + y
+    }
+
+    fun mul(x: u32, y: u32): u32 {
+        x * y
+    }
+
+    public entry fun main(): u32 {
+        mul(add(1, 2), 3)
+    }
+}
@@ -0,0 +1,5 @@
+module inputs::main {
+    public entry fun main(_flag: bool, x: u8): u8 {
+        x
+    }
+}
@@ -0,0 +1,18 @@
+module kernel::account {
+    native public fun get_id(): address;
+    native public fun incr_nonce(value: u32);
+}
+
+module kernel::tx {
+    native public fun get_block_number(): u32;
+}
+
+module kernel::main {
+    use kernel::account;
+    use kernel::tx;
+
+    public entry fun main(): (address, u32) {
+        account::incr_nonce(1);
+        (account::get_id(), tx::get_block_number())
+    }
+}
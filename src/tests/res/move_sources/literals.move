@@ -0,0 +1,5 @@
+module literals::main {
+    public entry fun main(): (bool, bool) {
+        (true, false)
+    }
+}
@@ -0,0 +1,7 @@
+module locals::main {
+    public entry fun main(): u32 {
+        let x = 40;
+        let y = x + 2;
+        y
+    }
+}
@@ -0,0 +1,9 @@
+module memo::scales {
+    fun scale(x: u32): u32 {
+        x * 3
+    }
+
+    public entry fun main(x: u32): u32 {
+        scale(7) + x + scale(7)
+    }
+}
@@ -0,0 +1,12 @@
+module merkle::main {
+    use miden::merkle;
+
+    public entry fun main(
+        r0: u64, r1: u64, r2: u64, r3: u64,
+        index: u64,
+        depth: u8,
+        l0: u64, l1: u64, l2: u64, l3: u64,
+    ) {
+        merkle::verify(r0, r1, r2, r3, index, depth, l0, l1, l2, l3);
+    }
+}
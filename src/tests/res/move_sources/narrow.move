@@ -0,0 +1,13 @@
+module narrow::main {
+    fun add(x: u8, y: u8): u8 {
+        x + y
+    }
+
+    fun scale(x: u16): u16 {
+        x * 300
+    }
+
+    public entry fun main(x: u8, y: u8, z: u16): (u8, u16) {
+        (add(x, y), scale(z))
+    }
+}
@@ -0,0 +1,22 @@
+module nested::loops {
+    public entry fun main(x: u64): u64 {
+        let i: u32 = 0;
+        while (i < 200) {
+            let j: u32 = 0;
+            while (j < 200) {
+                let k: u32 = 0;
+                while (k < 200) {
+                    let l: u32 = 0;
+                    while (l < 200) {
+                        x = x + 1;
+                        l = l + 1;
+                    };
+                    k = k + 1;
+                };
+                j = j + 1;
+            };
+            i = i + 1;
+        };
+        x
+    }
+}
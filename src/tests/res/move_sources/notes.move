@@ -0,0 +1,14 @@
+module notes::note {
+    native public fun create(faucet: address, amount: u64, tag: u32, recipient: vector<u8>): u32;
+}
+
+module notes::main {
+    use notes::note;
+
+    // Digest elements 1, 2, 3 and 4
+    const RECIPIENT: vector<u8> = x"0100000000000000020000000000000003000000000000000400000000000000";
+
+    public entry fun main(faucet: address, amount: u64): u32 {
+        note::create(faucet, amount, 0xc0000000, RECIPIENT)
+    }
+}
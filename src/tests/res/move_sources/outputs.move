@@ -0,0 +1,9 @@
+module outputs::main {
+    fun add(x: u32, y: u32): u32 {
+        x + y
+    }
+
+    public entry fun main(): (u32, u32) {
+        (add(2, 3), 7)
+    }
+}
@@ -0,0 +1,29 @@
+module overflow::ranges {
+    fun widened(x: u8): u32 {
+        (x as u32) + 1
+    }
+
+    fun add(x: u32, y: u32): u32 {
+        x + y
+    }
+
+    fun halves(x: u32): u32 {
+        x / 2 + x / 2
+    }
+
+    fun decrement(x: u32): u32 {
+        x - 1
+    }
+
+    fun masked(x: u32): u32 {
+        (x & 0xffff) * (x & 0xffff)
+    }
+
+    fun counter(): u32 {
+        let i = 0;
+        while (i < 10) {
+            i = i + 1;
+        };
+        i
+    }
+}
@@ -0,0 +1,6 @@
+module owners::main {
+    public entry fun main(owner: address, amount: u64): address {
+        let _ = amount;
+        owner
+    }
+}
@@ -0,0 +1,13 @@
+module package::util {
+    public fun one(): u32 {
+        1
+    }
+}
+
+module package::main {
+    use package::util;
+
+    public entry fun main() {
+        util::one();
+    }
+}
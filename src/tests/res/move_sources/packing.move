@@ -0,0 +1,17 @@
+module packing::hash {
+    native public fun pack(data: vector<u8>): vector<u32>;
+}
+
+module packing::main {
+    use packing::hash;
+
+    const DATA: vector<u8> = x"0102030405";
+
+    // Vector instructions can only be reached through the standard library, so
+    // tests replace this body with an access to the packed elements.
+    public entry fun main(i: u64): u32 {
+        let _ = hash::pack(DATA);
+        let _ = i;
+        0
+    }
+}
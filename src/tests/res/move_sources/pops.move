@@ -0,0 +1,25 @@
+module pops::main {
+    struct Pair has drop {
+        x: u64,
+        y: u32,
+    }
+
+    fun twice(x: u64): u64 {
+        x + x
+    }
+
+    fun widen(x: u64): u128 {
+        (x as u128) * 3
+    }
+
+    fun pair(x: u64, y: u32): Pair {
+        Pair { x, y }
+    }
+
+    public entry fun main(a: u64, b: u32): (u64, u32) {
+        let _ = twice(a);
+        let _ = widen(a);
+        let _ = pair(a, b);
+        (a + 1, b)
+    }
+}
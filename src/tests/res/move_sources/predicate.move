@@ -0,0 +1,5 @@
+module predicate::spend {
+    public entry fun unlocks(secret: u32): bool {
+        secret == 42
+    }
+}
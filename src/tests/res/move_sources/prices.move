@@ -0,0 +1,10 @@
+module prices::main {
+    use miden::oracle;
+
+    // Commitment to the public key of the oracle
+    const ORACLE_KEY: vector<u8> = x"0000000000000000000000000000000000000000000000000000000000000000";
+
+    public entry fun main(feed: u32): (u64, u64) {
+        oracle::read(feed, ORACLE_KEY)
+    }
+}
@@ -0,0 +1,10 @@
+module reduce::main {
+    native fun mystery(): u32;
+
+    public entry fun main(x: u32): u32 {
+        let a = x + 1;
+        let b = a * 2;
+        let c = mystery();
+        b + c
+    }
+}
@@ -0,0 +1,23 @@
+module references::main {
+    fun bump(x: &mut u64, by: u64) {
+        *x = *x + by;
+    }
+
+    fun double(x: &mut u32) {
+        *x = *x * 2;
+    }
+
+    fun incremented(x: u64): u64 {
+        bump(&mut x, 1);
+        x
+    }
+
+    public entry fun main(a: u64, b: u32): u64 {
+        let total = a;
+        bump(&mut total, 1);
+        let r = &total;
+        let copy = *r;
+        double(&mut b);
+        copy + (b as u64) + incremented(a)
+    }
+}
@@ -0,0 +1,6 @@
+module rotated::loops {
+    // Its code is replaced by the tests, with loops checking their condition last
+    public entry fun main(n: u32): u32 {
+        n
+    }
+}
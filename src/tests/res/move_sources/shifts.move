@@ -0,0 +1,5 @@
+module shifts::main {
+    public entry fun main(a: u8, n: u8, b: u64, m: u8, c: u32, k: u8): (u8, u64, u32) {
+        (a << n, b >> m, c << k)
+    }
+}
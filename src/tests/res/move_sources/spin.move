@@ -0,0 +1,9 @@
+module spin::main {
+    public entry fun main(n: u64): u64 {
+        let count = 0;
+        while (n != 0) {
+            count = count + 1;
+        };
+        count
+    }
+}
@@ -0,0 +1,17 @@
+module storage::bank {
+    struct Balance has key {
+        value: u64,
+    }
+
+    public fun balance(owner: address): u64 acquires Balance {
+        borrow_global<Balance>(owner).value
+    }
+
+    public fun total(first: address, second: address): u64 acquires Balance {
+        balance(first) + balance(second)
+    }
+
+    public fun zero(): u64 {
+        0
+    }
+}
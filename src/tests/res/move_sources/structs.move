@@ -0,0 +1,14 @@
+module structs::pair {
+    struct Pair has drop {
+        x: u32,
+        y: u32,
+    }
+
+    fun make(x: u32, y: u32): Pair {
+        Pair { x, y }
+    }
+
+    public entry fun main() {
+        let _ = make(2, 3);
+    }
+}
@@ -0,0 +1,46 @@
+module u128s::main {
+    fun add(x: u128, y: u128): u128 {
+        x + y
+    }
+
+    fun sub(x: u128, y: u128): u128 {
+        x - y
+    }
+
+    fun mul(x: u128, y: u128): u128 {
+        x * y
+    }
+
+    fun div(x: u128, y: u128): u128 {
+        x / y
+    }
+
+    fun mod(x: u128, y: u128): u128 {
+        x % y
+    }
+
+    fun lt(x: u128, y: u128): bool {
+        x < y
+    }
+
+    fun gt(x: u128, y: u128): bool {
+        x > y
+    }
+
+    fun le(x: u128, y: u128): bool {
+        x <= y
+    }
+
+    public entry fun main(x: u128, y: u128): u128 {
+        assert!(add(0xffffffffffffffffffffffff, 1) == 0x1000000000000000000000000, 1);
+        assert!(sub(0x1000000000000000000000000, 1) == 0xffffffffffffffffffffffff, 2);
+        assert!(mul(0x100000001, 0x10000000000000003) == 0x1000000010000000300000003, 3);
+        assert!(div(0x1000000010000000300000003, 0x100000001) == 0x10000000000000003, 4);
+        assert!(mod(0x1000000010000000300000008, 0x100000001) == 5, 5);
+        assert!(lt(0xffffffffffffffff, 0x10000000000000000), 6);
+        assert!(gt(0x1000000000000000000000000, 0xffffffffffffffffffffffff), 7);
+        assert!(le(5, 5), 8);
+        assert!(add(1, 2) != 4, 9);
+        x + y
+    }
+}
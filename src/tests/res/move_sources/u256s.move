@@ -0,0 +1,56 @@
+module u256s::main {
+    fun add(x: u256, y: u256): u256 {
+        x + y
+    }
+
+    fun sub(x: u256, y: u256): u256 {
+        x - y
+    }
+
+    fun mul(x: u256, y: u256): u256 {
+        x * y
+    }
+
+    fun div(x: u256, y: u256): u256 {
+        x / y
+    }
+
+    fun mod(x: u256, y: u256): u256 {
+        x % y
+    }
+
+    fun lt(x: u256, y: u256): bool {
+        x < y
+    }
+
+    fun gt(x: u256, y: u256): bool {
+        x > y
+    }
+
+    fun le(x: u256, y: u256): bool {
+        x <= y
+    }
+
+    fun widen(x: u64): u256 {
+        (x as u256)
+    }
+
+    fun narrow(x: u256): u8 {
+        (x as u8)
+    }
+
+    public entry fun main(x: u256, y: u256): u256 {
+        assert!(add(0xffffffffffffffffffffffffffffffffffffffffffffffff, 1) == 0x1000000000000000000000000000000000000000000000000, 1);
+        assert!(sub(0x1000000000000000000000000000000000000000000000000, 1) == 0xffffffffffffffffffffffffffffffffffffffffffffffff, 2);
+        assert!(mul(0x10000000000000001, 0x100000000000000000000000000000003) == 0x1000000000000000100000000000000030000000000000003, 3);
+        assert!(div(0x1000000000000000100000000000000030000000000000003, 0x10000000000000001) == 0x100000000000000000000000000000003, 4);
+        assert!(mod(0x1000000000000000100000000000000030000000000000008, 0x10000000000000001) == 5, 5);
+        assert!(lt(0xffffffffffffffffffffffffffffffff, 0x100000000000000000000000000000000), 6);
+        assert!(gt(0x1000000000000000000000000000000000000000000000000, 0xffffffffffffffffffffffffffffffffffffffffffffffff), 7);
+        assert!(le(5, 5), 8);
+        assert!(add(1, 2) != 4, 9);
+        assert!(widen(0x100000000) == 0x100000000, 10);
+        assert!(narrow(255) == 255, 11);
+        x + y
+    }
+}
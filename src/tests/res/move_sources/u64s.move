@@ -0,0 +1,40 @@
+module u64s::main {
+    fun add(x: u64, y: u64): u64 {
+        x + y
+    }
+
+    fun sub(x: u64, y: u64): u64 {
+        x - y
+    }
+
+    fun mul(x: u64, y: u64): u64 {
+        x * y
+    }
+
+    fun div(x: u64, y: u64): u64 {
+        x / y
+    }
+
+    fun mod(x: u64, y: u64): u64 {
+        x % y
+    }
+
+    fun lt(x: u64, y: u64): bool {
+        x < y
+    }
+
+    fun ge(x: u64, y: u64): bool {
+        x >= y
+    }
+
+    public entry fun main(x: u64, y: u64): u64 {
+        assert!(add(0xffffffff, 1) == 0x100000000, 1);
+        assert!(sub(0x100000000, 1) == 0xffffffff, 2);
+        assert!(mul(0x10000, 0x100000) == 0x1000000000, 3);
+        assert!(div(0x1000000000, 0x100000) == 0x10000, 4);
+        assert!(mod(0x1000000005, 0x100000) == 5, 5);
+        assert!(lt(0xffffffff, 0x100000000), 6);
+        assert!(ge(0x100000000, 0x100000000), 7);
+        x + y
+    }
+}
@@ -0,0 +1,11 @@
+module unsupported::main {
+    native fun mystery(): u32;
+
+    public entry fun main() {
+        let _ = mystery();
+    }
+
+    fun helper(x: u32): u32 {
+        x + 1
+    }
+}
@@ -0,0 +1,16 @@
+module vectors::access {
+    const VALUES: vector<u32> = vector[10, 20, 30];
+
+    fun values(): vector<u32> {
+        VALUES
+    }
+
+    // Vector instructions can only be reached through the standard library, so
+    // tests replace this function, which can't be an entry function as it takes
+    // a reference, with an element access of VALUES.
+    public entry fun main(values: &vector<u32>, i: u64): u32 {
+        let _ = values;
+        let _ = i;
+        0
+    }
+}
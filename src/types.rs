@@ -0,0 +1,104 @@
+//! Module for tracking the types of values on the Move operand stack.
+//!
+//! Move values may take up more than one element of the Miden stack, so the
+//! compiler needs to know the type of a value to manipulate it correctly
+//! (e.g. when dropping it). The Move bytecode verifier ensures the operand stack
+//! is empty at the start of each basic block, so tracking always starts from
+//! an empty stack at the beginning of a block.
+
+use {crate::diagnostics, anyhow::Error, move_binary_format::file_format::SignatureToken};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeStack {
+    stack: Vec<SignatureToken>,
+}
+
+impl TypeStack {
+    pub fn push(&mut self, token: SignatureToken) {
+        self.stack.push(token);
+    }
+
+    pub fn pop(&mut self) -> anyhow::Result<SignatureToken> {
+        self.stack
+            .pop()
+            .ok_or_else(|| Error::msg("Type stack underflow"))
+    }
+
+    pub fn pop_n(&mut self, n: usize) -> anyhow::Result<()> {
+        for _ in 0..n {
+            self.pop()?;
+        }
+        Ok(())
+    }
+
+    pub fn peek(&self) -> Option<&SignatureToken> {
+        self.stack.last()
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
+}
+
+/// Number of Miden stack elements used to represent a value of the given type.
+pub fn stack_width(token: &SignatureToken) -> anyhow::Result<usize> {
+    match token {
+        SignatureToken::Bool
+        | SignatureToken::U8
+        | SignatureToken::U16
+        | SignatureToken::U32
+        | SignatureToken::Address
+        | SignatureToken::Signer => Ok(1),
+        // u32 limbs (see `integers` module).
+        SignatureToken::U64 => Ok(2),
+        SignatureToken::U128 => Ok(4),
+        SignatureToken::U256 => Ok(8),
+        // Aggregates and references are represented by a pointer (see `memory` module).
+        SignatureToken::Struct(_)
+        | SignatureToken::StructInstantiation(_, _)
+        | SignatureToken::Vector(_)
+        | SignatureToken::Reference(_)
+        | SignatureToken::MutableReference(_) => Ok(1),
+        SignatureToken::TypeParameter(_) => Err(diagnostics::UNSUPPORTED_TYPE
+            .error(format!("Can't handle values of type {token:?} yet"))),
+    }
+}
+
+/// The type with its type parameters replaced by the given type arguments.
+pub fn instantiate(
+    token: &SignatureToken,
+    type_args: &[SignatureToken],
+) -> anyhow::Result<SignatureToken> {
+    let instantiate_all = |tokens: &[SignatureToken]| -> anyhow::Result<Vec<SignatureToken>> {
+        tokens.iter().map(|t| instantiate(t, type_args)).collect()
+    };
+    Ok(match token {
+        SignatureToken::TypeParameter(index) => type_args
+            .get(*index as usize)
+            .cloned()
+            .ok_or_else(|| anyhow::Error::msg("Missing type argument"))?,
+        SignatureToken::Vector(inner) => {
+            SignatureToken::Vector(Box::new(instantiate(inner, type_args)?))
+        }
+        SignatureToken::Reference(inner) => {
+            SignatureToken::Reference(Box::new(instantiate(inner, type_args)?))
+        }
+        SignatureToken::MutableReference(inner) => {
+            SignatureToken::MutableReference(Box::new(instantiate(inner, type_args)?))
+        }
+        SignatureToken::StructInstantiation(handle, args) => {
+            SignatureToken::StructInstantiation(*handle, instantiate_all(args)?)
+        }
+        token => token.clone(),
+    })
+}
+
+/// Whether values of the given type live in memory behind a pointer (see `memory` module).
+pub fn is_aggregate(token: &SignatureToken) -> bool {
+    matches!(
+        token,
+        SignatureToken::Struct(_)
+            | SignatureToken::StructInstantiation(_, _)
+            | SignatureToken::Vector(_)
+    )
+}
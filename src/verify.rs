@@ -0,0 +1,124 @@
+//! Module checking that a bundle is consistent before it is deployed.
+//!
+//! The assembly in the bundle is assembled again and must produce the recorded
+//! program hash, the recorded test vectors are executed again and must produce
+//! the recorded outputs, and the source references of procedures must point into
+//! the given Move sources. This catches bundles edited or corrupted after the
+//! build, and changes of the Miden toolchain affecting the program.
+
+use {
+    crate::{
+        artifact::CompiledArtifact,
+        backend::{AstBackend, DefaultBackend, MidenBackend},
+        bundle::{to_hex, Bundle, TestVector},
+        runner,
+    },
+    miden_assembly::ast::ProgramAst,
+    move_command_line_common::files::FileHash,
+};
+
+/// Runs the entry function of the artifact with `inputs` and records the outputs
+/// in the bundle, to be checked by `verify`.
+pub fn record_test_vector(
+    bundle: &mut Bundle,
+    artifact: &CompiledArtifact,
+    inputs: Vec<u64>,
+) -> anyhow::Result<()> {
+    let outputs = runner::run(artifact, &inputs)?;
+    bundle.test_vectors.push(TestVector { inputs, outputs });
+    Ok(())
+}
+
+/// Checks the bundle, returning a description of every problem found.
+/// `sources` holds the contents of the Move source files the bundle was built
+/// from; source references are only checked for consistency if it's empty.
+pub fn verify(bundle: &Bundle, sources: &[String]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let program = match ProgramAst::parse(&bundle.masm) {
+        Ok(program) => program,
+        Err(e) => {
+            problems.push(format!("Failed to parse the assembly of the bundle: {e}"));
+            return problems;
+        }
+    };
+    let backend = DefaultBackend::default();
+    match backend.assemble(&program) {
+        Ok(assembled) => {
+            let hash = to_hex(&backend.program_hash(&assembled));
+            if hash != bundle.program_hash {
+                problems.push(format!(
+                    "Program hash mismatch: recorded {}, assembled {hash}",
+                    bundle.program_hash
+                ));
+            }
+        }
+        Err(e) => problems.push(format!("Failed to assemble the bundle: {e:#}")),
+    }
+    check_test_vectors(bundle, &program, &mut problems);
+    check_sources(bundle, sources, &mut problems);
+    problems
+}
+
+fn check_test_vectors(bundle: &Bundle, program: &ProgramAst, problems: &mut Vec<String>) {
+    if bundle.test_vectors.is_empty() {
+        return;
+    }
+    let Some(abi) = &bundle.abi else {
+        problems.push("The bundle has test vectors but no ABI to encode them".into());
+        return;
+    };
+    // Programs are executed by the VM of the AST assembler whatever the backend
+    let program = match AstBackend.assemble(program) {
+        Ok(program) => program,
+        Err(e) => {
+            problems.push(format!("Failed to assemble the bundle: {e:#}"));
+            return;
+        }
+    };
+    for (index, vector) in bundle.test_vectors.iter().enumerate() {
+        match runner::execute(&program, abi, &bundle.error_map, &vector.inputs) {
+            Ok(outputs) if outputs == vector.outputs => (),
+            Ok(outputs) => problems.push(format!(
+                "Test vector {index} with inputs {:?}: expected outputs {:?}, got {outputs:?}",
+                vector.inputs, vector.outputs
+            )),
+            Err(e) => problems.push(format!(
+                "Test vector {index} with inputs {:?} failed: {e:#}",
+                vector.inputs
+            )),
+        }
+    }
+}
+
+fn check_sources(bundle: &Bundle, sources: &[String], problems: &mut Vec<String>) {
+    let files: Vec<(String, &String)> = sources
+        .iter()
+        .map(|contents| (to_hex(&FileHash::new(contents).0), contents))
+        .collect();
+    for procedure in &bundle.procedures {
+        let Some(source) = &procedure.source else {
+            continue;
+        };
+        if source.start > source.end {
+            problems.push(format!(
+                "Procedure {} has an invalid source range {}..{}",
+                procedure.name, source.start, source.end
+            ));
+            continue;
+        }
+        if files.is_empty() {
+            continue;
+        }
+        match files.iter().find(|(hash, _)| *hash == source.file_hash) {
+            Some((_, contents)) if source.end as usize <= contents.len() => (),
+            Some(_) => problems.push(format!(
+                "Procedure {} has a source range {}..{} past the end of its file",
+                procedure.name, source.start, source.end
+            )),
+            None => problems.push(format!(
+                "Procedure {} refers to a source file with hash {} which wasn't given",
+                procedure.name, source.file_hash
+            )),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Module for generating the advice inputs proving the resources a program
+//! reads from global storage.
+//!
+//! Global storage is committed to by a sparse Merkle tree of depth
+//! `STORAGE_DEPTH`. A resource is stored at the leaf whose index is derived
+//! from the hash of its owner account and struct name (see `leaf_index`), and
+//! the leaf holds the hash of its fields as laid out in memory, i.e. with
+//! `u64` values split into limbs (see `abi::StorageLayout`).
+//!
+//! Global storage instructions aren't lowered yet, so compiled programs don't
+//! check resources against the root on their own. Move code can check one by
+//! calling `merkle::verify` with the root, `leaf_index` and `leaf_value` of the
+//! resource, which reads its Merkle path from the advice provider.
+//! `storage_advice` builds the paths of the resources the entry function
+//! acquires from a snapshot of the state.
+
+use {
+    crate::{
+        abi::{AbiType, StorageLayout},
+        accounts::AccountId,
+        artifact::CompiledArtifact,
+    },
+    anyhow::Context,
+    miden::{
+        crypto::{MerkleStore, NodeIndex, Rpo256, SimpleSmt},
+        math::{Felt, StarkField},
+        AdviceInputs, Word,
+    },
+    std::collections::BTreeMap,
+};
+
+/// Depth of the sparse Merkle tree holding global storage.
+pub const STORAGE_DEPTH: u8 = 32;
+
+/// A resource in global storage, identified by its owner and struct name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ResourceKey {
+    pub account: AccountId,
+    pub resource: String,
+}
+
+/// Values of the resources in global storage, one value per field.
+pub type Snapshot = BTreeMap<ResourceKey, Vec<u64>>;
+
+/// Index of the leaf holding the resource.
+pub fn leaf_index(key: &ResourceKey) -> u64 {
+    let mut elements = vec![key.account.element()];
+    elements.extend(Rpo256::hash(key.resource.as_bytes()).as_elements());
+    let digest = Rpo256::hash_elements(&elements);
+    digest.as_elements()[0].as_int() & ((1 << STORAGE_DEPTH) - 1)
+}
+
+/// Value of the leaf holding a resource whose fields take the given elements.
+pub fn leaf_value(elements: &[u64]) -> Word {
+    let elements: Vec<Felt> = elements.iter().map(|value| Felt::new(*value)).collect();
+    Rpo256::hash_elements(&elements).into()
+}
+
+/// Root of the tree committing to the snapshot, after checking every
+/// resource matches its struct in the storage layout.
+pub fn storage_root(layout: &StorageLayout, snapshot: &Snapshot) -> anyhow::Result<Word> {
+    Ok(storage_tree(layout, snapshot)?.root().into())
+}
+
+/// Advice inputs holding the Merkle paths of the resources of the snapshot
+/// acquired by the entry function of the artifact.
+pub fn storage_advice(
+    artifact: &CompiledArtifact,
+    snapshot: &Snapshot,
+) -> anyhow::Result<AdviceInputs> {
+    let acquires = artifact
+        .procedures
+        .iter()
+        .find(|info| info.procedure.is_none())
+        .map(|info| info.acquires.as_slice())
+        .unwrap_or_default();
+    storage_advice_for(&artifact.layout, acquires, snapshot)
+}
+
+/// Advice inputs holding the Merkle paths of the resources of the snapshot
+/// whose struct is one of `acquires`, e.g. for a program rebuilt from a bundle.
+pub fn storage_advice_for(
+    layout: &StorageLayout,
+    acquires: &[String],
+    snapshot: &Snapshot,
+) -> anyhow::Result<AdviceInputs> {
+    let tree = storage_tree(layout, snapshot)?;
+    // Only the paths of acquired resources are handed to the program
+    let full = MerkleStore::from(&tree);
+    let mut store = MerkleStore::new();
+    for key in snapshot
+        .keys()
+        .filter(|key| acquires.contains(&key.resource))
+    {
+        let index = NodeIndex::new(STORAGE_DEPTH, leaf_index(key))?;
+        let path = full.get_path(tree.root(), index)?;
+        store.add_merkle_path(index.value(), path.value.into(), path.path)?;
+    }
+    Ok(AdviceInputs::default().with_merkle_store(store))
+}
+
+fn storage_tree(
+    layout: &StorageLayout,
+    snapshot: &Snapshot,
+) -> anyhow::Result<SimpleSmt<STORAGE_DEPTH>> {
+    let mut leaves = BTreeMap::new();
+    for (key, fields) in snapshot {
+        let layout = layout
+            .structs
+            .iter()
+            .find(|s| s.name == key.resource)
+            .with_context(|| format!("Unknown resource {}", key.resource))?;
+        anyhow::ensure!(
+            fields.len() == layout.fields.len(),
+            "Resource {} at {} has {} fields but {} were given",
+            key.resource,
+            key.account,
+            layout.fields.len(),
+            fields.len()
+        );
+        let elements: Vec<u64> = layout
+            .fields
+            .iter()
+            .zip(fields)
+            .flat_map(|(field, value)| match field.ty {
+                AbiType::U64 => vec![value & 0xffff_ffff, value >> 32],
+                _ => vec![*value],
+            })
+            .collect();
+        let index = leaf_index(key);
+        if let Some((other, _)) = leaves.insert(index, (key, elements)) {
+            anyhow::bail!(
+                "Resources {} at {} and {} at {} are stored at the same leaf",
+                other.resource,
+                other.account,
+                key.resource,
+                key.account
+            );
+        }
+    }
+    let tree = SimpleSmt::with_leaves(
+        leaves
+            .into_iter()
+            .map(|(index, (_, elements))| (index, leaf_value(&elements))),
+    )?;
+    Ok(tree)
+}